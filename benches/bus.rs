@@ -0,0 +1,52 @@
+//! Times CPU bus accesses spread over RAM, cartridge PRG ROM and the I/O
+//! registers, the mix the page table dispatches on.
+//!
+//! Run with `cargo bench --bench bus`. To compare a change, run it with
+//! `-- --save-baseline before` first and `-- --baseline before` after.
+
+use std::cell::RefCell;
+use std::hint::black_box;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use res::bus::SystemBus;
+use res::cartridge::Cartridge;
+use res::cpu::Memory;
+
+/// Number of accesses made in each iteration.
+const ACCESSES: u64 = 4096;
+
+/// Returns an MMC3 cartridge with 128 KB of PRG ROM and 8 KB of CHR ROM, so
+/// PRG reads go through a banking mapper.
+fn mmc3_cartridge() -> Cartridge {
+    let mut raw = b"NES\x1A".to_vec();
+    raw.extend([8, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    raw.extend((0..8 * 0x4000 + 0x2000).map(|i| i as u8));
+    Cartridge::new(&raw).unwrap()
+}
+
+fn bench_mixed_accesses(c: &mut Criterion) {
+    let cart = Rc::new(RefCell::new(mmc3_cartridge()));
+    let mut bus = SystemBus::new(cart, 44100.0, |_: &[u8]| {});
+
+    let mut group = c.benchmark_group("bus");
+    group.throughput(Throughput::Elements(ACCESSES));
+    group.bench_function("mixed_accesses", |b| {
+        b.iter(|| {
+            let mut sum = 0u8;
+            for i in 0..ACCESSES as u16 / 4 {
+                // Roughly what a game does: zero page and stack traffic,
+                // code fetches from PRG ROM and polling the controllers.
+                bus.mem_write_byte(i & 0x07FF, i as u8);
+                sum = sum.wrapping_add(bus.mem_read_byte(i.wrapping_mul(7) & 0x07FF));
+                sum = sum.wrapping_add(bus.mem_read_byte(0x8000 | i.wrapping_mul(37)));
+                sum = sum.wrapping_add(bus.mem_read_byte(0x4016));
+            }
+            black_box(sum)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_mixed_accesses);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+//! Times the PPU rendering a busy frame: a filled nametable, sprites on the
+//! top half of the screen eight to a line, and both pattern tables in use.
+//! The frame is timed with the PPU bus's decoded pattern rows and again with
+//! each row decoded as it is fetched, to show what the cache saves.
+//!
+//! Run with `cargo bench --bench ppu`, see `benches/bus.rs` for comparing a
+//! change against a baseline.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::io::{self, Read, Write};
+
+use res::bus::{Memory, PPUBus};
+use res::cartridge::Cartridge;
+use res::ppu::{MemoryInit, NesPpu, Ppu};
+
+/// Returns an NROM cartridge whose 8 KB of CHR ROM is filled with varied
+/// patterns, so no tile is transparent.
+fn busy_cartridge() -> Cartridge {
+    let mut raw = b"NES\x1A".to_vec();
+    raw.extend([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    raw.extend([0xEA; 0x4000]);
+    raw.extend((0..0x2000u32).map(|i| (i.wrapping_mul(0x9E37) >> 3) as u8));
+    Cartridge::new(&raw).unwrap()
+}
+
+/// A PPU bus which decodes pattern rows as they are fetched, through the
+/// default [`Memory::pattern_row`], rather than using the decoded cache.
+struct Decoding(PPUBus);
+
+impl Memory for Decoding {
+    fn write_data(&mut self, addr: u16, value: u8) {
+        self.0.write_data(addr, value)
+    }
+
+    fn read_data(&mut self, addr: u16) -> u8 {
+        self.0.read_data(addr)
+    }
+
+    fn peek_data(&self, addr: u16) -> u8 {
+        self.0.peek_data(addr)
+    }
+
+    fn observe(&mut self, addr: u16, dot: u64) {
+        self.0.observe(addr, dot)
+    }
+
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.0.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.0.load_state(r)
+    }
+}
+
+/// Returns a PPU with rendering enabled over the busy scene, decoding
+/// pattern rows on every fetch if asked to.
+fn busy_ppu(decoding: bool) -> NesPpu<'static> {
+    let cart = Rc::new(RefCell::new(busy_cartridge()));
+    let bus = PPUBus::new(cart, MemoryInit::Zero);
+    let bus: Box<dyn Memory> = match decoding {
+        true => Box::new(Decoding(bus)),
+        false => Box::new(bus),
+    };
+    let mut ppu = NesPpu::new(bus, MemoryInit::Zero, |_: &[u8]| {});
+
+    // Every tile of the first nametable, its attributes and the palettes.
+    for addr in 0x2000..0x2400u16 {
+        ppu.poke(addr, addr.wrapping_mul(13) as u8);
+    }
+    for addr in 0x3F00..0x3F20u16 {
+        ppu.poke(addr, addr as u8 & 0x3F);
+    }
+
+    // 64 8x16 sprites in 8 rows of 8, so each of the top 128 lines has the
+    // most sprites the PPU draws, half of them flipped.
+    let mut oam = [0; 256];
+    for (i, sprite) in oam.chunks_mut(4).enumerate() {
+        sprite[0] = (i / 8 * 16) as u8;
+        sprite[1] = (i * 2) as u8 | 1;
+        sprite[2] = (i as u8 & 0x3) | (i as u8 & 0x1) << 6;
+        sprite[3] = (i % 8 * 30) as u8;
+    }
+    ppu.write_oam_dma(&oam);
+
+    // 8x16 sprites, background from the second pattern table, rendering
+    // enabled including the left column.
+    ppu.write_ctrl(0x30);
+    ppu.write_mask(0x1E);
+    ppu
+}
+
+fn bench_busy_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ppu");
+    for (name, decoding) in [("busy_frame", false), ("busy_frame_decoding_rows", true)] {
+        let mut ppu = busy_ppu(decoding);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let frame = ppu.read_frame_count();
+                while ppu.read_frame_count() == frame {
+                    ppu.clock();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(50);
+    targets = bench_busy_frame
+}
+criterion_main!(benches);
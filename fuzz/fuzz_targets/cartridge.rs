@@ -0,0 +1,58 @@
+//! Loads arbitrary bytes as a ROM and, if the cartridge loader accepts them,
+//! drives the mapper with arbitrary PRG and CHR accesses. Neither may panic.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use res::cartridge::Cartridge;
+
+/// An access the buses make to the cartridge.
+#[derive(Arbitrary, Debug)]
+enum Access {
+    ReadPrg(u16),
+    WritePrg(u16, u8),
+    ReadChr(u16),
+    WriteChr(u16, u8),
+    Strobe(u8),
+    ChrFetch(u16),
+    ClockCpu,
+    ClockA12,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    rom: Vec<u8>,
+    accesses: Vec<Access>,
+}
+
+/// Returns the address in the cartridge space, $4020-$FFFF, the CPU bus
+/// hands to the mapper.
+fn prg_addr(addr: u16) -> u16 {
+    0x4020 + addr % (0x10000 - 0x4020) as u16
+}
+
+fuzz_target!(|input: Input| {
+    let Ok(mut cart) = Cartridge::new(&input.rom) else {
+        return;
+    };
+
+    for access in input.accesses {
+        match access {
+            Access::ReadPrg(addr) => {
+                cart.read_prg(prg_addr(addr));
+            }
+            Access::WritePrg(addr, data) => cart.write_prg(prg_addr(addr), data),
+            Access::ReadChr(addr) => {
+                cart.read_chr(addr & 0x1FFF);
+            }
+            Access::WriteChr(addr, data) => cart.write_chr(addr & 0x1FFF, data),
+            Access::Strobe(data) => cart.write_strobe(data),
+            Access::ChrFetch(addr) => {
+                cart.notify_chr_fetch(addr & 0x1FFF);
+            }
+            Access::ClockCpu => cart.clock_cpu(),
+            Access::ClockA12 => cart.clock_a12(),
+        }
+    }
+});
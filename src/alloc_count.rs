@@ -0,0 +1,126 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    /// Number of allocations made by the current thread.
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Global allocator for debug test builds which counts the allocations made
+/// by each thread, so tests can check the steady state of the emulation
+/// never touches the heap. Tests run on their own threads, so the counts
+/// aren't disturbed by other tests running alongside.
+struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Counts an allocation, unless the thread is being torn down.
+fn count() {
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+/// Returns the number of allocations made by the current thread so far.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::SystemBus;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::Cpu;
+    use crate::selfcheck;
+    use crate::trace::write_trace;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run_frame(cpu: &mut Cpu, samples: &mut Vec<f32>) {
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            cpu.clock();
+        }
+        samples.clear();
+        cpu.bus.drain_audio_samples(samples);
+    }
+
+    #[test]
+    fn test_counts_allocations() {
+        let before = allocations();
+        let v = vec![1u8; 16];
+        assert_eq!(allocations(), before + 1);
+        drop(v);
+    }
+
+    #[test]
+    fn test_steady_state_frames_do_not_allocate() {
+        // The self-check ROM keeps the PPU, APU, NMI and joypad busy.
+        let cart = Cartridge::new(&selfcheck::rom()).unwrap();
+        let frame = RefCell::new(vec![0; 256 * 240 * 3]);
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |pixels| {
+            frame.borrow_mut().copy_from_slice(pixels);
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        // Buffers grow to their steady size over the first frames.
+        let mut samples = Vec::new();
+        for _ in 0..10 {
+            run_frame(&mut cpu, &mut samples);
+        }
+
+        let before = allocations();
+        for i in 0..60 {
+            cpu.bus.set_controller_state(0, i as u8);
+            run_frame(&mut cpu, &mut samples);
+        }
+        assert_eq!(allocations(), before);
+    }
+
+    #[test]
+    fn test_trace_into_reused_buffer_does_not_allocate() {
+        let cart = Cartridge::new(&selfcheck::rom()).unwrap();
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        // The first trace builds the opcode table.
+        let mut line = String::with_capacity(128);
+        write_trace(&mut cpu, &mut line);
+        let mut samples = Vec::new();
+        run_frame(&mut cpu, &mut samples);
+
+        let before = allocations();
+        for _ in 0..10000 {
+            line.clear();
+            write_trace(&mut cpu, &mut line);
+            cpu.clock();
+
+            samples.clear();
+            cpu.bus.drain_audio_samples(&mut samples);
+        }
+        assert_eq!(allocations(), before);
+    }
+}
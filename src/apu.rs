@@ -1,6 +1,10 @@
 mod dmc;
+mod length;
 mod noise;
+mod panning;
 mod pulse;
+mod sample_clock;
+mod scope;
 mod triangle;
 
 /// Length counter values table
@@ -44,12 +48,80 @@ const STATUS_REGISTER: u16 = 0x4015;
 /// Frame counter register
 const FRAME_COUNTER: u16 = 0x4017;
 
+/// CPU cycles at which the frame sequencer clocks the envelopes and triangle
+/// linear counter (quarter frames), and additionally the length counters and
+/// sweep units (half frames).
+///
+/// See: https://www.nesdev.org/wiki/APU_Frame_Counter
+struct FrameSteps {
+    step_1: u32,
+    step_2: u32,
+    step_3: u32,
+    four_step_irq: u32,
+    four_step_4: u32,
+    four_step_period: u32,
+    five_step_5: u32,
+    five_step_period: u32,
+}
+
+const NTSC_FRAME_STEPS: FrameSteps = FrameSteps {
+    step_1: 7457,
+    step_2: 14913,
+    step_3: 22371,
+    four_step_irq: 29828,
+    four_step_4: 29829,
+    four_step_period: 29830,
+    five_step_5: 37281,
+    five_step_period: 37282,
+};
+
+const PAL_FRAME_STEPS: FrameSteps = FrameSteps {
+    step_1: 8313,
+    step_2: 16627,
+    step_3: 24939,
+    four_step_irq: 33252,
+    four_step_4: 33253,
+    four_step_period: 33254,
+    five_step_5: 41565,
+    five_step_period: 41566,
+};
+
+use std::io::{self, Read, Write};
+
+pub use panning::Panning;
+pub use sample_clock::SampleClock;
+pub use scope::{Scope, SCOPE_SAMPLES};
+
 use dmc::Dmc;
 use noise::Noise;
 use pulse::Pulse;
 use triangle::Triangle;
 
 use crate::filters::{Filter, HighPass, LowPass};
+use crate::region::Region;
+use crate::state::{read_bool, read_u32, read_u8, write_bool, write_u32, write_u8, SaveState};
+
+/// Read-only view of a channel's state, for visualisers and debuggers.
+/// Fields a channel doesn't have are zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelState {
+    /// Whether the channel is enabled in $4015.
+    pub enabled: bool,
+
+    /// Timer period in CPU cycles for the DMC and noise channels, and in
+    /// timer units as written to the registers for the others.
+    pub period: u16,
+
+    /// Length counter, or the number of sample bits left to play for the DMC.
+    pub length_counter: u16,
+
+    /// Constant or envelope volume of the pulse and noise channels, the
+    /// linear counter of the triangle and the output level of the DMC.
+    pub volume: u8,
+
+    /// Duty cycle of the pulse channels, 0-3.
+    pub duty: u8,
+}
 
 /// The mode in which the APU which loop over events.
 #[derive(PartialEq)]
@@ -61,13 +133,22 @@ enum SequencerMode {
 /// Represents the NES Audio Processing Unit (APU).
 pub struct Apu {
     cycles: u32,
-    frame_counter: u16,
-    disable_interrupt: bool,
-    pending_interrupt: Option<bool>,
 
-    sequencer: u8,
+    /// Frame sequencer position in CPU cycles.
+    frame_cycle: u32,
+    frame_steps: &'static FrameSteps,
     mode: SequencerMode,
 
+    /// CPU cycles until a $4017 write resets the sequencer.
+    frame_reset_delay: Option<u8>,
+
+    irq_inhibit: bool,
+    frame_irq: bool,
+
+    /// True if the frame IRQ flag was set by the current cycle, in which case
+    /// reading $4015 does not clear it.
+    frame_irq_set: bool,
+
     pulse1: Pulse,
     pulse2: Pulse,
     triangle: Triangle,
@@ -78,6 +159,16 @@ pub struct Apu {
     tnd_table: [f32; 203],
 
     filters: Vec<Box<dyn Filter>>,
+
+    /// Pan positions and right channel filters for stereo output.
+    panning: Panning,
+    filters_right: Vec<Box<dyn Filter>>,
+
+    /// Channels left out of the mix, for debugging.
+    muted: [bool; 5],
+
+    /// Recent channel levels, kept while the visualiser is shown.
+    scope: Option<Box<Scope>>,
 }
 
 impl Apu {
@@ -85,12 +176,15 @@ impl Apu {
     pub fn new(sample_rate: f32) -> Self {
         let mut apu = Apu {
             cycles: 0,
-            frame_counter: 0,
-            disable_interrupt: false,
-            pending_interrupt: None,
 
-            sequencer: 0,
+            frame_cycle: 0,
+            frame_steps: &NTSC_FRAME_STEPS,
             mode: SequencerMode::FourStep,
+            frame_reset_delay: None,
+
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_irq_set: false,
 
             pulse1: Pulse::new(),
             pulse2: Pulse::new(),
@@ -101,11 +195,13 @@ impl Apu {
             pulse_table: [0.0; 31],
             tnd_table: [0.0; 203],
 
-            filters: vec![
-                Box::new(HighPass::new(90.0, sample_rate)),
-                Box::new(HighPass::new(440.0, sample_rate)),
-                Box::new(LowPass::new(14000.0, sample_rate)),
-            ],
+            filters: new_filters(sample_rate),
+
+            panning: Panning::default(),
+            filters_right: new_filters(sample_rate),
+
+            muted: [false; 5],
+            scope: None,
         };
 
         // Precompute the pulse and tnd lookup tables.
@@ -121,6 +217,17 @@ impl Apu {
         apu
     }
 
+    /// Sets the TV system, which changes the noise and DMC periods and the
+    /// frame sequencer timing.
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_steps = match region.pal_apu() {
+            true => &PAL_FRAME_STEPS,
+            false => &NTSC_FRAME_STEPS,
+        };
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+    }
+
     /// Advances the state of the APU by one CPU cycle.
     pub fn clock(&mut self) {
         self.cycles = self.cycles.wrapping_add(1);
@@ -135,44 +242,96 @@ impl Apu {
             self.noise.clock_timer();
         }
 
-        // TODO: Don't understand any of this frame counter stuff!
-        self.frame_counter = self.frame_counter.wrapping_add(2);
-        if self.frame_counter >= 14915 {
-            self.frame_counter -= 14915;
+        self.clock_frame_counter();
 
-            self.sequencer = self.sequencer.wrapping_add(1);
-            match self.mode {
-                SequencerMode::FourStep => self.sequencer %= 4,
-                SequencerMode::FiveStep => self.sequencer %= 5,
+        // Length counter writes take effect after the frame sequencer has
+        // clocked the counters for this cycle.
+        self.pulse1.end_cycle();
+        self.pulse2.end_cycle();
+        self.triangle.end_cycle();
+        self.noise.end_cycle();
+    }
+
+    /// Advances the frame sequencer by one CPU cycle.
+    fn clock_frame_counter(&mut self) {
+        self.frame_irq_set = false;
+
+        // Writes to $4017 take effect after a short delay.
+        if let Some(delay) = self.frame_reset_delay {
+            if delay == 0 {
+                self.frame_reset_delay = None;
+                self.frame_cycle = 0;
+
+                // Five step mode immediately clocks a half frame.
+                if self.mode == SequencerMode::FiveStep {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            } else {
+                self.frame_reset_delay = Some(delay - 1);
             }
+        }
 
-            // Four step mode can request an interrupt on the last step
-            if !self.disable_interrupt
-                && self.mode == SequencerMode::FourStep
-                && self.sequencer == 0
-            {
-                self.pending_interrupt = Some(true);
+        self.frame_cycle += 1;
+
+        let steps = self.frame_steps;
+        let four_step = self.mode == SequencerMode::FourStep;
+        match self.frame_cycle {
+            c if c == steps.step_1 || c == steps.step_3 => self.clock_quarter_frame(),
+            c if c == steps.step_2 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
             }
 
-            // Sweep and length clocks.
-            if (self.frame_counter & 0x5) == 1 {
-                self.pulse1.clock_length();
-                self.pulse2.clock_length();
-                self.pulse1.clock_sweep(pulse::Channel::One);
-                self.pulse2.clock_sweep(pulse::Channel::Two);
-                self.triangle.clock_length();
-                self.noise.clock_length();
+            // The frame IRQ flag is asserted for three consecutive cycles, so
+            // it can be set again straight after a $4015 read clears it.
+            c if four_step && c == steps.four_step_irq => self.set_frame_irq(),
+            c if four_step && c == steps.four_step_4 => {
+                self.set_frame_irq();
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            c if four_step && c == steps.four_step_period => {
+                self.set_frame_irq();
+                self.frame_cycle = 0;
             }
 
-            if self.sequencer < 4 {
-                self.pulse1.clock_envelope();
-                self.pulse2.clock_envelope();
-                self.noise.clock_envelope();
-                self.triangle.clock_counter();
+            c if !four_step && c == steps.five_step_5 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
             }
+            c if !four_step && c == steps.five_step_period => self.frame_cycle = 0,
+
+            _ => {}
         }
     }
 
+    /// Sets the frame IRQ flag, unless inhibited.
+    fn set_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.frame_irq = true;
+            self.frame_irq_set = true;
+        }
+    }
+
+    /// Clocks the envelopes and triangle linear counter.
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_envelope();
+        self.pulse2.clock_envelope();
+        self.noise.clock_envelope();
+        self.triangle.clock_counter();
+    }
+
+    /// Clocks the length counters and sweep units.
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length();
+        self.pulse2.clock_length();
+        self.pulse1.clock_sweep(pulse::Channel::One);
+        self.pulse2.clock_sweep(pulse::Channel::Two);
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
     /// Reads a byte from the APU.
     pub fn read(&mut self, addr: u16) -> u8 {
         match addr {
@@ -219,23 +378,28 @@ impl Apu {
                 self.triangle.toggle(data & 0x4 != 0);
                 self.noise.toggle(data & 0x8 != 0);
                 self.dmc.toggle(data & 0x10 != 0);
+
+                // Writing the status register acknowledges the DMC interrupt.
+                self.dmc.clear_interrupt();
             }
 
+            // MI-- ----
+            // M: Sequencer mode (0: four step, 1: five step)
+            // I: Interrupt inhibit
             FRAME_COUNTER => {
                 self.mode = match data & 0x80 == 0 {
-                    true => SequencerMode::FiveStep,
-                    false => SequencerMode::FourStep,
+                    true => SequencerMode::FourStep,
+                    false => SequencerMode::FiveStep,
                 };
 
-                self.frame_counter = 0;
-                self.sequencer = 0;
+                // The sequencer is reset 3 CPU cycles after the write if it
+                // lands on an APU cycle, or 4 cycles if between APU cycles.
+                self.frame_reset_delay = Some(if self.cycles.is_multiple_of(2) { 2 } else { 3 });
 
-                self.disable_interrupt = data & 0x40 != 0;
-
-                // Clear the IRQ flag if set to disabled
-                if self.disable_interrupt {
-                    self.dmc.poll_interrupt();
-                    self.pending_interrupt = None;
+                // Setting the inhibit flag clears the frame interrupt.
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
                 }
             }
 
@@ -252,10 +416,10 @@ impl Apu {
         // tables.
         //
         // See: https://www.nesdev.org/wiki/APU_Mixer#Emulation
-        let pulse_output = self.pulse_table[(self.pulse1.output() + self.pulse2.output()) as usize];
+        let [pulse1, pulse2, triangle, noise, dmc] = self.mixed_levels();
+        let pulse_output = self.pulse_table[(pulse1 + pulse2) as usize];
 
-        let tnd_output = self.tnd_table
-            [(3 * self.triangle.output() + 2 * self.noise.output() + self.dmc.output()) as usize];
+        let tnd_output = self.tnd_table[(3 * triangle + 2 * noise + dmc) as usize];
 
         let sample = pulse_output + tnd_output;
 
@@ -264,10 +428,127 @@ impl Apu {
             .fold(sample, |sample, filter| filter.process(sample))
     }
 
-    /// Polls the IRQ flag
-    pub fn poll_interrupt(&mut self) -> bool {
-        // TODO: Hook this up to the system bus.
-        self.pending_interrupt.take().is_some() | self.dmc.poll_interrupt()
+    /// Sets the pan positions used by stereo output.
+    pub fn set_panning(&mut self, panning: Panning) {
+        self.panning = panning;
+    }
+
+    /// Returns a left and right audio sample from the APU, with each channel
+    /// panned according to the current pan positions.
+    ///
+    /// The mixer formulas are evaluated directly rather than through the
+    /// lookup tables, as the panned channel outputs are no longer integers.
+    pub fn output_stereo(&mut self) -> (f32, f32) {
+        let [pulse1, pulse2, triangle, noise, dmc] = self.mixed_levels();
+        let p = &self.panning;
+        let channels = [(pulse1, p.pulse1), (pulse2, p.pulse2)];
+        let tnd_channels = [
+            (3 * triangle, p.triangle),
+            (2 * noise, p.noise),
+            (dmc, p.dmc),
+        ];
+
+        let side = |channels: &[(u8, f32)], right: bool| -> f32 {
+            channels
+                .iter()
+                .map(|(out, pan)| {
+                    let (l, r) = panning::gains(*pan);
+                    *out as f32 * if right { r } else { l }
+                })
+                .sum()
+        };
+
+        let left = mix(side(&channels, false), side(&tnd_channels, false));
+        let right = mix(side(&channels, true), side(&tnd_channels, true));
+
+        (
+            self.filters.iter_mut().fold(left, |s, f| f.process(s)),
+            self.filters_right
+                .iter_mut()
+                .fold(right, |s, f| f.process(s)),
+        )
+    }
+
+    /// Returns the output level of pulse 1, pulse 2, triangle, noise and DMC
+    /// channels, in that order, before mixing.
+    fn levels(&self) -> [u8; 5] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ]
+    }
+
+    /// Returns the levels to mix for the next sample, with muted channels
+    /// silenced, keeping them for the visualiser if it is shown.
+    fn mixed_levels(&mut self) -> [u8; 5] {
+        let mut levels = self.levels();
+        if let Some(scope) = &mut self.scope {
+            scope.push(levels);
+        }
+        for (level, muted) in levels.iter_mut().zip(self.muted) {
+            if muted {
+                *level = 0;
+            }
+        }
+        levels
+    }
+
+    /// Mutes or unmutes the given channel, 0-4 in the order of
+    /// [`Apu::channels`], returning true if it is now muted. Stems still
+    /// record muted channels.
+    pub fn toggle_mute(&mut self, channel: usize) -> bool {
+        self.muted[channel] = !self.muted[channel];
+        self.muted[channel]
+    }
+
+    /// Returns which channels are muted, see [`Apu::toggle_mute`].
+    pub fn muted(&self) -> [bool; 5] {
+        self.muted
+    }
+
+    /// Sets whether recent channel levels are kept for the visualiser, see
+    /// [`Apu::scope`].
+    pub fn set_scope(&mut self, enabled: bool) {
+        self.scope = enabled.then(Box::default);
+    }
+
+    /// Returns the levels of each channel at the most recent samples, if
+    /// kept.
+    pub fn scope(&self) -> Option<&Scope> {
+        self.scope.as_deref()
+    }
+
+    /// Returns the output of pulse 1, pulse 2, triangle, noise and DMC
+    /// channels, in that order, each passed through the mixer on its own and
+    /// unfiltered. For recording the channels separately.
+    pub fn channel_outputs(&self) -> [f32; 5] {
+        [
+            self.pulse_table[self.pulse1.output() as usize],
+            self.pulse_table[self.pulse2.output() as usize],
+            self.tnd_table[3 * self.triangle.output() as usize],
+            self.tnd_table[2 * self.noise.output() as usize],
+            self.tnd_table[self.dmc.output() as usize],
+        ]
+    }
+
+    /// Returns the state of pulse 1, pulse 2, triangle, noise and DMC
+    /// channels, in that order.
+    pub fn channels(&self) -> [ChannelState; 5] {
+        [
+            self.pulse1.state(),
+            self.pulse2.state(),
+            self.triangle.state(),
+            self.noise.state(),
+            self.dmc.state(),
+        ]
+    }
+
+    /// Returns true if the frame counter or DMC is asserting an interrupt.
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq_flag()
     }
 
     /// Returns true if the DMC needs a new sample.
@@ -289,16 +570,24 @@ impl Apu {
     ///
     /// IF-D NT21
     ///
-    /// I: DMC Interrupt requested and clears it if set
-    /// F: Apu interrupt flag and clears it if set
+    /// I: DMC interrupt requested, acknowledged by writing $4015
+    /// F: Frame interrupt flag, cleared by the read
     /// D: 1 if DMC length counter > 0
     /// N: 1 if noise length counter > 0
     /// T: 1 if triangle length counter > 0
     /// 2: 1 if pulse 2 length counter > 0
     /// 1: 1 if pulse 1 length counter > 0
     fn status(&mut self) -> u8 {
-        (self.dmc.poll_interrupt() as u8) << 7
-            | (self.pending_interrupt.take().is_some() as u8) << 6
+        let frame_irq = self.frame_irq;
+
+        // Reading clears the frame interrupt, unless the flag is being set on
+        // this very cycle.
+        if !self.frame_irq_set {
+            self.frame_irq = false;
+        }
+
+        (self.dmc.irq_flag() as u8) << 7
+            | (frame_irq as u8) << 6
             | ((self.dmc.length_counter() > 0) as u8) << 4
             | ((self.noise.length_counter() > 0) as u8) << 3
             | ((self.triangle.length_counter() > 0) as u8) << 2
@@ -306,3 +595,220 @@ impl Apu {
             | (self.pulse1.length_counter() > 0) as u8
     }
 }
+
+/// Returns the filters applied to each output channel.
+fn new_filters(sample_rate: f32) -> Vec<Box<dyn Filter>> {
+    vec![
+        Box::new(HighPass::new(90.0, sample_rate)),
+        Box::new(HighPass::new(440.0, sample_rate)),
+        Box::new(LowPass::new(14000.0, sample_rate)),
+    ]
+}
+
+/// Returns the mixed output for the given pulse and triangle/noise/DMC
+/// inputs, using the same formulas as the lookup tables.
+///
+/// See: https://www.nesdev.org/wiki/APU_Mixer#Emulation
+fn mix(pulse: f32, tnd: f32) -> f32 {
+    let pulse_output = match pulse > 0.0 {
+        true => 95.52 / (8128.0 / pulse + 100.0),
+        false => 0.0,
+    };
+    let tnd_output = match tnd > 0.0 {
+        true => 163.67 / (24329.0 / tnd + 100.0),
+        false => 0.0,
+    };
+
+    pulse_output + tnd_output
+}
+
+impl SaveState for Apu {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u32(w, self.cycles)?;
+        write_u32(w, self.frame_cycle)?;
+        write_bool(w, self.mode == SequencerMode::FiveStep)?;
+        // No delay and delays of 0-254 cycles are saved as 0 and 1-255.
+        write_u8(w, self.frame_reset_delay.map_or(0, |d| d + 1))?;
+        write_bool(w, self.irq_inhibit)?;
+        write_bool(w, self.frame_irq)?;
+        write_bool(w, self.frame_irq_set)?;
+
+        self.pulse1.save_state(w)?;
+        self.pulse2.save_state(w)?;
+        self.triangle.save_state(w)?;
+        self.noise.save_state(w)?;
+        self.dmc.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.cycles = read_u32(r)?;
+        self.frame_cycle = read_u32(r)?;
+        self.mode = match read_bool(r)? {
+            true => SequencerMode::FiveStep,
+            false => SequencerMode::FourStep,
+        };
+        self.frame_reset_delay = read_u8(r)?.checked_sub(1);
+        self.irq_inhibit = read_bool(r)?;
+        self.frame_irq = read_bool(r)?;
+        self.frame_irq_set = read_bool(r)?;
+
+        self.pulse1.load_state(r)?;
+        self.pulse2.load_state(r)?;
+        self.triangle.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.dmc.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks the APU for the given number of CPU cycles.
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn test_frame_irq_four_step() {
+        let mut apu = Apu::new(44100.0);
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_irq - 1);
+        assert!(!apu.irq_pending());
+
+        run(&mut apu, 1);
+        assert!(apu.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_irq_pal() {
+        let mut apu = Apu::new(44100.0);
+        apu.set_region(Region::Pal);
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_irq);
+        assert!(!apu.irq_pending());
+
+        run(
+            &mut apu,
+            PAL_FRAME_STEPS.four_step_irq - NTSC_FRAME_STEPS.four_step_irq,
+        );
+        assert!(apu.irq_pending());
+
+        // The Dendy keeps the NTSC timing.
+        let mut apu = Apu::new(44100.0);
+        apu.set_region(Region::Dendy);
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_irq);
+        assert!(apu.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_irq_five_step() {
+        let mut apu = Apu::new(44100.0);
+        apu.write(FRAME_COUNTER, 0x80);
+        run(&mut apu, NTSC_FRAME_STEPS.five_step_period * 2);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit() {
+        let mut apu = Apu::new(44100.0);
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_irq);
+        assert!(apu.irq_pending());
+
+        apu.write(FRAME_COUNTER, 0x40);
+        assert!(!apu.irq_pending());
+
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_period * 2);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_status_read_race() {
+        let mut apu = Apu::new(44100.0);
+        run(&mut apu, NTSC_FRAME_STEPS.four_step_irq);
+
+        // Reading on the cycle the flag is set does not clear it.
+        assert_eq!(apu.read(STATUS_REGISTER) & 0x40, 0x40);
+        assert!(apu.irq_pending());
+
+        // The flag is asserted again for the rest of the three cycle window.
+        run(&mut apu, 1);
+        assert_eq!(apu.read(STATUS_REGISTER) & 0x40, 0x40);
+        run(&mut apu, 1);
+        assert!(apu.irq_pending());
+
+        // Once the window has passed, reading clears the flag.
+        run(&mut apu, 1);
+        assert_eq!(apu.read(STATUS_REGISTER) & 0x40, 0x40);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read(STATUS_REGISTER) & 0x40, 0);
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut apu = Apu::new(44100.0);
+        apu.write(STATUS_REGISTER, 0x0D);
+        apu.write(PULSE1_VOLUME, 0x9A);
+        apu.write(PULSE1_TIMER_LOW, 0xFD);
+        apu.write(PULSE1_TIMER_HIGH, 0x09);
+        apu.write(TRIANGLE_LINEAR, 0x7F);
+        apu.write(TRIANGLE_TIMER_HIGH, 0x08);
+        apu.write(NOISE_VOLUME, 0x05);
+        apu.write(NOISE_TIMER_LOW, 0x03);
+        apu.write(NOISE_TIMER_HIGH, 0x18);
+        apu.write(DMC_SAMPLE_RAW, 0x40);
+        run(&mut apu, 2);
+
+        let [pulse1, pulse2, triangle, noise, dmc] = apu.channels();
+        assert_eq!(
+            pulse1,
+            ChannelState {
+                enabled: true,
+                period: 0x1FD,
+                length_counter: 254,
+                volume: 10,
+                duty: 2,
+            }
+        );
+        assert_eq!(pulse2, ChannelState::default());
+        assert_eq!((triangle.length_counter, triangle.volume), (254, 0));
+        assert_eq!((noise.period, noise.volume), (32, 15));
+        assert_eq!((dmc.enabled, dmc.volume), (false, 0x40));
+    }
+
+    #[test]
+    fn test_mute() {
+        let mut apu = Apu::new(44100.0);
+        apu.set_scope(true);
+        apu.write(DMC_SAMPLE_RAW, 0x40);
+        assert_eq!(apu.mixed_levels(), [0, 0, 0, 0, 0x40]);
+
+        assert!(apu.toggle_mute(4));
+        assert_eq!(apu.mixed_levels(), [0; 5]);
+        assert_eq!(apu.muted(), [false, false, false, false, true]);
+
+        // The visualiser still shows muted channels.
+        let scope = apu.scope().unwrap();
+        assert_eq!(scope.samples(4).last(), Some(0x40));
+
+        assert!(!apu.toggle_mute(4));
+        assert_eq!(apu.mixed_levels(), [0, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn test_dmc_irq_acknowledged_by_status_write() {
+        let mut apu = Apu::new(44100.0);
+        apu.write(FRAME_COUNTER, 0x40);
+        apu.write(DMC_SAMPLE_FREQUENCY, 0x8F);
+        apu.write(DMC_SAMPLE_LENGTH, 0);
+        apu.write(STATUS_REGISTER, 0x10);
+        run(&mut apu, 1);
+
+        // Reading the status does not clear the DMC interrupt.
+        assert_eq!(apu.read(STATUS_REGISTER) & 0x80, 0x80);
+        assert!(apu.irq_pending());
+
+        apu.write(STATUS_REGISTER, 0x00);
+        assert!(!apu.irq_pending());
+    }
+}
@@ -1,18 +1,29 @@
+use std::io::{self, Read, Write};
+
+use super::ChannelState;
+use crate::region::Region;
+use crate::state::{read_bool, read_u16, read_u8, write_bool, write_u16, write_u8, SaveState};
+
 const RATE_TABLE: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
 ];
 
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
 /// Represents the NES delta modulation channel (DMC) which can output 1-bit
 /// delta-encoded samples or can have its 7-bit counter directly loaded,
 /// allowing flexible manual sample playback.
 pub struct Dmc {
     enabled: bool,
 
-    disable_interrupt: bool,
-    pending_interrupt: Option<bool>,
+    irq_enabled: bool,
+    irq_flag: bool,
 
     loop_sample: bool,
     rate: u16,
+    rate_table: &'static [u16; 16],
     rate_counter: u16,
 
     pending_read: Option<bool>,
@@ -31,10 +42,11 @@ impl Dmc {
     pub fn new() -> Self {
         Self {
             enabled: false,
-            disable_interrupt: false,
-            pending_interrupt: None,
+            irq_enabled: false,
+            irq_flag: false,
             loop_sample: false,
             rate: 0,
+            rate_table: &RATE_TABLE,
             rate_counter: 0,
             pending_read: None,
             addr: 0,
@@ -47,6 +59,14 @@ impl Dmc {
         }
     }
 
+    /// Selects the sample rates of the given TV system.
+    pub fn set_region(&mut self, region: Region) {
+        self.rate_table = match region.pal_apu() {
+            true => &PAL_RATE_TABLE,
+            false => &RATE_TABLE,
+        };
+    }
+
     /// Toggles the channel on or off.
     pub fn toggle(&mut self, enabled: bool) {
         self.enabled = enabled;
@@ -67,9 +87,14 @@ impl Dmc {
     /// L: Loop flag
     /// R: Rate index (frequency)
     pub fn write_sample_frequency(&mut self, data: u8) {
-        self.rate = RATE_TABLE[(data & 0xF) as usize];
+        self.rate = self.rate_table[(data & 0xF) as usize];
         self.loop_sample = data & 0x40 != 0;
-        self.disable_interrupt = data & 0x80 != 0;
+        self.irq_enabled = data & 0x80 != 0;
+
+        // Clearing the IRQ enable flag also acknowledges the interrupt.
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
     }
 
     /// Writes a raw PCM sample.
@@ -131,11 +156,13 @@ impl Dmc {
                 self.pending_read = Some(true);
                 self.phase = 8;
                 self.length_counter -= 1;
-            } else {
-                if !self.disable_interrupt {
-                    self.pending_interrupt = Some(true);
-                }
 
+                // The IRQ is raised when the last byte of a non-looping
+                // sample is fetched.
+                if self.length_counter == 0 && !self.loop_sample && self.irq_enabled {
+                    self.irq_flag = true;
+                }
+            } else {
                 self.enabled = false;
             }
         }
@@ -179,9 +206,26 @@ impl Dmc {
         self.length_counter
     }
 
-    /// Returns true if the DMC chanel is waiting for an interrupt.
-    pub fn poll_interrupt(&mut self) -> bool {
-        self.pending_interrupt.take().is_some()
+    /// Returns a view of the channel's state.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            enabled: self.enabled,
+            period: self.rate,
+            length_counter: self.length_counter,
+            volume: self.output_level,
+            duty: 0,
+        }
+    }
+
+    /// Returns true if the DMC channel is asserting an interrupt. The flag
+    /// stays set until acknowledged, reading it has no side effects.
+    pub fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Acknowledges the DMC interrupt.
+    pub fn clear_interrupt(&mut self) {
+        self.irq_flag = false;
     }
 
     /// Returns the output volume of the channel
@@ -190,6 +234,47 @@ impl Dmc {
     }
 }
 
+impl SaveState for Dmc {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.enabled)?;
+        write_bool(w, self.irq_enabled)?;
+        write_bool(w, self.irq_flag)?;
+        write_bool(w, self.loop_sample)?;
+        write_u16(w, self.rate)?;
+        write_u16(w, self.rate_counter)?;
+        // None, Some(false) and Some(true) are saved as 0, 1 and 2.
+        write_u8(w, self.pending_read.map_or(0, |p| p as u8 + 1))?;
+        write_u8(w, self.addr)?;
+        write_u16(w, self.last_addr)?;
+        write_u8(w, self.buf)?;
+        write_u8(w, self.phase)?;
+        write_u8(w, self.output_level)?;
+        write_u16(w, self.length_counter)?;
+        write_u16(w, self.pcm_length)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.enabled = read_bool(r)?;
+        self.irq_enabled = read_bool(r)?;
+        self.irq_flag = read_bool(r)?;
+        self.loop_sample = read_bool(r)?;
+        self.rate = read_u16(r)?;
+        self.rate_counter = read_u16(r)?;
+        self.pending_read = match read_u8(r)? {
+            0 => None,
+            p => Some(p == 2),
+        };
+        self.addr = read_u8(r)?;
+        self.last_addr = read_u16(r)?;
+        self.buf = read_u8(r)?;
+        self.phase = read_u8(r)?;
+        self.output_level = read_u8(r)?;
+        self.length_counter = read_u16(r)?;
+        self.pcm_length = read_u16(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,8 +283,8 @@ mod tests {
     fn test_new() {
         let dmc = Dmc::new();
         assert!(!dmc.enabled);
-        assert!(!dmc.disable_interrupt);
-        assert_eq!(dmc.pending_interrupt, None);
+        assert!(!dmc.irq_enabled);
+        assert!(!dmc.irq_flag);
         assert!(!dmc.loop_sample);
         assert_eq!(dmc.rate, 0);
         assert_eq!(dmc.rate_counter, 0);
@@ -228,7 +313,7 @@ mod tests {
         dmc.write_sample_frequency(0xCF);
         assert_eq!(dmc.rate, RATE_TABLE[0xF]);
         assert!(dmc.loop_sample);
-        assert!(dmc.disable_interrupt);
+        assert!(dmc.irq_enabled);
     }
 
     #[test]
@@ -262,4 +347,21 @@ mod tests {
         dmc.clock();
         assert_eq!(dmc.rate_counter, 4);
     }
+
+    #[test]
+    fn test_irq_at_end_of_sample() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_frequency(0x80);
+        dmc.write_sample_length(0);
+        dmc.toggle(true);
+
+        // The single byte sample is fetched on the first timer clock.
+        dmc.clock();
+        assert!(dmc.irq_flag());
+
+        // Reading the flag does not acknowledge it.
+        assert!(dmc.irq_flag());
+        dmc.clear_interrupt();
+        assert!(!dmc.irq_flag());
+    }
 }
@@ -0,0 +1,174 @@
+use std::io::{self, Read, Write};
+
+use super::LENGTH_TABLE;
+use crate::state::{read_bool, read_u8, write_bool, write_u8, SaveState};
+
+/// Length counter of the pulse, triangle and noise channels, which silences
+/// the channel when it reaches zero.
+///
+/// Writes to the counter and halt flag are latched and only take effect at
+/// the end of the CPU cycle, after the frame sequencer may have clocked the
+/// counter. So a reload in the same cycle as a clock of a non-zero counter is
+/// ignored, and a clock in the same cycle as a halt flag change uses the old
+/// flag.
+///
+/// See: https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+
+    /// Halt flag written this cycle.
+    new_halt: bool,
+
+    /// Value loaded this cycle, and the counter at the time of the load.
+    reload: Option<u8>,
+    previous: u8,
+}
+
+impl LengthCounter {
+    /// Enables or disables the counter. Disabling clears it, enabling does
+    /// not reload it.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.counter = 0;
+            self.reload = None;
+        }
+    }
+
+    /// Sets the halt flag at the end of the cycle.
+    pub fn set_halt(&mut self, halt: bool) {
+        self.new_halt = halt;
+    }
+
+    /// Loads the counter from the length table at the end of the cycle. Loads
+    /// are ignored while the channel is disabled.
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.reload = Some(LENGTH_TABLE[(index & 0x1F) as usize]);
+            self.previous = self.counter;
+        }
+    }
+
+    /// Clocks the counter, counting down unless halted.
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    /// Applies the writes made during the cycle. A reload is dropped if the
+    /// counter was clocked since it was written.
+    pub fn end_cycle(&mut self) {
+        if let Some(value) = self.reload.take() {
+            if self.counter == self.previous {
+                self.counter = value;
+            }
+        }
+        self.halt = self.new_halt;
+    }
+
+    /// Returns the counter value.
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.enabled)?;
+        write_bool(w, self.halt)?;
+        write_u8(w, self.counter)?;
+        write_bool(w, self.new_halt)?;
+        // A pending reload is saved as the value plus one, as no length table
+        // entry is 255.
+        write_u8(w, self.reload.map_or(0, |v| v + 1))?;
+        write_u8(w, self.previous)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.enabled = read_bool(r)?;
+        self.halt = read_bool(r)?;
+        self.counter = read_u8(r)?;
+        self.new_halt = read_bool(r)?;
+        self.reload = read_u8(r)?.checked_sub(1);
+        self.previous = read_u8(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> LengthCounter {
+        let mut length = LengthCounter::default();
+        length.set_enabled(true);
+        length
+    }
+
+    #[test]
+    fn test_load_ignored_while_disabled() {
+        let mut length = LengthCounter::default();
+        length.load(0x1F);
+        length.end_cycle();
+        assert_eq!(length.value(), 0);
+
+        // Enabling doesn't reload the counter either.
+        length.set_enabled(true);
+        length.end_cycle();
+        assert_eq!(length.value(), 0);
+
+        length.load(0x1F);
+        length.end_cycle();
+        assert_eq!(length.value(), LENGTH_TABLE[0x1F]);
+    }
+
+    #[test]
+    fn test_reload_during_clock() {
+        // A reload in the same cycle as a clock of a non-zero counter is
+        // ignored.
+        let mut length = enabled();
+        length.load(0x01);
+        length.end_cycle();
+        length.load(0x03);
+        length.clock();
+        length.end_cycle();
+        assert_eq!(length.value(), 253);
+
+        // But a counter at zero isn't clocked, so the reload happens.
+        let mut length = enabled();
+        length.load(0x03);
+        length.clock();
+        length.end_cycle();
+        assert_eq!(length.value(), 2);
+    }
+
+    #[test]
+    fn test_halt_during_clock() {
+        // Halting in the same cycle as a clock doesn't stop that clock.
+        let mut length = enabled();
+        length.load(0x01);
+        length.end_cycle();
+        length.set_halt(true);
+        length.clock();
+        length.end_cycle();
+        assert_eq!(length.value(), 253);
+
+        length.clock();
+        length.end_cycle();
+        assert_eq!(length.value(), 253);
+
+        // Likewise un-halting doesn't let that clock through.
+        length.set_halt(false);
+        length.clock();
+        length.end_cycle();
+        assert_eq!(length.value(), 253);
+
+        length.clock();
+        assert_eq!(length.value(), 252);
+    }
+}
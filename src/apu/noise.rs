@@ -1,9 +1,18 @@
-use super::LENGTH_TABLE;
+use std::io::{self, Read, Write};
+
+use super::length::LengthCounter;
+use super::ChannelState;
+use crate::region::Region;
+use crate::state::{read_bool, read_u16, read_u8, write_bool, write_u16, write_u8, SaveState};
 
 const TIMER_PERIODS: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+const PAL_TIMER_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
 /// Represents the NES Noise channel which generates pseudo-random 1-bit noise
 /// at 16 different frequencies.
 pub struct Noise {
@@ -12,10 +21,11 @@ pub struct Noise {
 
     timer: u16,
     timer_period: u16,
+    timer_periods: &'static [u16; 16],
 
-    length_halt: bool,
-    length_counter: u8,
+    length: LengthCounter,
 
+    envelope_loop: bool,
     constant_volume: bool,
     volume: u8,
 
@@ -31,10 +41,11 @@ impl Noise {
         Self {
             enabled: false,
             mode: false,
-            length_counter: 0,
             timer: 0,
             timer_period: 0,
-            length_halt: false,
+            timer_periods: &TIMER_PERIODS,
+            length: LengthCounter::default(),
+            envelope_loop: false,
             constant_volume: false,
             volume: 0,
             envelope_timer: 0,
@@ -43,13 +54,18 @@ impl Noise {
         }
     }
 
+    /// Selects the timer periods of the given TV system.
+    pub fn set_region(&mut self, region: Region) {
+        self.timer_periods = match region.pal_apu() {
+            true => &PAL_TIMER_PERIODS,
+            false => &TIMER_PERIODS,
+        };
+    }
+
     /// Toggles the channel on or off.
     pub fn toggle(&mut self, enabled: bool) {
         self.enabled = enabled;
-
-        if !self.enabled {
-            self.length_counter = 0;
-        }
+        self.length.set_enabled(enabled);
     }
 
     /// Sets the width of the pulse.
@@ -61,7 +77,8 @@ impl Noise {
     /// C: Output constant volume
     /// V: Volume value / envelope period
     pub fn write_volume(&mut self, data: u8) {
-        self.length_halt = data & 0x20 != 0;
+        self.envelope_loop = data & 0x20 != 0;
+        self.length.set_halt(self.envelope_loop);
         self.constant_volume = data & 0x10 != 0;
         self.volume = data & 0xF;
     }
@@ -75,7 +92,7 @@ impl Noise {
     /// P: Timer period table index
     pub fn write_timer_low(&mut self, data: u8) {
         self.mode = data & 0x80 != 0;
-        self.timer_period = TIMER_PERIODS[(data & 0xF) as usize];
+        self.timer_period = self.timer_periods[(data & 0xF) as usize];
     }
 
     /// Sets the timer high.
@@ -85,7 +102,7 @@ impl Noise {
     /// LLLL L---
     /// L: Length counter table index
     pub fn write_timer_high(&mut self, data: u8) {
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        self.length.load(data >> 3);
         self.envelope_volume = 15;
         self.envelope_timer = self.volume + 1;
     }
@@ -107,9 +124,12 @@ impl Noise {
 
     /// Clocks the length counter.
     pub fn clock_length(&mut self) {
-        if self.length_counter > 0 && !self.length_halt {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
+    }
+
+    /// Applies the length counter writes made during the CPU cycle.
+    pub fn end_cycle(&mut self) {
+        self.length.end_cycle();
     }
 
     /// Clocks the envelope.
@@ -125,7 +145,7 @@ impl Noise {
 
         if self.envelope_volume > 0 {
             self.envelope_volume -= 1;
-        } else if self.length_halt {
+        } else if self.envelope_loop {
             self.envelope_volume = 15;
         }
 
@@ -134,13 +154,27 @@ impl Noise {
 
     /// Returns the length counter value.
     pub fn length_counter(&self) -> u8 {
-        self.length_counter
+        self.length.value()
+    }
+
+    /// Returns a view of the channel's state.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            enabled: self.enabled,
+            period: self.timer_period,
+            length_counter: self.length.value() as u16,
+            volume: match self.constant_volume {
+                true => self.volume,
+                false => self.envelope_volume,
+            },
+            duty: 0,
+        }
     }
 
     /// Returns the output volume of the channel.
     pub fn output(&self) -> u8 {
         // All the conditions below silence the channel.
-        if !self.enabled || self.length_counter == 0 || self.shift & 0x1 != 0 {
+        if !self.enabled || self.length.value() == 0 || self.shift & 0x1 != 0 {
             return 0;
         }
 
@@ -152,6 +186,37 @@ impl Noise {
     }
 }
 
+impl SaveState for Noise {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.enabled)?;
+        write_bool(w, self.mode)?;
+        write_u16(w, self.timer)?;
+        write_u16(w, self.timer_period)?;
+        self.length.save_state(w)?;
+        write_bool(w, self.envelope_loop)?;
+        write_bool(w, self.constant_volume)?;
+        write_u8(w, self.volume)?;
+        write_u8(w, self.envelope_timer)?;
+        write_u8(w, self.envelope_volume)?;
+        write_u16(w, self.shift)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.enabled = read_bool(r)?;
+        self.mode = read_bool(r)?;
+        self.timer = read_u16(r)?;
+        self.timer_period = read_u16(r)?;
+        self.length.load_state(r)?;
+        self.envelope_loop = read_bool(r)?;
+        self.constant_volume = read_bool(r)?;
+        self.volume = read_u8(r)?;
+        self.envelope_timer = read_u8(r)?;
+        self.envelope_volume = read_u8(r)?;
+        self.shift = read_u16(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::apu::{noise::TIMER_PERIODS, LENGTH_TABLE};
@@ -163,10 +228,10 @@ mod tests {
         let noise = Noise::new();
         assert!(!noise.enabled);
         assert!(!noise.mode);
-        assert_eq!(noise.length_counter, 0);
+        assert_eq!(noise.length_counter(), 0);
         assert_eq!(noise.timer, 0);
         assert_eq!(noise.timer_period, 0);
-        assert!(!noise.length_halt);
+        assert!(!noise.envelope_loop);
         assert!(!noise.constant_volume);
         assert_eq!(noise.volume, 0);
         assert_eq!(noise.envelope_timer, 0);
@@ -181,14 +246,14 @@ mod tests {
         assert!(noise.enabled);
         noise.toggle(false);
         assert!(!noise.enabled);
-        assert_eq!(noise.length_counter, 0);
+        assert_eq!(noise.length_counter(), 0);
     }
 
     #[test]
     fn test_write_volume() {
         let mut noise = Noise::new();
         noise.write_volume(0x3F);
-        assert!(noise.length_halt);
+        assert!(noise.envelope_loop);
         assert!(noise.constant_volume);
         assert_eq!(noise.volume, 0xF);
     }
@@ -204,8 +269,10 @@ mod tests {
     #[test]
     fn test_write_timer_high() {
         let mut noise = Noise::new();
+        noise.toggle(true);
         noise.write_timer_high(0xF8);
-        assert_eq!(noise.length_counter, LENGTH_TABLE[0x1F]);
+        noise.end_cycle();
+        assert_eq!(noise.length_counter(), LENGTH_TABLE[0x1F]);
         assert_eq!(noise.envelope_volume, 15);
         assert_eq!(noise.envelope_timer, noise.volume + 1);
     }
@@ -221,9 +288,11 @@ mod tests {
     #[test]
     fn test_clock_length() {
         let mut noise = Noise::new();
-        noise.length_counter = 5;
+        noise.toggle(true);
+        noise.write_timer_high(0x28);
+        noise.end_cycle();
         noise.clock_length();
-        assert_eq!(noise.length_counter, 4);
+        assert_eq!(noise.length_counter(), 3);
     }
 
     #[test]
@@ -244,8 +313,9 @@ mod tests {
     fn test_output() {
         let mut noise = Noise::new();
         assert_eq!(noise.output(), 0);
-        noise.enabled = true;
-        noise.length_counter = 5;
+        noise.toggle(true);
+        noise.write_timer_high(0x28);
+        noise.end_cycle();
         noise.shift = 0;
         assert_eq!(noise.output(), noise.envelope_volume);
     }
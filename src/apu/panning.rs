@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+/// Stereo pan positions for each APU channel, from -1.0 (hard left) through
+/// 0.0 (centre) to 1.0 (hard right).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Panning {
+    pub pulse1: f32,
+    pub pulse2: f32,
+    pub triangle: f32,
+    pub noise: f32,
+    pub dmc: f32,
+}
+
+impl Default for Panning {
+    /// Pulses are split left and right, everything else stays centred.
+    fn default() -> Self {
+        Panning {
+            pulse1: -0.5,
+            pulse2: 0.5,
+            triangle: 0.0,
+            noise: 0.0,
+            dmc: 0.0,
+        }
+    }
+}
+
+impl FromStr for Panning {
+    type Err = String;
+
+    /// Parses comma separated pan positions in the order pulse 1, pulse 2,
+    /// triangle, noise and DMC.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let positions = s
+            .split(',')
+            .map(|p| match p.trim().parse::<f32>() {
+                Ok(p) if (-1.0..=1.0).contains(&p) => Ok(p),
+                _ => Err(format!("invalid pan position {:?}", p)),
+            })
+            .collect::<Result<Vec<f32>, String>>()?;
+
+        match positions[..] {
+            [pulse1, pulse2, triangle, noise, dmc] => Ok(Panning {
+                pulse1,
+                pulse2,
+                triangle,
+                noise,
+                dmc,
+            }),
+            _ => Err(String::from("expected 5 pan positions")),
+        }
+    }
+}
+
+/// Returns the left and right gain for the given pan position. A centred
+/// channel plays at full volume on both sides, so mono content keeps its
+/// loudness.
+pub fn gains(pan: f32) -> (f32, f32) {
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        let panning: Panning = "-1, 1, 0, 0.25, -0.25".parse().unwrap();
+        assert_eq!(panning.pulse1, -1.0);
+        assert_eq!(panning.pulse2, 1.0);
+        assert_eq!(panning.noise, 0.25);
+
+        assert!("0,0,0,0".parse::<Panning>().is_err());
+        assert!("0,0,0,0,2".parse::<Panning>().is_err());
+        assert!("0,0,0,0,x".parse::<Panning>().is_err());
+    }
+
+    #[test]
+    fn test_gains() {
+        assert_eq!(gains(0.0), (1.0, 1.0));
+        assert_eq!(gains(-1.0), (1.0, 0.0));
+        assert_eq!(gains(0.5), (0.5, 1.0));
+    }
+}
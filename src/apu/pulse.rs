@@ -1,4 +1,8 @@
-use crate::apu::LENGTH_TABLE;
+use std::io::{self, Read, Write};
+
+use super::length::LengthCounter;
+use super::ChannelState;
+use crate::state::{read_bool, read_u16, read_u8, write_bool, write_u16, write_u8, SaveState};
 
 /// 0 - 0 1 0 0 0 0 0 0 (12.5%)
 /// 1 - 0 1 1 0 0 0 0 0 (25%)
@@ -29,8 +33,7 @@ pub struct Pulse {
     constant_volume: bool,
     volume: u8,
 
-    length_halt: bool,
-    length_counter: u8,
+    length: LengthCounter,
 
     sweep_enabled: bool,
     sweep_period: u8,
@@ -58,8 +61,7 @@ impl Pulse {
             constant_volume: false,
             volume: 0,
 
-            length_halt: false,
-            length_counter: 0,
+            length: LengthCounter::default(),
 
             sweep_enabled: false,
             sweep_period: 0,
@@ -80,10 +82,7 @@ impl Pulse {
     /// Toggles the channel on or off.
     pub fn toggle(&mut self, enabled: bool) {
         self.enabled = enabled;
-
-        if !self.enabled {
-            self.length_counter = 0;
-        }
+        self.length.set_enabled(enabled);
     }
 
     /// Sets the width of the pulse.
@@ -97,8 +96,8 @@ impl Pulse {
     /// V: Volume value / envelope period
     pub fn write_volume(&mut self, data: u8) {
         self.duty_cycle = data >> 0x6;
-        self.length_halt = (data & 0x20) != 0;
-        self.envelope_loop = self.length_halt;
+        self.envelope_loop = (data & 0x20) != 0;
+        self.length.set_halt(self.envelope_loop);
         self.constant_volume = (data & 0x10) != 0;
         self.volume = data & 0xF;
         self.envelope_period = self.volume;
@@ -142,7 +141,7 @@ impl Pulse {
     /// T: Timer period high
     pub fn write_timer_high(&mut self, data: u8) {
         self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x7) << 8);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        self.length.load(data >> 3);
 
         // A write to this register reloads the length counter, restarts the
         // envelope, and resets the phase of the pulse generator.
@@ -166,9 +165,12 @@ impl Pulse {
 
     /// Clocks the length counter.
     pub fn clock_length(&mut self) {
-        if self.length_counter > 0 && !self.length_halt {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
+    }
+
+    /// Applies the length counter writes made during the CPU cycle.
+    pub fn end_cycle(&mut self) {
+        self.length.end_cycle();
     }
 
     /// Clocks the envelope.
@@ -213,7 +215,7 @@ impl Pulse {
 
         if !self.enabled
             || self.timer_period > 0x7FF
-            || self.length_counter == 0
+            || self.length.value() == 0
             || self.timer_period < 8
             || !duty
         {
@@ -228,7 +230,21 @@ impl Pulse {
 
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
-        self.length_counter
+        self.length.value()
+    }
+
+    /// Returns a view of the channel's state.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            enabled: self.enabled,
+            period: self.timer_period,
+            length_counter: self.length.value() as u16,
+            volume: match self.constant_volume {
+                true => self.volume,
+                false => self.envelope_volume,
+            },
+            duty: self.duty_cycle,
+        }
     }
 
     /// Adjusts the timer period based on the given channel.
@@ -245,6 +261,49 @@ impl Pulse {
     }
 }
 
+impl SaveState for Pulse {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.enabled)?;
+        write_u8(w, self.duty_cycle)?;
+        write_u8(w, self.duty_phase)?;
+        write_bool(w, self.constant_volume)?;
+        write_u8(w, self.volume)?;
+        self.length.save_state(w)?;
+        write_bool(w, self.sweep_enabled)?;
+        write_u8(w, self.sweep_period)?;
+        write_bool(w, self.sweep_negate)?;
+        write_u8(w, self.sweep_shift)?;
+        write_u8(w, self.sweep_timer)?;
+        write_u16(w, self.timer)?;
+        write_u16(w, self.timer_period)?;
+        write_bool(w, self.envelope_loop)?;
+        write_u8(w, self.envelope_period)?;
+        write_u8(w, self.envelope_timer)?;
+        write_u8(w, self.envelope_volume)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.enabled = read_bool(r)?;
+        self.duty_cycle = read_u8(r)?;
+        self.duty_phase = read_u8(r)?;
+        self.constant_volume = read_bool(r)?;
+        self.volume = read_u8(r)?;
+        self.length.load_state(r)?;
+        self.sweep_enabled = read_bool(r)?;
+        self.sweep_period = read_u8(r)?;
+        self.sweep_negate = read_bool(r)?;
+        self.sweep_shift = read_u8(r)?;
+        self.sweep_timer = read_u8(r)?;
+        self.timer = read_u16(r)?;
+        self.timer_period = read_u16(r)?;
+        self.envelope_loop = read_bool(r)?;
+        self.envelope_period = read_u8(r)?;
+        self.envelope_timer = read_u8(r)?;
+        self.envelope_volume = read_u8(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,12 +311,12 @@ mod tests {
     #[test]
     fn test_output() {
         let mut pulse = Pulse::new();
-        pulse.enabled = true;
+        pulse.toggle(true);
+        pulse.write_timer_high(0xFF);
+        pulse.end_cycle();
         pulse.duty_cycle = 3;
         pulse.duty_phase = 1;
-        pulse.length_halt = true;
         pulse.timer_period = 0x7F0;
-        pulse.length_counter = 10;
         pulse.constant_volume = true;
         pulse.volume = 5;
         assert_eq!(pulse.output(), 5);
@@ -266,7 +325,11 @@ mod tests {
     #[test]
     fn test_length_counter() {
         let mut pulse = Pulse::new();
-        pulse.length_counter = 10;
+        pulse.toggle(true);
+        pulse.write_timer_high(0x00);
+        assert_eq!(pulse.length_counter(), 0);
+
+        pulse.end_cycle();
         assert_eq!(pulse.length_counter(), 10);
     }
 
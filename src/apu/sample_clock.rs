@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+
+use crate::region::Region;
+use crate::state::{read_u64, write_u64, SaveState};
+
+/// Decides which CPU cycles the APU output is sampled on, resampling it to
+/// the audio sample rate.
+///
+/// The CPU clock rate isn't a whole number of Hz, so the number of samples
+/// per frame is fractional, e.g. 733.8 at 44.1 kHz on NTSC. The clock rate is
+/// an exact fraction of the master clock though, so the remainder is carried
+/// between cycles as an integer. Every frame then produces the whole number
+/// of samples either side of the exact count, identically on every platform,
+/// and the output never drifts from the sample rate.
+pub struct SampleClock {
+    sample_rate: u64,
+    region: Region,
+
+    /// Sample rate accumulated per CPU cycle, a sample is due once it reaches
+    /// the CPU clock rate. Both are scaled by the clock rate's denominator,
+    /// and by 1000 so the rate can be adjusted in steps of 0.1%.
+    phase: u64,
+    step: u64,
+    period: u64,
+
+    /// Adjustment to the sample rate in tenths of a percent.
+    adjust: i32,
+}
+
+impl SampleClock {
+    /// Returns a sample clock for the given sample rate in Hz, timed for
+    /// NTSC.
+    pub fn new(sample_rate: f32) -> Self {
+        let mut clock = SampleClock {
+            sample_rate: sample_rate.round().max(1.0) as u64,
+            region: Region::Ntsc,
+            phase: 0,
+            step: 0,
+            period: 0,
+            adjust: 0,
+        };
+        clock.set_region(Region::Ntsc);
+        clock
+    }
+
+    /// Sets the TV system the CPU clock is timed for.
+    pub fn set_region(&mut self, region: Region) {
+        let (num, _) = region.cpu_clock_fraction();
+        self.region = region;
+        self.period = num * 1000;
+        self.phase = 0;
+        self.set_adjust(self.adjust);
+    }
+
+    /// Speeds up or slows down the sample rate by the given tenths of a
+    /// percent, so the frontend can keep its audio buffer from draining or
+    /// filling up when the host's audio clock drifts from the emulation.
+    pub fn set_adjust(&mut self, adjust: i32) {
+        let (_, den) = self.region.cpu_clock_fraction();
+        self.adjust = adjust.clamp(-999, 999);
+        self.step = self.sample_rate * den * (1000 + self.adjust) as u64;
+    }
+
+    /// Advances the clock by one CPU cycle, returning true if a sample is due.
+    pub fn clock(&mut self) -> bool {
+        self.phase += self.step;
+        if self.phase >= self.period {
+            self.phase -= self.period;
+            return true;
+        }
+        false
+    }
+
+    /// Returns the average number of samples produced per frame, for sizing
+    /// audio buffers. Each frame produces this number rounded up or down.
+    pub fn samples_per_frame(&self) -> f64 {
+        self.sample_rate as f64 / self.region.frame_rate()
+    }
+}
+
+impl SaveState for SampleClock {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u64(w, self.phase)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.phase = read_u64(r)? % self.period;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_drift() {
+        // The NTSC CPU runs exactly 19687500 cycles in 11 seconds, which
+        // must produce exactly 11 seconds of samples.
+        let mut clock = SampleClock::new(44100.0);
+        let samples = (0..19687500).filter(|_| clock.clock()).count();
+        assert_eq!(samples, 11 * 44100);
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut clock = SampleClock::new(44100.0);
+        clock.set_adjust(5);
+        let samples = (0..19687500).filter(|_| clock.clock()).count();
+        assert_eq!(samples, 11 * 44100 * 1005 / 1000);
+    }
+
+    #[test]
+    fn test_samples_per_frame() {
+        // NTSC frames alternate between 29780 and 29781 CPU cycles.
+        let mut clock = SampleClock::new(44100.0);
+        let expected = clock.samples_per_frame();
+        for frame in 0..1000 {
+            let cycles = 29780 + frame % 2;
+            let samples = (0..cycles).filter(|_| clock.clock()).count() as f64;
+            assert!(samples == expected.floor() || samples == expected.ceil());
+        }
+    }
+}
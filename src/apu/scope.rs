@@ -0,0 +1,157 @@
+/// Number of samples kept for each channel, one per column of the waveform.
+pub const SCOPE_SAMPLES: usize = 240;
+
+/// Width of the frames drawn on, in pixels.
+const FRAME_WIDTH: usize = 256;
+
+/// Height of the strip drawn for each channel, in pixels.
+const STRIP_HEIGHT: usize = 24;
+
+/// Width of the volume meter left of each waveform, in pixels.
+const METER_WIDTH: usize = 8;
+
+/// Highest output level of pulse 1, pulse 2, triangle, noise and DMC.
+const MAX_LEVELS: [u8; 5] = [15, 15, 15, 15, 127];
+
+/// Colours of pulse 1, pulse 2, triangle, noise and DMC.
+const COLOURS: [[u8; 3]; 5] = [
+    [255, 96, 96],
+    [255, 192, 64],
+    [96, 224, 96],
+    [96, 160, 255],
+    [224, 96, 255],
+];
+
+/// Colour of muted channels.
+const MUTED_COLOUR: [u8; 3] = [96, 96, 96];
+
+/// Recent output levels of each APU channel, for the channel visualiser.
+pub struct Scope {
+    levels: [[u8; SCOPE_SAMPLES]; 5],
+
+    /// Index of the oldest sample, which the next one replaces.
+    next: usize,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope {
+            levels: [[0; SCOPE_SAMPLES]; 5],
+            next: 0,
+        }
+    }
+}
+
+impl Scope {
+    /// Takes the output level of pulse 1, pulse 2, triangle, noise and DMC,
+    /// in that order, replacing the oldest.
+    pub fn push(&mut self, levels: [u8; 5]) {
+        for (channel, level) in self.levels.iter_mut().zip(levels) {
+            channel[self.next] = level;
+        }
+        self.next = (self.next + 1) % SCOPE_SAMPLES;
+    }
+
+    /// Returns the levels kept for the given channel, oldest first.
+    pub fn samples(&self, channel: usize) -> impl Iterator<Item = u8> + '_ {
+        let (old, new) = self.levels[channel].split_at(self.next);
+        new.iter().chain(old).copied()
+    }
+
+    /// Draws a strip for each channel over the bottom of the 256 pixel wide
+    /// RGB24 frame, top to bottom in the order of [`Scope::push`]. Each strip
+    /// has a meter of the latest level followed by the waveform, over the
+    /// darkened picture. Muted channels are drawn grey.
+    pub fn draw(&self, muted: [bool; 5], frame: &mut [u8]) {
+        let height = frame.len() / (FRAME_WIDTH * 3);
+        let Some(top) = height.checked_sub(5 * STRIP_HEIGHT) else {
+            return;
+        };
+
+        // Darken the strips so the lines stand out from the game.
+        for pixel in frame[top * FRAME_WIDTH * 3..].iter_mut() {
+            *pixel /= 4;
+        }
+
+        let mut set_pixel = |x: usize, y: usize, colour: [u8; 3]| {
+            let i = (y * FRAME_WIDTH + x) * 3;
+            frame[i..i + 3].copy_from_slice(&colour);
+        };
+
+        for channel in 0..5 {
+            let colour = match muted[channel] {
+                true => MUTED_COLOUR,
+                false => COLOURS[channel],
+            };
+            let bottom = top + (channel + 1) * STRIP_HEIGHT - 2;
+            let y = |level: u8| {
+                bottom - level as usize * (STRIP_HEIGHT - 3) / MAX_LEVELS[channel] as usize
+            };
+
+            let latest = self.samples(channel).last().unwrap_or(0);
+            for row in y(latest)..=bottom {
+                for x in 1..METER_WIDTH - 1 {
+                    set_pixel(x, row, colour);
+                }
+            }
+
+            // Consecutive samples are joined with vertical lines, so square
+            // waves read as such.
+            let mut previous = None;
+            for (i, level) in self.samples(channel).enumerate() {
+                let (x, y) = (METER_WIDTH + i, y(level));
+                let (from, to) = match previous {
+                    Some(p) if p < y => (p, y),
+                    Some(p) => (y, p),
+                    None => (y, y),
+                };
+                for row in from..=to {
+                    set_pixel(x, row, colour);
+                }
+                previous = Some(y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples() {
+        let mut scope = Scope::default();
+        for i in 0..SCOPE_SAMPLES as u8 + 2 {
+            scope.push([i, 0, 0, 0, i / 2]);
+        }
+
+        let pulse: Vec<u8> = scope.samples(0).collect();
+        assert_eq!(pulse.len(), SCOPE_SAMPLES);
+        assert_eq!(pulse[0], 2);
+        assert_eq!(pulse[SCOPE_SAMPLES - 1], SCOPE_SAMPLES as u8 + 1);
+        assert_eq!(scope.samples(4).last(), Some(SCOPE_SAMPLES as u8 / 2));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut scope = Scope::default();
+        scope.push([15, 0, 0, 0, 0]);
+
+        let mut frame = vec![200; FRAME_WIDTH * 240 * 3];
+        scope.draw([false, true, false, false, false], &mut frame);
+        let pixel = |x: usize, y: usize| &frame[(y * FRAME_WIDTH + x) * 3..][..3];
+
+        // The picture above the strips is untouched.
+        assert_eq!(pixel(100, 119), [200; 3]);
+
+        // Pulse 1 is at full volume in the top strip, both in its meter and
+        // at the end of its waveform.
+        assert_eq!(pixel(3, 122), COLOURS[0]);
+        assert_eq!(pixel(METER_WIDTH + SCOPE_SAMPLES - 1, 122), COLOURS[0]);
+        assert_eq!(pixel(100, 122), [50; 3]);
+
+        // Pulse 2 is silent and muted.
+        assert_eq!(pixel(3, 120 + 2 * STRIP_HEIGHT - 2), MUTED_COLOUR);
+        assert_eq!(pixel(3, 120 + 2 * STRIP_HEIGHT - 3), [50; 3]);
+    }
+}
@@ -1,4 +1,8 @@
-use super::LENGTH_TABLE;
+use std::io::{self, Read, Write};
+
+use super::length::LengthCounter;
+use super::ChannelState;
+use crate::state::{read_bool, read_u16, read_u8, write_bool, write_u16, write_u8, SaveState};
 
 /// The sequencer sends the following looping 32-step sequence of values to the
 /// mixer.
@@ -17,7 +21,7 @@ pub struct Triangle {
     timer: u16,
 
     counter_halt: bool,
-    length_counter: u8,
+    length: LengthCounter,
 
     counter_reload: bool,
     counter_period: u8,
@@ -35,7 +39,7 @@ impl Triangle {
             timer: 0,
 
             counter_halt: false,
-            length_counter: 0,
+            length: LengthCounter::default(),
 
             counter_reload: false,
             counter_period: 0,
@@ -46,10 +50,7 @@ impl Triangle {
     /// Toggles the channel on or off.
     pub fn toggle(&mut self, enabled: bool) {
         self.enabled = enabled;
-
-        if !self.enabled {
-            self.length_counter = 0;
-        }
+        self.length.set_enabled(enabled);
     }
 
     /// Updates the linear counter.
@@ -62,6 +63,7 @@ impl Triangle {
     pub fn write_linear_counter(&mut self, data: u8) {
         self.counter_period = data & 0x7F;
         self.counter_halt = data & 0x80 != 0;
+        self.length.set_halt(self.counter_halt);
         if self.counter_halt {
             self.linear_counter = self.counter_period;
         }
@@ -88,7 +90,7 @@ impl Triangle {
     /// T: Timer period high
     pub fn write_timer_high(&mut self, data: u8) {
         self.timer_period = ((data & 0x7) as u16) << 8 | (self.timer_period & 0xFF);
-        self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        self.length.load(data >> 3);
         self.counter_reload = true;
     }
 
@@ -103,16 +105,19 @@ impl Triangle {
         self.timer = self.timer_period + 1;
 
         // The sequencer is clocked by the timer, move to the next phase.
-        if self.length_counter > 0 && self.linear_counter > 0 && self.timer_period > 1 {
+        if self.length.value() > 0 && self.linear_counter > 0 && self.timer_period > 1 {
             self.phase = (self.phase + 1) % 32;
         }
     }
 
     /// Clocks the length counter.
     pub fn clock_length(&mut self) {
-        if !self.counter_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
+    }
+
+    /// Applies the length counter writes made during the CPU cycle.
+    pub fn end_cycle(&mut self) {
+        self.length.end_cycle();
     }
 
     /// Clocks the linear counter.
@@ -131,13 +136,24 @@ impl Triangle {
 
     /// Returns the length counter value
     pub fn length_counter(&self) -> u8 {
-        self.length_counter
+        self.length.value()
+    }
+
+    /// Returns a view of the channel's state.
+    pub fn state(&self) -> ChannelState {
+        ChannelState {
+            enabled: self.enabled,
+            period: self.timer_period,
+            length_counter: self.length.value() as u16,
+            volume: self.linear_counter,
+            duty: 0,
+        }
     }
 
     /// Returns the output volume of the channel.
     pub fn output(&self) -> u8 {
         // All the conditions below silence the channel.
-        if !self.enabled || self.length_counter == 0 || self.linear_counter == 0 {
+        if !self.enabled || self.length.value() == 0 || self.linear_counter == 0 {
             return 0;
         }
 
@@ -145,6 +161,33 @@ impl Triangle {
     }
 }
 
+impl SaveState for Triangle {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.enabled)?;
+        write_u8(w, self.phase)?;
+        write_u16(w, self.timer_period)?;
+        write_u16(w, self.timer)?;
+        write_bool(w, self.counter_halt)?;
+        self.length.save_state(w)?;
+        write_bool(w, self.counter_reload)?;
+        write_u8(w, self.counter_period)?;
+        write_u8(w, self.linear_counter)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.enabled = read_bool(r)?;
+        self.phase = read_u8(r)?;
+        self.timer_period = read_u16(r)?;
+        self.timer = read_u16(r)?;
+        self.counter_halt = read_bool(r)?;
+        self.length.load_state(r)?;
+        self.counter_reload = read_bool(r)?;
+        self.counter_period = read_u8(r)?;
+        self.linear_counter = read_u8(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::apu::triangle::OUTPUT_LEVELS;
@@ -159,7 +202,7 @@ mod tests {
         assert_eq!(triangle.timer_period, 0);
         assert_eq!(triangle.timer, 0);
         assert!(!triangle.counter_halt);
-        assert_eq!(triangle.length_counter, 0);
+        assert_eq!(triangle.length_counter(), 0);
         assert!(!triangle.counter_reload);
         assert_eq!(triangle.counter_period, 0);
         assert_eq!(triangle.linear_counter, 0);
@@ -172,7 +215,7 @@ mod tests {
         assert!(triangle.enabled);
         triangle.toggle(false);
         assert!(!triangle.enabled);
-        assert_eq!(triangle.length_counter, 0);
+        assert_eq!(triangle.length_counter(), 0);
     }
 
     #[test]
@@ -187,9 +230,11 @@ mod tests {
     #[test]
     fn test_clock_length() {
         let mut triangle = Triangle::new();
-        triangle.length_counter = 5;
+        triangle.toggle(true);
+        triangle.write_timer_high(0x28);
+        triangle.end_cycle();
         triangle.clock_length();
-        assert_eq!(triangle.length_counter, 4);
+        assert_eq!(triangle.length_counter(), 3);
     }
 
     #[test]
@@ -211,8 +256,9 @@ mod tests {
     fn test_output() {
         let mut triangle = Triangle::new();
         assert_eq!(triangle.output(), 0);
-        triangle.enabled = true;
-        triangle.length_counter = 5;
+        triangle.toggle(true);
+        triangle.write_timer_high(0x28);
+        triangle.end_cycle();
         triangle.linear_counter = 5;
         assert_eq!(triangle.output(), OUTPUT_LEVELS[triangle.phase as usize]);
     }
@@ -0,0 +1,107 @@
+/// Largest adjustment to the sample rate, in tenths of a percent.
+const MAX_ADJUST: i32 = 5;
+
+/// Multiple of the target fill at which the queue is considered overrun.
+const OVERRUN: u32 = 3;
+
+/// Keeps the audio queue filled to a target level, trading latency for
+/// protection against the host's audio clock drifting from the emulation.
+///
+/// The queue is checked once per display refresh. The further it is from the
+/// target, the more the sample rate is nudged, up to 0.5% either way, which
+/// is too little to hear as a change in pitch. Underruns, where the queue
+/// ran dry and playback stuttered, and overruns, where so much was queued
+/// that it had to be dropped, are counted for reporting.
+pub struct AudioSync {
+    target: u32,
+    started: bool,
+    adjust: i32,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl AudioSync {
+    /// Returns a synchroniser keeping the given number of sample frames
+    /// queued.
+    pub fn new(target: u32) -> Self {
+        AudioSync {
+            target: target.max(1),
+            started: false,
+            adjust: 0,
+            underruns: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Checks the number of sample frames still queued, before the next are
+    /// added. Returns true if the queue has overrun and should be cleared.
+    pub fn update(&mut self, queued: u32) -> bool {
+        let started = std::mem::replace(&mut self.started, true);
+        if queued == 0 && started {
+            self.underruns += 1;
+        }
+
+        if queued > self.target * OVERRUN {
+            self.overruns += 1;
+            self.adjust = 0;
+            return true;
+        }
+
+        let error = (queued as f64 - self.target as f64) / self.target as f64;
+        self.adjust = -(error.clamp(-1.0, 1.0) * MAX_ADJUST as f64).round() as i32;
+        false
+    }
+
+    /// Forgets the queue's previous level, e.g. after it was cleared, so an
+    /// empty queue isn't counted as an underrun.
+    pub fn restart(&mut self) {
+        self.started = false;
+    }
+
+    /// Returns the adjustment to the sample rate in tenths of a percent.
+    pub fn adjust(&self) -> i32 {
+        self.adjust
+    }
+
+    /// Returns a summary of the underruns and overruns.
+    pub fn report(&self) -> String {
+        format!(
+            "audio: {} underruns, {} overruns",
+            self.underruns, self.overruns
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust() {
+        let mut sync = AudioSync::new(2048);
+
+        // Too little queued speeds up the sample rate, too much slows it.
+        sync.update(0);
+        assert_eq!(sync.adjust(), MAX_ADJUST);
+        sync.update(2048);
+        assert_eq!(sync.adjust(), 0);
+        sync.update(3072);
+        assert_eq!(sync.adjust(), -3);
+        sync.update(6000);
+        assert_eq!(sync.adjust(), -MAX_ADJUST);
+    }
+
+    #[test]
+    fn test_underruns_and_overruns() {
+        let mut sync = AudioSync::new(2048);
+
+        // The queue starts empty, which isn't an underrun.
+        assert!(!sync.update(0));
+        assert!(!sync.update(0));
+        assert!(sync.update(8000));
+
+        sync.restart();
+        assert!(!sync.update(0));
+        assert_eq!(sync.report(), "audio: 1 underruns, 1 overruns");
+    }
+}
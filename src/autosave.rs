@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// A save state waiting to be written, with the paths of the slots it may
+/// replace.
+struct Job {
+    slots: Vec<PathBuf>,
+    data: Vec<u8>,
+}
+
+/// Periodically saves the state to a rotating set of emergency files next to
+/// the ROM, so a crash or power cut doesn't lose progress in games without
+/// battery saves.
+///
+/// States are serialised on the emulation thread, which is quick, but written
+/// on a background thread so slow disks never stall a frame. Each save
+/// replaces the oldest slot, and is written to a temporary file first so a
+/// crash mid-write can't corrupt the previous saves.
+pub struct Autosave {
+    interval: Duration,
+    elapsed: Duration,
+    slots: usize,
+
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Result<PathBuf, String>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Autosave {
+    /// Returns an autosave writing every given interval of play to the given
+    /// number of slots.
+    pub fn new(interval: Duration, slots: usize) -> Self {
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let (done, results) = mpsc::channel();
+
+        let worker = thread::spawn(move || {
+            for job in queued {
+                let _ = done.send(write(&job.slots, &job.data));
+            }
+        });
+
+        Autosave {
+            interval,
+            elapsed: Duration::ZERO,
+            slots: slots.max(1),
+            jobs: Some(jobs),
+            results,
+            worker: Some(worker),
+        }
+    }
+
+    /// Advances the play time since the last save, returning true if a save
+    /// is due.
+    pub fn tick(&mut self, played: Duration) -> bool {
+        self.elapsed += played;
+        if self.elapsed < self.interval {
+            return false;
+        }
+
+        self.elapsed = Duration::ZERO;
+        true
+    }
+
+    /// Restarts the interval, e.g. when another ROM is loaded.
+    pub fn restart(&mut self) {
+        self.elapsed = Duration::ZERO;
+    }
+
+    /// Queues a save state of the ROM at the given path to be written.
+    pub fn save(&self, rom: &Path, data: Vec<u8>) {
+        let slots = (0..self.slots).map(|n| slot_path(rom, n)).collect();
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(Job { slots, data });
+        }
+    }
+
+    /// Returns the outcome of each save written since the last call, the
+    /// path written to or the error.
+    pub fn results(&self) -> Vec<Result<PathBuf, String>> {
+        self.results.try_iter().collect()
+    }
+}
+
+impl Drop for Autosave {
+    /// Waits for queued saves to be written.
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Returns the path of the given autosave slot for the ROM at the given
+/// path, e.g. `zelda.auto0.state` for `zelda.nes`.
+pub fn slot_path(rom: &Path, slot: usize) -> PathBuf {
+    rom.with_extension(format!("auto{}.state", slot))
+}
+
+/// Writes the state over the oldest of the slots.
+fn write(slots: &[PathBuf], data: &[u8]) -> Result<PathBuf, String> {
+    let modified: Vec<Option<SystemTime>> = slots
+        .iter()
+        .map(|path| path.metadata().and_then(|m| m.modified()).ok())
+        .collect();
+    let path = &slots[oldest(&modified)];
+
+    let temp = path.with_extension("state.tmp");
+    std::fs::write(&temp, data)
+        .and_then(|_| std::fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(path.clone())
+}
+
+/// Returns the index of the slot written longest ago, preferring the first
+/// slot which has never been written.
+fn oldest(modified: &[Option<SystemTime>]) -> usize {
+    modified
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, time)| *time)
+        .map_or(0, |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick() {
+        let mut autosave = Autosave::new(Duration::from_secs(60), 3);
+        assert!(!autosave.tick(Duration::from_secs(59)));
+        assert!(autosave.tick(Duration::from_secs(1)));
+        assert!(!autosave.tick(Duration::from_secs(30)));
+
+        autosave.restart();
+        assert!(!autosave.tick(Duration::from_secs(59)));
+    }
+
+    #[test]
+    fn test_oldest() {
+        let t = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(oldest(&[t(5), t(3), t(4)]), 1);
+        assert_eq!(oldest(&[t(5), None, None]), 1);
+        assert_eq!(oldest(&[]), 0);
+    }
+
+    #[test]
+    fn test_slot_path() {
+        assert_eq!(
+            slot_path(Path::new("roms/zelda.nes"), 2),
+            PathBuf::from("roms/zelda.auto2.state")
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use crate::rom::{Format, Header};
+
+/// Number of frames between flashes of the test program.
+pub const FLASH_INTERVAL: u64 = 60;
+
+/// NROM program run by the audio-visual sync test.
+///
+/// On reset it enables NMIs, the background and the first pulse channel then
+/// spins. The background is left blank, so the screen shows the backdrop
+/// colour. Every 60th NMI sets the backdrop to white and starts a short beep
+/// on the pulse channel, the rest set it back to black, so the flash and the
+/// click come out on the same frame.
+///
+/// ```text
+/// C000  SEI / CLD / LDX #$FF / TXS
+/// C005  LDA #$01 / STA $4015 / LDA #$00 / STA $10
+/// C00E  LDA #$80 / STA $2000 / LDA #$0A / STA $2001
+/// C018  JMP $C018
+/// C01B  LDX #$0F / LDA $10 / BNE $C032 / LDX #$30
+/// C023  LDA #$9F / STA $4000 / LDA #$FD / STA $4002 / LDA #$28 / STA $4003
+/// C032  LDA $2002 / LDA #$3F / STA $2006 / LDA #$00 / STA $2006 / STX $2007
+/// C042  LDA #$00 / STA $2005 / STA $2005 / LDA #$80 / STA $2000
+/// C04F  INC $10 / LDA $10 / CMP #$3C / BNE $C05B / LDA #$00 / STA $10
+/// C05B  RTI
+/// C05C  RTI
+/// ```
+const PROGRAM: [u8; 0x5D] = [
+    0x78, 0xD8, 0xA2, 0xFF, 0x9A, 0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0x00, 0x85, 0x10, 0xA9, 0x80,
+    0x8D, 0x00, 0x20, 0xA9, 0x0A, 0x8D, 0x01, 0x20, 0x4C, 0x18, 0xC0, 0xA2, 0x0F, 0xA5, 0x10, 0xD0,
+    0x11, 0xA2, 0x30, 0xA9, 0x9F, 0x8D, 0x00, 0x40, 0xA9, 0xFD, 0x8D, 0x02, 0x40, 0xA9, 0x28, 0x8D,
+    0x03, 0x40, 0xAD, 0x02, 0x20, 0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8E,
+    0x07, 0x20, 0xA9, 0x00, 0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20, 0xA9, 0x80, 0x8D, 0x00, 0x20, 0xE6,
+    0x10, 0xA5, 0x10, 0xC9, 0x3C, 0xD0, 0x04, 0xA9, 0x00, 0x85, 0x10, 0x40, 0x40,
+];
+
+/// NMI, reset and IRQ vectors of the program.
+const VECTORS: [u16; 3] = [0xC01B, 0xC000, 0xC05C];
+
+/// Returns the iNES image of the audio-visual sync test ROM, with blank
+/// pattern tables.
+pub fn rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    for (i, vector) in VECTORS.iter().enumerate() {
+        prg[0x3FFA + i * 2..0x3FFC + i * 2].copy_from_slice(&vector.to_le_bytes());
+    }
+
+    let mut rom = Header::new(0, 1, 1).to_bytes(Format::Ines).to_vec();
+    rom.extend(prg);
+    rom.extend([0; 0x2000]);
+    rom
+}
+
+/// Writes the test ROM to the temporary directory, so it can be run like any
+/// other ROM, and returns its path.
+pub fn write_rom() -> Result<PathBuf, String> {
+    let path = std::env::temp_dir().join("res-avsync.nes");
+    std::fs::write(&path, rom()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Splits an offset in milliseconds between the audio and video into the
+/// number of sample frames to delay the audio by, for a positive offset, or
+/// the number of frames to delay the picture by, for a negative one.
+pub fn delays(offset_ms: i32, sample_rate: u32, frame_rate: f64) -> (u32, usize) {
+    let secs = offset_ms.unsigned_abs() as f64 / 1000.0;
+    match offset_ms {
+        0.. => ((secs * sample_rate as f64).round() as u32, 0),
+        _ => (0, (secs * frame_rate).round() as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::SystemBus;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::Cpu;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_flash_and_click_on_the_same_frame() {
+        let cart = Cartridge::new(&rom()).unwrap();
+        let screen = Rc::new(RefCell::new(Vec::new()));
+        let frame = Rc::clone(&screen);
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, move |pixels| {
+            *frame.borrow_mut() = pixels.to_vec();
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        // Clicks are counted from the frame the sound starts on.
+        let mut flashes = Vec::new();
+        let mut clicks = Vec::new();
+        let mut was_silent = true;
+        for frame in 0..3 * FLASH_INTERVAL {
+            let frame_count = cpu.bus.ppu_frame_count();
+            while cpu.bus.ppu_frame_count() == frame_count {
+                cpu.clock();
+            }
+
+            let silent = cpu.bus.audio_samples().iter().all(|s| s.abs() < 0.01);
+            if !silent && was_silent {
+                clicks.push(frame);
+            }
+            was_silent = silent;
+
+            if screen.borrow().iter().all(|p| *p > 0xE0) {
+                flashes.push(frame);
+            }
+        }
+
+        assert_eq!(flashes.len(), 3);
+        assert_eq!(flashes[1] - flashes[0], FLASH_INTERVAL);
+        assert_eq!(clicks, flashes);
+    }
+
+    #[test]
+    fn test_delays() {
+        assert_eq!(delays(0, 44100, 60.0), (0, 0));
+        assert_eq!(delays(50, 44100, 60.0), (2205, 0));
+        assert_eq!(delays(-50, 44100, 60.0), (0, 3));
+        assert_eq!(delays(-50, 44100, 50.0), (0, 3));
+    }
+}
@@ -1,6 +1,7 @@
+mod dma;
 mod ppu;
 mod system;
 
 pub use ppu::Memory;
 pub use ppu::PPUBus;
-pub use system::SystemBus;
+pub use system::{Device, SystemBus};
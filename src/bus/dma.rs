@@ -0,0 +1,116 @@
+use std::io::{self, Read, Write};
+
+use crate::state::{read_bool, read_u16, write_bool, write_u16, SaveState};
+
+/// CPU cycles the CPU is halted for by an OAM DMA: a halt cycle, then a read
+/// and a write for each of the 256 bytes.
+const OAM_DMA_CYCLES: u16 = 513;
+
+/// CPU cycles the CPU is halted for by a DMC sample fetch: a halt cycle, a
+/// dummy cycle, an alignment cycle and the read.
+const DMC_DMA_CYCLES: u16 = 4;
+
+/// CPU cycles a DMC sample fetch adds to an OAM DMA it interrupts, which has
+/// already halted the CPU and lined up.
+const DMC_DURING_OAM_CYCLES: u16 = 2;
+
+/// Tracks the cycles DMA steals from the CPU. The copy itself is done at
+/// once by the bus, and the CPU is halted for the time it takes the real
+/// hardware to make it when the instruction which started it is done.
+///
+/// See: https://www.nesdev.org/wiki/DMA
+#[derive(Default)]
+pub struct Dma {
+    oam_pending: bool,
+
+    /// Cycles owed for DMC sample fetches since the stall was last taken.
+    dmc_cycles: u16,
+}
+
+impl Dma {
+    /// Starts an OAM DMA, which halts the CPU once the current instruction
+    /// is done.
+    pub fn start_oam(&mut self) {
+        self.oam_pending = true;
+    }
+
+    /// Records a DMC sample fetch, which halts the CPU once the current
+    /// instruction is done, or lengthens the OAM DMA if one is running.
+    pub fn fetch_dmc(&mut self) {
+        let cycles = match self.oam_pending {
+            true => DMC_DURING_OAM_CYCLES,
+            false => DMC_DMA_CYCLES,
+        };
+        self.dmc_cycles = self.dmc_cycles.saturating_add(cycles);
+    }
+
+    /// Returns the number of cycles the CPU is halted for, given the number
+    /// of CPU cycles run, and clears them. OAM DMA reads on get cycles and
+    /// writes on put cycles, so starting on an odd cycle costs an extra cycle
+    /// to line up.
+    pub fn take_stall(&mut self, cycles: u64) -> u16 {
+        let oam = match std::mem::take(&mut self.oam_pending) {
+            true => OAM_DMA_CYCLES + (cycles % 2) as u16,
+            false => 0,
+        };
+        oam + std::mem::take(&mut self.dmc_cycles)
+    }
+}
+
+impl SaveState for Dma {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.oam_pending)?;
+        write_u16(w, self.dmc_cycles)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.oam_pending = read_bool(r)?;
+        self.dmc_cycles = read_u16(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oam_stall() {
+        let mut dma = Dma::default();
+        assert_eq!(dma.take_stall(0), 0);
+
+        dma.start_oam();
+        assert_eq!(dma.take_stall(10), 513);
+        assert_eq!(dma.take_stall(10), 0);
+
+        dma.start_oam();
+        assert_eq!(dma.take_stall(11), 514);
+    }
+
+    #[test]
+    fn test_dmc_stall() {
+        let mut dma = Dma::default();
+        dma.fetch_dmc();
+        dma.fetch_dmc();
+        assert_eq!(dma.take_stall(0), 8);
+
+        // Fetches during OAM DMA cost less.
+        dma.start_oam();
+        dma.fetch_dmc();
+        assert_eq!(dma.take_stall(0), 515);
+        assert_eq!(dma.take_stall(0), 0);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut dma = Dma::default();
+        dma.start_oam();
+        dma.fetch_dmc();
+
+        let mut state = Vec::new();
+        dma.save_state(&mut state).unwrap();
+        let mut restored = Dma::default();
+        restored.load_state(&mut &state[..]).unwrap();
+        assert_eq!(restored.take_stall(1), dma.take_stall(1));
+    }
+}
@@ -1,6 +1,14 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Read, Write},
+    ops::Range,
+    rc::Rc,
+};
 
 use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::A12Filter;
+use crate::ppu::{decode_row, MemoryInit};
+use crate::state::SaveState;
 
 const ROM: u16 = 0x0000;
 const ROM_END: u16 = 0x1FFF;
@@ -14,27 +22,162 @@ const PALETTE_END: u16 = 0x3FFF;
 pub struct PPUBus {
     cart: Rc<RefCell<Cartridge>>,
 
+    /// Copy of the CHR memory currently mapped into the pattern tables, so
+    /// pattern fetches during rendering don't need to borrow the cartridge.
+    chr: Box<[u8; 0x2000]>,
+
+    /// Pattern rows of the cached CHR decoded to a byte per pixel, indexed
+    /// by [`row_index`].
+    rows: Box<[u64; 0x1000]>,
+
+    /// Cartridge bank generation the cache was last checked against.
+    bank_generation: Rc<Cell<u32>>,
+    chr_cached: u32,
+
+    /// 1 KB CHR banks mapped when each 1 KB of the cache was loaded.
+    chr_banks: [usize; 8],
+
     /// Internal reference to colour palettes.
     pub palette_table: [u8; 32],
 
-    /// Video RAM.
+    /// Video RAM, holding the first two nametables. Four-screen cartridges
+    /// provide the VRAM for the other two.
     pub vram: [u8; 2048],
+
+    /// Watches rendering fetches for the A12 edges that clock mapper
+    /// scanline counters.
+    a12: A12Filter,
+
+    /// Whether the mapper switches banks on pattern table fetches, asked
+    /// once so other boards don't borrow the cartridge on every fetch.
+    watch_chr: bool,
 }
 
 pub trait Memory {
     fn write_data(&mut self, addr: u16, value: u8);
     fn read_data(&mut self, addr: u16) -> u8;
+
+    /// Returns the data at the given address without any side effects, for
+    /// use by debugging tools.
+    fn peek_data(&self, addr: u16) -> u8;
+
+    /// Reports an address put on the bus by a rendering fetch at the given
+    /// PPU dot, so mappers can watch the address lines.
+    fn observe(&mut self, _addr: u16, _dot: u64) {}
+
+    /// Writes the memory's state for a save state, see [`SaveState`].
+    fn save_state(&self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Restores the memory's state from a save state, see [`SaveState`].
+    fn load_state(&mut self, _r: &mut dyn Read) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the pattern row at the given address decoded to a byte per
+    /// pixel, see [`decode_row`].
+    fn pattern_row(&mut self, addr: u16) -> u64 {
+        decode_row(self.read_data(addr), self.read_data(addr.wrapping_add(8)))
+    }
+}
+
+/// Returns true if the address is in the high bitplane of tile $FD or $FE of
+/// either pattern table, the only fetches which switch the MMC2 and MMC4
+/// latches.
+fn is_latch_fetch(addr: u16) -> bool {
+    addr <= ROM_END && matches!(addr & 0xFF8, 0xFD8 | 0xFE8)
+}
+
+/// Returns the index of the decoded pattern row holding the given CHR
+/// address.
+fn row_index(addr: u16) -> usize {
+    ((addr as usize >> 4) << 3) | (addr as usize & 0x7)
 }
 
 impl PPUBus {
-    pub fn new(cart: Rc<RefCell<Cartridge>>) -> Self {
-        PPUBus {
+    pub fn new(cart: Rc<RefCell<Cartridge>>, init: MemoryInit) -> Self {
+        let bank_generation = cart.borrow().bank_generation();
+        let watch_chr = cart.borrow().watches_chr_fetches();
+
+        let mut bus = PPUBus {
             cart,
+            chr: Box::new([0; 0x2000]),
+            rows: Box::new([0; 0x1000]),
+            bank_generation,
+            chr_cached: 0,
+            chr_banks: [0; 8],
             palette_table: [0; 32],
             vram: [0; 2048],
+            a12: A12Filter::default(),
+            watch_chr,
+        };
+
+        init.fill(&mut bus.vram);
+        init.fill(&mut bus.palette_table);
+        bus.reload_chr();
+        bus
+    }
+
+    /// Returns the CHR cache, refreshing it from the cartridge if the mapper
+    /// may have switched banks since it was last built.
+    fn chr(&mut self) -> &mut [u8; 0x2000] {
+        self.refresh();
+        &mut self.chr
+    }
+
+    /// Checks the CHR banks if the cartridge bank generation has changed. The
+    /// generation changes on every mapper register write, including those
+    /// which only switch PRG banks or set up IRQs, so only the banks which
+    /// actually changed are reloaded.
+    fn refresh(&mut self) {
+        let generation = self.bank_generation.get();
+        if self.chr_cached != generation {
+            self.chr_cached = generation;
+            self.refresh_banks();
         }
     }
 
+    /// Rebuilds the whole CHR cache and its decoded pattern rows.
+    fn reload_chr(&mut self) {
+        self.load_chr(0..0x2000);
+        self.chr_cached = self.bank_generation.get();
+    }
+
+    /// Reloads the parts of the CHR cache whose 1 KB banks have changed.
+    fn refresh_banks(&mut self) {
+        for start in (0..0x2000).step_by(0x400) {
+            let bank = self.cart.borrow().chr_bank(start);
+            if bank != self.chr_banks[start as usize / 0x400] {
+                self.load_chr(start..start + 0x400);
+            }
+        }
+    }
+
+    /// Loads the given 1 KB aligned range of the CHR cache from the cartridge
+    /// and decodes its pattern rows.
+    fn load_chr(&mut self, range: Range<u16>) {
+        let cart = self.cart.borrow();
+        for addr in range.clone() {
+            self.chr[addr as usize] = cart.read_chr(addr);
+        }
+        for start in range.clone().step_by(0x400) {
+            self.chr_banks[start as usize / 0x400] = cart.chr_bank(start);
+        }
+        drop(cart);
+
+        for addr in range.filter(|a| a & 0x8 == 0) {
+            self.decode(addr);
+        }
+    }
+
+    /// Decodes the cached pattern row at the given address.
+    fn decode(&mut self, addr: u16) {
+        let lo = self.chr[addr as usize];
+        let hi = self.chr[addr as usize + 8];
+        self.rows[row_index(addr)] = decode_row(lo, hi);
+    }
+
     /// Horizontal:
     ///   [ A ] [ a ]
     ///   [ B ] [ b ]
@@ -42,6 +185,18 @@ impl PPUBus {
     /// Vertical:
     ///   [ A ] [ B ]
     ///   [ a ] [ b ]
+    ///
+    /// Single-screen, lower or upper bank:
+    ///   [ A ] [ a ]
+    ///   [ a ] [ a ]
+    ///
+    /// Four-screen:
+    ///   [ A ] [ B ]
+    ///   [ C ] [ D ]
+    ///
+    /// The mode is asked of the cartridge on every access, as mappers switch
+    /// it at any time. Indexes past the 2 KB of internal VRAM are in the
+    /// cartridge's VRAM.
     fn mirror_vram_addr(&self, addr: u16) -> u16 {
         // Mirror down 0x3000-0x3EFF to 0x2000 - 0x2EFF
         let mirrored_vram = addr & 0x2FFF;
@@ -65,15 +220,40 @@ impl PPUBus {
             Mirroring::FourScreen => vram_index,
         }
     }
+
+    /// Returns the nametable byte at the given index, see
+    /// [`mirror_vram_addr`](Self::mirror_vram_addr).
+    fn read_vram(&self, index: u16) -> u8 {
+        match index {
+            0x000..=0x7FF => self.vram[index as usize],
+            _ => self.cart.borrow().read_vram(index - 0x800),
+        }
+    }
+
+    /// Writes the nametable byte at the given index.
+    fn write_vram(&mut self, index: u16, data: u8) {
+        match index {
+            0x000..=0x7FF => self.vram[index as usize] = data,
+            _ => self.cart.borrow_mut().write_vram(index - 0x800, data),
+        }
+    }
 }
 
 impl Memory for PPUBus {
     /// Writes data to appropriate location based on the address register.
     fn write_data(&mut self, addr: u16, data: u8) {
         match addr {
-            ROM..=ROM_END => self.cart.borrow_mut().write_chr(addr, data),
+            ROM..=ROM_END => {
+                self.cart.borrow_mut().write_chr(addr, data);
+
+                // Keep the cache in step with CHR RAM, mappers with CHR ROM
+                // ignore the write.
+                let value = self.cart.borrow().read_chr(addr);
+                self.chr()[addr as usize] = value;
+                self.decode(addr & !0x8);
+            }
             VRAM..=VRAM_END => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = data;
+                self.write_vram(self.mirror_vram_addr(addr), data);
             }
             // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of
             // $3F00/$3F04/$3F08/$3F0C
@@ -90,11 +270,231 @@ impl Memory for PPUBus {
 
     /// Retuns data from appropriate source based on the address register.
     fn read_data(&mut self, addr: u16) -> u8 {
+        match addr {
+            ROM..=ROM_END => self.chr()[addr as usize],
+            VRAM..=VRAM_END => self.read_vram(self.mirror_vram_addr(addr)),
+            PALETTE..=PALETTE_END => self.palette_table[(addr - 0x3F00) as usize],
+            _ => unreachable!("unexpected access to mirrored space {}", addr),
+        }
+    }
+
+    /// Returns the pattern row from the decoded cache.
+    fn pattern_row(&mut self, addr: u16) -> u64 {
+        self.refresh();
+        self.rows[row_index(addr)]
+    }
+
+    /// Clocks the cartridge on filtered rising edges of A12, and passes
+    /// pattern table fetches to it.
+    fn observe(&mut self, addr: u16, dot: u64) {
+        if self.a12.observe(addr, dot) {
+            self.cart.borrow_mut().clock_a12();
+        }
+
+        if self.watch_chr && is_latch_fetch(addr) && self.cart.borrow_mut().notify_chr_fetch(addr) {
+            self.refresh_banks();
+        }
+    }
+
+    /// Saves VRAM, palette RAM and the A12 filter. CHR RAM belongs to the
+    /// cartridge.
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.palette_table)?;
+        w.write_all(&self.vram)?;
+        self.a12.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        r.read_exact(&mut self.palette_table)?;
+        r.read_exact(&mut self.vram)?;
+        self.a12.load_state(r)?;
+
+        // The cartridge may have been restored to different banks or CHR RAM.
+        self.reload_chr();
+        Ok(())
+    }
+
+    /// Returns data from the same source as `read_data`, but reads CHR
+    /// straight from the cartridge rather than refreshing the cache.
+    fn peek_data(&self, addr: u16) -> u8 {
         match addr {
             ROM..=ROM_END => self.cart.borrow().read_chr(addr),
-            VRAM..=VRAM_END => self.vram[self.mirror_vram_addr(addr) as usize],
+            VRAM..=VRAM_END => self.read_vram(self.mirror_vram_addr(addr)),
             PALETTE..=PALETTE_END => self.palette_table[(addr - 0x3F00) as usize],
             _ => unreachable!("unexpected access to mirrored space {}", addr),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{test_cartridge, test_chr_ram_cartridge};
+
+    /// Returns a cartridge with 32 KB of PRG ROM and the given CHR ROM, or
+    /// CHR RAM if empty, with the given iNES flags 6 (mapper and mirroring)
+    /// in its header.
+    fn test_raw_cartridge(flags_6: u8, chr: &[u8]) -> Cartridge {
+        let mut raw = b"NES\x1A".to_vec();
+        raw.extend([
+            2,
+            (chr.len() / 0x2000) as u8,
+            flags_6,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        raw.resize(16 + 0x8000, 0);
+        raw.extend(chr);
+        Cartridge::new(&raw).unwrap()
+    }
+
+    /// Writes the MMC1 control register through the serial load register.
+    fn write_mmc1_control(cart: &RefCell<Cartridge>, value: u8) {
+        for i in 0..5 {
+            cart.borrow_mut().write_prg(0x8000, (value >> i) & 0x1);
+        }
+    }
+
+    #[test]
+    fn test_chr_ram_write_through() {
+        let cart = test_chr_ram_cartridge(vec![]).unwrap();
+
+        let mut bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+        assert_eq!(bus.read_data(0x0010), 0);
+
+        bus.write_data(0x0010, 0x55);
+        assert_eq!(bus.read_data(0x0010), 0x55);
+    }
+
+    #[test]
+    fn test_chr_cache_follows_switched_banks() {
+        // An MMC3 with the first byte of each 1 KB CHR bank set to its number.
+        let mut chr = vec![0; 0x4000];
+        for (i, bank) in chr.chunks_mut(0x400).enumerate() {
+            bank[0] = i as u8;
+        }
+        let cart = Rc::new(RefCell::new(test_raw_cartridge(0x40, &chr)));
+
+        let mut bus = PPUBus::new(Rc::clone(&cart), MemoryInit::Zero);
+        assert_eq!(bus.read_data(0x1000), 0);
+
+        // R2 selects the 1 KB bank at $1000.
+        cart.borrow_mut().write_prg(0x8000, 2);
+        cart.borrow_mut().write_prg(0x8001, 9);
+        assert_eq!(bus.read_data(0x1000), 9);
+        assert_eq!(bus.pattern_row(0x1000), decode_row(9, 0));
+    }
+
+    #[test]
+    fn test_chr_cache_keeps_unswitched_banks() {
+        let cart = Rc::new(RefCell::new(test_chr_ram_cartridge(vec![]).unwrap()));
+
+        let mut bus = PPUBus::new(Rc::clone(&cart), MemoryInit::Zero);
+        assert_eq!(bus.read_data(0x0020), 0);
+
+        // Modify CHR behind the bus' back. A mapper write which leaves the
+        // CHR banks alone doesn't reload them, so the stale cache is kept.
+        cart.borrow_mut().write_chr(0x0020, 0x77);
+        cart.borrow_mut().write_prg(0x8000, 0);
+        assert_eq!(bus.read_data(0x0020), 0);
+    }
+
+    #[test]
+    fn test_pattern_rows_follow_chr_writes() {
+        let cart = test_chr_ram_cartridge(vec![]).unwrap();
+
+        let mut bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+        assert_eq!(bus.pattern_row(0x1013), 0);
+
+        bus.write_data(0x1013, 0x80);
+        bus.write_data(0x101B, 0x81);
+        assert_eq!(bus.pattern_row(0x1013), 0x0300_0000_0000_0002);
+    }
+
+    #[test]
+    fn test_chr_fetch_switches_banks() {
+        // An MMC2 with the first byte of each 4 KB CHR bank set to its number.
+        let mut chr = vec![0; 0x8000];
+        for (i, bank) in chr.chunks_mut(0x1000).enumerate() {
+            bank[0] = i as u8;
+        }
+        let cart = Rc::new(RefCell::new(test_raw_cartridge(0x90, &chr)));
+        cart.borrow_mut().write_prg(0xB000, 1);
+        cart.borrow_mut().write_prg(0xC000, 2);
+        cart.borrow_mut().write_prg(0xE000, 3);
+
+        let mut bus = PPUBus::new(Rc::clone(&cart), MemoryInit::Zero);
+        assert_eq!(bus.pattern_row(0x0000), decode_row(2, 0));
+
+        // Fetching tile $FD switches the left pattern table for the next
+        // fetch, leaving the right one alone.
+        bus.observe(0x0FD8, 0);
+        assert_eq!(bus.pattern_row(0x0000), decode_row(1, 0));
+        assert_eq!(bus.read_data(0x0000), 1);
+        assert_eq!(bus.read_data(0x1000), 3);
+    }
+
+    #[test]
+    fn test_latch_fetches() {
+        assert!(is_latch_fetch(0x0FD8));
+        assert!(is_latch_fetch(0x0FEF));
+        assert!(is_latch_fetch(0x1FDB));
+        assert!(!is_latch_fetch(0x0FD0));
+        assert!(!is_latch_fetch(0x1FF8));
+        assert!(!is_latch_fetch(0x2FD8));
+
+        // Boards without latches don't watch fetches at all.
+        let cart = test_chr_ram_cartridge(vec![]).unwrap();
+        assert!(!cart.watches_chr_fetches());
+    }
+
+    #[test]
+    fn test_mirroring_follows_mapper() {
+        let cart = Rc::new(RefCell::new(test_raw_cartridge(0x10, &[])));
+        let mut bus = PPUBus::new(Rc::clone(&cart), MemoryInit::Zero);
+        bus.write_data(0x2005, 0x11);
+        bus.write_data(0x2C05, 0x22);
+
+        // Each mode set by the game takes effect on the next access.
+        for (control, reads) in [
+            (0x0C, [0x11; 4]),
+            (0x0D, [0x22; 4]),
+            (0x0E, [0x11, 0x22, 0x11, 0x22]),
+            (0x0F, [0x11, 0x11, 0x22, 0x22]),
+        ] {
+            write_mmc1_control(&cart, control);
+            for (addr, data) in [0x2005, 0x2405, 0x2805, 0x2C05].into_iter().zip(reads) {
+                assert_eq!(bus.read_data(addr), data, "control {:02X}", control);
+            }
+        }
+    }
+
+    #[test]
+    fn test_four_screen_overrides_mapper() {
+        let cart = Rc::new(RefCell::new(test_raw_cartridge(0x18, &[])));
+        write_mmc1_control(&cart, 0x0C);
+        assert_eq!(cart.borrow().mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_four_screen_nametables_are_distinct() {
+        let cart = test_cartridge(vec![], Some(Mirroring::FourScreen)).unwrap();
+
+        let mut bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            bus.write_data(addr + 5, i as u8 + 1);
+        }
+
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            assert_eq!(bus.read_data(addr + 5), i as u8 + 1);
+            assert_eq!(bus.read_data(addr + 0x1005), i as u8 + 1);
+        }
+    }
+}
@@ -1,13 +1,24 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::rc::Rc;
 
-use crate::apu::Apu;
+use crate::apu::{Apu, ChannelState, Panning, SampleClock};
 use crate::cartridge::Cartridge;
+use crate::config::EmuConfig;
 use crate::cpu::Memory;
+use crate::crash;
+use crate::debugger::Breakpoint;
+use crate::events::CoreEvent;
+use crate::inject::{Action, Injection};
 use crate::joypad::Joypad;
-use crate::ppu::NesPpu;
 use crate::ppu::Ppu;
+use crate::ppu::{FrameStatus, MemoryInit, NesPpu, RasterMarkers, Rgb, DOTS_PER_SCANLINE};
+use crate::region::Region;
+use crate::state::{read_u64, read_u8, write_u64, write_u8, SaveState};
+use crate::vs::Cabinet;
 
+use super::dma::Dma;
 use super::PPUBus;
 
 /// | Address range | Size  | Device                                                                  |
@@ -21,59 +32,452 @@ use super::PPUBus;
 /// | $4000-$4017   | $0018 | NES APU and I/O registers                                               |
 /// | $4018-$401F   | $0008 | APU and I/O functionality that is normally disabled. See CPU Test Mode. |
 /// | $4020-$FFFF   | $BFE0 | Cartridge space: PRG ROM, PRG RAM, and mapper registers (See Note)      |
+///
+/// Note: $4018-$5FFF is handed to the mapper, reads it doesn't respond to
+/// return open bus.
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
-const PRG: u16 = 0x4020;
+/// The normally disabled test registers and the start of the cartridge space
+/// are routed to the mapper, which is where boards such as the MMC5 keep
+/// their expansion registers and RAM.
+const EXPANSION: u16 = 0x4018;
 const PRG_END: u16 = 0xFFFF;
 
+/// PRG ROM, switched by the mapper in banks of at least 8 KB.
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+
+/// The APU and I/O registers share their page with the start of the cartridge
+/// space.
+const IO_PAGE_END: u16 = 0x40FF;
+const OAM_DMA: u16 = 0x4014;
+
+/// Size of each page in the address space, reads and writes are dispatched on
+/// the device mapped to the page rather than by matching address ranges.
+const PAGE_SIZE: usize = 0x100;
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
 const APU_REGISTERS: u16 = 0x4000;
 const APU_REGISTERS_END: u16 = 0x4013;
 const APU_STATUS: u16 = 0x4015;
 const APU_CHAN_ENABLE: u16 = 0x4015;
 const APU_FRAME_COUNTER: u16 = 0x4017;
 
-/// Delay betwen samples produced by the APU.
-const APU_SAMPLE_DELAY: f32 = 1.0 / 1789773.0;
+/// Debug console registers, in the normally disabled test registers. Bytes
+/// written to the first are collected as text, a write to the second asks
+/// the frontend to exit with the byte as the exit code.
+const DEBUG_CONSOLE: u16 = 0x401A;
+const DEBUG_EXIT: u16 = 0x401B;
+
+/// Devices which can be mapped into pages of the CPU address space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Device {
+    /// Internal RAM, mirrored every $0800 bytes.
+    Ram,
+
+    /// PPU registers, mirrored every 8 bytes.
+    Ppu,
+
+    /// APU and I/O registers.
+    Io,
+
+    /// Cartridge PRG ROM, PRG RAM and mapper registers.
+    Cartridge,
+
+    /// Cartridge PRG ROM at $8000-$FFFF, read from a copy of the banks the
+    /// mapper has selected rather than through the mapper. Writes still go
+    /// to the mapper.
+    PrgRom,
+
+    /// 64 KB of RAM spanning the whole address space, unmirrored, for
+    /// running raw 6502 programs without the rest of the console.
+    FlatRam,
+
+    /// Nothing connected, reads return 0 and writes are ignored.
+    Open,
+}
+
+/// Where the machine is within the current frame, see [`SystemBus::timing`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    /// Number of frames rendered, the current frame starting at vblank.
+    pub frame: u128,
+    pub scanline: i32,
+    pub dot: usize,
+
+    /// CPU cycles run since the current frame started.
+    pub frame_cycle: u64,
+    pub next_interrupt: NextInterrupt,
+}
+
+/// The next interrupt the CPU will take.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NextInterrupt {
+    /// An NMI is waiting to be taken before the next instruction.
+    NmiPending,
+
+    /// The IRQ line is asserted, and is taken once the I flag is clear.
+    Irq,
+
+    /// Vblank raises an NMI in the given number of CPU cycles.
+    Nmi(u64),
+
+    /// NMIs are disabled and nothing asserts IRQ.
+    None,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {} scanline {} dot {} cycle {}, ",
+            self.frame, self.scanline, self.dot, self.frame_cycle
+        )?;
+        match self.next_interrupt {
+            NextInterrupt::NmiPending => write!(f, "NMI pending"),
+            NextInterrupt::Irq => write!(f, "IRQ asserted"),
+            NextInterrupt::Nmi(cycles) => write!(f, "NMI in {} cycles", cycles),
+            NextInterrupt::None => write!(f, "no interrupt due"),
+        }
+    }
+}
 
 /// SystemBus abstracts a single location for data read/write, interrupts,
 /// memory mapping and PPU/CPU clock cycles.
 pub struct SystemBus<'a> {
+    /// Device mapped into each page of the address space.
+    pages: [Device; PAGES],
+
     ram: [u8; 2048],
+
+    /// Backing for [`Device::FlatRam`], only allocated once it is mapped.
+    flat_ram: Vec<u8>,
+
     cart: Rc<RefCell<Cartridge>>,
+
+    /// Backing for [`Device::PrgRom`], a copy of $8000-$FFFF which is only
+    /// allocated once it is mapped.
+    prg: Vec<u8>,
+
+    /// 8 KB PRG banks mapped when each 8 KB of the copy was loaded.
+    prg_banks: [usize; 4],
+
+    /// Cartridge bank generation the copy was last checked against.
+    bank_generation: Rc<Cell<u32>>,
+    prg_cached: u32,
+
     ppu: NesPpu<'a>,
-    pub joypad1: Joypad,
+    joypad1: Joypad,
+    joypad2: Joypad,
+
+    /// Coin slots and DIP switches, when running a Vs. System game.
+    cabinet: Option<Cabinet>,
+
+    /// Controller states waiting for the first strobe at or after the CPU
+    /// cycle they are due, with the port they are for.
+    pending_inputs: Vec<(u64, usize, u8)>,
+
+    /// Events waiting to be collected by the frontend.
+    events: Vec<CoreEvent>,
+
+    /// Last value on the CPU data bus, returned by reads nothing responds to.
+    open_bus: u8,
+
+    /// Whether the debug console is mapped, and the line written so far.
+    debug_console: bool,
+    debug_line: Vec<u8>,
+
+    /// CPU cycles until the current instruction's writes reach the bus.
+    write_cycle: u8,
+
+    /// Whether PPUMASK writes wait for the write cycle, see [`EmuConfig`].
+    mask_delay: bool,
+
+    /// Debug actions fired at a PPU position every frame.
+    injections: Vec<Injection>,
+
+    /// Addresses the debugger breaks on accesses to, and the first access
+    /// to one since it last looked.
+    watch_reads: Vec<u16>,
+    watch_writes: Vec<u16>,
+    watch_hit: Option<Breakpoint>,
+
+    /// CPU cycles run since power on.
+    cycles: u64,
+    dma: Dma,
+
+    region: Region,
+    ppu_dots: u8,
 
     apu: Apu,
-    apu_interval: f32,
-    apu_sample_time: f32,
+    sample_clock: SampleClock,
     apu_samples: Vec<f32>,
+    stereo: bool,
+
+    /// Outputs of each APU channel taken alongside each sample, when
+    /// recording stems.
+    stem_samples: Vec<[f32; 5]>,
+    stems: bool,
 }
 
 impl<'a> SystemBus<'a> {
     /// Returns an instantiated Bus.
-    pub fn new<F>(cart: Rc<RefCell<Cartridge>>, audio_sample_rate: f32, render_callback: F) -> Self
+    pub fn new<F, R>(
+        cart: Rc<RefCell<Cartridge>>,
+        audio_sample_rate: f32,
+        render_callback: F,
+    ) -> Self
     where
-        F: FnMut(&[u8]) + 'a,
+        F: FnMut(&[u8]) -> R + 'a,
+        R: Into<FrameStatus>,
     {
-        let ppu_bus = PPUBus::new(Rc::clone(&cart));
-        let ppu = NesPpu::new(Box::new(ppu_bus), Box::new(render_callback));
+        Self::with_memory_init(cart, audio_sample_rate, MemoryInit::Zero, render_callback)
+    }
 
-        SystemBus {
+    /// Returns an instantiated Bus with the PPU memory initialised according
+    /// to the given policy.
+    pub fn with_memory_init<F, R>(
+        cart: Rc<RefCell<Cartridge>>,
+        audio_sample_rate: f32,
+        init: MemoryInit,
+        render_callback: F,
+    ) -> Self
+    where
+        F: FnMut(&[u8]) -> R + 'a,
+        R: Into<FrameStatus>,
+    {
+        let events = vec![cart.borrow().loaded()];
+        let bank_generation = cart.borrow().bank_generation();
+        let cacheable_prg = cart.borrow().cacheable_prg();
+        crash::record_cartridge(&cart);
+        let ppu_bus = PPUBus::new(Rc::clone(&cart), init);
+        let ppu = NesPpu::new(Box::new(ppu_bus), init, Box::new(render_callback));
+
+        let mut bus = SystemBus {
+            pages: [Device::Open; PAGES],
             ram: [0; 2048],
+            flat_ram: Vec::new(),
             cart,
+            prg: Vec::new(),
+            prg_banks: [usize::MAX; 4],
+            prg_cached: bank_generation.get().wrapping_sub(1),
+            bank_generation,
             ppu,
             joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            cabinet: None,
+            pending_inputs: Vec::new(),
+
+            events,
+            open_bus: 0,
+            debug_console: false,
+            debug_line: Vec::new(),
+            write_cycle: 0,
+            mask_delay: true,
+            injections: Vec::new(),
+            watch_reads: Vec::new(),
+            watch_writes: Vec::new(),
+            watch_hit: None,
+
+            cycles: 0,
+            dma: Dma::default(),
+            region: Region::Ntsc,
+            ppu_dots: 0,
 
             apu: Apu::new(audio_sample_rate),
-            apu_interval: 0.0,
-            apu_sample_time: 1.0 / audio_sample_rate,
+            sample_clock: SampleClock::new(audio_sample_rate),
             apu_samples: Vec::new(),
+            stereo: false,
+            stem_samples: Vec::new(),
+            stems: false,
+        };
+
+        bus.map(RAM, RAM_MIRRORS_END, Device::Ram);
+        bus.map(PPU_REGISTERS, PPU_REGISTERS_MIRRORS_END, Device::Ppu);
+        bus.map(APU_REGISTERS, IO_PAGE_END, Device::Io);
+        bus.map(IO_PAGE_END + 1, PRG_END, Device::Cartridge);
+        if cacheable_prg {
+            bus.map(PRG_ROM, PRG_END, Device::PrgRom);
+        }
+        bus
+    }
+
+    /// Maps the pages covering the given address range to a device. Ranges
+    /// are widened to whole pages.
+    pub fn map(&mut self, start: u16, end: u16, device: Device) {
+        let pages = start as usize / PAGE_SIZE..=end as usize / PAGE_SIZE;
+        self.pages[pages].fill(device);
+
+        match device {
+            Device::FlatRam => self.flat_ram.resize(0x10000, 0),
+            Device::PrgRom => self.prg.resize(0x8000, 0),
+            _ => {}
+        }
+    }
+
+    /// Reloads the parts of the PRG ROM copy whose banks have changed, if the
+    /// mapper may have switched banks since it was last checked. Mapper
+    /// writes which only set up IRQs or CHR banks cost a check of 4 banks.
+    fn refresh_prg(&mut self) {
+        let generation = self.bank_generation.get();
+        if self.prg_cached == generation {
+            return;
+        }
+        self.prg_cached = generation;
+
+        let cart = self.cart.borrow();
+        for (slot, bank) in self.prg_banks.iter_mut().enumerate() {
+            let start = PRG_ROM + (slot * PRG_BANK_SIZE) as u16;
+            if cart.prg_bank(start) == *bank {
+                continue;
+            }
+            *bank = cart.prg_bank(start);
+
+            let copy = &mut self.prg[slot * PRG_BANK_SIZE..][..PRG_BANK_SIZE];
+            for (i, byte) in copy.iter_mut().enumerate() {
+                *byte = cart.read_prg(start + i as u16).unwrap_or(0);
+            }
+        }
+    }
+
+    /// Reads from the PPU registers, which are mirrored every 8 bytes.
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        match addr & 0x2007 {
+            0x2002 => self.ppu.read_status(),
+            0x2004 => self.ppu.read_oam_data(),
+            0x2007 => self.ppu.read_data(),
+
+            // Write only registers.
+            _ => 0,
+        }
+    }
+
+    /// Writes to the PPU registers, which are mirrored every 8 bytes.
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        match addr & 0x2007 {
+            PPU_REGISTERS => self.ppu.write_ctrl(data),
+            0x2001 => {
+                let (dots, cycles) = self.region.ppu_clock_ratio();
+                let delay = match self.mask_delay {
+                    true => self.write_cycle as u32 * dots as u32 / cycles as u32,
+                    false => 0,
+                };
+                self.ppu.write_mask_after(data, delay);
+            }
+            0x2003 => self.ppu.write_oam_addr(data),
+            0x2004 => self.ppu.write_oam_data(data),
+            0x2005 => self.ppu.write_scroll(data),
+            0x2006 => self.ppu.write_addr(data),
+            0x2007 => self.ppu.write_data(data),
+
+            // PPUSTATUS is read-only, writes only refresh the open bus latch.
+            _ => {}
+        }
+    }
+
+    /// Reads from the APU and I/O registers. The rest of the page belongs to
+    /// the cartridge, reads nothing on the cartridge responds to return open
+    /// bus.
+    fn read_io(&mut self, addr: u16) -> u8 {
+        match addr {
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS => self.apu.read(addr),
+
+            0x4016 => self.joypad1.read() | self.cabinet.as_ref().map_or(0, Cabinet::port1),
+            0x4017 => self.joypad2.read() | self.cabinet.as_ref().map_or(0, Cabinet::port2),
+
+            EXPANSION..=PRG_END => self.cart.borrow().read_prg(addr).unwrap_or(self.open_bus),
+
+            // OAMDMA is write only.
+            _ => 0,
+        }
+    }
+
+    /// Writes to the APU and I/O registers. The rest of the page belongs to
+    /// the cartridge.
+    fn write_io(&mut self, addr: u16, data: u8) {
+        match addr {
+            APU_REGISTERS..=APU_REGISTERS_END | APU_CHAN_ENABLE | APU_FRAME_COUNTER => {
+                self.apu.write(addr, data)
+            }
+
+            OAM_DMA => {
+                let mut buffer: [u8; 256] = [0; 256];
+                let hi: u16 = (data as u16) << 8;
+                for i in 0..256u16 {
+                    buffer[i as usize] = self.mem_read_byte(hi + i);
+                }
+
+                self.ppu.write_oam_dma(&buffer);
+                self.dma.start_oam();
+
+                // DMC can need a new sample during DMA, which lengthens it.
+                //
+                // See: https://www.nesdev.org/wiki/APU_DMC
+                self.update_dmc_sample();
+            }
+            // The strobe is wired to both controller ports.
+            0x4016 => {
+                if data & 1 != 0 {
+                    self.apply_pending_inputs();
+                }
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+                self.cart.borrow_mut().write_strobe(data);
+            }
+
+            DEBUG_CONSOLE if self.debug_console => match data {
+                b'\n' => self.flush_debug_line(),
+                _ => self.debug_line.push(data),
+            },
+            DEBUG_EXIT if self.debug_console => {
+                if !self.debug_line.is_empty() {
+                    self.flush_debug_line();
+                }
+                self.push_event(CoreEvent::DebugExit(data));
+            }
+
+            EXPANSION..=PRG_END => self.cart.borrow_mut().write_prg(addr, data),
+
+            // APU status is read only.
+            _ => {}
         }
     }
 
+    /// Connects the coin slots and DIP switches of a Vs. System cabinet,
+    /// with the given switches, or disconnects them.
+    pub fn set_vs_system(&mut self, dip_switches: Option<u8>) {
+        self.cabinet = dip_switches.map(Cabinet::new);
+    }
+
+    /// Sets whether the given VS_* coin slot or service button bits are held.
+    /// Ignored unless a Vs. System cabinet is connected.
+    pub fn set_cabinet_pressed(&mut self, buttons: u8, pressed: bool) {
+        if let Some(cabinet) = self.cabinet.as_mut() {
+            cabinet.set_pressed(buttons, pressed);
+        }
+    }
+
+    /// Sets the colours the PPU draws each palette index with.
+    pub fn set_palette(&mut self, palette: &[Rgb; 0x40]) {
+        self.ppu.set_palette(palette);
+    }
+
+    /// Maps or unmaps the debug console, see [`CoreEvent::DebugOutput`].
+    pub fn set_debug_console(&mut self, enabled: bool) {
+        self.debug_console = enabled;
+        self.debug_line.clear();
+    }
+
+    /// Queues the line written to the debug console.
+    fn flush_debug_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.debug_line).into_owned();
+        self.debug_line.clear();
+        self.push_event(CoreEvent::DebugOutput(line));
+    }
+
     /// Updates the APU DMC chanel with a new sample if it needs one.
     fn update_dmc_sample(&mut self) {
         if self.apu.need_dmc_sample() {
@@ -81,31 +485,111 @@ impl<'a> SystemBus<'a> {
             let sample = self.mem_read_byte(addr);
 
             self.apu.set_dmc_sample(sample);
-
-            self.tick(4);
+            self.dma.fetch_dmc();
         }
     }
 
+    /// Sets the number of CPU cycles before the writes of the instruction
+    /// being executed reach the bus. The CPU runs each instruction at once
+    /// and ticks the bus afterwards, but stores land on the instruction's
+    /// last cycle, so writes which affect rendering are deferred to match.
+    pub fn set_write_cycle(&mut self, cycles: u8) {
+        self.write_cycle = cycles;
+    }
+
+    /// Returns the number of cycles the CPU is halted for by DMA started by
+    /// the instruction just run, see [`Dma::take_stall`].
+    pub fn take_dma_stall(&mut self) -> u16 {
+        self.dma.take_stall(self.cycles)
+    }
+
     /// For every CPU tick, run the PPU and APU appropriately.
     pub fn tick(&mut self, cycles: u8) {
         for _ in 0..cycles {
-            // PPU runs three times faster than CPU.
-            for _ in 0..3 {
-                self.ppu.clock();
+            self.cycles += 1;
+            self.write_cycle = self.write_cycle.saturating_sub(1);
+
+            // PPU runs three times faster than CPU on NTSC, and 3.2 times on
+            // PAL, so the remainder is carried between cycles.
+            let (dots, cycles) = self.region.ppu_clock_ratio();
+            self.ppu_dots += dots;
+            let due = self.ppu_dots / cycles;
+            self.ppu_dots %= cycles;
+
+            // Injections fire on exact dots, so the PPU is clocked one at a
+            // time while there are any.
+            if self.injections.is_empty() {
+                self.ppu.clock_dots(due as u32);
+            } else {
+                for _ in 0..due {
+                    self.ppu.clock();
+                    self.fire_injections();
+                }
             }
 
             // The APU runs at the same speed as the CPU.
             self.apu.clock();
+            self.cart.borrow_mut().clock_cpu();
             self.update_dmc_sample();
 
-            // Ensure the APU stays in sync.
-            self.apu_interval += APU_SAMPLE_DELAY;
+            if self.sample_clock.clock() {
+                if self.stereo {
+                    let (left, right) = self.apu.output_stereo();
+                    self.apu_samples.extend([left, right]);
+                } else {
+                    let sample = self.apu.output();
+                    self.apu_samples.push(sample);
+                }
+                if self.stems {
+                    self.stem_samples.push(self.apu.channel_outputs());
+                }
+            }
+        }
+    }
+
+    /// Sets the debug actions fired at a PPU scanline and dot every frame,
+    /// see [`Injection`].
+    pub fn set_injections(&mut self, injections: Vec<Injection>) {
+        self.injections = injections;
+    }
+
+    /// Sets the addresses whose reads and writes are recorded for the
+    /// debugger from the given breakpoints. Other breakpoints are ignored.
+    pub fn set_watchpoints(&mut self, breakpoints: &[Breakpoint]) {
+        self.watch_reads.clear();
+        self.watch_writes.clear();
+        self.watch_hit = None;
+        for breakpoint in breakpoints {
+            match *breakpoint {
+                Breakpoint::Read(addr) => self.watch_reads.push(addr),
+                Breakpoint::Write(addr) => self.watch_writes.push(addr),
+                Breakpoint::Pc(_) => {}
+            }
+        }
+    }
+
+    /// Returns the first watched access since the last call, if any.
+    pub fn take_watch_hit(&mut self) -> Option<Breakpoint> {
+        self.watch_hit.take()
+    }
 
-            if self.apu_interval >= self.apu_sample_time {
-                self.apu_interval -= self.apu_sample_time;
+    /// Fires the injections at the PPU's current position. Writes land on
+    /// the dot as if the CPU had stored them at that moment.
+    fn fire_injections(&mut self) {
+        let position = self.ppu.position();
+        for i in 0..self.injections.len() {
+            let injection = self.injections[i];
+            if (injection.scanline, injection.dot) != position {
+                continue;
+            }
 
-                let sample = self.apu.output();
-                self.apu_samples.push(sample);
+            match injection.action {
+                Action::Nmi => self.ppu.nmi_interrupt = Some(true),
+                Action::Write(addr, data) => {
+                    let write_cycle = std::mem::take(&mut self.write_cycle);
+                    self.mem_write_byte(addr, data);
+                    self.write_cycle = write_cycle;
+                }
             }
         }
     }
@@ -115,116 +599,398 @@ impl<'a> SystemBus<'a> {
         self.ppu.poll_nmi()
     }
 
+    /// Returns true if the PPU has an NMI waiting for the CPU, without
+    /// acknowledging it.
+    pub fn nmi_pending(&self) -> bool {
+        self.ppu.nmi_interrupt.is_some()
+    }
+
+    /// Returns true if the APU or cartridge is asserting an IRQ. The IRQ line
+    /// is level triggered, so it stays asserted until acknowledged.
+    pub fn irq_status(&self) -> bool {
+        self.apu.irq_pending() || self.cart.borrow().irq_pending()
+    }
+
+    /// Returns a copy of the cartridge's PRG RAM, which holds battery saves,
+    /// or an empty vector if it has none.
+    pub fn prg_ram(&self) -> Vec<u8> {
+        self.cart.borrow().prg_ram().to_vec()
+    }
+
+    /// Replaces the contents of the cartridge's PRG RAM, which must be the
+    /// same size as the given data.
+    pub fn set_prg_ram(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut cart = self.cart.borrow_mut();
+        let ram = cart.prg_ram_mut();
+        if ram.is_empty() {
+            return Err(String::from("the cartridge has no PRG RAM"));
+        }
+        if ram.len() != data.len() {
+            return Err(format!(
+                "expected {} bytes of PRG RAM, got {}",
+                ram.len(),
+                data.len()
+            ));
+        }
+
+        ram.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Returns the number of CPU cycles run since power on.
+    pub fn cpu_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the number of rendered frames the render callback dropped.
+    pub fn dropped_frames(&self) -> u64 {
+        self.ppu.dropped_frames()
+    }
+
     /// Returns the number of rendered frames from the PPU.
     pub fn ppu_frame_count(&self) -> u128 {
         self.ppu.read_frame_count()
     }
 
-    /// Returns the audio samples generated by the APU.
-    pub fn audio_samples(&mut self) -> Vec<f32> {
-        std::mem::take(self.apu_samples.as_mut())
+    /// Returns the TV system the bus is timed for.
+    pub fn region(&self) -> Region {
+        self.region
     }
-}
 
-impl Memory for SystemBus<'_> {
-    fn mem_read_byte(&mut self, addr: u16) -> u8 {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00000111_11111111;
-                self.ram[mirror_down_addr as usize]
-            }
-            PPU_REGISTERS | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => 0,
-            0x2002 => self.ppu.read_status(),
-            0x2004 => self.ppu.read_oam_data(),
-            0x2007 => self.ppu.read_data(),
+    /// Sets which emulation behaviours are on, see [`EmuConfig`].
+    pub fn set_config(&mut self, config: EmuConfig) {
+        self.mask_delay = config.mask_delay;
+        self.ppu.set_config(config);
+    }
 
-            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS => self.apu.read(addr),
+    /// Sets the TV system the PPU and APU are timed for.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_dots = 0;
+        self.sample_clock.set_region(region);
+        self.apu.set_region(region);
+        self.ppu.set_region(region);
+    }
 
-            0x4016 => self.joypad1.read(),
+    /// Resets the devices on the bus when the reset button is pressed. The
+    /// APU channels are silenced and PPUCTRL and PPUMASK are cleared.
+    pub fn reset(&mut self) {
+        self.apu.write(APU_CHAN_ENABLE, 0);
+        self.ppu.write_ctrl(0);
+        self.ppu.write_mask(0);
+    }
 
-            0x4017 => {
-                // ignore joypad 2
-                0
-            }
-            0x2008..=PPU_REGISTERS_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_read_byte(mirror_down_addr)
-            }
-            PRG..=PRG_END => self.cart.borrow().read_prg(addr),
+    /// Puts the PPU into its power-on state, see [`NesPpu::power_up`].
+    pub fn power_up(&mut self, seed: Option<u64>) {
+        self.ppu.power_up(seed)
+    }
+
+    /// Returns where the machine is within the current frame and the next
+    /// interrupt due, for stepping through it while paused.
+    pub fn timing(&self) -> Timing {
+        // PPU dots are converted to whole CPU cycles, rounding up so an NMI
+        // is never reported as due before it is.
+        let (dots, cycles) = self.region.ppu_clock_ratio();
+        let (dots, cycles) = (dots as u64, cycles as u64);
+        let (scanline, dot) = self.ppu.position();
+
+        let next_interrupt = if self.nmi_pending() {
+            NextInterrupt::NmiPending
+        } else if self.irq_status() {
+            NextInterrupt::Irq
+        } else if self.ppu.nmi_enabled() {
+            NextInterrupt::Nmi((self.ppu.dots_until_vblank() * cycles).div_ceil(dots))
+        } else {
+            NextInterrupt::None
+        };
+
+        Timing {
+            frame: self.ppu_frame_count(),
+            scanline,
+            dot,
+            frame_cycle: self.ppu.frame_dots() * cycles / dots,
+            next_interrupt,
+        }
+    }
+
+    /// Returns the current scanline and cycle of the PPU.
+    pub fn ppu_position(&self) -> (i32, usize) {
+        self.ppu.position()
+    }
+
+    /// Returns the byte at the given PPU address, see [`NesPpu::peek`].
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.ppu.peek(addr)
+    }
+
+    /// Returns the state of each APU channel, see [`Apu::channels`].
+    pub fn apu_channels(&self) -> [ChannelState; 5] {
+        self.apu.channels()
+    }
+
+    /// Mutes or unmutes an APU channel, see [`Apu::toggle_mute`].
+    pub fn toggle_apu_mute(&mut self, channel: usize) -> bool {
+        self.apu.toggle_mute(channel)
+    }
+
+    /// Toggles keeping recent APU channel levels for the visualiser.
+    pub fn toggle_apu_scope(&mut self) {
+        self.apu.set_scope(self.apu.scope().is_none())
+    }
+
+    /// Draws the APU channel visualiser over the bottom of the RGB24 frame,
+    /// if shown, see
+    /// [`crate::apu::Scope::draw`].
+    pub fn draw_apu_scope(&self, frame: &mut [u8]) {
+        if let Some(scope) = self.apu.scope() {
+            scope.draw(self.apu.muted(), frame);
+        }
+    }
+
+    /// Returns the CPU work RAM.
+    pub fn ram(&self) -> &[u8; 2048] {
+        &self.ram
+    }
+
+    /// Writes the byte to the given PPU address, see [`NesPpu::poke`].
+    pub fn ppu_poke(&mut self, addr: u16, value: u8) {
+        self.ppu.poke(addr, value)
+    }
+
+    /// Returns the PPU object attribute memory.
+    pub fn oam(&self) -> &[u8; 256] {
+        self.ppu.oam()
+    }
+
+    /// Returns the PPU palette RAM.
+    pub fn palette_ram(&self) -> [u8; 32] {
+        self.ppu.palette_ram()
+    }
+
+    /// Toggles the PPU priority debug render mode.
+    pub fn toggle_priority_debug(&mut self) {
+        self.ppu.toggle_priority_debug()
+    }
+
+    /// Sets the raster markers drawn over each frame, or None to draw none.
+    pub fn set_raster_overlay(&mut self, markers: Option<RasterMarkers>) {
+        self.ppu.set_raster_overlay(markers)
+    }
 
+    /// Toggles the raster overlay.
+    pub fn toggle_raster_overlay(&mut self) {
+        self.ppu.toggle_raster_overlay()
+    }
+
+    /// Toggles the sprite bounding boxes.
+    pub fn toggle_sprite_boxes(&mut self) {
+        self.ppu.toggle_sprite_boxes()
+    }
+
+    /// Enables stereo output with the given pan positions, or mono output if
+    /// none are provided. Stereo samples are interleaved left then right.
+    pub fn set_stereo(&mut self, panning: Option<Panning>) {
+        self.stereo = panning.is_some();
+        if let Some(panning) = panning {
+            self.apu.set_panning(panning);
+        }
+    }
+
+    /// Sets whether the output of each APU channel is kept alongside the
+    /// mixed samples, see [`SystemBus::drain_stem_samples`].
+    pub fn set_stems(&mut self, stems: bool) {
+        self.stems = stems;
+    }
+
+    /// Speeds up or slows down the audio sample rate, see
+    /// [`SampleClock::set_adjust`].
+    pub fn set_sample_rate_adjust(&mut self, adjust: i32) {
+        self.sample_clock.set_adjust(adjust);
+    }
+
+    /// Returns the pressed state of every button of the controller in the given
+    /// port, numbered from 0. Unconnected ports report no buttons pressed.
+    pub fn controller_state(&self, port: usize) -> u8 {
+        match port {
+            0 => self.joypad1.buttons(),
+            1 => self.joypad2.buttons(),
             _ => 0,
         }
     }
 
-    fn mem_write_byte(&mut self, addr: u16, data: u8) {
-        self.ppu.refresh_open_bus(data);
+    /// Sets the pressed state of every button of the controller in the given
+    /// port at once, using the JOYPAD_* bits. Writes to unconnected ports are
+    /// ignored.
+    ///
+    /// This is the input entry point for anything driving the emulator a
+    /// frame at a time, such as movie playback or the frame pipe.
+    pub fn set_controller_state(&mut self, port: usize, buttons: u8) {
+        self.pending_inputs.retain(|(_, p, _)| *p != port);
+        match port {
+            0 => self.joypad1.set_buttons(buttons),
+            1 => self.joypad2.set_buttons(buttons),
+            _ => {}
+        }
+    }
 
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b11111111111;
-                self.ram[mirror_down_addr as usize] = data;
+    /// Queues a state for the controller in the given port which takes effect
+    /// at the first strobe once the given number of scanlines have been
+    /// rendered, rather than straight away. This lets a change which arrived
+    /// part way through a frame be seen by the game part way through it too.
+    pub fn queue_controller_state(&mut self, port: usize, buttons: u8, scanlines: u16) {
+        let (dots, cycles) = self.region.ppu_clock_ratio();
+        let due = scanlines as u64 * DOTS_PER_SCANLINE * cycles as u64 / dots as u64;
+        self.pending_inputs.push((self.cycles + due, port, buttons));
+    }
+
+    /// Applies the queued controller states which are due, in the order they
+    /// were queued.
+    fn apply_pending_inputs(&mut self) {
+        let now = self.cycles;
+        let mut i = 0;
+        while i < self.pending_inputs.len() {
+            let (due, port, buttons) = self.pending_inputs[i];
+            if due > now {
+                i += 1;
+                continue;
             }
-            PPU_REGISTERS => {
-                self.ppu.write_ctrl(data);
+            self.pending_inputs.remove(i);
+            match port {
+                0 => self.joypad1.set_buttons(buttons),
+                1 => self.joypad2.set_buttons(buttons),
+                _ => {}
             }
+        }
+    }
 
-            0x2001 => {
-                self.ppu.write_mask(data);
-            }
-            0x2002 => panic!("attempt to write to PPU status register"),
+    /// Returns the average number of samples generated per frame, per channel
+    /// when stereo, see [`SampleClock::samples_per_frame`].
+    pub fn samples_per_frame(&self) -> f64 {
+        self.sample_clock.samples_per_frame()
+    }
 
-            0x2003 => {
-                self.ppu.write_oam_addr(data);
-            }
-            0x2004 => {
-                self.ppu.write_oam_data(data);
-            }
-            0x2005 => {
-                self.ppu.write_scroll(data);
-            }
-            0x2006 => {
-                self.ppu.write_addr(data);
-            }
-            0x2007 => {
-                self.ppu.write_data(data);
-            }
-            0x2008..=PPU_REGISTERS_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_write_byte(mirror_down_addr, data);
-            }
+    /// Queues an event for the frontend.
+    pub fn push_event(&mut self, event: CoreEvent) {
+        self.events.push(event);
+    }
 
-            APU_REGISTERS..=APU_REGISTERS_END | APU_CHAN_ENABLE | APU_FRAME_COUNTER => {
-                self.apu.write(addr, data)
-            }
+    /// Returns the events queued since the last call, starting with the
+    /// loading of the ROM.
+    pub fn events(&mut self) -> Vec<CoreEvent> {
+        std::mem::take(&mut self.events)
+    }
 
-            0x4014 => {
-                let mut buffer: [u8; 256] = [0; 256];
-                let hi: u16 = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.mem_read_byte(hi + i);
-                }
+    /// Returns the audio samples generated by the APU.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        std::mem::take(self.apu_samples.as_mut())
+    }
 
-                self.ppu.write_oam_dma(&buffer);
+    /// Moves the audio samples generated by the APU onto the end of the given
+    /// buffer. Unlike [`SystemBus::audio_samples`], both buffers keep their
+    /// capacity, so once they have grown to a frame's worth of samples no
+    /// more allocations are made.
+    pub fn drain_audio_samples(&mut self, out: &mut Vec<f32>) {
+        out.extend_from_slice(&self.apu_samples);
+        self.apu_samples.clear();
+    }
 
-                // DMC can need a new sample during DMA.
-                //
-                // See: https://www.nesdev.org/wiki/APU_DMC
-                self.update_dmc_sample();
-            }
-            0x4016 => {
-                self.joypad1.write(data);
+    /// Moves the outputs of pulse 1, pulse 2, triangle, noise and DMC
+    /// channels taken with each mixed sample onto the end of the given
+    /// buffer, so they line up with the samples of
+    /// [`SystemBus::drain_audio_samples`].
+    pub fn drain_stem_samples(&mut self, out: &mut Vec<[f32; 5]>) {
+        out.extend_from_slice(&self.stem_samples);
+        self.stem_samples.clear();
+    }
+}
+
+impl Memory for SystemBus<'_> {
+    fn mem_read_byte(&mut self, addr: u16) -> u8 {
+        let data = match self.pages[addr as usize / PAGE_SIZE] {
+            Device::Ram => self.ram[addr as usize & 0x07FF],
+            Device::Ppu => self.read_ppu(addr),
+            Device::Io => self.read_io(addr),
+            Device::Cartridge => self.cart.borrow().read_prg(addr).unwrap_or(self.open_bus),
+            Device::PrgRom => {
+                self.refresh_prg();
+                self.prg[addr as usize & 0x7FFF]
             }
+            Device::FlatRam => self.flat_ram[addr as usize],
+            Device::Open => 0,
+        };
 
-            PRG..=PRG_END => self.cart.borrow_mut().write_prg(addr, data),
+        // With the debugger off the lists are empty, costing a single branch.
+        if !self.watch_reads.is_empty() && self.watch_reads.contains(&addr) {
+            self.watch_hit.get_or_insert(Breakpoint::Read(addr));
+        }
 
-            _ => unreachable!("unreachable write at: {}", addr),
+        self.open_bus = data;
+        data
+    }
+
+    fn mem_write_byte(&mut self, addr: u16, data: u8) {
+        if !self.watch_writes.is_empty() && self.watch_writes.contains(&addr) {
+            self.watch_hit.get_or_insert(Breakpoint::Write(addr));
         }
+
+        self.open_bus = data;
+        self.ppu.refresh_open_bus(data);
+
+        match self.pages[addr as usize / PAGE_SIZE] {
+            Device::Ram => self.ram[addr as usize & 0x07FF] = data,
+            Device::Ppu => self.write_ppu(addr, data),
+            Device::Io => self.write_io(addr, data),
+
+            // Writes to the cartridge space are forwarded to the mapper, this is
+            // how bank switching registers are configured.
+            Device::Cartridge | Device::PrgRom => self.cart.borrow_mut().write_prg(addr, data),
+            Device::FlatRam => self.flat_ram[addr as usize] = data,
+            Device::Open => {}
+        }
+    }
+}
+
+impl SaveState for SystemBus<'_> {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.ram)?;
+        write_u8(w, self.open_bus)?;
+        write_u64(w, self.cycles)?;
+        write_u8(w, self.ppu_dots)?;
+        self.sample_clock.save_state(w)?;
+        self.dma.save_state(w)?;
+
+        self.cart.borrow().save_state(w)?;
+        self.ppu.save_state(w)?;
+        self.apu.save_state(w)?;
+        self.joypad1.save_state(w)?;
+        self.joypad2.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        // Queued controller states were timed against the old cycle count.
+        self.pending_inputs.clear();
+        r.read_exact(&mut self.ram)?;
+        self.open_bus = read_u8(r)?;
+        self.cycles = read_u64(r)?;
+        self.ppu_dots = read_u8(r)?;
+        self.sample_clock.load_state(r)?;
+        self.dma.load_state(r)?;
+
+        self.cart.borrow_mut().load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.joypad1.load_state(r)?;
+        self.joypad2.load_state(r)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use crate::cartridge::tests::test_cartridge;
+    use crate::joypad::{JOYPAD_BUTTON_A, JOYPAD_START, JOYPAD_UP};
+    use crate::vs::{VS_COIN_1, VS_COIN_2};
 
     use super::*;
 
@@ -236,4 +1002,357 @@ mod tests {
         bus.mem_write_byte(0x01, 0x55);
         assert_eq!(bus.mem_read_byte(0x01), 0x55);
     }
+
+    #[test]
+    fn test_map_device() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x0801, 0x55);
+        assert_eq!(bus.mem_read_byte(0x1801), 0x55);
+
+        // Unmapping the upper RAM mirrors leaves the rest in place.
+        bus.map(0x1000, 0x1FFF, Device::Open);
+        assert_eq!(bus.mem_read_byte(0x0001), 0x55);
+        assert_eq!(bus.mem_read_byte(0x1801), 0);
+    }
+
+    #[test]
+    fn test_mem_write_to_ppu_status_is_ignored() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x2002, 0x1F);
+        bus.mem_write_byte(0x3FFA, 0x1F);
+
+        // Only the open bus bits are refreshed by the write.
+        assert_eq!(bus.mem_read_byte(0x2002), 0x1F);
+    }
+
+    #[test]
+    fn test_expansion_area_without_mapper_registers_is_open_bus() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        for addr in [0x4018, 0x4020, 0x5FFF] {
+            bus.mem_write_byte(0x01, addr as u8);
+            assert_eq!(bus.mem_read_byte(addr), addr as u8);
+        }
+
+        bus.mem_write_byte(0x4018, 0x55);
+        assert_eq!(bus.mem_read_byte(0x4018), 0x55);
+    }
+
+    #[test]
+    fn test_injections_fire_at_their_position() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_injections(vec![
+            "10,100:0010=AB".parse().unwrap(),
+            "20,0:nmi".parse().unwrap(),
+        ]);
+
+        // Scanline 10 dot 100 is 3,510 dots, or 1,170 CPU cycles, in.
+        let run = |bus: &mut SystemBus, cycles| (0..cycles).for_each(|_| bus.tick(1));
+        run(&mut bus, 1169);
+        assert_eq!(bus.ram()[0x10], 0);
+        run(&mut bus, 1);
+        assert_eq!(bus.ram()[0x10], 0xAB);
+
+        // NMIs fire even while disabled in PPUCTRL.
+        assert!(!bus.nmi_pending());
+        run(&mut bus, 1200);
+        assert!(bus.nmi_pending());
+    }
+
+    #[test]
+    fn test_cartridge_open_bus() {
+        // UxROM has no PRG RAM.
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x20];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let cart = Cartridge::new(&rom).unwrap();
+
+        // Reads nothing responds to return the last value on the data bus.
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x01, 0x55);
+        assert_eq!(bus.mem_read_byte(0x6000), 0x55);
+        assert_eq!(bus.mem_read_byte(0x01), 0x55);
+        bus.mem_write_byte(0x01, 0x12);
+        assert_eq!(bus.mem_read_byte(0x7FFF), 0x12);
+    }
+
+    #[test]
+    fn test_events() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        assert!(matches!(
+            bus.events()[..],
+            [CoreEvent::RomLoaded { mapper: 0, .. }]
+        ));
+        assert!(bus.events().is_empty());
+
+        bus.push_event(CoreEvent::StateLoaded);
+        assert_eq!(bus.events(), [CoreEvent::StateLoaded]);
+    }
+
+    #[test]
+    fn test_debug_console() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.events();
+
+        // Writes are ignored until the console is enabled.
+        bus.mem_write_byte(0x401B, 1);
+        assert!(bus.events().is_empty());
+
+        bus.set_debug_console(true);
+        for byte in b"ok\npartial" {
+            bus.mem_write_byte(0x401A, *byte);
+        }
+        bus.mem_write_byte(0x401B, 3);
+        assert_eq!(
+            bus.events(),
+            [
+                CoreEvent::DebugOutput(String::from("ok")),
+                CoreEvent::DebugOutput(String::from("partial")),
+                CoreEvent::DebugExit(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dropped_frames() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        // Drop every other frame.
+        let mut busy = false;
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {
+            busy = !busy;
+            match busy {
+                true => FrameStatus::Dropped,
+                false => FrameStatus::Accepted,
+            }
+        });
+        while bus.ppu_frame_count() < 3 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.dropped_frames(), 2);
+    }
+
+    #[test]
+    fn test_set_controller_state() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_controller_state(0, JOYPAD_BUTTON_A | JOYPAD_UP);
+        assert_eq!(bus.controller_state(0), JOYPAD_BUTTON_A | JOYPAD_UP);
+
+        // The whole state is read back serially after a strobe.
+        bus.mem_write_byte(0x4016, 1);
+        bus.mem_write_byte(0x4016, 0);
+        let bits: Vec<u8> = (0..8).map(|_| bus.mem_read_byte(0x4016)).collect();
+        assert_eq!(bits, [1, 0, 0, 0, 1, 0, 0, 0]);
+
+        // The second port is strobed along with the first.
+        bus.set_controller_state(1, JOYPAD_START);
+        assert_eq!(bus.controller_state(1), JOYPAD_START);
+        bus.mem_write_byte(0x4016, 1);
+        bus.mem_write_byte(0x4016, 0);
+        let bits: Vec<u8> = (0..8).map(|_| bus.mem_read_byte(0x4017)).collect();
+        assert_eq!(bits, [0, 0, 0, 1, 0, 0, 0, 0]);
+
+        // Further ports aren't connected.
+        bus.set_controller_state(2, 0xFF);
+        assert_eq!(bus.controller_state(2), 0);
+    }
+
+    #[test]
+    fn test_vs_system() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_cabinet_pressed(VS_COIN_1, true);
+        assert_eq!(bus.mem_read_byte(0x4016), 0);
+
+        // The cabinet's inputs share the controller ports.
+        bus.set_vs_system(Some(0x81));
+        bus.set_cabinet_pressed(VS_COIN_2, true);
+        bus.set_controller_state(0, JOYPAD_BUTTON_A);
+        bus.mem_write_byte(0x4016, 1);
+        bus.mem_write_byte(0x4016, 0);
+        assert_eq!(bus.mem_read_byte(0x4016), 0x49);
+        assert_eq!(bus.mem_read_byte(0x4017), 0x80);
+    }
+
+    #[test]
+    fn test_stem_samples() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_stems(true);
+        for _ in 0..100 {
+            bus.tick(100);
+        }
+
+        // A stem sample is taken with every mixed sample.
+        let (mut samples, mut stems) = (Vec::new(), Vec::new());
+        bus.drain_audio_samples(&mut samples);
+        bus.drain_stem_samples(&mut stems);
+        assert!(!samples.is_empty());
+        assert_eq!(stems.len(), samples.len());
+    }
+
+    #[test]
+    fn test_timing() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        assert_eq!(bus.timing().next_interrupt, NextInterrupt::None);
+
+        // From the start of scanline 0, vblank begins 82,183 dots later, 27,395
+        // CPU cycles rounded up.
+        bus.mem_write_byte(0x2000, 0x80);
+        let timing = bus.timing();
+        assert_eq!((timing.scanline, timing.dot), (0, 0));
+        assert_eq!(timing.next_interrupt, NextInterrupt::Nmi(27395));
+
+        for _ in 0..27394 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.timing().next_interrupt, NextInterrupt::Nmi(1));
+        bus.tick(1);
+
+        let timing = bus.timing();
+        assert_eq!(timing.frame, 1);
+        assert_eq!(timing.frame_cycle, 0);
+        assert_eq!(timing.next_interrupt, NextInterrupt::NmiPending);
+        assert_eq!(
+            timing.to_string(),
+            "frame 1 scanline 241 dot 4 cycle 0, NMI pending"
+        );
+    }
+
+    #[test]
+    fn test_dendy_timing() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.set_region(Region::Dendy);
+
+        // Vblank begins 50 scanlines later than on NTSC, 99,232 dots or 33,078
+        // CPU cycles rounded up from the start of scanline 0.
+        bus.mem_write_byte(0x2000, 0x80);
+        assert_eq!(bus.timing().next_interrupt, NextInterrupt::Nmi(33078));
+
+        for _ in 0..33078 {
+            bus.tick(1);
+        }
+        let timing = bus.timing();
+        assert_eq!(timing.next_interrupt, NextInterrupt::NmiPending);
+        assert_eq!(timing.scanline, 291);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x6001, 0x55);
+        let mut ram = bus.prg_ram();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[1], 0x55);
+
+        ram[2] = 0xAA;
+        bus.set_prg_ram(&ram).unwrap();
+        assert_eq!(bus.mem_read_byte(0x6002), 0xAA);
+        assert!(bus.set_prg_ram(&ram[1..]).is_err());
+    }
+
+    #[test]
+    fn test_queue_controller_state() {
+        let cart = test_cartridge(vec![], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.queue_controller_state(0, JOYPAD_BUTTON_A, 0);
+        bus.queue_controller_state(0, JOYPAD_BUTTON_A | JOYPAD_UP, 10);
+
+        // Nothing changes until the game strobes the controllers.
+        assert_eq!(bus.controller_state(0), 0);
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.controller_state(0), JOYPAD_BUTTON_A);
+
+        // Ten scanlines are 3,410 dots, or 1,136 whole CPU cycles.
+        (0..5).for_each(|_| bus.tick(227));
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.controller_state(0), JOYPAD_BUTTON_A);
+        bus.tick(1);
+        bus.mem_write_byte(0x4016, 0);
+        assert_eq!(bus.controller_state(0), JOYPAD_BUTTON_A);
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.controller_state(0), JOYPAD_BUTTON_A | JOYPAD_UP);
+
+        // Setting the state directly drops anything queued for the port.
+        bus.queue_controller_state(1, JOYPAD_START, 0);
+        bus.set_controller_state(1, JOYPAD_UP);
+        bus.mem_write_byte(0x4016, 1);
+        assert_eq!(bus.controller_state(1), JOYPAD_UP);
+    }
+
+    #[test]
+    fn test_mem_read_write_to_cartridge() {
+        let cart = test_cartridge(vec![0xA9], None).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(0x6000, 0x55);
+        assert_eq!(bus.mem_read_byte(0x6000), 0x55);
+
+        // Writes to ROM are handed to the mapper and do not modify PRG ROM.
+        bus.mem_write_byte(0x8000, 0x55);
+        assert_eq!(bus.mem_read_byte(0x8000), 0xA9);
+    }
+
+    #[test]
+    fn test_prg_rom_copy_follows_bank_switches() {
+        // A UxROM with the first byte of each 16 KB bank set to its number.
+        let mut raw = b"NES\x1A".to_vec();
+        raw.extend([4, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for bank in 0..4 {
+            raw.push(bank);
+            raw.extend([0; 0x3FFF]);
+        }
+        let cart = Cartridge::new(&raw).unwrap();
+
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        assert_eq!(bus.pages[0x80], Device::PrgRom);
+        assert_eq!(bus.mem_read_byte(0x8000), 0);
+        assert_eq!(bus.mem_read_byte(0xC000), 3);
+
+        bus.mem_write_byte(0x8000, 2);
+        assert_eq!(bus.mem_read_byte(0x8000), 2);
+        assert_eq!(bus.mem_read_byte(0xC000), 3);
+    }
+
+    proptest! {
+        #[test]
+        fn test_ppu_register_mirrors(mirror in 0u16..0x400, addr in 0x2000u16..0x3000, data: u8) {
+            let cart = test_cartridge(vec![], None).unwrap();
+            let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+            let base = 0x2000 + mirror * 8;
+
+            // Write through the mirrors of PPUADDR and PPUDATA.
+            bus.mem_write_byte(base + 6, (addr >> 8) as u8);
+            bus.mem_write_byte(base + 6, addr as u8);
+            bus.mem_write_byte(base + 7, data);
+
+            // Read back through the canonical registers.
+            bus.mem_read_byte(0x2002);
+            bus.mem_write_byte(0x2006, (addr >> 8) as u8);
+            bus.mem_write_byte(0x2006, addr as u8);
+            bus.mem_read_byte(0x2007);
+            prop_assert_eq!(bus.mem_read_byte(0x2007), data);
+        }
+    }
 }
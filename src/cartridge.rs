@@ -1,10 +1,23 @@
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
 use crate::{
-    mapper::{Mapper, Nrom, Uxrom, MMC1},
+    crash, dumps,
+    events::CoreEvent,
+    mapper::{
+        Mapper, MapperInfo, Mmc2Variant, Namco108, Namco108Variant, Nrom, Uxrom, Vrc, VrcVariant,
+        VsSystem, MMC1, MMC2, MMC3,
+    },
+    region::Region,
     rom::Rom,
+    state::{read_u8, write_u8, SaveState},
 };
 
 /// Represents the screen mirroring mode.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
@@ -13,9 +26,64 @@ pub enum Mirroring {
     FourScreen,
 }
 
+impl SaveState for Mirroring {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u8(w, *self as u8)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        *self = match read_u8(r)? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLo,
+            3 => Mirroring::SingleScreenHi,
+            4 => Mirroring::FourScreen,
+            m => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid mirroring {}", m),
+                ))
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Size of the extra VRAM on four-screen boards.
+const FOUR_SCREEN_VRAM_SIZE: usize = 0x800;
+
 /// Represents a NES cartridge.
 pub struct Cartridge {
     mapper: Box<dyn Mapper>,
+
+    /// Extra VRAM on four-screen boards holding the third and fourth
+    /// nametables, empty on other boards.
+    vram: Vec<u8>,
+
+    /// Incremented whenever the mapper may have switched PRG or CHR banks,
+    /// allowing the buses to cache the current PRG ROM and CHR contents.
+    bank_generation: Rc<Cell<u32>>,
+
+    /// iNES mapper number.
+    number: u16,
+
+    /// Whether the PRG RAM is battery backed.
+    battery: bool,
+
+    /// TV system declared by the ROM header.
+    region: Region,
+
+    /// Whether the ROM is for the Vs. System rather than the NES.
+    vs_system: bool,
+
+    /// Description of the problem if the ROM is a known bad dump.
+    bad_dump: Option<String>,
+
+    /// CRC32 of the PRG and CHR ROM data.
+    checksum: u32,
+
+    /// Description of the ROM header, reported when the ROM is loaded.
+    loaded: CoreEvent,
 }
 
 impl Cartridge {
@@ -26,27 +94,87 @@ impl Cartridge {
             Err(e) => return Err(e),
         };
 
+        let region = rom.header.region();
+        let vs_system = rom.header.vs_system();
+        let checksum = rom.checksum();
+        let bad_dump = match dumps::check(checksum) {
+            Some(reason) => Some(reason.to_string()),
+            None if rom.excess() > 0 => {
+                Some(format!("overdump, {} bytes of trailing data", rom.excess()))
+            }
+            None => None,
+        };
+
         let mapper = rom.header.mapper();
+        let battery = rom.header.battery();
+        let loaded = CoreEvent::RomLoaded {
+            mapper,
+            mirroring: rom.header.mirroring(),
+            prg_kb: rom.header.prg_size() * 16,
+            chr_kb: rom.header.chr_size() * 8,
+            battery,
+            region,
+        };
+        let vram = four_screen_vram(&rom);
         let cart = Cartridge {
             mapper: match mapper {
                 0 => Box::new(Nrom::new(rom)),
                 1 => Box::new(MMC1::new(rom)),
                 2 => Box::new(Uxrom::new(rom)),
-                _ => return Err(format!("Mapper {} is not supported", mapper)),
+                4 => Box::new(MMC3::new(rom)),
+                99 => Box::new(VsSystem::new(rom)),
+                _ => match (
+                    VrcVariant::from_mapper(mapper),
+                    Namco108Variant::from_mapper(mapper),
+                    Mmc2Variant::from_mapper(mapper),
+                ) {
+                    (Some(variant), _, _) => Box::new(Vrc::new(rom, variant)),
+                    (_, Some(variant), _) => Box::new(Namco108::new(rom, variant)),
+                    (_, _, Some(variant)) => Box::new(MMC2::new(rom, variant)),
+                    _ => return Err(format!("Mapper {} is not supported", mapper)),
+                },
             },
+            vram,
+            bank_generation: Rc::new(Cell::new(1)),
+            number: mapper,
+            battery,
+            region,
+            vs_system,
+            bad_dump,
+            checksum,
+            loaded,
         };
 
         Ok(cart)
     }
 
-    /// Returns a byte from PRG ROM at the given address.
-    pub fn read_prg(&self, addr: u16) -> u8 {
+    /// Returns a byte from PRG ROM at the given address, or None if the read
+    /// returns open bus.
+    pub fn read_prg(&self, addr: u16) -> Option<u8> {
         self.mapper.read_prg(addr)
     }
 
     /// Writes a byte to PRG ROM at the given address.
     pub fn write_prg(&mut self, addr: u16, data: u8) {
-        self.mapper.write_prg(addr, data)
+        self.mapper.write_prg(addr, data);
+
+        // Mapper registers live in the ROM area, so a write there may have
+        // switched banks.
+        if addr >= 0x8000 {
+            crash::record_mapper_write(addr, data);
+            self.bank_generation
+                .set(self.bank_generation.get().wrapping_add(1));
+        }
+    }
+
+    /// Passes a write to the controller strobe at $4016 to the mapper.
+    pub fn write_strobe(&mut self, data: u8) {
+        // Vs. System boards switch banks with it.
+        if self.mapper.write_strobe(data) {
+            crash::record_mapper_write(0x4016, data);
+            self.bank_generation
+                .set(self.bank_generation.get().wrapping_add(1));
+        }
     }
 
     /// Returns a byte from CHR ROM at the given address.
@@ -59,23 +187,222 @@ impl Cartridge {
         self.mapper.write_chr(addr, data)
     }
 
-    /// Returns the Mirroring mode.
+    /// Returns the Mirroring mode, as currently set by the mapper. Boards
+    /// with their own VRAM are always four-screen, whatever the mapper.
     pub fn mirroring(&self) -> Mirroring {
-        self.mapper.mirroring()
+        match self.vram.is_empty() {
+            true => self.mapper.mirroring(),
+            false => Mirroring::FourScreen,
+        }
+    }
+
+    /// Returns a byte from the cartridge's nametable VRAM, or 0 if the board
+    /// has none.
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// Writes a byte to the cartridge's nametable VRAM, if the board has any.
+    pub fn write_vram(&mut self, addr: u16, data: u8) {
+        if let Some(b) = self.vram.get_mut(addr as usize) {
+            *b = data;
+        }
+    }
+
+    /// Clocks the mapper once per CPU cycle.
+    pub fn clock_cpu(&mut self) {
+        self.mapper.clock_cpu()
+    }
+
+    /// Clocks the mapper on a filtered rising edge of PPU A12.
+    pub fn clock_a12(&mut self) {
+        self.mapper.clock_a12()
+    }
+
+    /// Passes the address of a pattern table fetch made while rendering to
+    /// the mapper, returning true if it switched CHR banks. The bank generation
+    /// isn't bumped, as the PPU bus refreshes only the banks which changed.
+    pub fn notify_chr_fetch(&mut self, addr: u16) -> bool {
+        self.mapper.notify_chr_fetch(addr)
+    }
+
+    /// Returns true if the mapper switches CHR banks on pattern table
+    /// fetches, and needs them passed to [`Cartridge::notify_chr_fetch`].
+    pub fn watches_chr_fetches(&self) -> bool {
+        self.mapper.watches_chr_fetches()
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    pub fn prg_bank(&self, addr: u16) -> usize {
+        self.mapper.prg_bank(addr)
+    }
+
+    /// Returns true if reads of $8000-$FFFF can be cached, see
+    /// [`Mapper::cacheable_prg`].
+    pub fn cacheable_prg(&self) -> bool {
+        self.mapper.cacheable_prg()
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    pub fn chr_bank(&self, addr: u16) -> usize {
+        self.mapper.chr_bank(addr)
+    }
+
+    /// Returns true if the mapper is asserting an IRQ.
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+
+    /// Returns the PRG RAM at $6000-$7FFF, empty if the board has none.
+    pub fn prg_ram(&self) -> &[u8] {
+        self.mapper.prg_ram()
+    }
+
+    /// Returns the PRG RAM for writing, see [`Cartridge::prg_ram`].
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.mapper.prg_ram_mut()
+    }
+
+    /// Returns the counter which is incremented whenever the PRG or CHR banks
+    /// may have changed.
+    pub fn bank_generation(&self) -> Rc<Cell<u32>> {
+        Rc::clone(&self.bank_generation)
+    }
+
+    /// Returns the TV system declared by the ROM header.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns true if the ROM is for the Vs. System arcade cabinet.
+    pub fn vs_system(&self) -> bool {
+        self.vs_system
+    }
+
+    /// Returns the CRC32 of the PRG and CHR ROM data.
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Returns the event describing the ROM's header.
+    pub fn loaded(&self) -> CoreEvent {
+        self.loaded.clone()
+    }
+
+    /// Returns what the board can do and the banks it has selected.
+    pub fn mapper_info(&self) -> MapperInfo {
+        MapperInfo::new(self.mapper.as_ref(), self.number, self.battery)
+    }
+
+    /// Returns a description of the problem if the ROM is a known bad dump.
+    pub fn bad_dump(&self) -> Option<&str> {
+        self.bad_dump.as_deref()
+    }
+}
+
+/// Returns the extra VRAM for the ROM's board, which is only present on
+/// four-screen boards.
+fn four_screen_vram(rom: &Rom) -> Vec<u8> {
+    match rom.header.four_screen() {
+        true => vec![0; FOUR_SCREEN_VRAM_SIZE],
+        false => Vec::new(),
+    }
+}
+
+impl SaveState for Cartridge {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.mapper.save_state(w)?;
+        w.write_all(&self.vram)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.mapper.load_state(r)?;
+        r.read_exact(&mut self.vram)?;
+
+        // The restored banks invalidate the buses' PRG and CHR caches.
+        self.bank_generation
+            .set(self.bank_generation.get().wrapping_add(1));
+        Ok(())
     }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use proptest::prelude::*;
+
     use super::*;
-    use crate::rom::tests::test_rom;
+    use crate::{mapper::ScriptedMapper, rom::tests::test_rom};
+
+    /// Returns the loaded event for an NROM test cartridge.
+    fn test_loaded(rom: &Rom) -> CoreEvent {
+        CoreEvent::RomLoaded {
+            mapper: 0,
+            mirroring: rom.header.mirroring(),
+            prg_kb: 16,
+            chr_kb: rom.header.chr_size() * 8,
+            battery: false,
+            region: Region::Ntsc,
+        }
+    }
 
     /// Creates a new Cartridge from the given PRG ROM data.
     pub fn test_cartridge(prg: Vec<u8>, mirroring: Option<Mirroring>) -> Result<Cartridge, String> {
         let rom = test_rom(1, prg, 1, vec![], None, None, mirroring).unwrap();
+        let loaded = test_loaded(&rom);
+
+        Ok(Cartridge {
+            vram: four_screen_vram(&rom),
+            mapper: Box::new(Nrom::new(rom)),
+            bank_generation: Rc::new(Cell::new(1)),
+            number: 0,
+            battery: false,
+            region: Region::Ntsc,
+            vs_system: false,
+            bad_dump: None,
+            checksum: 0,
+            loaded,
+        })
+    }
+
+    /// Creates a new Cartridge around a scripted board, see [`ScriptedMapper`].
+    pub fn test_scripted_cartridge(mapper: ScriptedMapper) -> Cartridge {
+        Cartridge {
+            mapper: Box::new(mapper),
+            vram: Vec::new(),
+            bank_generation: Rc::new(Cell::new(1)),
+            number: 0,
+            battery: false,
+            region: Region::Ntsc,
+            vs_system: false,
+            bad_dump: None,
+            checksum: 0,
+            loaded: CoreEvent::RomLoaded {
+                mapper: 0,
+                mirroring: Mirroring::Horizontal,
+                prg_kb: 32,
+                chr_kb: 0,
+                battery: false,
+                region: Region::Ntsc,
+            },
+        }
+    }
+
+    /// Creates a new Cartridge with CHR RAM from the given PRG ROM data.
+    pub fn test_chr_ram_cartridge(prg: Vec<u8>) -> Result<Cartridge, String> {
+        let rom = test_rom(1, prg, 0, vec![], None, None, None).unwrap();
+        let loaded = test_loaded(&rom);
 
         Ok(Cartridge {
+            vram: four_screen_vram(&rom),
             mapper: Box::new(Nrom::new(rom)),
+            bank_generation: Rc::new(Cell::new(1)),
+            number: 0,
+            battery: false,
+            region: Region::Ntsc,
+            vs_system: false,
+            bad_dump: None,
+            checksum: 0,
+            loaded,
         })
     }
 
@@ -83,14 +410,14 @@ pub mod tests {
     fn test_new_cartridge() {
         let prg = vec![0; 16384];
         let cartridge = test_cartridge(prg.clone(), None).unwrap();
-        assert_eq!(cartridge.read_prg(0), prg[0]);
+        assert_eq!(cartridge.read_prg(0x8000), Some(prg[0]));
     }
 
     #[test]
     fn test_read_prg() {
         let prg = vec![0; 16384];
         let cartridge = test_cartridge(prg.clone(), None).unwrap();
-        assert_eq!(cartridge.read_prg(0), prg[0]);
+        assert_eq!(cartridge.read_prg(0x8000), Some(prg[0]));
     }
 
     #[test]
@@ -98,7 +425,7 @@ pub mod tests {
         let prg = vec![0; 16384];
         let mut cartridge = test_cartridge(prg.clone(), None).unwrap();
         cartridge.write_prg(0x6000, 1);
-        assert_eq!(cartridge.read_prg(0x6000), 1);
+        assert_eq!(cartridge.read_prg(0x6000), Some(1));
     }
 
     #[test]
@@ -107,10 +434,68 @@ pub mod tests {
         assert_eq!(cartridge.read_chr(0), 0);
     }
 
+    #[test]
+    fn test_mapper_info() {
+        let cartridge = test_cartridge(vec![0; 16384], None).unwrap();
+        assert_eq!(
+            cartridge.mapper_info().to_string(),
+            "Mapper: 0 (NROM)\n\
+             PRG banks: 0 1 0 1\n\
+             CHR banks: 0 1 2 3 4 5 6 7\n\
+             PRG RAM: 8 KB\n\
+             IRQ counter: none"
+        );
+    }
+
     #[test]
     fn test_mirroring() {
         let prg = vec![0; 16384];
         let cartridge = test_cartridge(prg.clone(), None).unwrap();
         assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
     }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_files_never_panic(raw in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = Cartridge::new(&raw);
+        }
+
+        #[test]
+        fn test_malformed_roms_never_panic(
+            header in prop::array::uniform12(any::<u8>()),
+            mapper in prop::sample::select(vec![0u8, 1, 2, 4, 9, 10, 21, 22, 23, 25, 76, 88, 95, 99, 154, 206]),
+            prg_size in 0u8..4,
+            chr_size in 0u8..3,
+            len in 0usize..0x12000,
+            writes in prop::collection::vec((0x4020u16..=0xFFFF, any::<u8>()), 0..32),
+        ) {
+            // Headers are random apart from the mapper and sizes. Sizes are
+            // kept small so the file length covers every truncation point.
+            let mut raw = vec![0x4E, 0x45, 0x53, 0x1A];
+            raw.extend(header);
+            raw[4] = prg_size;
+            raw[5] = chr_size;
+            raw[6] = (raw[6] & 0x0F) | (mapper << 4);
+            raw[7] = (raw[7] & 0x03) | (mapper & 0xF0);
+            raw.extend((0..len).map(|i| i as u8));
+
+            // Bank switching to banks the ROM doesn't have mustn't panic
+            // either.
+            if let Ok(mut cart) = Cartridge::new(&raw) {
+                for (addr, data) in writes {
+                    cart.write_prg(addr, data);
+                    cart.write_strobe(data);
+                }
+                for addr in 0x4020..=0xFFFF {
+                    cart.read_prg(addr);
+                }
+                for addr in 0..0x2000 {
+                    cart.notify_chr_fetch(addr);
+                    cart.read_chr(addr);
+                    cart.write_chr(addr, 0);
+                }
+                cart.mirroring();
+            }
+        }
+    }
 }
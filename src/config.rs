@@ -0,0 +1,43 @@
+/// Emulation behaviours which can be switched on and off individually, so the
+/// one breaking a game can be found by turning them off one at a time rather
+/// than swapping whole accuracy profiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmuConfig {
+    /// Start the PPU in its documented power-on state, see
+    /// [`crate::ppu::NesPpu::power_up`].
+    pub power_up: bool,
+
+    /// Skip emulating the CPU in idle loops, see
+    /// [`crate::cpu::Cpu::set_idle_skip`].
+    pub idle_skip: bool,
+
+    /// Honour the 8x16 sprite size bit of PPUCTRL. When off, sprites are
+    /// always 8x8.
+    pub sprites_8x16: bool,
+
+    /// Set the sprite 0 hit flag in PPUSTATUS.
+    pub sprite0_hit: bool,
+
+    /// Land PPUMASK writes on the dot of the instruction's write cycle
+    /// rather than when the instruction starts.
+    pub mask_delay: bool,
+
+    /// Skip the first dot of odd frames while rendering, on regions which
+    /// do so.
+    pub odd_frame_skip: bool,
+}
+
+impl Default for EmuConfig {
+    /// Returns the configuration of the fast profile, with every PPU
+    /// behaviour on.
+    fn default() -> Self {
+        EmuConfig {
+            power_up: false,
+            idle_skip: false,
+            sprites_8x16: true,
+            sprite0_hit: true,
+            mask_delay: true,
+            odd_frame_skip: true,
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use core::panic;
+use std::io::{self, Read, Write};
 
 use crate::bus::SystemBus;
+use crate::crash;
 use crate::instructions::OPCODES;
+use crate::state::{read_u16, write_u16, SaveState};
 
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
@@ -144,6 +147,25 @@ pub struct Cpu<'a> {
     /// Handles data read/write, interrupts, memory mapping and PPU/CPU clock
     /// cycles.
     pub bus: SystemBus<'a>,
+
+    /// Fast-forward through busy-wait loops rather than emulating every
+    /// instruction, see [`Cpu::set_idle_skip`].
+    idle_skip: bool,
+
+    /// Stop at BRK rather than taking the interrupt, see
+    /// [`Cpu::set_halt_on_brk`].
+    halt_on_brk: bool,
+}
+
+/// Busy-wait loops recognised when skipping idle loops.
+#[derive(Clone, Copy)]
+enum IdleLoop {
+    /// A jump or taken branch to itself, which only an interrupt can leave.
+    Spin { cycles: u8 },
+
+    /// LDA or BIT $2002, given by its opcode, followed by BPL back to it,
+    /// waiting for vblank. The branch takes the given number of cycles.
+    VblankWait { load: u8, branch_cycles: u8 },
 }
 
 impl Memory for Cpu<'_> {
@@ -172,6 +194,8 @@ mod interrupt {
     #[derive(PartialEq, Eq)]
     pub enum InterruptType {
         Nmi,
+        Irq,
+        Brk,
     }
 
     #[derive(PartialEq, Eq)]
@@ -187,6 +211,22 @@ mod interrupt {
         status_mask: 0b00100000,
         cpu_cycles: 7,
     };
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::Irq,
+        vector_addr: 0xFFFE,
+        status_mask: 0b00100000,
+        cpu_cycles: 7,
+    };
+
+    /// BRK shares the IRQ vector, setting the break flag in the pushed status
+    /// so the handler can tell them apart. Its cycles are counted by the
+    /// instruction.
+    pub(super) const BRK: Interrupt = Interrupt {
+        itype: InterruptType::Brk,
+        vector_addr: 0xFFFE,
+        status_mask: 0b00110000,
+        cpu_cycles: 0,
+    };
 }
 
 impl<'a> Cpu<'a> {
@@ -200,9 +240,32 @@ impl<'a> Cpu<'a> {
             pc: 0,
             sp: STACK_RESET,
             bus,
+            idle_skip: false,
+            halt_on_brk: false,
         }
     }
 
+    /// Makes BRK halt the CPU, so `clock` returns true, rather than calling
+    /// the interrupt handler.
+    ///
+    /// Test programs run outside a cartridge usually end by falling through
+    /// to zeroed memory, which BRK would otherwise loop through forever.
+    pub fn set_halt_on_brk(&mut self, halt: bool) {
+        self.halt_on_brk = halt;
+    }
+
+    /// Enables or disables idle loop skipping.
+    ///
+    /// Games commonly wait for vblank by polling $2002 or spinning on a jump
+    /// to itself until the NMI arrives. When enabled, these loops are not
+    /// interpreted; the bus is ticked a whole loop iteration at a time until
+    /// the vblank or interrupt they wait on is due, then execution resumes.
+    /// The PPU and APU still see every cycle and the loop's reads have no
+    /// lasting side effects, so the machine ends up in the same state.
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.idle_skip = enabled;
+    }
+
     /// Resets the CPU and marks where it should begin execution.
     ///
     /// Emulates the "reset interrupt" signal that is sent to the NES CPU when a
@@ -217,6 +280,19 @@ impl<'a> Cpu<'a> {
         self.pc = self.mem_read_word(RESET_VECTOR);
     }
 
+    /// Emulates pressing the reset button. Unlike a power cycle, RAM and the
+    /// registers are kept, the stack pointer is decremented by 3 and
+    /// interrupts are disabled.
+    ///
+    /// See: https://www.nesdev.org/wiki/CPU_power_up_state#After_reset
+    pub fn soft_reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.status |= INTERRUPT_DISABLE;
+        self.bus.reset();
+
+        self.pc = self.mem_read_word(RESET_VECTOR);
+    }
+
     /// Pops a byte off the stack and increments the stack pointer.
     fn stack_pop_byte(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
@@ -262,9 +338,122 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    /// Sets the pressed state of the given button.
-    pub fn set_button_pressed_status(&mut self, button: u8, pressed: bool) {
-        self.bus.joypad1.set_button_pressed_status(button, pressed);
+    /// Returns true if the branch with the given opcode would be taken.
+    fn branch_taken(&self, code: u8) -> bool {
+        let (flag, set) = match code {
+            0x10 => (NEGATIVE, false),
+            0x30 => (NEGATIVE, true),
+            0x50 => (OVERFLOW, false),
+            0x70 => (OVERFLOW, true),
+            0x90 => (CARRY, false),
+            0xB0 => (CARRY, true),
+            0xD0 => (ZERO, false),
+            _ => (ZERO, true),
+        };
+        (self.status & flag == flag) == set
+    }
+
+    /// Returns the busy-wait loop at the program counter, if there is one.
+    fn idle_loop(&mut self) -> Option<IdleLoop> {
+        let pc = self.pc;
+
+        // Only look for loops in RAM and cartridge space, where peeking at
+        // the code has no side effects.
+        if !matches!(pc, 0x0000..=0x1FFA | 0x6000..=0xFFFA) {
+            return None;
+        }
+
+        // A branch back to an earlier page takes an extra cycle.
+        let crossed = |from: u16| (from & 0xFF00 != pc & 0xFF00) as u8;
+
+        match self.mem_read_byte(pc) {
+            0x4C if self.mem_read_word(pc + 1) == pc => Some(IdleLoop::Spin { cycles: 3 }),
+            code @ (0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0) => {
+                let to_self = self.mem_read_byte(pc + 1) == 0xFE;
+                (to_self && self.branch_taken(code)).then(|| IdleLoop::Spin {
+                    cycles: 3 + crossed(pc + 2),
+                })
+            }
+            load @ (0xAD | 0x2C) => {
+                let wait = self.mem_read_word(pc + 1) == 0x2002
+                    && self.mem_read_byte(pc + 3) == 0x10
+                    && self.mem_read_byte(pc + 4) == 0xFB;
+                wait.then(|| IdleLoop::VblankWait {
+                    load,
+                    branch_cycles: 3 + crossed(pc + 5),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if an interrupt will be taken before the next instruction.
+    fn interrupt_pending(&self) -> bool {
+        let irq = self.bus.irq_status() && self.status & INTERRUPT_DISABLE == 0;
+        self.bus.nmi_pending() || irq
+    }
+
+    /// Skips iterations of the busy-wait loop at the program counter, if there
+    /// is one, and returns true if any were skipped.
+    ///
+    /// Nothing is skipped once an interrupt is pending, so it is taken on the
+    /// same instruction boundary as without skipping. A spin is otherwise
+    /// skipped until a frame completes, so the caller still sees every frame.
+    /// A vblank wait is skipped while the PPU is rendering, leaving the
+    /// iterations around the start of vblank to run normally so the flag is
+    /// read on the exact cycle. Its load still reads $2002 and sets the
+    /// flags, and an interrupt which becomes pending during the load leaves
+    /// the CPU at the branch, where it would be taken.
+    fn skip_idle_loop(&mut self) -> bool {
+        let Some(idle) = self.idle_loop() else {
+            return false;
+        };
+
+        let frame_count = self.bus.ppu_frame_count();
+        let mut skipped = false;
+        loop {
+            if self.interrupt_pending() {
+                break;
+            }
+
+            let cycles = match idle {
+                IdleLoop::Spin { cycles } => {
+                    if self.bus.ppu_frame_count() != frame_count {
+                        break;
+                    }
+                    cycles
+                }
+                IdleLoop::VblankWait {
+                    load,
+                    branch_cycles,
+                } => {
+                    if !(0..240).contains(&self.bus.ppu_position().0) {
+                        break;
+                    }
+
+                    // Vblank doesn't start while rendering, so the branch is
+                    // always taken.
+                    let status = self.mem_read_byte(0x2002);
+                    match load {
+                        0xAD => self.set_accumulator(status),
+                        _ => self.test_bits(status),
+                    }
+                    self.bus.tick(4);
+                    skipped = true;
+
+                    if self.interrupt_pending() {
+                        self.pc += 3;
+                        break;
+                    }
+                    branch_cycles
+                }
+            };
+
+            self.bus.tick(cycles);
+            skipped = true;
+        }
+
+        skipped
     }
 
     /// Returns the address of the operand for a given non-immediate addressing
@@ -328,12 +517,38 @@ impl<'a> Cpu<'a> {
     /// Clocks the CPU exactly once, returning true if the CPU should be shut
     /// down.
     pub fn clock(&mut self) -> bool {
+        // Interrupts are polled between instructions. NMI takes priority, and
+        // the IRQ line stays asserted until the handler acknowledges it.
         if self.bus.nmi_status() {
-            self.interrupt(interrupt::NMI);
+            self.nmi();
+        } else if self.bus.irq_status() {
+            self.irq();
+        }
+
+        if self.idle_skip && self.skip_idle_loop() {
+            return false;
         }
 
         // Get the opcode at the program counter.
         let code = self.mem_read_byte(self.pc);
+
+        // Keep a record of recent state in case of a crash.
+        let (scanline, cycle) = self.bus.ppu_position();
+        crash::record(crash::Record {
+            pc: self.pc,
+            code,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            status: self.status,
+            sp: self.sp,
+            scanline,
+            cycle,
+        });
+
+        #[cfg(feature = "opstats")]
+        crate::opstats::record(code);
+
         self.pc += 1;
         let current_pc = self.pc;
 
@@ -342,9 +557,13 @@ impl<'a> Cpu<'a> {
             .get(&code)
             .unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
 
+        // Writes happen on the last cycle of the instruction.
+        self.bus.set_write_cycle(opcode.cycles.saturating_sub(1));
+
         match opcode.code {
             // Official opcodes.
-            0x00 => return true,
+            0x00 if self.halt_on_brk => return true,
+            0x00 => self.brk(),
 
             // ADC.
             0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
@@ -637,6 +856,20 @@ impl<'a> Cpu<'a> {
         // order for the other components to process as appropriate.
         self.bus.tick(opcode.cycles);
 
+        // The CPU is halted while DMA started by the instruction runs, which
+        // may itself run into further DMC sample fetches.
+        loop {
+            let mut stall = self.bus.take_dma_stall();
+            if stall == 0 {
+                break;
+            }
+            while stall > 0 {
+                let cycles = stall.min(u8::MAX as u16);
+                self.bus.tick(cycles as u8);
+                stall -= cycles;
+            }
+        }
+
         // Program counter needs to be incremented by the number of bytes
         // used in the opcode, if not done so elsewhere.
         if current_pc == self.pc {
@@ -773,7 +1006,11 @@ impl<'a> Cpu<'a> {
         let (addr, _) = self.get_operand_address(mode);
 
         let param = self.mem_read_byte(addr);
+        self.test_bits(param);
+    }
 
+    /// Sets the flags as BIT does for the value read from memory.
+    fn test_bits(&mut self, param: u8) {
         // Update zero flag.
         if param & self.a == 0 {
             self.status |= ZERO;
@@ -1483,9 +1720,13 @@ impl<'a> Cpu<'a> {
     /// AND memory with stack pointer, transfer result to accumulator, X register
     /// and stack pointer.
     fn las(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let (addr, page_cross) = self.get_operand_address(mode);
         let mut data = self.mem_read_byte(addr);
 
+        if page_cross {
+            self.bus.tick(1);
+        }
+
         data &= self.sp;
         self.a = data;
         self.x = data;
@@ -1496,9 +1737,13 @@ impl<'a> Cpu<'a> {
 
     /// LAX: Load accumulator and X register with memory.
     fn lax(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(mode);
+        let (addr, page_cross) = self.get_operand_address(mode);
         let data = self.mem_read_byte(addr);
 
+        if page_cross {
+            self.bus.tick(1);
+        }
+
         self.set_accumulator(data);
         self.x = data;
     }
@@ -1767,14 +2012,37 @@ impl<'a> Cpu<'a> {
     }
 
     /// Handles the CPU interrupt.
-    fn interrupt(&mut self, interrupt: interrupt::Interrupt) {
-        self.stack_push_word(self.pc);
+    /// Services a non-maskable interrupt, as raised by the PPU at the start
+    /// of vblank.
+    pub fn nmi(&mut self) {
+        self.interrupt(interrupt::NMI);
+    }
 
-        let mut status = self.status;
+    /// Services an interrupt request, as raised by the APU or the cartridge,
+    /// unless interrupts are disabled. Returns true if it was taken.
+    pub fn irq(&mut self) -> bool {
+        if self.status & INTERRUPT_DISABLE != 0 {
+            return false;
+        }
 
-        status &= !BREAK;
-        status &= !BREAK2;
+        self.interrupt(interrupt::IRQ);
+        true
+    }
+
+    /// BRK: Force Interrupt
+    /// Pushes the program counter and status then jumps through the IRQ
+    /// vector. The byte after BRK is padding, skipped on return.
+    fn brk(&mut self) {
+        self.pc = self.pc.wrapping_add(1);
+        self.interrupt(interrupt::BRK);
+    }
 
+    /// Pushes the program counter and status, with the break flags given by
+    /// the interrupt, disables interrupts and jumps to the handler.
+    fn interrupt(&mut self, interrupt: interrupt::Interrupt) {
+        self.stack_push_word(self.pc);
+
+        let status = (self.status & !(BREAK | BREAK2)) | interrupt.status_mask;
         self.stack_push_byte(status);
 
         // Set interrupt disable flag.
@@ -1794,12 +2062,30 @@ fn page_cross(addr1: u16, addr2: u16) -> bool {
     addr1 & 0xFF00 != addr2 & 0xFF00
 }
 
+impl SaveState for Cpu<'_> {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&[self.a, self.x, self.y, self.status, self.sp])?;
+        write_u16(w, self.pc)?;
+        self.bus.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        let mut regs = [0; 5];
+        r.read_exact(&mut regs)?;
+        [self.a, self.x, self.y, self.status, self.sp] = regs;
+        self.pc = read_u16(r)?;
+        self.bus.load_state(r)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cartridge::tests::test_cartridge;
+    use crate::bus::Device;
+    use crate::cartridge::tests::{test_cartridge, test_scripted_cartridge};
     use crate::cartridge::Cartridge;
-    use crate::trace::trace;
+    use crate::mapper::ScriptedMapper;
+    use crate::trace::{trace, trace_full};
     use std::cell::RefCell;
     use std::fs::File;
     use std::io::{BufRead, BufReader};
@@ -1807,6 +2093,7 @@ mod tests {
 
     fn test_cpu(cart: Cartridge) -> Cpu<'static> {
         let mut cpu = Cpu::new(SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {}));
+        cpu.set_halt_on_brk(true);
 
         // Force the program counter to the start of PRG ROM.
         // TODO: This should be handled by the ROM mapper instead. Loading the
@@ -1871,6 +2158,200 @@ mod tests {
         assert_eq!(cpu.mem_read_byte(0x20), 0x05)
     }
 
+    #[test]
+    fn test_oam_dma_stalls_cpu() {
+        // LDA #$02, STA $4014, twice.
+        let program = vec![0xA9, 0x02, 0x8D, 0x14, 0x40, 0x8D, 0x14, 0x40];
+        let cart = test_cartridge(program, None).unwrap();
+
+        let mut cpu = test_cpu(cart);
+        run_test_cpu(&mut cpu, 2);
+        assert_eq!(cpu.bus.cpu_cycles(), 2 + 4 + 513);
+
+        // Starting on an odd cycle takes an extra cycle to line up.
+        run_test_cpu(&mut cpu, 1);
+        assert_eq!(cpu.bus.cpu_cycles(), 519 + 4 + 514);
+    }
+
+    /// Returns a CPU with flat RAM over the whole address space, with the
+    /// NMI and IRQ vectors pointing at $0300 and $0400.
+    fn flat_ram_cpu() -> Cpu<'static> {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.map(0x0000, 0xFFFF, Device::FlatRam);
+        bus.mem_write_word(0xFFFA, 0x0300);
+        bus.mem_write_word(0xFFFE, 0x0400);
+
+        let mut cpu = Cpu::new(bus);
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn test_brk() {
+        let mut cpu = flat_ram_cpu();
+        cpu.mem_write_byte(0x0200, 0x00);
+
+        // BRK is taken even with interrupts disabled.
+        cpu.status = STATUS_DEFAULT;
+        let cycles = cpu.bus.cpu_cycles();
+        assert!(!cpu.clock());
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(cpu.bus.cpu_cycles() - cycles, 7);
+
+        // The return address skips the padding byte and the break flag is set.
+        assert_eq!(cpu.stack_pop_byte(), STATUS_DEFAULT | BREAK);
+        assert_eq!(cpu.stack_pop_word(), 0x0202);
+
+        cpu.set_halt_on_brk(true);
+        cpu.pc = 0x0200;
+        assert!(cpu.clock());
+    }
+
+    #[test]
+    fn test_irq_and_nmi() {
+        let mut cpu = flat_ram_cpu();
+
+        cpu.status = STATUS_DEFAULT;
+        assert!(!cpu.irq());
+        assert_eq!(cpu.pc, 0x0200);
+
+        cpu.status = CARRY;
+        let cycles = cpu.bus.cpu_cycles();
+        assert!(cpu.irq());
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(cpu.bus.cpu_cycles() - cycles, 7);
+        assert_eq!(cpu.status & INTERRUPT_DISABLE, INTERRUPT_DISABLE);
+        assert_eq!(cpu.stack_pop_byte(), CARRY | BREAK2);
+        assert_eq!(cpu.stack_pop_word(), 0x0200);
+
+        // NMI can't be disabled.
+        cpu.nmi();
+        assert_eq!(cpu.pc, 0x0300);
+    }
+
+    #[test]
+    fn test_mask_write_lands_on_last_cycle() {
+        // LDA #$09 / STA $2001 turns on greyscale mid-scanline.
+        let cart = test_cartridge(vec![0xA9, 0x09, 0x8D, 0x01, 0x20], None).unwrap();
+        let frame = RefCell::new(Vec::new());
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |pixels| {
+            *frame.borrow_mut() = pixels.to_vec();
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.pc = 0x8000;
+        cpu.bus.ppu_poke(0x3F00, 0x16);
+        cpu.mem_write_byte(0x2001, 0x08);
+
+        while cpu.bus.ppu_position().0 != 100 {
+            cpu.bus.tick(1);
+        }
+        let (_, start) = cpu.bus.ppu_position();
+        run_test_cpu(&mut cpu, 2);
+
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            cpu.bus.tick(1);
+        }
+
+        // LDA takes 6 dots and the store lands 9 dots into STA, so the dot
+        // after those draws the first greyscale pixel.
+        let frame = frame.borrow();
+        let pixel = |x: usize| &frame[(100 * 256 + x) * 3..(100 * 256 + x) * 3 + 3];
+        let first = start + 15 - 1;
+        assert_eq!(pixel(first - 1), pixel(0));
+        assert_ne!(pixel(first), pixel(0));
+        assert_eq!(pixel(255), pixel(first));
+    }
+
+    // Runs the CPU for the given number of frames, returning the number of
+    // instructions executed.
+    fn run_frames(cpu: &mut Cpu, frames: u32) -> u32 {
+        let mut clocks = 0;
+        for _ in 0..frames {
+            let frame_count = cpu.bus.ppu_frame_count();
+            while cpu.bus.ppu_frame_count() == frame_count {
+                cpu.clock();
+                clocks += 1;
+            }
+        }
+        clocks
+    }
+
+    fn assert_same_state(a: &Cpu, b: &Cpu) {
+        assert_eq!(
+            (a.pc, a.a, a.x, a.y, a.sp, a.status),
+            (b.pc, b.a, b.x, b.y, b.sp, b.status)
+        );
+        assert_eq!(a.bus.ram(), b.bus.ram());
+        assert_eq!(a.bus.ppu_position(), b.bus.ppu_position());
+    }
+
+    #[test]
+    fn test_idle_skip_vblank_wait() {
+        // BIT $2002 / BPL $8000 / INC $10 / JMP $8000 counts frames in $10.
+        let prg = vec![0x2C, 0x02, 0x20, 0x10, 0xFB, 0xE6, 0x10, 0x4C, 0x00, 0x80];
+        let mut a = test_cpu(test_cartridge(prg.clone(), None).unwrap());
+        let mut b = test_cpu(test_cartridge(prg, None).unwrap());
+        b.set_idle_skip(true);
+
+        let clocks = run_frames(&mut a, 10);
+        let skipped_clocks = run_frames(&mut b, 10);
+
+        assert_same_state(&a, &b);
+        assert!(b.bus.ram()[0x10] >= 9);
+        assert!(skipped_clocks < clocks / 10);
+    }
+
+    #[test]
+    fn test_idle_skip_vblank_wait_irq() {
+        // CLI, then BIT $2002 / BPL waits for vblank. The IRQ handler at
+        // $9000 loads X, and is run along with taking the IRQ.
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..6].copy_from_slice(&[0x58, 0x2C, 0x02, 0x20, 0x10, 0xFB]);
+        prg[0x1000..0x1002].copy_from_slice(&[0xA2, 0x42]);
+        prg[0x7FFE..].copy_from_slice(&[0x00, 0x90]);
+
+        // The IRQ is raised mid-frame, while the PPU is rendering.
+        let new_cpu = |idle_skip: bool| {
+            let mapper = ScriptedMapper::new(prg.clone()).irq_at(10000);
+            let mut cpu = test_cpu(test_scripted_cartridge(mapper));
+            cpu.set_idle_skip(idle_skip);
+            cpu
+        };
+        let mut a = new_cpu(false);
+        let mut b = new_cpu(true);
+
+        for cpu in [&mut a, &mut b] {
+            while cpu.x != 0x42 {
+                cpu.clock();
+            }
+            assert!((0..240).contains(&cpu.bus.ppu_position().0));
+        }
+        assert_same_state(&a, &b);
+        assert_eq!(a.bus.cpu_cycles(), b.bus.cpu_cycles());
+    }
+
+    #[test]
+    fn test_idle_skip_spin() {
+        // The self-check ROM spins on a JMP to itself between NMIs.
+        let new_cpu = || {
+            let cart = Cartridge::new(&crate::selfcheck::rom()).unwrap();
+            let mut cpu = test_cpu(cart);
+            cpu.reset();
+            cpu
+        };
+        let mut a = new_cpu();
+        let mut b = new_cpu();
+        b.set_idle_skip(true);
+
+        let clocks = run_frames(&mut a, 10);
+        let skipped_clocks = run_frames(&mut b, 10);
+
+        assert_same_state(&a, &b);
+        assert!(skipped_clocks < clocks / 10);
+    }
+
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
         let cart = test_cartridge(vec![0xAA, 0x00], None).unwrap();
@@ -1925,6 +2406,7 @@ mod tests {
 
         let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
         let mut cpu = Cpu::new(bus);
+        cpu.set_halt_on_brk(true);
         cpu.reset();
         cpu.pc = 0xC000;
 
@@ -1947,4 +2429,80 @@ mod tests {
             assert_eq!(result[i], line_str);
         }
     }
+
+    #[test]
+    fn test_compare_nestest_rom_cycles() {
+        let bytes: Vec<u8> = std::fs::read("nestest.nes").unwrap();
+        let cart = Cartridge::new(&bytes).unwrap();
+
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.pc = 0xC000;
+
+        // The golden log starts after the 7 cycles of the reset sequence.
+        cpu.bus.tick(7);
+
+        let golden_file = File::open("nestest.log").expect("no such file");
+        let reader = BufReader::new(golden_file);
+
+        for line in reader.lines() {
+            let mut line_str = line.expect("could not read line");
+
+            // The log shows APU registers as FF rather than reading them, as
+            // nestest_no_cycle.log does with 00.
+            if line_str.contains(" $40") {
+                line_str = line_str.replace(" = FF ", " = 00 ");
+            }
+            assert_eq!(trace_full(&mut cpu), line_str);
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn test_parallel_cores_are_deterministic() {
+        // The core is not Send, so each thread builds its own from the same
+        // ROM and configuration. With no shared mutable state, every core must
+        // produce the same picture, sound and CPU state.
+        let bytes = std::fs::read("nestest.nes").unwrap();
+
+        let run = |bytes: Vec<u8>| {
+            let cart = Cartridge::new(&bytes).unwrap();
+
+            let screen = Rc::new(RefCell::new(0));
+            let frame = Rc::clone(&screen);
+            let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, move |pixels| {
+                *frame.borrow_mut() = crc32fast::hash(pixels);
+            });
+
+            let mut cpu = Cpu::new(bus);
+            cpu.bus.power_up(Some(42));
+            cpu.reset();
+
+            let mut samples = 0;
+            for _ in 0..30 {
+                let frame_count = cpu.bus.ppu_frame_count();
+                while cpu.bus.ppu_frame_count() == frame_count {
+                    cpu.clock();
+                }
+                samples += cpu.bus.audio_samples().len();
+            }
+
+            let crc = *screen.borrow();
+            (
+                crc, samples, cpu.pc, cpu.a, cpu.x, cpu.y, cpu.sp, cpu.status,
+            )
+        };
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let bytes = bytes.clone();
+                std::thread::spawn(move || run(bytes))
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(results.iter().all(|r| *r == results[0]));
+        assert_ne!(results[0].0, 0);
+    }
 }
@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::panic;
+use std::rc::{Rc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cartridge::Cartridge;
+use crate::instructions::OPCODES;
+
+/// Number of executed instructions retained for the crash report.
+const HISTORY_SIZE: usize = 64;
+
+/// Snapshot of the CPU registers and PPU position taken just before an
+/// instruction is executed.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Record {
+    pub pc: u16,
+    pub code: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub sp: u8,
+    pub scanline: i32,
+    pub cycle: usize,
+}
+
+/// Recent emulator state, kept up to date while the emulator runs so it can be
+/// written out if a component panics.
+#[derive(Default)]
+struct Context {
+    history: VecDeque<Record>,
+
+    /// Address and value of the last write to the mapper's registers, kept
+    /// raw as writes are frequent and the report is rarely written.
+    mapper_write: Option<(u16, u8)>,
+
+    /// The running cartridge, whose mapper banks are described in the
+    /// report.
+    cart: Weak<RefCell<Cartridge>>,
+}
+
+// Kept per thread rather than globally, so cores running on separate threads
+// don't share history. Cores sharing a thread also share the report.
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// Records the state of the CPU ahead of executing an instruction.
+pub fn record(rec: Record) {
+    CONTEXT.with(|ctx| {
+        let history = &mut ctx.borrow_mut().history;
+        if history.len() == HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(rec);
+    });
+}
+
+/// Records a write to the mapper's registers.
+pub fn record_mapper_write(addr: u16, data: u8) {
+    CONTEXT.with(|ctx| ctx.borrow_mut().mapper_write = Some((addr, data)));
+}
+
+/// Records the cartridge now running, forgetting the last mapper write of
+/// the previous one.
+pub fn record_cartridge(cart: &Rc<RefCell<Cartridge>>) {
+    CONTEXT.with(|ctx| {
+        let mut ctx = ctx.borrow_mut();
+        ctx.cart = Rc::downgrade(cart);
+        ctx.mapper_write = None;
+    });
+}
+
+/// Installs a panic hook which writes the recent emulator state to a crash
+/// file before deferring to the default hook.
+pub fn install_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = format!("res-crash-{}.log", secs);
+
+        let report = format!("{}\n\n{}", info, dump());
+        match std::fs::write(&path, report) {
+            Ok(_) => eprintln!("crash report written to {}", path),
+            Err(e) => eprintln!("could not write crash report: {}", e),
+        }
+
+        default_hook(info);
+    }));
+}
+
+/// Returns a human readable report of the recorded emulator state.
+pub fn dump() -> String {
+    CONTEXT.with(|ctx| {
+        // The panic may have happened while the context was borrowed.
+        let Ok(ctx) = ctx.try_borrow() else {
+            return String::from("emulator context unavailable");
+        };
+
+        let mut out = String::new();
+
+        if let Some(last) = ctx.history.back() {
+            let _ = writeln!(
+                out,
+                "Registers: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                last.pc, last.a, last.x, last.y, last.status, last.sp
+            );
+            let _ = writeln!(out, "PPU: scanline {} cycle {}", last.scanline, last.cycle);
+        }
+        // The banks are only described here, as the report is rarely
+        // written. The panic may have happened while the cartridge was
+        // borrowed too.
+        if let Some(cart) = ctx.cart.upgrade() {
+            match cart.try_borrow() {
+                Ok(cart) => {
+                    let _ = writeln!(out, "{}", cart.mapper_info());
+                }
+                Err(_) => {
+                    let _ = writeln!(out, "Mapper: unavailable");
+                }
+            }
+        }
+        if let Some((addr, data)) = ctx.mapper_write {
+            let _ = writeln!(out, "Last mapper write: ${:02X} to ${:04X}", data, addr);
+        }
+
+        let _ = writeln!(out, "\nLast {} instructions:", ctx.history.len());
+        for rec in ctx.history.iter() {
+            let _ = writeln!(out, "{}", format_record(rec));
+        }
+
+        out
+    })
+}
+
+/// Returns a single trace line for the given record.
+fn format_record(rec: &Record) -> String {
+    let mnemonic = OPCODES.get(&rec.code).map_or("???", |op| op.mnemonic);
+
+    format!(
+        "{:04X}  {:02X} {: >4}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3}",
+        rec.pc,
+        rec.code,
+        mnemonic,
+        rec.a,
+        rec.x,
+        rec.y,
+        rec.status,
+        rec.sp,
+        rec.scanline,
+        rec.cycle
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_cartridge;
+
+    #[test]
+    fn test_history_is_bounded() {
+        for pc in 0..(HISTORY_SIZE as u16 + 10) {
+            record(Record {
+                pc,
+                ..Default::default()
+            });
+        }
+
+        CONTEXT.with(|ctx| {
+            let ctx = ctx.borrow();
+            assert_eq!(ctx.history.len(), HISTORY_SIZE);
+            assert_eq!(ctx.history.front().unwrap().pc, 10);
+        });
+    }
+
+    #[test]
+    fn test_dump() {
+        record(Record {
+            pc: 0xC000,
+            code: 0xA9,
+            a: 0x01,
+            scanline: 241,
+            cycle: 12,
+            ..Default::default()
+        });
+        let cart = Rc::new(RefCell::new(test_cartridge(vec![], None).unwrap()));
+        record_cartridge(&cart);
+        record_mapper_write(0x8000, 0x03);
+
+        let report = dump();
+        assert!(report.contains("Registers: PC:C000 A:01"));
+        assert!(report.contains("PPU: scanline 241 cycle 12"));
+        assert!(report.contains("Mapper: 0 (NROM)"));
+        assert!(report.contains("PRG banks: 0 1 0 1"));
+        assert!(report.contains("Last mapper write: $03 to $8000"));
+
+        // A cartridge borrowed when the panic happened isn't described.
+        let borrowed = cart.borrow_mut();
+        assert!(dump().contains("Mapper: unavailable"));
+        drop(borrowed);
+        assert!(report.contains("C000  A9  LDA"));
+    }
+}
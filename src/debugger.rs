@@ -0,0 +1,306 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::cpu::Cpu;
+use crate::trace::TraceLog;
+
+/// A condition which pauses emulation while the debugger is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// The program counter reaching the address.
+    Pc(u16),
+
+    /// An instruction reading the address, including fetching its opcode or
+    /// operands from it.
+    Read(u16),
+
+    /// An instruction writing the address.
+    Write(u16),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /// Parses `[pc:]<addr>`, `read:<addr>` or `write:<addr>`, with the address
+    /// in hex, e.g. `C000` or `write:4016`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid breakpoint {:?}", s);
+
+        let (kind, addr) = s.split_once(':').unwrap_or(("pc", s));
+        let addr = addr.trim().trim_start_matches('$');
+        let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "pc" => Ok(Breakpoint::Pc(addr)),
+            "read" => Ok(Breakpoint::Read(addr)),
+            "write" => Ok(Breakpoint::Write(addr)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(addr) => write!(f, "PC ${:04X}", addr),
+            Breakpoint::Read(addr) => write!(f, "read of ${:04X}", addr),
+            Breakpoint::Write(addr) => write!(f, "write to ${:04X}", addr),
+        }
+    }
+}
+
+/// How far to run a paused machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// A single instruction, or the interrupt taken before it.
+    Instruction,
+
+    /// Until the PPU reaches the next scanline.
+    Scanline,
+
+    /// Until the PPU finishes the frame.
+    Frame,
+}
+
+/// Why the debugger stopped running the CPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// The step or frame ran to completion.
+    Done,
+
+    /// A breakpoint was hit, leaving the debugger paused.
+    Breakpoint(Breakpoint),
+
+    /// The CPU halted.
+    Halted,
+}
+
+/// Pauses, steps and breaks the emulation.
+///
+/// Pausing and stepping work at any time. Breakpoints are only checked while
+/// the debugger is enabled, in which case frames are run through
+/// [`Debugger::run_frame`], checking after every instruction. Disabled, the
+/// frontend clocks the CPU directly and the bus has no watchpoints armed, so
+/// the normal path pays nothing for it.
+///
+/// The CPU executes whole instructions, so breakpoints stop after the
+/// instruction which hit them, or before the instruction at a PC breakpoint.
+///
+/// A trace log, if set, is written to for every instruction the debugger
+/// runs, so frames are run through it while tracing too.
+pub struct Debugger {
+    enabled: bool,
+    paused: bool,
+    breakpoints: Vec<Breakpoint>,
+
+    trace: Option<TraceLog>,
+
+    /// The error which stopped the trace log, until taken.
+    trace_error: Option<io::Error>,
+}
+
+impl Debugger {
+    /// Returns a debugger with the given breakpoints, enabled if there are
+    /// any.
+    pub fn new(breakpoints: Vec<Breakpoint>) -> Self {
+        Debugger {
+            enabled: !breakpoints.is_empty(),
+            paused: false,
+            breakpoints,
+            trace: None,
+            trace_error: None,
+        }
+    }
+
+    /// Writes a trace line for each instruction run from now on to the log.
+    pub fn set_trace_log(&mut self, log: TraceLog) {
+        self.trace = Some(log);
+    }
+
+    /// Returns true if instructions are being traced.
+    pub fn tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Returns the error which stopped tracing, if any since last taken.
+    /// Tracing stops at the first error writing the log.
+    pub fn take_trace_error(&mut self) -> Option<io::Error> {
+        self.trace_error.take()
+    }
+
+    /// Stops tracing, writing out the rest of the log.
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        match self.trace.take() {
+            Some(mut log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if breakpoints are being checked.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns breakpoint checking on or off, returning whether it is now on.
+    pub fn toggle(&mut self, cpu: &mut Cpu) -> bool {
+        self.enabled = !self.enabled;
+        self.arm(cpu);
+        self.enabled
+    }
+
+    /// Arms the memory breakpoints on the bus if enabled, or clears them.
+    /// Called whenever the machine is replaced.
+    pub fn arm(&self, cpu: &mut Cpu) {
+        match self.enabled {
+            true => cpu.bus.set_watchpoints(&self.breakpoints),
+            false => cpu.bus.set_watchpoints(&[]),
+        }
+    }
+
+    /// Returns true if the emulation is paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes the emulation, returning whether it is now paused.
+    pub fn toggle_pause(&mut self) -> bool {
+        self.paused = !self.paused;
+        self.paused
+    }
+
+    /// Runs the CPU until the PPU finishes a frame, stopping early at a
+    /// breakpoint.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Stop {
+        self.step(cpu, Step::Frame)
+    }
+
+    /// Runs the CPU by the given step, stopping early at a breakpoint if
+    /// enabled. The first instruction is always run, so stepping moves off
+    /// a breakpoint.
+    ///
+    /// A scanline step stops on the first instruction boundary past the start
+    /// of the next scanline.
+    pub fn step(&mut self, cpu: &mut Cpu, step: Step) -> Stop {
+        let (scanline, _) = cpu.bus.ppu_position();
+        let frame = cpu.bus.ppu_frame_count();
+        cpu.bus.take_watch_hit();
+
+        loop {
+            if let Some(Err(e)) = self.trace.as_mut().map(|log| log.write(cpu)) {
+                self.trace = None;
+                self.trace_error = Some(e);
+            }
+            if cpu.clock() {
+                return Stop::Halted;
+            }
+            if let Some(hit) = self.hit(cpu) {
+                self.paused = true;
+                return Stop::Breakpoint(hit);
+            }
+
+            let done = match step {
+                Step::Instruction => true,
+                Step::Scanline => cpu.bus.ppu_position().0 != scanline,
+                Step::Frame => cpu.bus.ppu_frame_count() != frame,
+            };
+            if done {
+                return Stop::Done;
+            }
+        }
+    }
+
+    /// Returns the breakpoint hit by the last instruction, if enabled.
+    fn hit(&self, cpu: &mut Cpu) -> Option<Breakpoint> {
+        if !self.enabled {
+            return None;
+        }
+        cpu.bus.take_watch_hit().or_else(|| {
+            self.breakpoints
+                .iter()
+                .find(|b| **b == Breakpoint::Pc(cpu.pc))
+                .copied()
+        })
+    }
+}
+
+/// Returns the CPU registers, in the style of the nestest log.
+pub fn registers(cpu: &Cpu) -> String {
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        cpu.pc, cpu.a, cpu.x, cpu.y, cpu.status, cpu.sp
+    )
+}
+
+/// Returns the status line shown while paused, with the machine's position
+/// within the frame, the next interrupt due and the registers.
+pub fn status(cpu: &Cpu) -> String {
+    format!("Paused - {} - {}", cpu.bus.timing(), registers(cpu))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{bus::SystemBus, cartridge::tests::test_cartridge};
+
+    /// Returns a CPU running the given program from $8000.
+    fn test_cpu(program: &[u8]) -> Cpu<'static> {
+        let mut prg = program.to_vec();
+        prg.resize(0x1000, 0xEA);
+        let cart = test_cartridge(prg, None).unwrap();
+        let mut cpu = Cpu::new(SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {}));
+        cpu.pc = 0x8000;
+        cpu
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("C000".parse(), Ok(Breakpoint::Pc(0xC000)));
+        assert_eq!("pc:$8004".parse(), Ok(Breakpoint::Pc(0x8004)));
+        assert_eq!("read:2002".parse(), Ok(Breakpoint::Read(0x2002)));
+        assert_eq!("WRITE:4016".parse(), Ok(Breakpoint::Write(0x4016)));
+        assert!("exec:C000".parse::<Breakpoint>().is_err());
+        assert!("write:10000".parse::<Breakpoint>().is_err());
+    }
+
+    #[test]
+    fn test_step() {
+        // A run of NOPs, two cycles each.
+        let mut cpu = test_cpu(&[]);
+        let mut debugger = Debugger::new(Vec::new());
+        let cycles = cpu.bus.cpu_cycles();
+        assert_eq!(debugger.step(&mut cpu, Step::Instruction), Stop::Done);
+        assert_eq!(cpu.bus.cpu_cycles(), cycles + 2);
+
+        let (scanline, _) = cpu.bus.ppu_position();
+        debugger.step(&mut cpu, Step::Scanline);
+        let (next, dot) = cpu.bus.ppu_position();
+        assert_eq!(next, scanline + 1);
+        assert!(dot < 6);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // LDA #$01 / STA $0200 / NOP...
+        let mut cpu = test_cpu(&[0xA9, 0x01, 0x8D, 0x00, 0x02]);
+        let mut debugger = Debugger::new(vec![Breakpoint::Pc(0x8010), Breakpoint::Write(0x0200)]);
+        debugger.arm(&mut cpu);
+
+        assert_eq!(
+            debugger.run_frame(&mut cpu),
+            Stop::Breakpoint(Breakpoint::Write(0x0200))
+        );
+        assert_eq!(cpu.pc, 0x8005);
+        assert!(debugger.paused());
+
+        assert_eq!(
+            debugger.run_frame(&mut cpu),
+            Stop::Breakpoint(Breakpoint::Pc(0x8010))
+        );
+
+        // Disabled, the frame runs through.
+        debugger.toggle(&mut cpu);
+        assert_eq!(debugger.run_frame(&mut cpu), Stop::Done);
+    }
+}
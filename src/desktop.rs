@@ -0,0 +1,163 @@
+use std::path::Path;
+
+use crate::icon;
+
+/// Name of the desktop entry, icon and MIME package files.
+const NAME: &str = "res";
+
+/// MIME type of iNES ROMs, as named by shared-mime-info.
+const MIME_TYPE: &str = "application/x-nes-rom";
+
+/// Registers the emulator with the desktop as the handler for .nes files, so
+/// ROMs open in it when double clicked.
+///
+/// Installs an XDG desktop entry, a MIME type for .nes files and the icon
+/// under the user's data directory, e.g. `~/.local/share`, then asks the
+/// desktop to refresh its databases and make the entry the default handler.
+/// The databases are refreshed with the usual command line tools, if they are
+/// installed.
+#[cfg(target_os = "linux")]
+pub fn install() -> Result<(), String> {
+    let data = dirs::data_dir().ok_or("could not find the data directory")?;
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+
+    let files = [
+        (
+            data.join("applications").join(format!("{}.desktop", NAME)),
+            desktop_entry(&exe),
+        ),
+        (
+            data.join("mime")
+                .join("packages")
+                .join(format!("{}.xml", NAME)),
+            mime_package(),
+        ),
+        (
+            data.join("icons/hicolor/scalable/apps")
+                .join(format!("{}.svg", NAME)),
+            icon::svg(),
+        ),
+    ];
+    for (path, contents) in files.iter() {
+        write(path, contents)?;
+        println!("wrote {}", path.display());
+    }
+
+    let desktop_file = format!("{}.desktop", NAME);
+    let mime = data.join("mime");
+    let applications = data.join("applications");
+    let commands: [(&str, Vec<&std::ffi::OsStr>); 3] = [
+        ("update-mime-database", vec![mime.as_os_str()]),
+        ("update-desktop-database", vec![applications.as_os_str()]),
+        (
+            "xdg-mime",
+            vec![
+                "default".as_ref(),
+                desktop_file.as_ref(),
+                MIME_TYPE.as_ref(),
+            ],
+        ),
+    ];
+    for (program, args) in commands {
+        match std::process::Command::new(program).args(args).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("warning: {} failed: {}", program, status),
+            Err(e) => eprintln!("warning: could not run {}: {}", program, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Desktop entries are an XDG standard, used by Linux desktops.
+#[cfg(not(target_os = "linux"))]
+pub fn install() -> Result<(), String> {
+    Err(String::from("desktop entries are only supported on Linux"))
+}
+
+/// Writes the file, creating its directory if needed.
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Returns a desktop entry launching the emulator at the given path with the
+/// opened ROM.
+///
+/// See: https://specifications.freedesktop.org/desktop-entry-spec/latest/
+fn desktop_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=RES\n\
+         GenericName=NES Emulator\n\
+         Comment=A NES emulator implemented in Rust\n\
+         Exec={} --rom %f\n\
+         Icon={}\n\
+         Terminal=false\n\
+         Categories=Game;Emulator;\n\
+         MimeType={};\n\
+         StartupWMClass={}\n",
+        quote_exec(&exe.to_string_lossy()),
+        NAME,
+        MIME_TYPE,
+        NAME
+    )
+}
+
+/// Quotes an argument of the Exec key, escaping the characters which are
+/// reserved inside quotes.
+fn quote_exec(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns a shared-mime-info package matching .nes files. Most systems
+/// already know the type, this covers those which don't.
+fn mime_package() -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  \
+           <mime-type type=\"{}\">\n    \
+             <comment>NES ROM</comment>\n    \
+             <glob pattern=\"*.nes\"/>\n  \
+           </mime-type>\n\
+         </mime-info>\n",
+        MIME_TYPE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_desktop_entry() {
+        let entry = desktop_entry(Path::new("/opt/my games/res"));
+        assert!(entry.starts_with("[Desktop Entry]\n"));
+        assert!(entry.contains("\nExec=\"/opt/my games/res\" --rom %f\n"));
+        assert!(entry.contains("\nMimeType=application/x-nes-rom;\n"));
+    }
+
+    #[test]
+    fn test_quote_exec() {
+        assert_eq!(quote_exec("/usr/bin/res"), "\"/usr/bin/res\"");
+        assert_eq!(quote_exec("/a$b\\c\"d"), "\"/a\\$b\\\\c\\\"d\"");
+    }
+
+    #[test]
+    fn test_mime_package() {
+        let package = mime_package();
+        assert!(package.contains("  <mime-type type=\"application/x-nes-rom\">\n"));
+        assert!(package.contains("    <glob pattern=\"*.nes\"/>\n"));
+    }
+}
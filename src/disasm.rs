@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{AddressingMode, Memory};
+use crate::instructions::{OpCode, OPCODES};
+
+/// Size of the PRG ROM banks listed by [`write_prg_listing`].
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// The operand of an instruction, as written in the assembly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    None,
+    Accumulator,
+    Immediate(u8),
+    ZeroPage(u8),
+    ZeroPageX(u8),
+    ZeroPageY(u8),
+    Absolute(u16),
+    AbsoluteX(u16),
+    AbsoluteY(u16),
+    IndirectX(u8),
+    IndirectY(u8),
+
+    /// The pointer of an indirect JMP.
+    Indirect(u16),
+
+    /// The target of a JMP or JSR.
+    Jump(u16),
+
+    /// The target of a branch, resolved from its offset.
+    Relative(u16),
+}
+
+impl Operand {
+    /// Returns the address the instruction branches or jumps to, if known
+    /// without running it.
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Operand::Jump(addr) | Operand::Relative(addr) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisasmLine {
+    pub addr: u16,
+
+    /// The opcode and operand bytes, of which the first `len` are used.
+    pub bytes: [u8; 3],
+    pub len: u8,
+
+    /// The mnemonic, starting with `*` for unofficial opcodes.
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+
+    /// True if another instruction in the listing branches or jumps here.
+    pub label: bool,
+
+    /// True if the target of the instruction is labelled in the listing.
+    pub to_label: bool,
+}
+
+impl DisasmLine {
+    /// Returns the bytes of the instruction.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    /// Formats the line as a label column, the address, the bytes and the
+    /// assembly, e.g. `L_C004  C004  D0 FC     BNE L_C002`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.label {
+            true => write!(f, "L_{:04X}  ", self.addr)?,
+            false => write!(f, "        ")?,
+        }
+        write!(f, "{:04X} ", self.addr)?;
+        for byte in self.bytes() {
+            write!(f, " {:02X}", byte)?;
+        }
+        write!(f, "{:1$}", "", 3 * (3 - self.len as usize) + 1)?;
+        write!(f, "{: >4}", self.mnemonic)?;
+
+        match (self.operand, self.to_label) {
+            (Operand::None, _) => Ok(()),
+            (Operand::Jump(addr) | Operand::Relative(addr), true) => write!(f, " L_{:04X}", addr),
+            (operand, _) => write!(f, " {}", operand),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::None => Ok(()),
+            Operand::Accumulator => write!(f, "A"),
+            Operand::Immediate(value) => write!(f, "#${:02X}", value),
+            Operand::ZeroPage(addr) => write!(f, "${:02X}", addr),
+            Operand::ZeroPageX(addr) => write!(f, "${:02X},X", addr),
+            Operand::ZeroPageY(addr) => write!(f, "${:02X},Y", addr),
+            Operand::Absolute(addr) | Operand::Jump(addr) | Operand::Relative(addr) => {
+                write!(f, "${:04X}", addr)
+            }
+            Operand::AbsoluteX(addr) => write!(f, "${:04X},X", addr),
+            Operand::AbsoluteY(addr) => write!(f, "${:04X},Y", addr),
+            Operand::IndirectX(addr) => write!(f, "(${:02X},X)", addr),
+            Operand::IndirectY(addr) => write!(f, "(${:02X}),Y", addr),
+            Operand::Indirect(addr) => write!(f, "(${:04X})", addr),
+        }
+    }
+}
+
+/// Returns the instruction at the given address. Every opcode is defined, so
+/// any byte decodes, though data will decode to nonsense.
+pub fn decode<M: Memory + ?Sized>(mem: &mut M, addr: u16) -> DisasmLine {
+    let code = mem.mem_read_byte(addr);
+    let op: &OpCode = OPCODES[&code];
+
+    let mut bytes = [code, 0, 0];
+    for (i, byte) in bytes.iter_mut().enumerate().take(op.len as usize).skip(1) {
+        *byte = mem.mem_read_byte(addr.wrapping_add(i as u16));
+    }
+    let byte = bytes[1];
+    let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+
+    // Branches, jumps and shifts of the accumulator are all implied in the
+    // opcode table, so they are told apart by length and opcode.
+    let operand = match (&op.mode, op.len) {
+        (AddressingMode::Immediate, _) => Operand::Immediate(byte),
+        (AddressingMode::ZeroPage, _) => Operand::ZeroPage(byte),
+        (AddressingMode::ZeroPageX, _) => Operand::ZeroPageX(byte),
+        (AddressingMode::ZeroPageY, _) => Operand::ZeroPageY(byte),
+        (AddressingMode::Absolute, _) => Operand::Absolute(word),
+        (AddressingMode::AbsoluteX, _) => Operand::AbsoluteX(word),
+        (AddressingMode::AbsoluteY, _) => Operand::AbsoluteY(word),
+        (AddressingMode::IndirectX, _) => Operand::IndirectX(byte),
+        (AddressingMode::IndirectY, _) => Operand::IndirectY(byte),
+        (AddressingMode::Implied, 1) => match code {
+            0x0A | 0x4A | 0x2A | 0x6A => Operand::Accumulator,
+            _ => Operand::None,
+        },
+        (AddressingMode::Implied, 2) => {
+            Operand::Relative(addr.wrapping_add(2).wrapping_add(byte as i8 as u16))
+        }
+        (AddressingMode::Implied, _) => match code {
+            0x6C => Operand::Indirect(word),
+            _ => Operand::Jump(word),
+        },
+    };
+
+    DisasmLine {
+        addr,
+        bytes,
+        len: op.len,
+        mnemonic: op.mnemonic,
+        operand,
+        label: false,
+        to_label: false,
+    }
+}
+
+/// Returns the instructions decoded one after another through the range,
+/// with labels at the instructions branched or jumped to from within it.
+/// Memory is read as the CPU would, so ranges covering registers with side
+/// effects on read should be avoided.
+pub fn disassemble<M: Memory + ?Sized>(mem: &mut M, range: RangeInclusive<u16>) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut addr = *range.start();
+    while addr <= *range.end() {
+        let line = decode(mem, addr);
+        lines.push(line);
+        match addr.checked_add(line.len as u16) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+
+    // Only targets at the start of a decoded instruction are labelled.
+    let starts: HashSet<u16> = lines.iter().map(|l| l.addr).collect();
+    let targets: HashSet<u16> = lines
+        .iter()
+        .filter_map(|l| l.operand.target())
+        .filter(|t| starts.contains(t))
+        .collect();
+    for line in &mut lines {
+        line.label = targets.contains(&line.addr);
+        line.to_label = line.operand.target().is_some_and(|t| targets.contains(&t));
+    }
+
+    lines
+}
+
+/// A bank of PRG ROM mapped at an address, for disassembly.
+struct PrgBank<'a> {
+    data: &'a [u8],
+    base: u16,
+}
+
+impl Memory for PrgBank<'_> {
+    /// Returns the byte of the bank at the given address, or 0 outside it.
+    fn mem_read_byte(&mut self, addr: u16) -> u8 {
+        let offset = addr.wrapping_sub(self.base) as usize;
+        self.data.get(offset).copied().unwrap_or(0)
+    }
+
+    /// The bank is read only.
+    fn mem_write_byte(&mut self, _addr: u16, _data: u8) {}
+}
+
+/// Writes a listing of the whole PRG ROM a 16 KB bank at a time. Which bank
+/// is mapped where depends on the board, so the last bank is listed at $C000,
+/// where the vectors are on boards with a fixed last bank, and the others at
+/// $8000. A single bank is listed at $C000, where NROM mirrors it too.
+pub fn write_prg_listing(prg: &[u8], out: &mut dyn Write) -> io::Result<()> {
+    let banks = prg.chunks(PRG_BANK_SIZE).count();
+    for (i, data) in prg.chunks(PRG_BANK_SIZE).enumerate() {
+        let base = if i + 1 == banks { 0xC000 } else { 0x8000 };
+        let end = base + (data.len() - 1) as u16;
+        writeln!(out, "; bank {} at ${:04X}", i, base)?;
+        for line in disassemble(&mut PrgBank { data, base }, base..=end) {
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a bank holding the program at $C000.
+    fn test_bank(program: &[u8]) -> PrgBank<'_> {
+        PrgBank {
+            data: program,
+            base: 0xC000,
+        }
+    }
+
+    #[test]
+    fn test_operands() {
+        let program = [
+            0xA9, 0x01, // LDA #$01
+            0x0A, // ASL A
+            0xB6, 0x10, // LDX $10,Y
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0xB1, 0x20, // LDA ($20),Y
+            0xA7, 0x30, // *LAX $30
+            0x02, // *HLT
+        ];
+        let lines: Vec<String> = disassemble(&mut test_bank(&program), 0xC000..=0xC00F)
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            [
+                "        C000  A9 01     LDA #$01",
+                "        C002  0A        ASL A",
+                "        C003  B6 10     LDX $10,Y",
+                "        C005  9D 00 02  STA $0200,X",
+                "        C008  6C FC FF  JMP ($FFFC)",
+                "        C00B  B1 20     LDA ($20),Y",
+                "        C00D  A7 30    *LAX $30",
+                "        C00F  02       *HLT",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let program = [
+            0xA2, 0x08, // LDX #$08
+            0xCA, // DEX
+            0xD0, 0xFD, // BNE $C002
+            0x20, 0x00, 0xD0, // JSR $D000
+            0x4C, 0x01, 0xC0, // JMP $C001, inside LDX
+        ];
+        let lines = disassemble(&mut test_bank(&program), 0xC000..=0xC00A);
+
+        assert!(lines[1].label);
+        assert_eq!(lines[2].to_string(), "        C003  D0 FD     BNE L_C002");
+        assert_eq!(lines[2].operand.target(), Some(0xC002));
+
+        // Targets outside the range or within an instruction aren't labelled.
+        assert_eq!(lines[3].to_string(), "        C005  20 00 D0  JSR $D000");
+        assert_eq!(lines[4].to_string(), "        C008  4C 01 C0  JMP $C001");
+        assert_eq!(lines[1].to_string(), "L_C002  C002  CA        DEX");
+    }
+
+    #[test]
+    fn test_prg_listing() {
+        let mut prg = vec![0xEA; 2 * PRG_BANK_SIZE];
+        prg[PRG_BANK_SIZE - 1] = 0x20;
+
+        let mut out = Vec::new();
+        write_prg_listing(&prg, &mut out).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+
+        assert!(listing.starts_with("; bank 0 at $8000\n        8000  EA        NOP\n"));
+        assert!(listing.contains("; bank 1 at $C000\n        C000  EA        NOP\n"));
+
+        // An instruction running off the end of a bank is padded with zeros.
+        assert!(listing.contains("        BFFF  20 00 00  JSR $0000\n"));
+    }
+}
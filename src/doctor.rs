@@ -0,0 +1,274 @@
+use std::time::{Duration, Instant};
+
+use sdl2::audio::AudioSpecDesired;
+use spin_sleep::SpinSleeper;
+
+/// Sample rate and buffer size the emulator opens the audio device with by
+/// default.
+const SAMPLE_RATE: i32 = 44100;
+const AUDIO_BUFFER: u16 = 1024;
+
+/// Length of a frame at 60 Hz, the sleep the frame timer most often makes.
+const FRAME: Duration = Duration::from_micros(16_639);
+
+/// Number of sleeps timed to measure the timer resolution.
+const SLEEPS: u32 = 20;
+
+/// Mean oversleep above which frame pacing visibly judders.
+const MAX_OVERSLEEP: Duration = Duration::from_millis(1);
+
+/// Longest the audio device may take to start playing queued samples.
+const AUDIO_START_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Outcome of a single check, with the details it found.
+struct Check {
+    name: &'static str,
+    passed: bool,
+    details: Vec<String>,
+}
+
+/// Checks the video, audio and timer backends the emulator relies on and
+/// prints what was found, to diagnose black windows, missing sound and
+/// juddering without a ROM. Each backend is initialised on its own, so one
+/// failing doesn't hide the state of the others. Returns the process exit
+/// code, which is non-zero if any check failed.
+pub fn run() -> i32 {
+    let checks = [
+        check("video", video()),
+        check("audio", audio()),
+        check("timer", timer()),
+    ];
+
+    print!("{}", report(&checks));
+    if checks.iter().all(|c| c.passed) {
+        0
+    } else {
+        1
+    }
+}
+
+/// Wraps the result of a check, listing the reason first if it failed.
+fn check(name: &'static str, result: Result<Vec<String>, (String, Vec<String>)>) -> Check {
+    match result {
+        Ok(details) => Check {
+            name,
+            passed: true,
+            details,
+        },
+        Err((reason, mut details)) => {
+            details.insert(0, reason);
+            Check {
+                name,
+                passed: false,
+                details,
+            }
+        }
+    }
+}
+
+/// Initialises SDL video, opens a hidden window and creates an accelerated
+/// renderer for it, as the emulator does.
+fn video() -> Result<Vec<String>, (String, Vec<String>)> {
+    let mut details = vec![format!(
+        "available drivers: {}",
+        sdl2::video::drivers().collect::<Vec<_>>().join(", ")
+    )];
+    let fail = |e: String, details: &Vec<String>| (e, details.clone());
+
+    let sdl = sdl2::init().map_err(|e| fail(e, &details))?;
+    let video = sdl.video().map_err(|e| fail(e, &details))?;
+    details.push(format!("driver: {}", video.current_video_driver()));
+    if let Ok(mode) = video.current_display_mode(0) {
+        details.push(format!(
+            "display: {}x{} at {} Hz",
+            mode.w, mode.h, mode.refresh_rate
+        ));
+    }
+
+    let window = video
+        .window("RES doctor", 256, 240)
+        .hidden()
+        .build()
+        .map_err(|e| fail(e.to_string(), &details))?;
+    let canvas = window
+        .into_canvas()
+        .accelerated()
+        .present_vsync()
+        .build()
+        .map_err(|e| fail(e.to_string(), &details))?;
+    details.push(format!("renderer: {}", canvas.info().name));
+
+    Ok(details)
+}
+
+/// Initialises SDL audio, opens the default device with the emulator's
+/// settings and measures how long it takes to start playing.
+fn audio() -> Result<Vec<String>, (String, Vec<String>)> {
+    let mut details = vec![format!(
+        "available drivers: {}",
+        sdl2::audio::drivers().collect::<Vec<_>>().join(", ")
+    )];
+    let fail = |e: String, details: &Vec<String>| (e, details.clone());
+
+    let sdl = sdl2::init().map_err(|e| fail(e, &details))?;
+    let audio = sdl.audio().map_err(|e| fail(e, &details))?;
+    details.push(format!("driver: {}", audio.current_audio_driver()));
+    let devices = (0..audio.num_audio_playback_devices().unwrap_or(0))
+        .filter_map(|i| audio.audio_playback_device_name(i).ok())
+        .collect::<Vec<_>>();
+    details.push(format!("devices: {}", devices.join(", ")));
+
+    let spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(AUDIO_BUFFER),
+    };
+    let queue = audio
+        .open_queue::<f32, _>(None, &spec)
+        .map_err(|e| fail(e, &details))?;
+    let obtained = queue.spec();
+    details.push(format!(
+        "buffer: {} samples at {} Hz, {:.1} ms",
+        obtained.samples,
+        obtained.freq,
+        obtained.samples as f64 * 1000.0 / obtained.freq as f64
+    ));
+
+    // The queue only shrinks once the device pulls samples from it.
+    let silence = vec![0.0; obtained.freq as usize / 4];
+    queue.queue_audio(&silence).map_err(|e| fail(e, &details))?;
+    let queued = queue.size();
+    let start = Instant::now();
+    queue.resume();
+    while queue.size() == queued {
+        if start.elapsed() > AUDIO_START_TIMEOUT {
+            return Err(fail(
+                String::from("the device did not play queued samples"),
+                &details,
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    details.push(format!("device started after {}", millis(start.elapsed())));
+
+    Ok(details)
+}
+
+/// Measures how far a native sleep and the spin sleeper used by the frame
+/// timer overshoot a frame.
+fn timer() -> Result<Vec<String>, (String, Vec<String>)> {
+    let sleeper = SpinSleeper::default();
+    let native = measure(SLEEPS, FRAME, std::thread::sleep);
+    let spin = measure(SLEEPS, FRAME, |d| sleeper.sleep(d));
+
+    let details = vec![
+        format!(
+            "native sleep oversleeps {} on average, {} at most",
+            millis(native.mean),
+            millis(native.max)
+        ),
+        format!(
+            "spin sleep oversleeps {} on average, {} at most",
+            millis(spin.mean),
+            millis(spin.max)
+        ),
+    ];
+
+    if spin.mean > MAX_OVERSLEEP {
+        return Err((
+            String::from("the timer is too coarse for smooth frame pacing"),
+            details,
+        ));
+    }
+    Ok(details)
+}
+
+/// How far sleeps overshot the requested duration.
+#[derive(Debug, PartialEq)]
+struct Oversleep {
+    mean: Duration,
+    max: Duration,
+}
+
+/// Times the given number of sleeps of the given duration.
+fn measure(sleeps: u32, dur: Duration, sleep: impl Fn(Duration)) -> Oversleep {
+    let mut total = Duration::ZERO;
+    let mut max = Duration::ZERO;
+    for _ in 0..sleeps {
+        let start = Instant::now();
+        sleep(dur);
+        let over = start.elapsed().saturating_sub(dur);
+        total += over;
+        max = max.max(over);
+    }
+
+    Oversleep {
+        mean: total / sleeps.max(1),
+        max,
+    }
+}
+
+/// Formats a duration in milliseconds.
+fn millis(dur: Duration) -> String {
+    format!("{:.2} ms", dur.as_secs_f64() * 1000.0)
+}
+
+/// Formats the checks as a table followed by a summary line.
+fn report(checks: &[Check]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        let status = if check.passed { "OK" } else { "FAIL" };
+        out += &format!("{:<6}{}\n", check.name, status);
+        for detail in &check.details {
+            out += &format!("      {}\n", detail);
+        }
+    }
+
+    let passed = checks.iter().filter(|c| c.passed).count();
+    out += &format!("{} of {} checks passed\n", passed, checks.len());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let oversleep = measure(4, Duration::from_millis(1), |_| {});
+        assert_eq!(
+            oversleep,
+            Oversleep {
+                mean: Duration::ZERO,
+                max: Duration::ZERO,
+            }
+        );
+
+        let oversleep = measure(2, Duration::ZERO, |_| {
+            std::thread::sleep(Duration::from_millis(2))
+        });
+        assert!(oversleep.max >= Duration::from_millis(2));
+        assert!(oversleep.mean <= oversleep.max);
+    }
+
+    #[test]
+    fn test_report() {
+        let checks = [
+            check("video", Ok(vec![String::from("driver: x11")])),
+            check(
+                "audio",
+                Err((
+                    String::from("no device"),
+                    vec![String::from("driver: dummy")],
+                )),
+            ),
+        ];
+
+        assert_eq!(
+            report(&checks),
+            "video OK\n      driver: x11\n\
+             audio FAIL\n      no device\n      driver: dummy\n\
+             1 of 2 checks passed\n"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+/// Checksums (CRC32 of the PRG and CHR data, excluding the header) of ROM
+/// images known to be bad dumps, with a description of the problem.
+///
+/// Entries should only be added once the checksum has been verified against
+/// a dump database such as No-Intro or the NesCartDB.
+const BAD_DUMPS: &[(u32, &str)] = &[];
+
+/// Returns a description of the problem if the ROM with the given checksum is
+/// a known bad dump.
+pub fn check(checksum: u32) -> Option<&'static str> {
+    find(BAD_DUMPS, checksum)
+}
+
+/// Returns the description of the given checksum in the list.
+fn find(list: &[(u32, &'static str)], checksum: u32) -> Option<&'static str> {
+    list.iter()
+        .find(|(crc, _)| *crc == checksum)
+        .map(|(_, reason)| *reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let list = [(0xDEADBEEF, "overdump"), (0x12345678, "bad CHR")];
+
+        assert_eq!(find(&list, 0x12345678), Some("bad CHR"));
+        assert_eq!(find(&list, 0x00000000), None);
+    }
+}
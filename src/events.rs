@@ -0,0 +1,80 @@
+use std::fmt;
+
+use crate::{cartridge::Mirroring, region::Region};
+
+/// Notable events in the emulator core, queued on the bus for the frontend
+/// to collect once per frame, see [`SystemBus::events`].
+///
+/// [`SystemBus::events`]: crate::bus::SystemBus::events
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoreEvent {
+    /// A ROM was loaded, described by its header.
+    RomLoaded {
+        mapper: u16,
+        mirroring: Mirroring,
+        prg_kb: usize,
+        chr_kb: usize,
+        battery: bool,
+        region: Region,
+    },
+
+    /// A save state was restored.
+    StateLoaded,
+
+    /// A line of text written to the debug console.
+    DebugOutput(String),
+
+    /// The program asked to exit with the given code through the debug
+    /// console.
+    DebugExit(u8),
+}
+
+impl fmt::Display for CoreEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreEvent::RomLoaded {
+                mapper,
+                mirroring,
+                prg_kb,
+                chr_kb,
+                battery,
+                region,
+            } => {
+                write!(f, "mapper {}, {} KB PRG ROM, ", mapper, prg_kb)?;
+                match chr_kb {
+                    0 => write!(f, "CHR RAM")?,
+                    _ => write!(f, "{} KB CHR ROM", chr_kb)?,
+                }
+                write!(f, ", {:?} mirroring, {:?}", mirroring, region)?;
+                if *battery {
+                    write!(f, ", battery")?;
+                }
+                Ok(())
+            }
+            CoreEvent::StateLoaded => write!(f, "state loaded"),
+            CoreEvent::DebugOutput(line) => write!(f, "{}", line),
+            CoreEvent::DebugExit(code) => write!(f, "exit with code {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let event = CoreEvent::RomLoaded {
+            mapper: 1,
+            mirroring: Mirroring::Horizontal,
+            prg_kb: 128,
+            chr_kb: 0,
+            battery: true,
+            region: Region::Ntsc,
+        };
+        assert_eq!(
+            event.to_string(),
+            "mapper 1, 128 KB PRG ROM, CHR RAM, Horizontal mirroring, Ntsc, battery"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::PathBuf;
+
+/// Represents the position and size of the emulator window, saved on exit so
+/// the window reopens where it was left.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Geometry {
+    /// Loads the geometry from the file at the given path, or None if the
+    /// file is missing or invalid.
+    pub fn load(path: Option<PathBuf>) -> Option<Self> {
+        let contents = std::fs::read_to_string(path?).ok()?;
+        parse(&contents)
+    }
+
+    /// Returns the default location of the window geometry file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("res").join("window.txt"))
+    }
+
+    /// Writes the geometry to the file at the given path.
+    pub fn save(&self, path: Option<PathBuf>) -> Result<(), String> {
+        let Some(path) = path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        std::fs::write(path, format(self)).map_err(|e| e.to_string())
+    }
+}
+
+/// Width of a NES pixel relative to its height, as shown on a TV.
+pub const PIXEL_ASPECT: f64 = 8.0 / 7.0;
+
+/// Returns the largest rect which is a whole multiple of the frame's height
+/// and fits in the window, centred, with pixels drawn `pixel_aspect` times as
+/// wide as they are tall. Windows too small for the frame at 1x fall back to
+/// [`fit`].
+pub fn fit_integer(
+    frame: (u32, u32),
+    pixel_aspect: f64,
+    window: (u32, u32),
+) -> (i32, i32, u32, u32) {
+    let width = |scale: u32| (frame.0 as f64 * scale as f64 * pixel_aspect).round() as u32;
+    let scale = (1..)
+        .take_while(|&s| width(s) <= window.0 && frame.1 * s <= window.1)
+        .last();
+
+    let Some(scale) = scale else {
+        return fit((width(1), frame.1), window);
+    };
+    let (w, h) = (width(scale), frame.1 * scale);
+    let x = (window.0 as i32 - w as i32) / 2;
+    let y = (window.1 as i32 - h as i32) / 2;
+    (x, y, w, h)
+}
+
+/// Returns the largest rect with the frame's aspect ratio which fits in the
+/// window, centred, as its x, y, width and height.
+pub fn fit(frame: (u32, u32), window: (u32, u32)) -> (i32, i32, u32, u32) {
+    let scale = f64::min(
+        window.0 as f64 / frame.0 as f64,
+        window.1 as f64 / frame.1 as f64,
+    );
+    let w = ((frame.0 as f64 * scale).round() as u32).max(1);
+    let h = ((frame.1 as f64 * scale).round() as u32).max(1);
+
+    let x = (window.0 as i32 - w as i32) / 2;
+    let y = (window.1 as i32 - h as i32) / 2;
+    (x, y, w, h)
+}
+
+/// Parses lines of "<field>=<value>", every field is required.
+fn parse(contents: &str) -> Option<Geometry> {
+    let field = |name: &str| {
+        contents
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim())
+    };
+
+    let geometry = Geometry {
+        x: field("x")?.parse().ok()?,
+        y: field("y")?.parse().ok()?,
+        w: field("w")?.parse().ok()?,
+        h: field("h")?.parse().ok()?,
+    };
+
+    (geometry.w > 0 && geometry.h > 0).then_some(geometry)
+}
+
+/// Formats the geometry as lines of "<field>=<value>".
+fn format(geometry: &Geometry) -> String {
+    format!(
+        "x={}\ny={}\nw={}\nh={}\n",
+        geometry.x, geometry.y, geometry.w, geometry.h
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        let geometry = Geometry {
+            x: -10,
+            y: 20,
+            w: 800,
+            h: 600,
+        };
+        assert_eq!(parse(&format(&geometry)), Some(geometry));
+    }
+
+    #[test]
+    fn test_parse_rejects_incomplete() {
+        assert_eq!(parse("x=1\ny=2\nw=3\n"), None);
+        assert_eq!(parse("x=1\ny=2\nw=0\nh=4\n"), None);
+    }
+
+    #[test]
+    fn test_fit() {
+        // Exact multiples fill the window.
+        assert_eq!(fit((256, 240), (768, 720)), (0, 0, 768, 720));
+
+        // Wider windows are pillarboxed and taller ones letterboxed.
+        assert_eq!(fit((256, 240), (1000, 480)), (244, 0, 512, 480));
+        assert_eq!(fit((256, 240), (512, 600)), (0, 60, 512, 480));
+    }
+
+    #[test]
+    fn test_fit_integer() {
+        // 3x is the largest multiple which fits, letterboxed on all sides.
+        assert_eq!(
+            fit_integer((256, 240), PIXEL_ASPECT, (1000, 800)),
+            (61, 40, 878, 720)
+        );
+
+        // The 8:7 pixels make 3x too wide for a 768x720 window.
+        assert_eq!(
+            fit_integer((256, 240), PIXEL_ASPECT, (768, 720)),
+            (91, 120, 585, 480)
+        );
+
+        // Windows smaller than 1x are fitted with the pixel aspect kept.
+        assert_eq!(
+            fit_integer((256, 240), PIXEL_ASPECT, (200, 200)),
+            (0, 18, 200, 164)
+        );
+    }
+}
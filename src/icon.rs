@@ -0,0 +1,109 @@
+/// Width and height of the icon artwork in pixels.
+pub const SIZE: usize = 16;
+
+/// Factor the artwork is scaled up by for the window icon.
+pub const SCALE: usize = 2;
+
+/// The application icon, a NES controller, one character per pixel. Each
+/// character is looked up in `COLOURS`, '.' is transparent.
+const ART: [&str; SIZE] = [
+    "................",
+    "................",
+    "................",
+    "................",
+    "................",
+    "gggggggggggggggg",
+    "gkkkkkkkkkkkkkkg",
+    "gkkgkkkkkkkkkkkg",
+    "gkgggkkddkrrkrrg",
+    "gkkgkkkddkrrkrrg",
+    "gkkkkkkkkkkkkkkg",
+    "gggggggggggggggg",
+    "................",
+    "................",
+    "................",
+    "................",
+];
+
+/// RGB colour of each character used in the artwork.
+const COLOURS: [(char, [u8; 3]); 4] = [
+    ('g', [0xBC, 0xBC, 0xBC]),
+    ('k', [0x24, 0x24, 0x24]),
+    ('d', [0x74, 0x74, 0x74]),
+    ('r', [0xD8, 0x28, 0x00]),
+];
+
+/// Returns the colour of the pixel at the given position, or None if it is
+/// transparent.
+fn pixel(x: usize, y: usize) -> Option<[u8; 3]> {
+    let c = ART[y].as_bytes()[x] as char;
+    COLOURS.iter().find(|(k, _)| *k == c).map(|(_, rgb)| *rgb)
+}
+
+/// Returns the icon as RGBA pixels, scaled up by `SCALE`.
+pub fn rgba() -> Vec<u8> {
+    let size = SIZE * SCALE;
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            match pixel(x / SCALE, y / SCALE) {
+                Some([r, g, b]) => pixels.extend([r, g, b, 0xFF]),
+                None => pixels.extend([0; 4]),
+            }
+        }
+    }
+    pixels
+}
+
+/// Returns the icon as an SVG image, for desktop environments.
+pub fn svg() -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" shape-rendering=\"crispEdges\">\n",
+        SIZE
+    );
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            if let Some([r, g, b]) = pixel(x, y) {
+                svg += &format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" fill=\"#{:02X}{:02X}{:02X}\"/>\n",
+                    x, y, r, g, b
+                );
+            }
+        }
+    }
+    svg + "</svg>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgba() {
+        let pixels = rgba();
+        let size = SIZE * SCALE;
+        assert_eq!(pixels.len(), size * size * 4);
+
+        // The corners are transparent, the body is opaque.
+        assert_eq!(pixels[..4], [0; 4]);
+        let body = (6 * SCALE * size + 4 * SCALE) * 4;
+        assert_eq!(pixels[body..body + 4], [0x24, 0x24, 0x24, 0xFF]);
+    }
+
+    #[test]
+    fn test_art_uses_known_colours() {
+        for row in ART {
+            assert_eq!(row.len(), SIZE);
+            assert!(row
+                .chars()
+                .all(|c| c == '.' || COLOURS.iter().any(|(k, _)| *k == c)));
+        }
+    }
+
+    #[test]
+    fn test_svg() {
+        let svg = svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("<rect x=\"0\" y=\"5\" width=\"1\" height=\"1\" fill=\"#BCBCBC\"/>"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+/// What happens when an injection fires.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Raises an NMI, as if the PPU had entered vblank.
+    Nmi,
+
+    /// Writes the value to the CPU address, as if the program had stored it.
+    Write(u16, u8),
+}
+
+/// An action fired at the same PPU scanline and dot every frame, for
+/// prototyping raster effects such as split scrolling or palette changes
+/// without rebuilding the ROM. Scanline -1 is the pre-render line, lines past
+/// the end of the region's frame never fire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Injection {
+    pub scanline: i32,
+    pub dot: usize,
+    pub action: Action,
+}
+
+impl FromStr for Injection {
+    type Err = String;
+
+    /// Parses `<scanline>,<dot>:nmi` or `<scanline>,<dot>:<addr>=<value>`,
+    /// with the address and value in hex, e.g. `120,256:2001=1E`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid injection {:?}", s);
+
+        let (position, action) = s.split_once(':').ok_or_else(invalid)?;
+        let (scanline, dot) = position.split_once(',').ok_or_else(invalid)?;
+        let scanline: i32 = scanline.trim().parse().map_err(|_| invalid())?;
+        let dot: usize = dot.trim().parse().map_err(|_| invalid())?;
+        if !(-1..=310).contains(&scanline) || dot > 340 {
+            return Err(format!("{} is not a PPU scanline and dot", position));
+        }
+
+        let action = match action.trim().split_once('=') {
+            None if action.trim().eq_ignore_ascii_case("nmi") => Action::Nmi,
+            Some((addr, value)) => {
+                let addr = u16::from_str_radix(hex(addr), 16).map_err(|_| invalid())?;
+                let value = u8::from_str_radix(hex(value), 16).map_err(|_| invalid())?;
+                Action::Write(addr, value)
+            }
+            None => return Err(invalid()),
+        };
+
+        Ok(Injection {
+            scanline,
+            dot,
+            action,
+        })
+    }
+}
+
+/// Returns the digits of a hex number, optionally prefixed with $.
+fn hex(s: &str) -> &str {
+    s.trim().trim_start_matches('$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "120,256:2001=1E".parse(),
+            Ok(Injection {
+                scanline: 120,
+                dot: 256,
+                action: Action::Write(0x2001, 0x1E),
+            })
+        );
+        assert_eq!(
+            "-1, 0 : NMI".parse(),
+            Ok(Injection {
+                scanline: -1,
+                dot: 0,
+                action: Action::Nmi,
+            })
+        );
+        assert_eq!(
+            "10,5:$2005=$80".parse::<Injection>().map(|i| i.action),
+            Ok(Action::Write(0x2005, 0x80))
+        );
+
+        assert!("120:nmi".parse::<Injection>().is_err());
+        assert!("120,341:nmi".parse::<Injection>().is_err());
+        assert!("311,0:nmi".parse::<Injection>().is_err());
+        assert!("120,0:2001".parse::<Injection>().is_err());
+        assert!("120,0:2001=100".parse::<Injection>().is_err());
+    }
+}
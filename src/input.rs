@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+/// Queues joypad button changes from the frontend so they are applied one
+/// emulated frame at a time, rather than whenever events are pumped.
+///
+/// When several frames are emulated per pump, e.g. when fast-forwarding, a
+/// press and release of the same button can arrive together. Applying them
+/// immediately would leave the button pressed for no frames at all, so a
+/// change to a button already changed this frame waits for the next one.
+///
+/// Each change is stamped with the scanline of the frame it arrived during,
+/// so it can also be applied part way through the frame, see
+/// [`InputQueue::next_frame_timed`].
+#[derive(Default)]
+pub struct InputQueue {
+    changes: VecDeque<(u8, bool, u16)>,
+}
+
+impl InputQueue {
+    /// Queues a press or release of the given button, which arrived during
+    /// the given scanline of the frame.
+    pub fn push(&mut self, button: u8, pressed: bool, scanline: u16) {
+        self.changes.push_back((button, pressed, scanline));
+    }
+
+    /// Applies the queued changes for the next frame to the given button
+    /// state, returning the new state.
+    pub fn next_frame(&mut self, buttons: u8) -> u8 {
+        self.apply(buttons, |_, _| {})
+    }
+
+    /// Applies the queued changes for the next frame like
+    /// [`InputQueue::next_frame`], also adding the button state after each
+    /// change to `timed` along with the scanline it arrived during. Changes
+    /// held over from an earlier frame are timed no earlier than those
+    /// before them, so the scanlines never go backwards.
+    pub fn next_frame_timed(&mut self, buttons: u8, timed: &mut Vec<(u16, u8)>) -> u8 {
+        let mut last = 0;
+        self.apply(buttons, |scanline, buttons| {
+            last = scanline.max(last);
+            timed.push((last, buttons));
+        })
+    }
+
+    /// Drops every queued change.
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Applies the changes for the next frame, calling `changed` with the
+    /// scanline and button state after each.
+    fn apply(&mut self, mut buttons: u8, mut changed: impl FnMut(u16, u8)) -> u8 {
+        let mut seen = 0;
+
+        while let Some(&(button, pressed, scanline)) = self.changes.front() {
+            if seen & button != 0 {
+                break;
+            }
+            self.changes.pop_front();
+
+            match pressed {
+                true => buttons |= button,
+                false => buttons &= !button,
+            }
+            seen |= button;
+            changed(scanline, buttons);
+        }
+
+        buttons
+    }
+}
+
+/// Returns the scanline of a frame with the given number of lines that an
+/// event arrived during, placing the time between two event pumps across the
+/// frame. Times are in milliseconds, as SDL stamps events.
+pub fn arrival_scanline(time: u32, last_pump: u32, pump: u32, lines: u16) -> u16 {
+    let span = pump.saturating_sub(last_pump);
+    if span == 0 {
+        return 0;
+    }
+
+    let elapsed = time.clamp(last_pump, pump) - last_pump;
+    let scanline = elapsed as u64 * lines as u64 / span as u64;
+    (scanline as u16).min(lines.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::{JOYPAD_BUTTON_A, JOYPAD_START};
+
+    #[test]
+    fn test_tap_lasts_a_frame() {
+        let mut input = InputQueue::default();
+        input.push(JOYPAD_BUTTON_A, true, 0);
+        input.push(JOYPAD_START, true, 0);
+        input.push(JOYPAD_BUTTON_A, false, 0);
+
+        let buttons = input.next_frame(0);
+        assert_eq!(buttons, JOYPAD_BUTTON_A | JOYPAD_START);
+        assert_eq!(input.next_frame(buttons), JOYPAD_START);
+        assert_eq!(input.next_frame(JOYPAD_START), JOYPAD_START);
+    }
+
+    #[test]
+    fn test_next_frame_timed() {
+        let mut input = InputQueue::default();
+        input.push(JOYPAD_BUTTON_A, true, 100);
+        input.push(JOYPAD_START, true, 150);
+        input.push(JOYPAD_BUTTON_A, false, 20);
+
+        let mut timed = Vec::new();
+        let buttons = input.next_frame_timed(0, &mut timed);
+        assert_eq!(buttons, JOYPAD_BUTTON_A | JOYPAD_START);
+        assert_eq!(
+            timed,
+            [
+                (100, JOYPAD_BUTTON_A),
+                (150, JOYPAD_BUTTON_A | JOYPAD_START)
+            ]
+        );
+
+        // The held over release keeps its own scanline in the next frame.
+        timed.clear();
+        assert_eq!(input.next_frame_timed(buttons, &mut timed), JOYPAD_START);
+        assert_eq!(timed, [(20, JOYPAD_START)]);
+    }
+
+    #[test]
+    fn test_arrival_scanline() {
+        assert_eq!(arrival_scanline(1000, 1000, 1016, 262), 0);
+        assert_eq!(arrival_scanline(1008, 1000, 1016, 262), 131);
+        assert_eq!(arrival_scanline(1016, 1000, 1016, 262), 261);
+
+        // Events stamped outside the pumps are clamped to the frame.
+        assert_eq!(arrival_scanline(990, 1000, 1016, 262), 0);
+        assert_eq!(arrival_scanline(1030, 1000, 1016, 262), 261);
+
+        // Pumps in the same millisecond can't be told apart.
+        assert_eq!(arrival_scanline(1000, 1000, 1000, 262), 0);
+    }
+}
@@ -1,3 +1,7 @@
+use std::io::{self, Read, Write};
+
+use crate::state::{read_bool, read_u8, write_bool, write_u8, SaveState};
+
 pub const JOYPAD_RIGHT: u8 = 0b10000000;
 pub const JOYPAD_LEFT: u8 = 0b01000000;
 pub const JOYPAD_DOWN: u8 = 0b00100000;
@@ -26,6 +30,12 @@ pub struct Joypad {
     button_status: u8,
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     /// Returns an instantiated joypad.
     pub fn new() -> Self {
@@ -60,6 +70,16 @@ impl Joypad {
         response
     }
 
+    /// Returns the pressed state of every button.
+    pub fn buttons(&self) -> u8 {
+        self.button_status
+    }
+
+    /// Sets the pressed state of every button.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.button_status = buttons;
+    }
+
     /// Sets the pressed state of the given button.
     pub fn set_button_pressed_status(&mut self, button: u8, pressed: bool) {
         if pressed {
@@ -70,6 +90,21 @@ impl Joypad {
     }
 }
 
+impl SaveState for Joypad {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.strobe)?;
+        write_u8(w, self.button_index)?;
+        write_u8(w, self.button_status)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.strobe = read_bool(r)?;
+        self.button_index = read_u8(r)?;
+        self.button_status = read_u8(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
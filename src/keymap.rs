@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+
+use crate::joypad;
+
+/// Joypad buttons in the order they are prompted for when remapping.
+pub const BUTTONS: [(&str, u8); 8] = [
+    ("Up", joypad::JOYPAD_UP),
+    ("Down", joypad::JOYPAD_DOWN),
+    ("Left", joypad::JOYPAD_LEFT),
+    ("Right", joypad::JOYPAD_RIGHT),
+    ("Select", joypad::JOYPAD_SELECT),
+    ("Start", joypad::JOYPAD_START),
+    ("A", joypad::JOYPAD_BUTTON_A),
+    ("B", joypad::JOYPAD_BUTTON_B),
+];
+
+/// Number of controller ports with key bindings.
+pub const PORTS: usize = 2;
+
+/// Default key for each button of each controller, in the same order as
+/// `BUTTONS`.
+const DEFAULT_KEYS: [[&str; 8]; PORTS] = [
+    ["Up", "Down", "Left", "Right", "Space", "Return", "A", "S"],
+    ["I", "K", "J", "L", "U", "O", "M", "N"],
+];
+
+/// Represents the keyboard binding of each joypad button. Keys are stored by
+/// their SDL name so the file stays readable.
+pub struct KeyMap {
+    path: Option<PathBuf>,
+    keys: [String; 8],
+}
+
+impl KeyMap {
+    /// Loads the bindings of the controller in the given port from the file
+    /// at the given path. A missing file or button falls back to the default
+    /// key.
+    pub fn load(port: usize, path: Option<PathBuf>) -> Self {
+        let contents = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .unwrap_or_default();
+
+        KeyMap {
+            path,
+            keys: parse(&contents, port),
+        }
+    }
+
+    /// Returns the default location of the key bindings file of the
+    /// controller in the given port, `keys.txt` for the first and
+    /// `keys2.txt` for the second.
+    pub fn default_path(port: usize) -> Option<PathBuf> {
+        let name = match port {
+            0 => String::from("keys.txt"),
+            _ => format!("keys{}.txt", port + 1),
+        };
+        dirs::config_dir().map(|d| d.join("res").join(name))
+    }
+
+    /// Returns the key name and joypad button of each binding.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.keys
+            .iter()
+            .zip(BUTTONS)
+            .map(|(key, (_, button))| (key.as_str(), button))
+    }
+
+    /// Replaces every binding and writes them back to the file they were
+    /// loaded from.
+    pub fn set(&mut self, keys: [String; 8]) -> Result<(), String> {
+        self.keys = keys;
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        std::fs::write(path, format(&self.keys)).map_err(|e| e.to_string())
+    }
+}
+
+/// Captures keys one button at a time for press-to-bind remapping.
+pub struct Remap {
+    port: usize,
+    keys: Vec<String>,
+}
+
+impl Remap {
+    /// Starts remapping the controller in the given port.
+    pub fn new(port: usize) -> Self {
+        Remap {
+            port,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Returns the controller port being remapped.
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    /// Returns the prompt for the next button.
+    pub fn prompt(&self) -> String {
+        format!(
+            "Press key for player {} {}...",
+            self.port + 1,
+            BUTTONS[self.keys.len()].0
+        )
+    }
+
+    /// Binds the key to the next button, returning every binding once the
+    /// last button has been bound.
+    pub fn bind(&mut self, key: &str) -> Option<[String; 8]> {
+        self.keys.push(key.to_string());
+        if self.keys.len() < BUTTONS.len() {
+            return None;
+        }
+
+        std::mem::take(&mut self.keys).try_into().ok()
+    }
+}
+
+/// Parses lines of "<button>=<key>", falling back to the default keys of the
+/// given port.
+fn parse(contents: &str, port: usize) -> [String; 8] {
+    let mut keys = DEFAULT_KEYS[port].map(String::from);
+
+    for line in contents.lines() {
+        let Some((button, key)) = line.split_once('=') else {
+            continue;
+        };
+
+        if let Some(i) = BUTTONS.iter().position(|(name, _)| *name == button.trim()) {
+            keys[i] = key.trim().to_string();
+        }
+    }
+
+    keys
+}
+
+/// Formats the keys as lines of "<button>=<key>".
+fn format(keys: &[String; 8]) -> String {
+    BUTTONS
+        .iter()
+        .zip(keys)
+        .map(|((button, _), key)| format!("{}={}\n", button, key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        let mut keys = DEFAULT_KEYS[0].map(String::from);
+        keys[6] = String::from("Left Shift");
+
+        let contents = format(&keys);
+        assert!(contents.contains("A=Left Shift\n"));
+        assert_eq!(parse(&contents, 0), keys);
+    }
+
+    #[test]
+    fn test_parse_falls_back_to_defaults() {
+        let keys = parse("nonsense\nStart=Z\nTurbo=X\n", 0);
+        assert_eq!(keys[5], "Z");
+        assert_eq!(keys[0], "Up");
+
+        let keys = parse("Start=Z\n", 1);
+        assert_eq!(keys[5], "Z");
+        assert_eq!(keys[0], "I");
+    }
+
+    #[test]
+    fn test_default_keys_are_distinct() {
+        let keys: Vec<_> = DEFAULT_KEYS.iter().flatten().collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "{} is bound twice", key);
+        }
+    }
+
+    #[test]
+    fn test_remap() {
+        let mut remap = Remap::new(0);
+        assert_eq!(remap.prompt(), "Press key for player 1 Up...");
+
+        for key in ["W", "S", "A", "D", "Q", "E", "K"] {
+            assert!(remap.bind(key).is_none());
+        }
+        assert_eq!(remap.prompt(), "Press key for player 1 B...");
+        assert_eq!(Remap::new(1).prompt(), "Press key for player 2 Up...");
+
+        let keys = remap.bind("J").unwrap();
+        assert_eq!(keys[0], "W");
+        assert_eq!(keys[7], "J");
+    }
+}
@@ -0,0 +1,60 @@
+//! The emulator core, free of any windowing or audio library so it can be
+//! embedded in other front ends, fuzzers and test harnesses. The `res`
+//! binary is an SDL2 front end built on it.
+//!
+//! ```no_run
+//! use res::{cartridge::Cartridge, nes::NesBuilder};
+//!
+//! let bytes = std::fs::read("game.nes").unwrap();
+//! let cart = Cartridge::new(&bytes).unwrap();
+//! let mut cpu = NesBuilder::new().build(cart, |pixels: &[u8]| {
+//!     // 256x240 RGB24 pixels of each frame.
+//! });
+//!
+//! // Run a frame.
+//! let frame_count = cpu.bus.ppu_frame_count();
+//! while cpu.bus.ppu_frame_count() == frame_count {
+//!     cpu.clock();
+//! }
+//! ```
+
+#[cfg(all(test, debug_assertions))]
+mod alloc_count;
+pub mod apu;
+pub mod avsync;
+pub mod bus;
+pub mod cartridge;
+pub mod config;
+pub mod cpu;
+pub mod crash;
+pub mod debugger;
+pub mod disasm;
+pub mod dumps;
+pub mod events;
+pub mod filters;
+pub mod inject;
+pub mod input;
+pub mod instructions;
+pub mod joypad;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+#[cfg(feature = "opstats")]
+pub mod opstats;
+pub mod pipe;
+pub mod ppu;
+#[cfg(feature = "ppuprof")]
+pub mod ppuprof;
+pub mod rawbin;
+pub mod region;
+pub mod rewind;
+pub mod rom;
+pub mod selfcheck;
+pub mod sram;
+pub mod state;
+pub mod stems;
+pub mod testsuite;
+#[cfg(feature = "image")]
+pub mod thumbs;
+pub mod trace;
+pub mod vs;
@@ -1,40 +1,77 @@
 extern crate core;
 
-mod apu;
-mod bus;
-mod cartridge;
-mod cpu;
-mod filters;
-mod instructions;
-mod joypad;
-mod mapper;
-mod ppu;
-mod rom;
+mod audiosync;
+mod autosave;
+mod desktop;
+mod doctor;
+mod geometry;
+mod icon;
+mod keymap;
+mod present;
+mod scale;
+mod session;
+mod stats;
 mod timer;
-mod trace;
 
-use bus::SystemBus;
+#[cfg(feature = "opstats")]
+use res::opstats;
+#[cfg(feature = "ppuprof")]
+use res::ppuprof;
+#[cfg(feature = "image")]
+use res::thumbs;
+use res::{
+    apu, avsync, cartridge, config, cpu, crash, debugger, disasm, events, inject, input, joypad,
+    movie, nes, pipe, ppu, rawbin, region, rewind, rom, selfcheck, sram, state, stems, testsuite,
+    trace, vs,
+};
+
+use apu::Panning;
+use audiosync::AudioSync;
+use autosave::Autosave;
 use cartridge::Cartridge;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::EmuConfig;
 use cpu::Cpu;
+use debugger::{Breakpoint, Debugger, Step, Stop};
+use events::CoreEvent;
+use geometry::Geometry;
+use inject::Injection;
+use input::InputQueue;
+use keymap::{KeyMap, Remap, PORTS};
+use movie::{Event as MovieEvent, Movie};
+use nes::NesBuilder;
+use ppu::{FrameStatus, MemoryInit, OamEntry, RasterMarkers};
+use present::{FramePolicy, FrameQueue, PresentGate, Presenter, Show};
+use region::Region;
+use rewind::Rewind;
+use rom::Rom;
+use scale::ScaleFilter;
 use sdl2::audio::AudioSpecDesired;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+use sdl2::video::{FullscreenType, Window};
+use session::Session;
+use stats::PlayTime;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::time::Duration;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use stems::{Stems, STEM_NAMES};
 use timer::Timer;
-
-// Time between each frame (at 60fps)
-const SECS_PER_FRAME: f64 = 1.0 / 60.0;
+use trace::TraceLog;
+use vs::{DipSwitches, VS_COIN_1, VS_COIN_2, VS_SERVICE};
 
 #[derive(Parser, Debug)]
 #[command(
+    subcommand_negates_reqs = true,
     version = "0.1.0",
     about = "A NES emulator implemented in Rust",
-    long_about = "A NES emulator implemented in Rust\n\nControls:\n\nUp arrow\t= D-pad up\nDown arrow\t= D-pad down\nLeft arrow\t= D-pad left\nRight arrow\t= D-pad right\nSpace bar\t= Select\nReturn\t\t= Start\nA\t\t= A\nS\t\t= B"
+    long_about = "A NES emulator implemented in Rust\n\nControls:\n\nUp arrow\t= D-pad up\nDown arrow\t= D-pad down\nLeft arrow\t= D-pad left\nRight arrow\t= D-pad right\nSpace bar\t= Select\nReturn\t\t= Start\nA\t\t= A\nS\t\t= B\nPage up\t\t= Previous ROM\nPage down\t= Next ROM\nF1\t\t= Toggle priority debug colours\nF2\t\t= Remap joypad keys\nF3\t\t= Reset\nF4\t\t= Power cycle\nF5\t\t= Save state\nF6\t\t= Toggle raster overlay\nShift+F6\t= Toggle sprite boxes\nCtrl+F6\t\t= List sprites\nShift+F1\t= Toggle APU channel visualiser\nCtrl+1 to Ctrl+5\t= Mute pulse 1, pulse 2, triangle, noise or DMC\nF7\t\t= Load state\nBackspace (hold)\t= Rewind\nF10\t\t= Pause\nF11\t\t= Step instruction while paused\nShift+F11\t= Step scanline while paused\nCtrl+F11\t= Step frame while paused\nF12\t\t= Toggle debugger breakpoints\n5 / 6\t\t= Insert coin (Vs. System)\n9\t\t= Service button (Vs. System)\nF8\t\t= Dump PRG RAM\nTab (hold)\t= Fast-forward\nAlt+Return\t= Toggle fullscreen\nShift+Alt+Return\t= Toggle exclusive fullscreen\nAlt+1 to Alt+4\t= Resize window to 1x to 4x"
 )]
 struct Args {
     /// Width of emulator window.
@@ -45,138 +82,1521 @@ struct Args {
     #[arg(short = 'y', long, default_value_t = 240)]
     window_h: u32,
 
-    /// Pixel scaling factor.
-    #[arg(short, long, default_value_t = 3.0)]
-    pixel_scale: f32,
-
-    /// path/to/rom
+    /// Pixel scaling factor [default: 3]. If not given, the window reopens
+    /// at the size it was left.
     #[arg(short, long)]
-    rom: String,
+    pixel_scale: Option<f32>,
+
+    /// Starts fullscreen, at the desktop resolution or by switching the
+    /// display mode. Alt+Return toggles it.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "desktop")]
+    fullscreen: Option<FullscreenMode>,
+
+    /// Scales the picture by whole multiples only, with the 8:7 pixel aspect
+    /// ratio of a TV, leaving black bars around it.
+    #[arg(long)]
+    integer_scale: bool,
+
+    /// path/to/rom, may be repeated to cycle between ROMs.
+    #[arg(short, long, required_unless_present_any = ["playlist", "raw_bin", "install_desktop_entry"])]
+    rom: Vec<String>,
+
+    /// path/to/playlist containing one ROM path per line.
+    #[arg(short = 'l', long)]
+    playlist: Option<String>,
+
+    /// Upscaling filter applied to each frame on the CPU before display.
+    #[arg(short, long, value_enum, default_value_t = ScaleFilter::None)]
+    scale_filter: ScaleFilter,
+
+    /// Emulation accuracy profile.
+    #[arg(short, long, value_enum, default_value_t = Accuracy::Fast)]
+    accuracy: Accuracy,
+
+    /// Seed for the unpredictable power-on state, random if not provided.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Power-on contents of VRAM, palette RAM and OAM.
+    #[arg(long, value_enum, default_value_t = MemoryInitMode::Zero)]
+    memory_init: MemoryInitMode,
+
+    /// Skips emulating the CPU in loops which wait for vblank or an
+    /// interrupt, reducing host CPU usage. Ignored by the accurate profile.
+    #[arg(long)]
+    idle_skip: bool,
+
+    /// Ignores the 8x16 sprite size bit, drawing every sprite as 8x8. For
+    /// narrowing down which PPU behaviour breaks a game.
+    #[arg(long)]
+    force_sprite_8x8: bool,
+
+    /// Never sets the sprite 0 hit flag. For narrowing down which PPU
+    /// behaviour breaks a game.
+    #[arg(long)]
+    disable_sprite0: bool,
+
+    /// Applies PPUMASK writes when the instruction starts rather than on its
+    /// write cycle. For narrowing down which PPU behaviour breaks a game.
+    #[arg(long)]
+    disable_mask_delay: bool,
+
+    /// Renders every frame at full length, without skipping a dot on odd
+    /// frames. For narrowing down which PPU behaviour breaks a game.
+    #[arg(long)]
+    disable_odd_frame_skip: bool,
+
+    /// Display refresh rate in Hz, detected from the display if not given.
+    /// When it differs from the console's frame rate, e.g. for a PAL game on
+    /// a 60 Hz display, frames are paced against it rather than vsync, some
+    /// being presented over several refreshes.
+    #[arg(long)]
+    refresh_rate: Option<f64>,
+
+    /// What to present on refreshes between emulated frames.
+    #[arg(long, value_enum, default_value_t = FramePolicy::Repeat)]
+    frame_repeat: FramePolicy,
+
+    /// Enables stereo output, with optional pan positions from -1 (left) to 1
+    /// (right) for pulse 1, pulse 2, triangle, noise and DMC.
+    #[arg(long, num_args = 0..=1, default_missing_value = "-0.5,0.5,0,0,0")]
+    stereo: Option<Panning>,
+
+    /// path/to/directory to record each APU channel to, as pulse1.wav,
+    /// pulse2.wav, triangle.wav, noise.wav and dmc.wav, for remixing.
+    #[arg(long)]
+    stems: Option<String>,
+
+    /// Delays the sound by the given number of milliseconds, or the picture if
+    /// negative, to line them up when the display or sound system adds
+    /// latency. Measure it with the avsync-test subcommand.
+    #[arg(long, allow_negative_numbers = true, default_value_t = 0)]
+    av_offset: i32,
+
+    /// Size of the audio device buffer in samples. Smaller buffers lower the
+    /// latency but may crackle on slower machines. About two buffers are kept
+    /// queued.
+    #[arg(long, default_value_t = 1024)]
+    audio_buffer: u16,
+
+    /// Overrides the TV system detected from the ROM header.
+    #[arg(long, value_enum)]
+    force_region: Option<Region>,
+
+    /// Runs PAL and Dendy games at the NTSC frame rate, about 20% faster with
+    /// higher pitched sound, rather than at an authentic 50 Hz.
+    #[arg(long)]
+    pal_60hz: bool,
+
+    /// path/to/movie to record inputs, resets and power cycles to, in the
+    /// FM2 format if it ends in .fm2.
+    #[arg(long, conflicts_with = "play")]
+    record: Option<String>,
+
+    /// path/to/movie to replay, keyboard input is ignored while it plays.
+    #[arg(long, visible_alias = "playback")]
+    play: Option<String>,
+
+    /// Runs the given number of frames as fast as possible before starting in
+    /// real time. Inputs are taken from the movie given by --play, if any.
+    #[arg(long, default_value_t = 0)]
+    skip_frames: u64,
+
+    /// Applies joypad changes at the first controller strobe after the
+    /// scanline they arrived during, rather than at the start of the next
+    /// frame, lowering input latency when events are pumped more often than
+    /// once per frame. Movies only hold whole frames, so it can't be recorded.
+    #[arg(long, conflicts_with = "record")]
+    sub_frame_input: bool,
+
+    /// path/to/state to boot into, as saved with F5 or by --autosave.
+    #[arg(long)]
+    state: Option<String>,
+
+    /// Seconds of play kept to step back through by holding Backspace, or 0
+    /// to turn rewinding off.
+    #[arg(long, default_value_t = 30)]
+    rewind_seconds: u32,
+
+    /// Saves the state every given number of minutes of play, to a rotating
+    /// set of files next to the ROM such as zelda.auto0.state.
+    #[arg(long)]
+    autosave: Option<u32>,
+
+    /// Number of autosave files to rotate between.
+    #[arg(long, requires = "autosave", default_value_t = 3)]
+    autosave_slots: usize,
+
+    /// Runs headlessly in lockstep with another process over stdin and
+    /// stdout, reading joypad input and writing frames each frame.
+    #[arg(long, conflicts_with_all = ["record", "play"])]
+    frame_pipe: bool,
+
+    /// Maps a debug console for homebrew at $401A, printing bytes written to
+    /// it as text, and an exit register at $401B which stops the emulator
+    /// with the written byte as the exit code.
+    #[arg(long)]
+    debug_console: bool,
+
+    /// Fires an NMI or a register write at a PPU scanline and dot every frame,
+    /// for prototyping raster effects, e.g. 120,256:nmi or 120,256:2001=1E
+    /// with the address and value in hex. May be repeated.
+    #[arg(long, allow_hyphen_values = true)]
+    inject: Vec<Injection>,
+
+    /// Pauses when the CPU reaches an address, e.g. C000, or reads or writes
+    /// one, e.g. read:2002 or write:4016, with the address in hex. May be
+    /// repeated. Turns the debugger on, which F12 toggles.
+    #[arg(long = "break")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// path/to/trace.log to write a line to for every instruction, with the
+    /// PPU scanline and dot and the CPU cycle count as in the logs of nestest,
+    /// FCEUX and Mesen. Logs grow by tens of MB a second.
+    #[arg(long)]
+    trace_log: Option<String>,
+
+    /// Marks where sprite 0 hit is set and the scanlines where the scroll is
+    /// split over each frame. F6 toggles the overlay.
+    #[arg(long, value_enum)]
+    raster_overlay: Option<RasterMarkers>,
+
+    /// path/to/palette.pal of 64 RGB colours to draw with in place of the
+    /// built-in palette, e.g. the palette of a Vs. System game's PPU.
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// DIP switches of a Vs. System game in hex, switch 1 in bit 0. They are
+    /// remembered for the game, and default to all off.
+    #[arg(long, value_parser = parse_dip_switches)]
+    dip_switches: Option<u8>,
+
+    /// path/to/binary of a raw 6502 program, without an iNES header, to run
+    /// headlessly in 64 KB of RAM, printing a trace of each instruction.
+    #[arg(long, conflicts_with_all = ["rom", "playlist"])]
+    raw_bin: Option<String>,
+
+    /// Address the raw program is loaded at, in hex.
+    #[arg(long, requires = "raw_bin", value_parser = parse_addr, default_value = "0000")]
+    load_addr: u16,
+
+    /// Address the raw program starts running from, in hex. Defaults to the
+    /// load address.
+    #[arg(long, requires = "raw_bin", value_parser = parse_addr)]
+    entry: Option<u16>,
+
+    /// Registers the emulator with the desktop as the handler for .nes files,
+    /// so ROMs open in it when double clicked. Linux only.
+    #[arg(long)]
+    install_desktop_entry: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs a directory of test ROMs headlessly and prints a summary.
+    Testsuite {
+        /// path/to/test/roms
+        #[arg(short, long)]
+        dir: String,
+    },
+
+    /// Runs a built-in ROM with a fixed input script and prints a hash of the
+    /// final machine state, which must match on every platform.
+    Selfcheck {
+        /// Number of frames to run for.
+        #[arg(short, long, default_value_t = selfcheck::DEFAULT_FRAMES)]
+        frames: u64,
+
+        /// Exit with a failure status unless the hash matches, e.g. 1A2B3C4D.
+        #[arg(short, long)]
+        expect: Option<String>,
+    },
+
+    /// Runs a built-in ROM which flashes the screen white and clicks once a
+    /// second, to measure the offset for --av-offset.
+    AvsyncTest,
+
+    /// Prints the header of a ROM and the board's capabilities and banks at
+    /// power on.
+    Info {
+        /// path/to/rom
+        rom: String,
+    },
+
+    /// Prints a disassembly of a ROM's PRG ROM, a 16 KB bank at a time.
+    Disasm {
+        /// path/to/rom
+        rom: String,
+    },
+
+    /// Checks SDL video and audio, the audio latency and the timer
+    /// resolution, and prints a summary for bug reports.
+    Doctor,
+
+    /// Exports or imports the PRG RAM held in a ROM's save state, to back up
+    /// or edit battery saves.
+    Sram {
+        #[command(subcommand)]
+        action: SramAction,
+    },
+
+    /// Runs a directory of ROMs headlessly until each shows a still screen,
+    /// such as its title screen, and saves it as a PNG thumbnail.
+    #[cfg(feature = "image")]
+    Thumbs {
+        /// path/to/roms
+        dir: String,
+
+        /// Number of identical frames after which the screen is still.
+        #[arg(short, long, default_value_t = thumbs::DEFAULT_STABLE_FRAMES)]
+        stable_frames: u32,
+
+        /// Directory to save thumbnails to, instead of next to each ROM.
+        #[arg(short, long)]
+        out: Option<String>,
+    },
+}
+
+/// What to do with a ROM's PRG RAM.
+#[derive(Subcommand, Debug)]
+enum SramAction {
+    /// Writes the PRG RAM from the ROM's save state to a file.
+    Export {
+        /// path/to/rom
+        rom: String,
+
+        /// path/to/file to write.
+        file: String,
+    },
+
+    /// Reads the PRG RAM in the ROM's save state from a file, creating the
+    /// save state if there isn't one.
+    Import {
+        /// path/to/rom
+        rom: String,
+
+        /// path/to/file to read.
+        file: String,
+    },
+}
+
+/// Ways of filling the screen.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum FullscreenMode {
+    /// A borderless window covering the desktop.
+    Desktop,
+
+    /// Switches the display mode to the window's size.
+    Exclusive,
+}
+
+impl From<FullscreenMode> for FullscreenType {
+    fn from(mode: FullscreenMode) -> Self {
+        match mode {
+            FullscreenMode::Desktop => FullscreenType::Desktop,
+            FullscreenMode::Exclusive => FullscreenType::True,
+        }
+    }
+}
+
+/// Emulation accuracy profiles.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Accuracy {
+    /// Start from a clean, deterministic state.
+    Fast,
+
+    /// Emulate documented hardware quirks such as the PPU power-up state.
+    Accurate,
+}
+
+/// Power-on memory contents, see [`MemoryInit`].
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum MemoryInitMode {
+    /// Every byte is zero.
+    Zero,
+
+    /// Random contents, derived from --seed if provided.
+    Random,
+
+    /// A fixed pattern of $00 and $FF blocks.
+    Pattern,
 }
 
 impl Args {
-    fn scaled_window_w(&self) -> u32 {
-        (self.window_w as f32 * self.pixel_scale) as u32
+    /// Returns the size of a window showing the picture at the given scale,
+    /// widened for the pixel aspect ratio when integer scaling.
+    fn window_size(&self, scale: f32) -> (u32, u32) {
+        let aspect = match self.integer_scale {
+            true => geometry::PIXEL_ASPECT as f32,
+            false => 1.0,
+        };
+        (
+            (self.window_w as f32 * scale * aspect).round() as u32,
+            (self.window_h as f32 * scale) as u32,
+        )
+    }
+
+    /// Returns the emulation behaviours of the accuracy profile, less those
+    /// turned off by the debug flags.
+    fn emu_config(&self) -> EmuConfig {
+        let accurate = self.accuracy == Accuracy::Accurate;
+        EmuConfig {
+            power_up: accurate,
+            idle_skip: self.idle_skip && !accurate,
+            sprites_8x16: !self.force_sprite_8x8,
+            sprite0_hit: !self.disable_sprite0,
+            mask_delay: !self.disable_mask_delay,
+            odd_frame_skip: !self.disable_odd_frame_skip,
+        }
     }
 
-    fn scaled_window_h(&self) -> u32 {
-        (self.window_h as f32 * self.pixel_scale) as u32
+    /// Returns the rate frames of a game for the given region are emulated
+    /// at.
+    fn frame_rate(&self, region: Region) -> f64 {
+        match region {
+            Region::Pal | Region::Dendy if self.pal_60hz => Region::Ntsc.frame_rate(),
+            _ => region.frame_rate(),
+        }
+    }
+
+    fn memory_init(&self) -> MemoryInit {
+        match self.memory_init {
+            MemoryInitMode::Zero => MemoryInit::Zero,
+            MemoryInitMode::Random => MemoryInit::Random(self.seed.unwrap_or_else(rand::random)),
+            MemoryInitMode::Pattern => MemoryInit::Pattern,
+        }
+    }
+}
+
+/// Pixel scaling factor of a new window.
+const DEFAULT_PIXEL_SCALE: f32 = 3.0;
+
+/// Number of frames emulated per displayed frame while fast-forwarding.
+const FAST_FORWARD_SPEED: u32 = 4;
+
+/// Number of frames between rewind snapshots. Rewinding steps back a
+/// snapshot per frame, so plays back at this many times normal speed.
+const REWIND_INTERVAL: u32 = 2;
+
+/// Loads the ROM at the given path and returns a CPU ready to run it, along
+/// with the checksum of the ROM.
+fn load_rom<'a, F, R>(
+    path: &Path,
+    args: &Args,
+    sample_rate: f32,
+    render: F,
+) -> Result<(Cpu<'a>, u32), String>
+where
+    F: FnMut(&[u8]) -> R + 'a,
+    R: Into<FrameStatus>,
+{
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cart = Cartridge::new(&bytes)?;
+
+    if let Some(reason) = cart.bad_dump() {
+        eprintln!("warning: {} is a bad dump ({})", path.display(), reason);
+    }
+    let region = args.force_region.unwrap_or(cart.region());
+    let checksum = cart.checksum();
+    let vs_system = cart.vs_system();
+
+    if region != Region::Ntsc {
+        eprintln!(
+            "{} game running at {:.3} Hz{}",
+            match region {
+                Region::Dendy => "Dendy",
+                _ => "PAL",
+            },
+            args.frame_rate(region),
+            if args.pal_60hz {
+                ""
+            } else {
+                ", --pal-60hz speeds it up to 60 Hz"
+            }
+        );
+    }
+
+    // Sped up games make sound faster too, so fewer samples are taken per
+    // emulated second to keep up with the device.
+    let sample_rate = sample_rate * (region.frame_rate() / args.frame_rate(region)) as f32;
+
+    let mut builder = NesBuilder::new()
+        .region(region)
+        .config(args.emu_config())
+        .audio_rate(sample_rate)
+        .memory_init(args.memory_init())
+        .seed(args.seed)
+        .stereo(args.stereo)
+        .stems(args.stems.is_some());
+    if let Some(path) = &args.palette {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        builder = builder.palette(ppu::parse_pal(&bytes).map_err(|e| format!("{}: {}", path, e))?);
+    }
+
+    #[cfg(feature = "opstats")]
+    opstats::reset();
+    #[cfg(feature = "ppuprof")]
+    ppuprof::reset();
+
+    let mut cpu = builder.build(cart, render);
+    let bus = &mut cpu.bus;
+    bus.set_debug_console(args.debug_console);
+    bus.set_injections(args.inject.clone());
+    bus.set_raster_overlay(args.raster_overlay);
+    if vs_system {
+        let mut dips = DipSwitches::load(DipSwitches::default_path());
+        if let Some(switches) = args.dip_switches {
+            dips.set(checksum, switches);
+            if let Err(e) = dips.save() {
+                eprintln!("could not save DIP switches: {}", e);
+            }
+        }
+        bus.set_vs_system(Some(dips.get(checksum)));
+    }
+
+    Ok((cpu, checksum))
+}
+
+/// Parses a 16-bit address in hex, optionally prefixed with $ or 0x.
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let hex = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|e| format!("invalid address {:?}: {}", s, e))
+}
+
+/// Parses DIP switch settings given as a hex byte.
+fn parse_dip_switches(s: &str) -> Result<u8, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u8::from_str_radix(hex, 16).map_err(|e| format!("invalid DIP switches {:?}: {}", s, e))
+}
+
+/// Returns the path of the save state for the ROM at the given path.
+fn state_path(rom: &Path) -> PathBuf {
+    rom.with_extension("state")
+}
+
+/// Returns the controller port and joypad button bound to each key. Bindings
+/// to unknown key names are reported and skipped.
+fn joypad_keys(bindings: &[KeyMap; PORTS]) -> HashMap<Keycode, (usize, u8)> {
+    bindings
+        .iter()
+        .enumerate()
+        .flat_map(|(port, b)| b.bindings().map(move |(name, button)| (port, name, button)))
+        .filter_map(|(port, name, button)| match Keycode::from_name(name) {
+            Some(key) => Some((key, (port, button))),
+            None => {
+                eprintln!("unknown key {:?} in key bindings", name);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Presses the given buttons on each controller, e.g. once the machine has
+/// been replaced.
+fn set_controllers(cpu: &mut Cpu, buttons: [u8; PORTS]) {
+    for (port, pressed) in buttons.into_iter().enumerate() {
+        cpu.bus.set_controller_state(port, pressed);
+    }
+}
+
+/// Returns the Vs. System cabinet button of a coin or service hotkey.
+fn cabinet_button(key: Keycode) -> u8 {
+    match key {
+        Keycode::Num5 => VS_COIN_1,
+        Keycode::Num6 => VS_COIN_2,
+        _ => VS_SERVICE,
+    }
+}
+
+/// Switches the window between windowed and the given fullscreen mode.
+fn toggle_fullscreen(window: &mut Window, mode: FullscreenMode) {
+    let next = match window.fullscreen_state() {
+        FullscreenType::Off => mode.into(),
+        _ => FullscreenType::Off,
+    };
+    if let Err(e) = window.set_fullscreen(next) {
+        eprintln!("could not switch fullscreen: {}", e);
     }
 }
 
+/// Returns the window title showing the ROM name and its total play time.
+fn window_title(path: &Path, played: Duration) -> String {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    format!("RES - {} ({} played)", name, stats::format_duration(played))
+}
+
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    if let Some(Command::Testsuite { dir }) = &args.command {
+        std::process::exit(testsuite::run(Path::new(dir)));
+    }
+
+    if let Some(Command::Info { rom }) = &args.command {
+        let result = std::fs::read(rom)
+            .map_err(|e| format!("{}: {}", rom, e))
+            .and_then(|bytes| Cartridge::new(&bytes));
+        match result {
+            Ok(cart) => {
+                println!("{}", cart.loaded());
+                println!("CRC32: {:08X}", cart.checksum());
+                if let Some(reason) = cart.bad_dump() {
+                    println!("Bad dump: {}", reason);
+                }
+                println!("{}", cart.mapper_info());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Disasm { rom }) = &args.command {
+        let result = std::fs::read(rom)
+            .map_err(|e| format!("{}: {}", rom, e))
+            .and_then(|bytes| Rom::new(&bytes))
+            .and_then(|r| {
+                disasm::write_prg_listing(&r.prg, &mut io::stdout().lock())
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Doctor) = &args.command {
+        std::process::exit(doctor::run());
+    }
+
+    if let Some(Command::Sram { action }) = &args.command {
+        let result = match action {
+            SramAction::Export { rom, file } => {
+                let rom = Path::new(rom);
+                sram::export(rom, &state_path(rom), Path::new(file))
+                    .map(|n| format!("exported {} bytes to {}", n, file))
+            }
+            SramAction::Import { rom, file } => {
+                let rom = Path::new(rom);
+                sram::import(rom, &state_path(rom), Path::new(file))
+                    .map(|n| format!("imported {} bytes from {}", n, file))
+            }
+        };
+        match result {
+            Ok(msg) => eprintln!("{}", msg),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Selfcheck { frames, expect }) = &args.command {
+        let hash = selfcheck::run(*frames).unwrap();
+        println!("frames: {} hash: {:08X}", frames, hash);
+
+        let matches = expect
+            .as_deref()
+            .is_none_or(|e| e.eq_ignore_ascii_case(&format!("{:08X}", hash)));
+        std::process::exit(if matches { 0 } else { 1 });
+    }
+
+    #[cfg(feature = "image")]
+    if let Some(Command::Thumbs {
+        dir,
+        stable_frames,
+        out,
+    }) = &args.command
+    {
+        let out = out.as_deref().map(Path::new);
+        std::process::exit(thumbs::run(Path::new(dir), out, *stable_frames));
+    }
+
+    if args.install_desktop_entry {
+        if let Err(e) = desktop::install() {
+            eprintln!("could not install desktop entry: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.raw_bin {
+        let program = std::fs::read(path).unwrap();
+        let entry = args.entry.unwrap_or(args.load_addr);
+        let stop = rawbin::run(
+            &program,
+            args.load_addr,
+            entry,
+            &mut io::BufWriter::new(io::stdout().lock()),
+        )
+        .unwrap();
+        eprintln!("{}", stop);
+        return;
+    }
+
+    // Movies hold the events applied before each frame, either replayed from
+    // a file or recorded from the keyboard and reset/power hotkeys.
+    let mut movie = args
+        .play
+        .as_ref()
+        .map(|path| Movie::load(Path::new(path)).unwrap());
+    let mut recording = args.record.as_ref().map(|_| Movie::default());
+
+    // The unpredictable power-on state is pinned to a seed kept in the
+    // movie, so it replays from the same state it was recorded from.
+    match (&movie, &mut recording) {
+        (Some(m), _) => args.seed = m.seed().or(args.seed),
+        (_, Some(r)) => r.set_seed(*args.seed.get_or_insert_with(rand::random)),
+        _ => {}
+    }
+
+    let mut session = match args.command {
+        Some(Command::AvsyncTest) => {
+            let path = avsync::write_rom().unwrap();
+            eprintln!(
+                "flashing and clicking every {} frames, adjust --av-offset until they line up",
+                avsync::FLASH_INTERVAL
+            );
+            Session::new(&[path.to_string_lossy().into_owned()], None).unwrap()
+        }
+        _ => Session::new(&args.rom, args.playlist.as_deref()).unwrap(),
+    };
+
+    if args.frame_pipe {
+        let frame = RefCell::new(Vec::new());
+        let (mut cpu, checksum) = load_rom(session.current(), &args, 44100.0, |pixels: &[u8]| {
+            *frame.borrow_mut() = pixels.to_vec();
+        })
+        .unwrap();
+        if let Some(path) = &args.state {
+            state::load_file(Path::new(path), &mut cpu, checksum).unwrap();
+        }
+
+        let frames = pipe::run(&mut cpu, &frame, io::stdin().lock(), io::stdout().lock()).unwrap();
+        eprintln!("frame pipe closed after {} frames", frames);
+        return;
+    }
 
-    let window_w = args.scaled_window_w();
+    crash::install_hook();
 
-    // Initialise SDL.
+    // Initialise SDL. The app name and window class match the desktop entry,
+    // so desktops group the window with it.
+    sdl2::hint::set("SDL_APP_NAME", "RES");
+    sdl2::hint::set("SDL_VIDEO_X11_WMCLASS", "res");
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let audio_subsystem = sdl_context.audio().unwrap();
-    let window = video_subsystem
-        .window(
-            "RES - Rustendo Entertainment System",
-            window_w,
-            args.scaled_window_h(),
-        )
-        .position_centered()
-        .build()
-        .unwrap();
+
+    // The window reopens where it was left, at the same size unless a pixel
+    // scale is given.
+    let saved = Geometry::load(Geometry::default_path());
+    let (window_w, window_h) = match saved {
+        Some(g) if args.pixel_scale.is_none() => (g.w, g.h),
+        _ => args.window_size(args.pixel_scale.unwrap_or(DEFAULT_PIXEL_SCALE)),
+    };
+    let mut window =
+        video_subsystem.window("RES - Rustendo Entertainment System", window_w, window_h);
+    window.resizable();
+    match saved {
+        Some(g) => window.position(g.x, g.y),
+        None => window.position_centered(),
+    };
+    let mut window = window.build().unwrap();
+    if let Some(mode) = args.fullscreen {
+        toggle_fullscreen(&mut window, mode);
+    }
+    let display_rate = window
+        .display_mode()
+        .ok()
+        .map(|m| m.refresh_rate)
+        .filter(|rate| *rate > 0)
+        .map(f64::from);
+
+    let size = (icon::SIZE * icon::SCALE) as u32;
+    let mut icon_pixels = icon::rgba();
+    match Surface::from_data(
+        &mut icon_pixels,
+        size,
+        size,
+        size * 4,
+        PixelFormatEnum::RGBA32,
+    ) {
+        Ok(icon) => window.set_icon(icon),
+        Err(e) => eprintln!("could not set window icon: {}", e),
+    }
 
     // Initialise graphics.
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let canvas = window.into_canvas().present_vsync().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas
-        .set_scale(args.pixel_scale, args.pixel_scale)
-        .unwrap();
+    let sdl_timer = sdl_context.timer().unwrap();
+
+    // Post-process scalers output a larger frame, so the texture is sized to
+    // match. It is stretched to fit the window either way.
+    let scaler = args.scale_filter.scaler();
+    let factor = args.scale_filter.factor();
+    let scaled_frame = Vec::new();
 
     let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, args.window_w, args.window_h)
+    let texture = creator
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            args.window_w * factor as u32,
+            args.window_h * factor as u32,
+        )
         .unwrap();
 
     // Initialise sound.
-    let buffer_size = 1024;
     let sample_rate = 44100;
     let spec = AudioSpecDesired {
         freq: Some(sample_rate),
-        channels: Some(1),
-        samples: Some(buffer_size),
+        channels: Some(if args.stereo.is_some() { 2 } else { 1 }),
+        samples: Some(args.audio_buffer),
     };
     let queue = audio_subsystem.open_queue::<f32, _>(None, &spec).unwrap();
     queue.resume();
+    let frame_bytes = std::mem::size_of::<f32>() as u32 * spec.channels.unwrap_or(1) as u32;
+
+    // A positive offset delays the sound by keeping more of it queued, a
+    // negative one delays the picture once the frame rate is known.
+    let (audio_delay, _) = avsync::delays(args.av_offset, sample_rate as u32, 0.0);
+    let mut audio_sync = AudioSync::new(args.audio_buffer as u32 * 2 + audio_delay);
 
     // Samples stores the audio samples generated by the APU.
     let mut samples = vec![0.0; 1024];
     let volume = 1.0;
 
-    // Load ROM.
-    let bytes: Vec<u8> = std::fs::read(args.rom).unwrap();
-    let cart = Cartridge::new(&bytes).unwrap();
-
-    // Initialise joypad.
-    let mut key_map = HashMap::new();
-    key_map.insert(Keycode::Up, joypad::JOYPAD_UP);
-    key_map.insert(Keycode::Down, joypad::JOYPAD_DOWN);
-    key_map.insert(Keycode::Left, joypad::JOYPAD_LEFT);
-    key_map.insert(Keycode::Right, joypad::JOYPAD_RIGHT);
-    key_map.insert(Keycode::Space, joypad::JOYPAD_SELECT);
-    key_map.insert(Keycode::Return, joypad::JOYPAD_START);
-    key_map.insert(Keycode::A, joypad::JOYPAD_BUTTON_A);
-    key_map.insert(Keycode::S, joypad::JOYPAD_BUTTON_B);
-
-    let bus = SystemBus::new(
-        Rc::new(RefCell::new(cart)),
-        sample_rate as f32,
-        move |frame| {
-            texture.update(None, frame, window_w as usize).unwrap();
-
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-        },
-    );
-
-    let mut cpu = Cpu::new(bus);
-    cpu.reset();
+    // Initialise joypads.
+    let mut bindings: [KeyMap; PORTS] =
+        std::array::from_fn(|port| KeyMap::load(port, KeyMap::default_path(port)));
+    let mut key_map = joypad_keys(&bindings);
+    let mut remap: Option<Remap> = None;
+
+    // The render callback is shared by every loaded ROM, so it only queues
+    // the frames. They are presented separately, as the display may refresh
+    // more often than frames are rendered.
+    let rendered = RefCell::new(FrameQueue::default());
+    let render = |frame: &[u8]| rendered.borrow_mut().push(frame);
+
+    let display = RefCell::new((canvas, texture, scaled_frame, Vec::new()));
+    let present = |show: Show| -> Result<(), String> {
+        let rendered = rendered.borrow();
+        let (prev, latest) = (rendered.previous(), rendered.latest());
+        let (canvas, texture, scaled_frame, blended) = &mut *display.borrow_mut();
+
+        let frame = match show {
+            Show::Black => {
+                canvas.set_draw_color(Color::BLACK);
+                canvas.clear();
+                canvas.present();
+                return Ok(());
+            }
+            Show::Blend if prev.len() == latest.len() => {
+                present::blend(prev, latest, blended);
+                &blended[..]
+            }
+            _ => latest,
+        };
+
+        // Nothing has been rendered yet.
+        if frame.is_empty() {
+            return Ok(());
+        }
+
+        match &scaler {
+            Some(s) => {
+                let w = args.window_w as usize;
+                s.scale(frame, w, args.window_h as usize, scaled_frame);
+                texture.update(None, scaled_frame, w * factor * 3)
+            }
+            None => texture.update(None, frame, args.window_w as usize * 3),
+        }
+        .map_err(|e| e.to_string())?;
+
+        // The frame keeps its aspect ratio however the window is resized,
+        // with black bars filling the rest.
+        let frame = (args.window_w, args.window_h);
+        let (x, y, w, h) = match args.integer_scale {
+            true => geometry::fit_integer(frame, geometry::PIXEL_ASPECT, canvas.output_size()?),
+            false => geometry::fit(frame, canvas.output_size()?),
+        };
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+        canvas.copy(texture, None, Rect::new(x, y, w, h))?;
+        canvas.present();
+        Ok(())
+    };
+
+    let (mut cpu, mut checksum) =
+        load_rom(session.current(), &args, sample_rate as f32, render).unwrap();
+    if let Some(path) = &args.state {
+        state::load_file(Path::new(path), &mut cpu, checksum).unwrap();
+    }
+    let frame_rate = args.frame_rate(cpu.bus.region());
+    let (_, video_delay) = avsync::delays(args.av_offset, sample_rate as u32, frame_rate);
+    rendered.borrow_mut().set_delay(video_delay);
+
+    // Make room for a frame of audio up front, so the buffer isn't regrown
+    // while playing.
+    samples
+        .reserve(cpu.bus.samples_per_frame().ceil() as usize * spec.channels.unwrap_or(1) as usize);
+
+    // Play time is counted in emulated frames, and added to the statistics
+    // whenever the ROM is switched or the emulator exits.
+    let mut play_time = PlayTime::load(PlayTime::default_path());
+    let mut played = Duration::ZERO;
+    let set_title = |title: &str| {
+        if let Err(e) = display.borrow_mut().0.window_mut().set_title(title) {
+            eprintln!("could not set window title: {}", e);
+        }
+    };
+    set_title(&window_title(session.current(), play_time.get(checksum)));
+
+    let mut stems = args
+        .stems
+        .as_ref()
+        .map(|dir| Stems::create(Path::new(dir), sample_rate as u32).unwrap());
+    let mut stem_samples = Vec::new();
+    let mut frame: u64 = 0;
+    let mut buttons = [0; PORTS];
+    let mut inputs: [InputQueue; PORTS] = Default::default();
+    let mut timed_inputs = Vec::new();
+    let mut last_pump = sdl_timer.ticks();
+    let mut hotkey_events = Vec::new();
+    let mut fast_forward = false;
+    let mut rewind = (args.rewind_seconds > 0).then(|| {
+        let frames = args.rewind_seconds as f64 * frame_rate;
+        Rewind::new(REWIND_INTERVAL, frames as usize / REWIND_INTERVAL as usize)
+    });
+    let mut rewinding = false;
+    let mut snapshot = Vec::new();
+    let mut debugger = Debugger::new(args.breakpoints.clone());
+    debugger.arm(&mut cpu);
+    if let Some(path) = &args.trace_log {
+        match TraceLog::create(Path::new(path)) {
+            Ok(log) => debugger.set_trace_log(log),
+            Err(e) => {
+                eprintln!("could not create trace log {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut gate = PresentGate::new();
+    let mut present_failed = false;
+    let mut exit_code = 0;
+    let mut failed = false;
+    let mut error_frame = Vec::new();
+    let mut autosave = args.autosave.map(|minutes| {
+        Autosave::new(
+            Duration::from_secs(minutes as u64 * 60),
+            args.autosave_slots,
+        )
+    });
 
+    let mut presenter = Presenter::new(args.frame_repeat, args.refresh_rate.or(display_rate));
     let mut timer = Timer::new();
-    loop {
+    'running: loop {
+        // Key events are stamped with the scanline they arrived during, taking
+        // the time since the last pump as the frame.
+        let pump = sdl_timer.ticks();
+        let lines = (cpu.bus.region().last_scanline() + 2) as u16;
+        let since = std::mem::replace(&mut last_pump, pump);
+        let arrival = |time| input::arrival_scanline(time, since, pump, lines);
+
         for event in event_pump.poll_iter() {
+            // While remapping, key presses are captured for the next button
+            // rather than passed to the joypad. Escape cancels remapping.
+            if let (
+                Some(r),
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                },
+            ) = (remap.as_mut(), &event)
+            {
+                let done = match key {
+                    Keycode::Escape => true,
+                    _ => match r.bind(&key.name()) {
+                        Some(keys) => {
+                            if let Err(e) = bindings[r.port()].set(keys) {
+                                eprintln!("could not save key bindings: {}", e);
+                            }
+                            key_map = joypad_keys(&bindings);
+                            true
+                        }
+                        None => false,
+                    },
+                };
+
+                match done {
+                    true => {
+                        remap = None;
+                        set_title(&window_title(session.current(), play_time.get(checksum)));
+                    }
+                    false => set_title(&r.prompt()),
+                }
+                continue;
+            }
+
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        cpu.set_button_pressed_status(*key, true);
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::PageUp | Keycode::PageDown)),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = match key {
+                        Keycode::PageUp => session.prev(),
+                        _ => session.next(),
+                    };
+
+                    // A ROM that fails to load is reported and the current one
+                    // keeps running.
+                    match load_rom(path, &args, sample_rate as f32, render) {
+                        Ok((next, next_checksum)) => {
+                            #[cfg(feature = "opstats")]
+                            eprintln!("{}", opstats::report());
+                            #[cfg(feature = "ppuprof")]
+                            eprintln!("{}", ppuprof::report());
+
+                            play_time.add(checksum, std::mem::take(&mut played));
+                            set_title(&window_title(path, play_time.get(next_checksum)));
+
+                            cpu = next;
+                            debugger.arm(&mut cpu);
+                            checksum = next_checksum;
+                            failed = false;
+                            queue.clear();
+                            audio_sync.restart();
+                            inputs.iter_mut().for_each(InputQueue::clear);
+                            buttons = [0; PORTS];
+                            if let Some(rewind) = rewind.as_mut() {
+                                rewind.clear();
+                            }
+                            if let Some(autosave) = autosave.as_mut() {
+                                autosave.restart();
+                            }
+                        }
+                        Err(e) => eprintln!("could not load ROM: {}", e),
+                    }
+                }
+                Event::Window { win_event, .. } => match win_event {
+                    WindowEvent::Minimized | WindowEvent::Hidden => gate.set_visible(false),
+                    WindowEvent::Restored
+                    | WindowEvent::Maximized
+                    | WindowEvent::Shown
+                    | WindowEvent::Exposed => gate.set_visible(true),
+                    _ => {}
+                },
+                // Alt+Return toggles fullscreen, exclusive with Shift.
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    let mode = match keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        true => FullscreenMode::Exclusive,
+                        false => args.fullscreen.unwrap_or(FullscreenMode::Desktop),
+                    };
+                    toggle_fullscreen(display.borrow_mut().0.window_mut(), mode);
+                }
+                // Alt+1 to Alt+4 resize the window to that scale.
+                Event::KeyDown {
+                    keycode:
+                        Some(key @ (Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4)),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    let scale = key as i32 - Keycode::Num0 as i32;
+                    let (w, h) = args.window_size(scale as f32);
+                    let mut display = display.borrow_mut();
+                    let window = display.0.window_mut();
+                    if window.fullscreen_state() == FullscreenType::Off {
+                        if let Err(e) = window.set_size(w, h) {
+                            eprintln!("could not resize window: {}", e);
+                        }
+                    }
+                }
+                // Shift+F1 shows the APU channel visualiser instead.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => match keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    true => cpu.bus.toggle_apu_scope(),
+                    false => cpu.bus.toggle_priority_debug(),
+                },
+                // Ctrl+1 to Ctrl+5 mute pulse 1, pulse 2, triangle, noise and
+                // DMC.
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            key @ (Keycode::Num1
+                            | Keycode::Num2
+                            | Keycode::Num3
+                            | Keycode::Num4
+                            | Keycode::Num5),
+                        ),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    let channel = (key as i32 - Keycode::Num1 as i32) as usize;
+                    let muted = cpu.bus.toggle_apu_mute(channel);
+                    let state = if muted { "muted" } else { "unmuted" };
+                    eprintln!("{} {}", STEM_NAMES[channel], state);
+                }
+                // Shift+F6 outlines the sprites instead, and Ctrl+F6 lists
+                // every OAM entry on the console.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        cpu.bus.toggle_sprite_boxes();
+                    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+                        for entry in OamEntry::all(cpu.bus.oam()) {
+                            eprintln!("{}", entry);
+                        }
+                    } else {
+                        cpu.bus.toggle_raster_overlay();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    // Shift+F2 remaps the second controller.
+                    let r = Remap::new(keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) as usize);
+                    set_title(&r.prompt());
+                    remap = Some(r);
+                }
+                // The console's state is unknown after an error, so both reset
+                // and power cycle start the game afresh.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3 | Keycode::F4),
+                    repeat: false,
+                    ..
+                } if failed => match load_rom(session.current(), &args, sample_rate as f32, render)
+                {
+                    Ok((next, _)) => {
+                        cpu = next;
+                        debugger.arm(&mut cpu);
+                        set_controllers(&mut cpu, buttons);
+                        queue.clear();
+                        audio_sync.restart();
+                        failed = false;
+                        set_title(&window_title(session.current(), play_time.get(checksum)));
+                    }
+                    Err(e) => eprintln!("could not power cycle: {}", e),
+                },
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::F3 | Keycode::F4)),
+                    repeat: false,
+                    ..
+                } if movie.is_none() => hotkey_events.push(match key {
+                    Keycode::F3 => MovieEvent::Reset,
+                    _ => MovieEvent::Power,
+                }),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = state_path(session.current());
+                    match state::save_file(&path, &cpu, checksum) {
+                        Ok(()) => eprintln!("saved state to {}", path.display()),
+                        Err(e) => eprintln!("could not save state: {}", e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } if movie.is_none() => {
+                    let path = state_path(session.current());
+                    match state::load_file(&path, &mut cpu, checksum) {
+                        Ok(()) => {
+                            if std::mem::take(&mut failed) {
+                                set_title(&window_title(
+                                    session.current(),
+                                    play_time.get(checksum),
+                                ));
+                            }
+                            queue.clear();
+                            audio_sync.restart();
+                            set_controllers(&mut cpu, buttons);
+                        }
+                        Err(e) => eprintln!("could not load state: {}", e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => match debugger.toggle_pause() {
+                    true => set_title(&debugger::status(&cpu)),
+                    false => set_title(&window_title(session.current(), play_time.get(checksum))),
+                },
+                // Shift+F11 steps a scanline and Ctrl+F11 a frame rather than
+                // an instruction.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    keymod,
+                    ..
+                } if debugger.paused() => {
+                    let step = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        Step::Scanline
+                    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+                        Step::Frame
+                    } else {
+                        Step::Instruction
+                    };
+                    match debugger.step(&mut cpu, step) {
+                        Stop::Halted => break 'running,
+                        Stop::Breakpoint(hit) => {
+                            eprintln!("breakpoint: {} - {}", hit, debugger::registers(&cpu))
+                        }
+                        Stop::Done => {}
+                    }
+                    set_title(&debugger::status(&cpu));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => match debugger.toggle(&mut cpu) {
+                    true => eprintln!("debugger on, {} breakpoints", args.breakpoints.len()),
+                    false => eprintln!("debugger off"),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = sram::dump_path(session.current());
+                    match sram::write(&path, &cpu.bus.prg_ram()) {
+                        Ok(()) => eprintln!("dumped PRG RAM to {}", path.display()),
+                        Err(e) => eprintln!("could not dump PRG RAM: {}", e),
+                    }
+                }
+                #[cfg(feature = "ppuprof")]
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => eprintln!("{}", ppuprof::report()),
+                // Rewinding would desync movies, which are indexed by frame.
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    repeat: false,
+                    ..
+                } if movie.is_none() && recording.is_none() => {
+                    rewinding = rewind.is_some();
+                    queue.clear();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = false,
+                Event::KeyDown { .. } | Event::KeyUp { .. } if movie.is_some() => {}
+                // The coin slots and service button of Vs. System games.
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::Num5 | Keycode::Num6 | Keycode::Num9)),
+                    ..
+                } => cpu.bus.set_cabinet_pressed(cabinet_button(key), true),
+                Event::KeyUp {
+                    keycode: Some(key @ (Keycode::Num5 | Keycode::Num6 | Keycode::Num9)),
+                    ..
+                } => cpu.bus.set_cabinet_pressed(cabinet_button(key), false),
+                Event::KeyDown {
+                    timestamp,
+                    keycode,
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some((port, button)) =
+                        key_map.get(&keycode.unwrap_or(Keycode::Ampersand))
+                    {
+                        inputs[*port].push(*button, true, arrival(timestamp));
                     }
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        cpu.set_button_pressed_status(*key, false);
+                Event::KeyUp {
+                    timestamp, keycode, ..
+                } => {
+                    if let Some((port, button)) =
+                        key_map.get(&keycode.unwrap_or(Keycode::Ampersand))
+                    {
+                        inputs[*port].push(*button, false, arrival(timestamp));
                     }
                 }
                 _ => { /* do nothing */ }
             }
         }
 
-        // Clock the CPU until a frame has been rendered.
-        let frame_count = cpu.bus.ppu_frame_count();
-        while cpu.bus.ppu_frame_count() == frame_count {
-            let halted = cpu.clock();
-            if halted {
-                std::process::exit(0);
+        // Each pass of the loop is one display refresh, which may be due zero
+        // or more emulated frames. Fast-forward emulates several frames per
+        // refresh, and frames skipped at startup are all emulated in the
+        // first. Inputs are queued and applied a frame at a time, so none
+        // are lost.
+        let frame_rate = args.frame_rate(cpu.bus.region());
+        let due = presenter.tick(frame_rate);
+        let skipping = frame < args.skip_frames;
+        let frames = if debugger.paused() || failed {
+            0
+        } else if skipping {
+            (args.skip_frames - frame) as u32
+        } else if fast_forward {
+            due * FAST_FORWARD_SPEED
+        } else {
+            due
+        };
+        for i in 0..frames {
+            // Only the last two frames before a refresh can be presented, so
+            // the rest aren't copied.
+            rendered.borrow_mut().set_busy(i + 2 < frames);
+
+            // While rewinding, each frame steps back to the previous snapshot
+            // and runs on from it to show it, in place of taking input. Once
+            // the oldest is reached the picture holds until Backspace is let
+            // go.
+            if rewinding {
+                if !rewind.as_mut().is_some_and(|r| r.pop(&mut snapshot)) {
+                    break;
+                }
+                if let Err(e) = state::restore(&mut cpu, checksum, &mut &snapshot[..]) {
+                    eprintln!("could not rewind: {}", e);
+                    rewinding = false;
+                }
+                set_controllers(&mut cpu, buttons);
+            }
+
+            // Apply the events for this frame, recording them if requested.
+            let events: Vec<MovieEvent> = match movie.as_mut() {
+                _ if rewinding => Vec::new(),
+                Some(m) => m.replay(frame).iter().map(|(_, e)| *e).collect(),
+                None => {
+                    let mut events = Vec::new();
+                    for (port, input) in inputs.iter_mut().enumerate() {
+                        // Sub-frame changes are handed to the bus to apply at
+                        // the next strobe, so aren't frame events.
+                        if args.sub_frame_input {
+                            buttons[port] =
+                                input.next_frame_timed(buttons[port], &mut timed_inputs);
+                            for (scanline, pressed) in timed_inputs.drain(..) {
+                                cpu.bus.queue_controller_state(port, pressed, scanline);
+                            }
+                            continue;
+                        }
+
+                        let pressed = input.next_frame(buttons[port]);
+                        if pressed != buttons[port] {
+                            events.push(MovieEvent::Input(port, pressed));
+                        }
+                    }
+                    events.append(&mut hotkey_events);
+                    events
+                }
+            };
+            for event in events {
+                match event {
+                    MovieEvent::Input(port, pressed) => {
+                        cpu.bus.set_controller_state(port, pressed);
+                        buttons[port] = pressed;
+                    }
+                    MovieEvent::Reset => cpu.soft_reset(),
+                    MovieEvent::Power => {
+                        match load_rom(session.current(), &args, sample_rate as f32, render) {
+                            Ok((next, _)) => {
+                                cpu = next;
+                                debugger.arm(&mut cpu);
+                                set_controllers(&mut cpu, buttons);
+                                queue.clear();
+                                audio_sync.restart();
+                            }
+                            Err(e) => eprintln!("could not power cycle: {}", e),
+                        }
+                    }
+                }
+
+                if let Some(r) = recording.as_mut() {
+                    r.record(frame, event);
+                }
+            }
+            frame += 1;
+
+            // Hand control back to the keyboard once the movie has played out.
+            if movie.as_ref().is_some_and(|m| m.finished()) {
+                eprintln!("movie finished at frame {}", frame);
+                movie = None;
+            }
+
+            // Behaviour the core doesn't implement panics, which stops the
+            // game rather than the emulator.
+            let stop = panic::catch_unwind(AssertUnwindSafe(|| {
+                // Clock the CPU until a frame has been rendered. Breakpoints
+                // are only checked and instructions traced through the
+                // debugger, keeping this loop tight otherwise.
+                if debugger.enabled() || debugger.tracing() {
+                    return debugger.run_frame(&mut cpu);
+                }
+                let frame_count = cpu.bus.ppu_frame_count();
+                while cpu.bus.ppu_frame_count() == frame_count {
+                    if cpu.clock() {
+                        return Stop::Halted;
+                    }
+                }
+                Stop::Done
+            }));
+            match stop {
+                Ok(Stop::Halted) => break 'running,
+                Ok(Stop::Breakpoint(hit)) => {
+                    eprintln!("breakpoint: {} - {}", hit, debugger::registers(&cpu));
+                    set_title(&debugger::status(&cpu));
+                    break;
+                }
+                Ok(Stop::Done) => {
+                    if i + 2 >= frames {
+                        cpu.bus.draw_apu_scope(rendered.borrow_mut().newest_mut());
+                    }
+                }
+                Err(e) => {
+                    let e = testsuite::panic_message(e);
+                    eprintln!("emulation stopped: {}", e);
+                    set_title(&format!("Error: {} - F3 or F4 to restart, Esc to quit", e));
+
+                    let mut rendered = rendered.borrow_mut();
+                    present::error_screen(rendered.latest(), &mut error_frame);
+                    rendered.set_busy(false);
+                    rendered.push(&error_frame);
+                    queue.clear();
+                    failed = true;
+                    break;
+                }
+            }
+
+            // Snapshot played frames for rewinding.
+            if let (Some(r), false) = (rewind.as_mut(), rewinding) {
+                if r.tick() {
+                    snapshot.clear();
+                    match state::save(&cpu, checksum, &mut snapshot) {
+                        Ok(()) => r.push(&snapshot),
+                        Err(e) => eprintln!("could not snapshot for rewind: {}", e),
+                    }
+                }
+            }
+        }
+
+        // Presents are skipped while the window can't take them, so
+        // emulation and audio carry on regardless. Errors are reported once
+        // until presenting works again.
+        let now = Instant::now();
+        if gate.open(now) {
+            let result = present(presenter.show(frames > 0));
+            let refresh = Duration::from_secs_f64(presenter.refresh_time(frame_rate));
+            gate.presented(now, now.elapsed(), refresh);
+            match result {
+                Ok(()) => present_failed = false,
+                Err(e) if !present_failed => {
+                    eprintln!("could not present frame: {}", e);
+                    present_failed = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        if let Some(e) = debugger.take_trace_error() {
+            eprintln!("could not write trace log: {}", e);
+        }
+
+        // There's no on-screen display yet, so events are reported on the
+        // console.
+        for event in cpu.bus.events() {
+            match event {
+                CoreEvent::RomLoaded { .. } => {
+                    eprintln!("loaded {}: {}", session.current().display(), event)
+                }
+                CoreEvent::StateLoaded => eprintln!("{}", event),
+                CoreEvent::DebugOutput(line) => println!("{}", line),
+                CoreEvent::DebugExit(code) => {
+                    exit_code = code;
+                    break 'running;
+                }
             }
         }
 
-        // Forcing 60FPS by waiting for the next frame (if not enough time has
-        // already elapsed).
-        timer.wait(Duration::from_secs_f64(SECS_PER_FRAME));
+        // Forcing the refresh rate by waiting for the next refresh (if not
+        // enough time has already elapsed).
+        let elapsed = Duration::from_secs_f64(1.0 / frame_rate) * frames;
+        played += elapsed;
+
+        // Autosaves are written in the background and reported once done.
+        if let Some(autosave) = autosave.as_mut() {
+            if autosave.tick(elapsed) {
+                let mut data = Vec::new();
+                match state::save(&cpu, checksum, &mut data) {
+                    Ok(()) => autosave.save(session.current(), data),
+                    Err(e) => eprintln!("could not autosave: {}", e),
+                }
+            }
+            for result in autosave.results() {
+                match result {
+                    Ok(path) => eprintln!("autosaved to {}", path.display()),
+                    Err(e) => eprintln!("could not autosave: {}", e),
+                }
+            }
+        }
+        timer.wait(Duration::from_secs_f64(presenter.refresh_time(frame_rate)));
         timer.reset();
 
-        samples.append(&mut cpu.bus.audio_samples());
+        // Audio can't keep up with fast-forward, so it is dropped rather
+        // than letting the queue fall behind.
+        cpu.bus.drain_audio_samples(&mut samples);
+        cpu.bus.drain_stem_samples(&mut stem_samples);
+        if let Some(s) = &mut stems {
+            if let Err(e) = s.write(&stem_samples) {
+                eprintln!("could not record stems: {}", e);
+                stems = None;
+            }
+        }
+        stem_samples.clear();
+        if fast_forward || skipping {
+            samples.clear();
+            audio_sync.restart();
+        } else {
+            // Nudge the sample rate to keep the queue near its target fill.
+            if audio_sync.update(queue.size() / frame_bytes) {
+                queue.clear();
+            }
+            cpu.bus.set_sample_rate_adjust(audio_sync.adjust());
+        }
 
         // Adjust the volume.
         samples.iter_mut().for_each(|s| *s *= volume);
@@ -187,4 +1607,50 @@ fn main() {
         // Clear the samples buffer before the next frame.
         samples.clear();
     }
+    #[cfg(feature = "opstats")]
+    eprintln!("{}", opstats::report());
+    #[cfg(feature = "ppuprof")]
+    eprintln!("{}", ppuprof::report());
+    eprintln!("{}", audio_sync.report());
+
+    if let (Some(r), Some(path)) = (&mut recording, &args.record) {
+        r.set_length(frame);
+        if let Err(e) = r.save(Path::new(path)) {
+            eprintln!("could not save movie: {}", e);
+        }
+    }
+
+    if let Err(e) = debugger.finish_trace() {
+        eprintln!("could not write trace log: {}", e);
+    }
+
+    if let Some(s) = stems {
+        if let Err(e) = s.finish() {
+            eprintln!("could not record stems: {}", e);
+        }
+    }
+
+    play_time.add(checksum, played);
+    if let Err(e) = play_time.save() {
+        eprintln!("could not save play time: {}", e);
+    }
+
+    // A fullscreen window's geometry is the display's, so the windowed one
+    // saved before is kept.
+    let geometry = {
+        let display = display.borrow();
+        let window = display.0.window();
+        let ((x, y), (w, h)) = (window.position(), window.size());
+        (window.fullscreen_state() == FullscreenType::Off).then_some(Geometry { x, y, w, h })
+    };
+    if let Err(e) = geometry.map_or(Ok(()), |g| g.save(Geometry::default_path())) {
+        eprintln!("could not save window geometry: {}", e);
+    }
+
+    // Let any autosave still being written finish.
+    drop(autosave);
+
+    if exit_code != 0 {
+        std::process::exit(exit_code as i32);
+    }
 }
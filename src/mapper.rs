@@ -1,16 +1,46 @@
+mod a12;
+mod bank_select;
 mod mmc1;
+mod mmc2;
+mod mmc3;
+mod namco108;
 mod nrom;
+mod prg_ram;
+#[cfg(test)]
+mod scripted;
 mod uxrom;
+mod vrc;
+mod vs;
 
+pub use a12::A12Filter;
+pub use bank_select::BankSelect;
 pub use mmc1::MMC1;
+pub use mmc2::{Variant as Mmc2Variant, MMC2};
+pub use mmc3::MMC3;
+pub use namco108::{Namco108, Variant as Namco108Variant};
 pub use nrom::Nrom;
+pub use prg_ram::PrgRam;
+#[cfg(test)]
+pub use scripted::ScriptedMapper;
 pub use uxrom::Uxrom;
+pub use vrc::{Variant as VrcVariant, Vrc};
+pub use vs::VsSystem;
+
+use std::fmt;
 
 use crate::cartridge::Mirroring;
+use crate::state::SaveState;
 
-pub trait Mapper {
-    /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8;
+/// Implemented by each cartridge board. Mappers capture their bank registers,
+/// IRQ counters and any RAM in save states.
+pub trait Mapper: SaveState {
+    /// Returns a byte from PRG ROM at the given address, or None if nothing
+    /// drives the data bus and the read returns open bus.
+    ///
+    /// Mappers see the whole of $4018-$FFFF, including the expansion area at
+    /// $4018-$5FFF where boards such as the MMC5, FDS and Namco 163 keep
+    /// their registers and RAM.
+    fn read_prg(&self, addr: u16) -> Option<u8>;
 
     /// Writes a byte to PRG ROM at the given address.
     fn write_prg(&mut self, addr: u16, data: u8);
@@ -23,4 +53,140 @@ pub trait Mapper {
 
     /// Returns the Mirroring mode.
     fn mirroring(&self) -> Mirroring;
+
+    /// Returns the name of the board or chip.
+    fn name(&self) -> &'static str;
+
+    /// Returns the PRG ROM bank mapped at the given address in $8000-$FFFF,
+    /// in 8 KB units so that every board can be shown the same way.
+    fn prg_bank(&self, addr: u16) -> usize;
+
+    /// Returns the CHR bank mapped at the given address in $0000-$1FFF, in
+    /// 1 KB units.
+    fn chr_bank(&self, addr: u16) -> usize;
+
+    /// Returns true if reads of $8000-$FFFF always return PRG ROM from the
+    /// banks given by [`Mapper::prg_bank`], so the bus can cache them. Boards
+    /// with registers or RAM readable there return false.
+    fn cacheable_prg(&self) -> bool {
+        true
+    }
+
+    /// Returns the value of the IRQ counter, or None if the board has none.
+    fn irq_counter(&self) -> Option<u16> {
+        None
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        String::from("fixed")
+    }
+
+    /// Clocks the mapper once per CPU cycle, for mappers with cycle based
+    /// IRQ counters.
+    fn clock_cpu(&mut self) {}
+
+    /// Clocks the mapper on a filtered rising edge of PPU A12, for mappers
+    /// which count scanlines, see [`A12Filter`].
+    fn clock_a12(&mut self) {}
+
+    /// Returns true if the mapper is asserting an IRQ.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Receives the address of each pattern table fetch made while
+    /// rendering, for mappers which switch CHR banks when particular tiles
+    /// are fetched. Returns true if the banks may have changed.
+    fn notify_chr_fetch(&mut self, _addr: u16) -> bool {
+        false
+    }
+
+    /// Returns true if the mapper switches banks on pattern table fetches,
+    /// see [`Mapper::notify_chr_fetch`]. Fetches aren't passed to mappers
+    /// which don't, keeping them off the rendering hot path.
+    fn watches_chr_fetches(&self) -> bool {
+        false
+    }
+
+    /// Receives writes to the controller strobe at $4016, whose other
+    /// output lines Vs. System boards use to select banks. Returns true if
+    /// the banks may have changed.
+    fn write_strobe(&mut self, _data: u8) -> bool {
+        false
+    }
+
+    /// Returns the PRG RAM mapped at $6000-$7FFF, whether or not it is
+    /// currently enabled. Boards without RAM return an empty slice.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Returns the PRG RAM for writing, see [`Mapper::prg_ram`].
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+/// What a cartridge board can do and the banks it has selected, for
+/// debuggers and `res info`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapperInfo {
+    /// iNES mapper number.
+    pub number: u16,
+    pub name: &'static str,
+
+    /// 8 KB PRG ROM banks mapped at $8000, $A000, $C000 and $E000.
+    pub prg_banks: [usize; 4],
+
+    /// 1 KB CHR banks mapped at each 1 KB of $0000-$1FFF.
+    pub chr_banks: [usize; 8],
+
+    /// Size of the PRG RAM at $6000-$7FFF, zero if there is none.
+    pub prg_ram: usize,
+
+    /// Whether the PRG RAM is battery backed.
+    pub battery: bool,
+
+    pub irq_counter: Option<u16>,
+}
+
+impl MapperInfo {
+    /// Returns the capabilities and current banks of the given board.
+    pub fn new(mapper: &dyn Mapper, number: u16, battery: bool) -> Self {
+        MapperInfo {
+            number,
+            name: mapper.name(),
+            prg_banks: std::array::from_fn(|i| mapper.prg_bank(0x8000 + 0x2000 * i as u16)),
+            chr_banks: std::array::from_fn(|i| mapper.chr_bank(0x400 * i as u16)),
+            prg_ram: mapper.prg_ram().len(),
+            battery,
+            irq_counter: mapper.irq_counter(),
+        }
+    }
+}
+
+impl fmt::Display for MapperInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |banks: &[usize]| {
+            banks
+                .iter()
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        writeln!(f, "Mapper: {} ({})", self.number, self.name)?;
+        writeln!(f, "PRG banks: {}", list(&self.prg_banks))?;
+        writeln!(f, "CHR banks: {}", list(&self.chr_banks))?;
+        match (self.prg_ram, self.battery) {
+            (0, _) => writeln!(f, "PRG RAM: none")?,
+            (n, true) => writeln!(f, "PRG RAM: {} KB, battery", n / 1024)?,
+            (n, false) => writeln!(f, "PRG RAM: {} KB", n / 1024)?,
+        }
+        match self.irq_counter {
+            Some(counter) => write!(f, "IRQ counter: {}", counter),
+            None => write!(f, "IRQ counter: none"),
+        }
+    }
 }
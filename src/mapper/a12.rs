@@ -0,0 +1,70 @@
+use std::io::{self, Read, Write};
+
+use crate::state::{read_bool, read_u64, write_bool, write_u64, SaveState};
+
+/// Minimum number of PPU dots A12 must stay low before a rising edge is
+/// counted. The MMC3 only clocks its scanline counter once A12 has been low
+/// for three falling edges of M2, a little over three CPU cycles. This
+/// filters out the drops for nametable fetches between pattern fetches,
+/// including the 9 dot gap around the start of each scanline.
+///
+/// See: https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+const MIN_LOW_DOTS: u64 = 10;
+
+/// Watches PPU address line 12 for the filtered rising edges used to clock
+/// scanline counters.
+#[derive(Default)]
+pub struct A12Filter {
+    high: bool,
+    low_since: u64,
+}
+
+impl A12Filter {
+    /// Observes an address on the PPU bus at the given dot, returning true if
+    /// it is a rising edge of A12 after a long enough low period.
+    pub fn observe(&mut self, addr: u16, dot: u64) -> bool {
+        let high = addr & 0x1000 != 0;
+        let rising = high && !self.high && dot.wrapping_sub(self.low_since) >= MIN_LOW_DOTS;
+
+        if self.high && !high {
+            self.low_since = dot;
+        }
+        self.high = high;
+
+        rising
+    }
+}
+
+impl SaveState for A12Filter {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_bool(w, self.high)?;
+        write_u64(w, self.low_since)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.high = read_bool(r)?;
+        self.low_since = read_u64(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_short_low_periods() {
+        let mut a12 = A12Filter::default();
+        assert!(a12.observe(0x1000, 20));
+
+        // Nametable fetches between pattern fetches only drop A12 briefly.
+        assert!(!a12.observe(0x2000, 21));
+        assert!(!a12.observe(0x1010, 25));
+        assert!(!a12.observe(0x1018, 27));
+
+        // A long enough low period is followed by a counted edge.
+        assert!(!a12.observe(0x0FF0, 30));
+        assert!(!a12.observe(0x2000, 35));
+        assert!(a12.observe(0x1FF0, 40));
+    }
+}
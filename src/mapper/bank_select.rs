@@ -0,0 +1,128 @@
+use std::io::{self, Read, Write};
+
+use crate::state::{read_u8, write_u8, SaveState};
+
+/// The bank select and bank data register pair of the Namco 108 and its
+/// descendant the MMC3. A write to an even address selects one of eight bank
+/// registers, which the next write to an odd address sets.
+///
+/// R0-R1 select 2 KB CHR banks and R2-R5 1 KB CHR banks, numbered in 1 KB
+/// units. R6-R7 select 8 KB PRG banks, the last two PRG banks are fixed.
+///
+/// See: https://www.nesdev.org/wiki/MMC3#Registers
+#[derive(Default)]
+pub struct BankSelect {
+    select: u8,
+    registers: [u8; 8],
+}
+
+impl BankSelect {
+    /// Writes the bank select register at even addresses, or the selected
+    /// bank register at odd addresses.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr & 0x1 {
+            0 => self.select = data,
+            _ => self.registers[(self.select & 0x7) as usize] = data,
+        }
+    }
+
+    /// Returns the last value written to the bank select register, whose
+    /// upper bits hold the MMC3 PRG and CHR modes.
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    /// Returns the value of bank register R0-R7.
+    pub fn register(&self, n: usize) -> u8 {
+        self.registers[n]
+    }
+
+    /// Returns the 8 KB PRG bank mapped at the given address, out of the
+    /// given number of banks. With `swap` the second last bank is fixed at
+    /// $8000 rather than $C000.
+    pub fn prg_bank(&self, addr: u16, bank_count: usize, swap: bool) -> usize {
+        let second_last = bank_count.saturating_sub(2);
+        let bank = match (addr & 0xE000, swap) {
+            (0x8000, false) | (0xC000, true) => self.registers[6] as usize,
+            (0x8000, true) | (0xC000, false) => second_last,
+            (0xA000, _) => self.registers[7] as usize,
+            _ => bank_count - 1,
+        };
+        bank % bank_count
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address. With `invert`
+    /// the 2 KB banks are mapped at $1000 rather than $0000.
+    pub fn chr_bank(&self, addr: u16, invert: bool) -> usize {
+        let mut slot = (addr as usize >> 10) & 0x7;
+        if invert {
+            slot ^= 0x4;
+        }
+
+        match slot {
+            0..=3 => (self.registers[slot >> 1] & !0x1) as usize | (slot & 0x1),
+            _ => self.registers[slot - 2] as usize,
+        }
+    }
+
+    /// Returns a description of the bank registers.
+    pub fn banks(&self) -> String {
+        format!(
+            "PRG: {:?} CHR: {:?}",
+            &self.registers[6..],
+            &self.registers[..6]
+        )
+    }
+}
+
+impl SaveState for BankSelect {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u8(w, self.select)?;
+        w.write_all(&self.registers)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.select = read_u8(r)?;
+        r.read_exact(&mut self.registers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prg_bank() {
+        let mut banks = BankSelect::default();
+        banks.write(0x8000, 6);
+        banks.write(0x8001, 3);
+        banks.write(0x8000, 7);
+        banks.write(0x8001, 5);
+
+        assert_eq!(banks.prg_bank(0x8000, 16, false), 3);
+        assert_eq!(banks.prg_bank(0xA000, 16, false), 5);
+        assert_eq!(banks.prg_bank(0xC000, 16, false), 14);
+        assert_eq!(banks.prg_bank(0xFFFF, 16, false), 15);
+
+        assert_eq!(banks.prg_bank(0x8000, 16, true), 14);
+        assert_eq!(banks.prg_bank(0xC000, 16, true), 3);
+    }
+
+    #[test]
+    fn test_chr_bank() {
+        let mut banks = BankSelect::default();
+        for (n, bank) in [5, 8, 20, 21, 22, 23].into_iter().enumerate() {
+            banks.write(0x8000, n as u8);
+            banks.write(0x8001, bank);
+        }
+
+        // The low bit of the 2 KB bank numbers is ignored.
+        let chr = |invert| {
+            (0..8)
+                .map(|slot| banks.chr_bank(slot * 0x400, invert))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(chr(false), [4, 5, 8, 9, 20, 21, 22, 23]);
+        assert_eq!(chr(true), [20, 21, 22, 23, 4, 5, 8, 9]);
+    }
+}
@@ -1,5 +1,14 @@
-use super::Mapper;
-use crate::{cartridge::Mirroring, rom::Rom};
+use std::io::{self, Read, Write};
+
+use super::{Mapper, PrgRam};
+use crate::{
+    cartridge::Mirroring,
+    rom::Rom,
+    state::{read_u8, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
 
 /// MMC1 is a memory mapper used in Nintendo's SxROM and NES-EVENT Game Pak
 /// boards.
@@ -40,13 +49,17 @@ pub struct MMC1 {
     load: u8,
 
     count: u8,
-    ram: Vec<u8>,
+    ram: PrgRam,
     mirroring: Mirroring,
 }
 
 impl MMC1 {
     pub fn new(rom: Rom) -> Self {
         let prg_hi = (rom.header.prg_size() - 1) as u8;
+        let ram = PrgRam::new(rom.header.prg_ram_size());
+
+        // The header's mirroring is used until the game sets it.
+        let mirroring = rom.header.mirroring();
 
         MMC1 {
             rom,
@@ -62,35 +75,59 @@ impl MMC1 {
             count: 0,
             load: 0,
 
-            ram: vec![0; 0x2000],
-            mirroring: Mirroring::Vertical,
+            ram,
+            mirroring,
         }
     }
+
+    /// Returns the address in PRG ROM of the given CPU address.
+    fn prg_addr(&self, addr: u16) -> usize {
+        // Switch PRG ROM bank based on the control register.
+        let index = if self.control & 0x8 != 0 {
+            if addr <= 0xBFFF {
+                self.prg_lo as usize * 0x4000 + (addr & 0x3FFF) as usize
+            } else {
+                self.prg_hi as usize * 0x4000 + (addr & 0x3FFF) as usize
+            }
+        } else {
+            self.prg_32k as usize * 0x8000 + (addr & 0x7FFF) as usize
+        };
+
+        // Banks past the end of the ROM wrap around.
+        index % self.rom.prg.len()
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        if self.rom.header.chr_size() == 0 {
+            return addr as usize;
+        }
+
+        // Check if the CHR ROM bank mode is 8 KB or 4 KB.
+        let index = if self.control & 0x10 != 0 {
+            match addr {
+                0x0000..=0x0FFF => self.chr_lo as usize * 0x1000 + (addr & 0xFFF) as usize,
+                0x1000..=0x1FFF => self.chr_hi as usize * 0x1000 + (addr & 0xFFF) as usize,
+                _ => 0,
+            }
+        } else {
+            self.chr_8k as usize * 0x2000 + (addr & 0x1FFF) as usize
+        };
+
+        index % self.rom.chr.len()
+    }
 }
 
 impl Mapper for MMC1 {
     /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    fn read_prg(&self, addr: u16) -> Option<u8> {
         match addr {
             // 8 KB PRG RAM bank.
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF => self.ram.read(addr),
 
             // 16 KB PRG ROM bank.
-            0x8000..=0xFFFF => {
-                // Switch PRG ROM bank based on the control register.
-                let index = if self.control & 0x8 != 0 {
-                    if addr >= 0x8000 && addr <= 0xBFFF {
-                        self.prg_lo as usize * 0x4000 + (addr & 0x3FFF) as usize
-                    } else {
-                        self.prg_hi as usize * 0x4000 + (addr & 0x3FFF) as usize
-                    }
-                } else {
-                    self.prg_32k as usize * 0x8000 + (addr & 0x7FFF) as usize
-                };
-
-                self.rom.prg[index]
-            }
-            _ => 0,
+            0x8000..=0xFFFF => Some(self.rom.prg[self.prg_addr(addr)]),
+            _ => None,
         }
     }
 
@@ -98,7 +135,7 @@ impl Mapper for MMC1 {
     fn write_prg(&mut self, addr: u16, data: u8) {
         match addr {
             // 8 KB PRG RAM bank.
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = data,
+            0x6000..=0x7FFF => self.ram.write(addr, data),
 
             // 16 KB PRG ROM bank.
             0x8000..=0xFFFF => {
@@ -136,6 +173,9 @@ impl Mapper for MMC1 {
                                 }
                             }
                             _ => {
+                                // Bit 4 disables PRG RAM.
+                                self.ram.set_enabled(self.load & 0x10 == 0);
+
                                 let prg_mode = (self.control >> 2) & 0x3;
 
                                 match prg_mode {
@@ -163,22 +203,7 @@ impl Mapper for MMC1 {
 
     /// Returns a byte from CHR ROM at the given address.
     fn read_chr(&self, addr: u16) -> u8 {
-        if self.rom.header.chr_size() == 0 {
-            return self.rom.chr[addr as usize];
-        }
-
-        // Check if the CHR ROM bank mode is 8 KB or 4 KB.
-        let index = if self.control & 0x10 != 0 {
-            match addr {
-                0x0000..=0x0FFF => self.chr_lo as usize * 0x1000 + (addr & 0xFFF) as usize,
-                0x1000..=0x1FFF => self.chr_hi as usize * 0x1000 + (addr & 0xFFF) as usize,
-                _ => 0,
-            }
-        } else {
-            self.chr_8k as usize * 0x2000 + (addr & 0x1FFF) as usize
-        };
-
-        self.rom.chr[index]
+        self.rom.chr[self.chr_addr(addr)]
     }
 
     /// Writes a byte to CHR ROM at the given address.
@@ -188,8 +213,164 @@ impl Mapper for MMC1 {
         }
     }
 
-    /// Returns the Mirroring mode.
+    /// Returns the Mirroring mode set by the control register.
     fn mirroring(&self) -> Mirroring {
-        self.rom.header.mirroring()
+        self.mirroring
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "MMC1"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        format!(
+            "control: {:02X} PRG: {}/{} (32K: {}) CHR: {}/{} (8K: {})",
+            self.control,
+            self.prg_lo,
+            self.prg_hi,
+            self.prg_32k,
+            self.chr_lo,
+            self.chr_hi,
+            self.chr_8k
+        )
+    }
+
+    /// Returns the PRG RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the PRG RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for MMC1 {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)?;
+        w.write_all(&[
+            self.chr_lo,
+            self.chr_hi,
+            self.chr_8k,
+            self.prg_lo,
+            self.prg_hi,
+            self.prg_32k,
+            self.control,
+            self.load,
+            self.count,
+        ])?;
+        self.mirroring.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)?;
+        self.chr_lo = read_u8(r)?;
+        self.chr_hi = read_u8(r)?;
+        self.chr_8k = read_u8(r)?;
+        self.prg_lo = read_u8(r)?;
+        self.prg_hi = read_u8(r)?;
+        self.prg_32k = read_u8(r)?;
+        self.control = read_u8(r)?;
+        self.load = read_u8(r)?;
+        self.count = read_u8(r)?;
+        self.mirroring.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    /// Writes a value to a register through the serial load register.
+    fn write_register(mmc1: &mut MMC1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.write_prg(addr, (value >> i) & 0x1);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_disable() {
+        let rom = test_rom(2, vec![], 1, vec![], None, None, None).unwrap();
+        let mut mmc1 = MMC1::new(rom);
+        mmc1.write_prg(0x6000, 0x55);
+        assert_eq!(mmc1.read_prg(0x6000), Some(0x55));
+
+        // Disabled PRG RAM is open bus and ignores writes.
+        write_register(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.read_prg(0x6000), None);
+        mmc1.write_prg(0x6000, 0xAA);
+
+        write_register(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.read_prg(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let rom = test_rom(2, vec![], 1, vec![], None, None, Some(Mirroring::Vertical)).unwrap();
+        let mut mmc1 = MMC1::new(rom);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+
+        for (control, mirroring) in [
+            (0x0C, Mirroring::SingleScreenLo),
+            (0x0D, Mirroring::SingleScreenHi),
+            (0x0E, Mirroring::Vertical),
+            (0x0F, Mirroring::Horizontal),
+        ] {
+            write_register(&mut mmc1, 0x8000, control);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut prg = vec![0; 4 * 0x4000];
+        for (i, bank) in prg.chunks_mut(0x4000).enumerate() {
+            bank[0] = i as u8;
+        }
+        let new = || MMC1::new(test_rom(4, prg.clone(), 0, vec![], None, None, None).unwrap());
+
+        let mut mmc1 = new();
+        mmc1.write_prg(0x6000, 0x55);
+        mmc1.write_chr(0x0010, 0x66);
+        write_register(&mut mmc1, 0x8000, 0x0F);
+        write_register(&mut mmc1, 0xE000, 0x02);
+
+        // Leave a write part way through the shift register.
+        mmc1.write_prg(0xE000, 0x1);
+
+        let mut state = Vec::new();
+        mmc1.save_state(&mut state).unwrap();
+        let mut restored = new();
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x6000), Some(0x55));
+        assert_eq!(restored.read_chr(0x0010), 0x66);
+        assert_eq!(restored.read_prg(0x8000), Some(2));
+        assert_eq!(restored.banks(), mmc1.banks());
+
+        // Both finish the pending write the same way.
+        for m in [&mut mmc1, &mut restored] {
+            for _ in 0..4 {
+                m.write_prg(0xE000, 0);
+            }
+        }
+        assert_eq!(restored.read_prg(0x8000), Some(1));
+        assert_eq!(restored.banks(), mmc1.banks());
     }
 }
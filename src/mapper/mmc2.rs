@@ -0,0 +1,358 @@
+use std::io::{self, Read, Write};
+
+use super::{Mapper, PrgRam};
+use crate::{
+    cartridge::Mirroring,
+    rom::Rom,
+    state::{read_bool, read_u8, write_bool, write_u8, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// The MMC2 and MMC4 differ only in PRG ROM banking, PRG RAM and which
+/// fetches of the left pattern table flip its latch.
+///
+/// See: https://www.nesdev.org/wiki/MMC2 and https://www.nesdev.org/wiki/MMC4
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// Mapper 9: PxROM, used by Punch-Out!!. One 8 KB PRG ROM bank is
+    /// switchable and there is no PRG RAM.
+    Mmc2,
+
+    /// Mapper 10: FxROM, used by the Fire Emblem games. One 16 KB PRG ROM
+    /// bank is switchable and there is 8 KB of PRG RAM.
+    Mmc4,
+}
+
+impl Variant {
+    /// Returns the variant for the given iNES mapper number.
+    pub fn from_mapper(mapper: u16) -> Option<Self> {
+        match mapper {
+            9 => Some(Variant::Mmc2),
+            10 => Some(Variant::Mmc4),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the chip.
+    fn name(&self) -> &'static str {
+        match self {
+            Variant::Mmc2 => "MMC2",
+            Variant::Mmc4 => "MMC4",
+        }
+    }
+
+    /// Returns the size of the switchable PRG ROM bank at $8000.
+    fn prg_bank_size(&self) -> usize {
+        match self {
+            Variant::Mmc2 => 0x2000,
+            Variant::Mmc4 => 0x4000,
+        }
+    }
+}
+
+/// The MMC2 and MMC4 switch each 4 KB pattern table between two CHR banks
+/// when the PPU fetches tile $FD or $FE from it, letting a game change banks
+/// part way through a scanline without an IRQ.
+pub struct MMC2 {
+    rom: Rom,
+    variant: Variant,
+    ram: PrgRam,
+
+    prg_bank: u8,
+
+    /// 4 KB CHR banks for each pattern table, used while its latch holds $FD
+    /// and $FE respectively.
+    chr_banks: [[u8; 2]; 2],
+
+    /// Whether the latch of each pattern table holds $FE rather than $FD.
+    latches: [bool; 2],
+
+    mirroring: Mirroring,
+}
+
+impl MMC2 {
+    /// Returns an instantiated MMC2 or MMC4.
+    pub fn new(rom: Rom, variant: Variant) -> Self {
+        let mirroring = rom.header.mirroring();
+        let ram = match variant {
+            Variant::Mmc2 => PrgRam::new(0),
+            Variant::Mmc4 => PrgRam::new(rom.header.prg_ram_size()),
+        };
+
+        MMC2 {
+            rom,
+            variant,
+            ram,
+
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [true; 2],
+            mirroring,
+        }
+    }
+
+    /// Returns the address in PRG ROM of the given CPU address. Everything
+    /// after the switchable bank is fixed to the end of PRG ROM.
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_size = self.variant.prg_bank_size();
+        let offset = addr as usize - 0x8000;
+
+        // The fixed banks are counted back from the end of PRG ROM, which is
+        // at least 16 KB, so twice its length is added to stay positive.
+        let index = match offset < bank_size {
+            true => self.prg_bank as usize * bank_size + offset,
+            false => self.rom.prg.len() * 2 + offset - 0x8000,
+        };
+
+        // Banks past the end of the ROM wrap around.
+        index % self.rom.prg.len()
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        let table = (addr as usize >> 12) & 0x1;
+        let bank = self.chr_banks[table][self.latches[table] as usize] as usize;
+        (bank * 0x1000 + (addr & 0xFFF) as usize) % self.rom.chr.len()
+    }
+}
+
+impl Mapper for MMC2 {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => self.ram.read(addr),
+            0x8000..=0xFFFF => Some(self.rom.prg[self.prg_addr(addr)]),
+            _ => None,
+        }
+    }
+
+    /// Writes a byte to PRG ROM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.ram.write(addr, data),
+            0xA000..=0xAFFF => self.prg_bank = data & 0xF,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = match data & 0x1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from CHR ROM at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_addr(addr)]
+    }
+
+    /// Writes a byte to CHR ROM at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.rom.header.chr_size() == 0 {
+            let addr = self.chr_addr(addr);
+            self.rom.chr[addr] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the name of the chip.
+    fn name(&self) -> &'static str {
+        self.variant.name()
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        let latch = |table: usize| match self.latches[table] {
+            false => "FD",
+            true => "FE",
+        };
+
+        format!(
+            "PRG: {} CHR: {}/{} ({}) {}/{} ({})",
+            self.prg_bank,
+            self.chr_banks[0][0],
+            self.chr_banks[0][1],
+            latch(0),
+            self.chr_banks[1][0],
+            self.chr_banks[1][1],
+            latch(1)
+        )
+    }
+
+    /// Sets the latch of a pattern table when the high bitplane of tile $FD
+    /// or $FE is fetched from it. The MMC2 only watches the first row of the
+    /// tile in the left pattern table, but every row in the right one.
+    fn notify_chr_fetch(&mut self, addr: u16) -> bool {
+        let table = (addr as usize >> 12) & 0x1;
+        let any_row = table == 1 || self.variant == Variant::Mmc4;
+
+        let latch = match addr & 0xFF8 {
+            0xFD8 if any_row || addr & 0x7 == 0 => false,
+            0xFE8 if any_row || addr & 0x7 == 0 => true,
+            _ => return false,
+        };
+
+        let changed = self.latches[table] != latch;
+        self.latches[table] = latch;
+        changed
+    }
+
+    fn watches_chr_fetches(&self) -> bool {
+        true
+    }
+
+    /// Returns the PRG RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the PRG RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for MMC2 {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)?;
+        write_u8(w, self.prg_bank)?;
+        for bank in self.chr_banks.iter().flatten() {
+            write_u8(w, *bank)?;
+        }
+        write_bool(w, self.latches[0])?;
+        write_bool(w, self.latches[1])?;
+        self.mirroring.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)?;
+        self.prg_bank = read_u8(r)? & 0xF;
+        for bank in self.chr_banks.iter_mut().flatten() {
+            *bank = read_u8(r)? & 0x1F;
+        }
+
+        self.latches[0] = read_bool(r)?;
+        self.latches[1] = read_bool(r)?;
+        self.mirroring.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    /// Returns a board with 128 KB of PRG ROM and CHR ROM, with the first
+    /// byte of each 8 KB PRG and 4 KB CHR bank set to its bank number.
+    fn test_mmc2(variant: Variant) -> MMC2 {
+        let mut prg = vec![0; 8 * 0x4000];
+        for (i, bank) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+        let mut chr = vec![0; 16 * 0x2000];
+        for (i, bank) in chr.chunks_mut(0x1000).enumerate() {
+            bank[0] = i as u8;
+        }
+
+        let rom = test_rom(8, prg, 16, chr, None, None, None).unwrap();
+        MMC2::new(rom, variant)
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc2 = test_mmc2(Variant::Mmc2);
+        mmc2.write_prg(0xA000, 3);
+        assert_eq!(mmc2.read_prg(0x8000), Some(3));
+        assert_eq!(mmc2.read_prg(0xA000), Some(13));
+        assert_eq!(mmc2.read_prg(0xE000), Some(15));
+        assert_eq!(mmc2.read_prg(0x6000), None);
+
+        let mut mmc4 = test_mmc2(Variant::Mmc4);
+        mmc4.write_prg(0xA000, 3);
+        assert_eq!(mmc4.read_prg(0x8000), Some(6));
+        assert_eq!(mmc4.read_prg(0xA000), Some(7));
+        assert_eq!(mmc4.read_prg(0xC000), Some(14));
+        mmc4.write_prg(0x6000, 0x55);
+        assert_eq!(mmc4.read_prg(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn test_chr_latches() {
+        let mut mmc2 = test_mmc2(Variant::Mmc2);
+        for (addr, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mmc2.write_prg(addr, bank);
+        }
+
+        // Both latches start at $FE.
+        assert_eq!(mmc2.read_chr(0x0000), 2);
+        assert_eq!(mmc2.read_chr(0x1000), 4);
+
+        // Only the high bitplane of tile $FD switches the left table.
+        assert!(!mmc2.notify_chr_fetch(0x0FD0));
+        assert!(mmc2.notify_chr_fetch(0x0FD8));
+        assert!(!mmc2.notify_chr_fetch(0x0FD8));
+        assert_eq!(mmc2.read_chr(0x0000), 1);
+        assert_eq!(mmc2.read_chr(0x1000), 4);
+
+        // The left table only watches the first row, the right every row.
+        assert!(!mmc2.notify_chr_fetch(0x0FE9));
+        assert!(mmc2.notify_chr_fetch(0x1FDB));
+        assert_eq!(mmc2.read_chr(0x0000), 1);
+        assert_eq!(mmc2.read_chr(0x1000), 3);
+
+        // The MMC4 watches every row of both.
+        let mut mmc4 = test_mmc2(Variant::Mmc4);
+        assert!(mmc4.notify_chr_fetch(0x0FDF));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc2 = test_mmc2(Variant::Mmc2);
+        mmc2.write_prg(0xF000, 1);
+        assert_eq!(mmc2.mirroring(), Mirroring::Horizontal);
+        mmc2.write_prg(0xF000, 0);
+        assert_eq!(mmc2.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut mmc4 = test_mmc2(Variant::Mmc4);
+        mmc4.write_prg(0x6000, 0x55);
+        mmc4.write_prg(0xA000, 2);
+        mmc4.write_prg(0xB000, 5);
+        mmc4.write_prg(0xF000, 1);
+        mmc4.notify_chr_fetch(0x0FD8);
+
+        let mut state = Vec::new();
+        mmc4.save_state(&mut state).unwrap();
+        let mut restored = test_mmc2(Variant::Mmc4);
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x6000), Some(0x55));
+        assert_eq!(restored.read_prg(0x8000), Some(4));
+        assert_eq!(restored.read_chr(0x0000), 5);
+        assert_eq!(restored.mirroring(), Mirroring::Horizontal);
+    }
+}
@@ -0,0 +1,365 @@
+use std::io::{self, Read, Write};
+
+use super::{BankSelect, Mapper, PrgRam};
+use crate::{
+    cartridge::Mirroring,
+    rom::Rom,
+    state::{read_bool, read_u8, write_bool, write_u8, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// The MMC3 (TxROM) extends the Namco 108 bank registers with PRG and CHR
+/// mode bits, mirroring control, switchable PRG RAM and a scanline counter
+/// clocked by PPU A12 which raises an IRQ when it reaches zero.
+///
+/// See: https://www.nesdev.org/wiki/MMC3
+pub struct MMC3 {
+    rom: Rom,
+    ram: PrgRam,
+    banks: BankSelect,
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl MMC3 {
+    /// Returns an instantiated MMC3.
+    pub fn new(rom: Rom) -> Self {
+        MMC3 {
+            ram: PrgRam::new(rom.header.prg_ram_size()),
+            banks: BankSelect::default(),
+            mirroring: rom.header.mirroring(),
+            rom,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    /// Returns the address in PRG ROM of the given CPU address.
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.rom.prg.len() / PRG_BANK_SIZE;
+        let swap = self.banks.select() & 0x40 != 0;
+        let bank = self.banks.prg_bank(addr, bank_count, swap);
+        bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        let banks = (self.rom.chr.len() / CHR_BANK_SIZE).max(1);
+        let invert = self.banks.select() & 0x80 != 0;
+        let bank = self.banks.chr_bank(addr, invert) % banks;
+        bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl Mapper for MMC3 {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF => self.ram.read(addr),
+
+            // 4 x 8 KB PRG ROM banks.
+            0x8000..=0xFFFF => Some(self.rom.prg[self.prg_addr(addr)]),
+            _ => None,
+        }
+    }
+
+    /// Writes a byte to PRG ROM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        // Each pair of registers is selected by A0 within an 8 KB range.
+        match (addr & 0xE000, addr & 0x1) {
+            (0x6000, _) => self.ram.write(addr, data),
+
+            (0x8000, _) => self.banks.write(addr, data),
+
+            // Boards with four-screen VRAM have no mirroring control.
+            (0xA000, 0) if self.mirroring != Mirroring::FourScreen => {
+                self.mirroring = match data & 0x1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            (0xA000, 1) => {
+                self.ram.set_enabled(data & 0x80 != 0);
+                self.ram.set_write_protected(data & 0x40 != 0);
+            }
+
+            (0xC000, 0) => self.irq_latch = data,
+            (0xC000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+
+            (0xE000, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000, _) => self.irq_enabled = true,
+
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from CHR ROM at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_addr(addr)]
+    }
+
+    /// Writes a byte to CHR ROM at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        // Boards with CHR RAM bank it like CHR ROM.
+        if self.rom.header.chr_size() == 0 {
+            let addr = self.chr_addr(addr);
+            self.rom.chr[addr] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "MMC3"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        format!(
+            "{} (PRG swap: {} CHR invert: {})",
+            self.banks.banks(),
+            self.banks.select() & 0x40 != 0,
+            self.banks.select() & 0x80 != 0
+        )
+    }
+
+    /// Clocks the scanline counter. The counter is reloaded from the latch
+    /// when it is zero or a reload was requested, otherwise decremented, and
+    /// raises an IRQ when it ends up at zero.
+    fn clock_a12(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    /// Returns true if the mapper is asserting an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Returns the scanline counter.
+    fn irq_counter(&self) -> Option<u16> {
+        Some(self.irq_counter as u16)
+    }
+
+    /// Returns the PRG RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the PRG RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for MMC3 {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)?;
+        self.banks.save_state(w)?;
+        self.mirroring.save_state(w)?;
+
+        write_u8(w, self.irq_latch)?;
+        write_u8(w, self.irq_counter)?;
+        write_bool(w, self.irq_reload)?;
+        write_bool(w, self.irq_enabled)?;
+        write_bool(w, self.irq_pending)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)?;
+        self.banks.load_state(r)?;
+        self.mirroring.load_state(r)?;
+
+        self.irq_latch = read_u8(r)?;
+        self.irq_counter = read_u8(r)?;
+        self.irq_reload = read_bool(r)?;
+        self.irq_enabled = read_bool(r)?;
+        self.irq_pending = read_bool(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mapper::MapperInfo, rom::tests::test_rom};
+
+    /// Returns an MMC3 with 128 KB of PRG ROM and CHR ROM, with the first
+    /// byte of each bank set to its bank number.
+    fn test_mmc3() -> MMC3 {
+        let mut prg = vec![0; 8 * 0x4000];
+        for (i, bank) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+        let mut chr = vec![0; 16 * 0x2000];
+        for (i, bank) in chr.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+
+        let rom = test_rom(8, prg, 16, chr, None, None, None).unwrap();
+        MMC3::new(rom)
+    }
+
+    fn write_bank(mmc3: &mut MMC3, register: u8, bank: u8) {
+        mmc3.write_prg(0x8000, register);
+        mmc3.write_prg(0x8001, bank);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc3 = test_mmc3();
+        write_bank(&mut mmc3, 6, 3);
+        write_bank(&mut mmc3, 7, 5);
+
+        assert_eq!(mmc3.read_prg(0x8000), Some(3));
+        assert_eq!(mmc3.read_prg(0xA000), Some(5));
+        assert_eq!(mmc3.read_prg(0xC000), Some(14));
+        assert_eq!(mmc3.read_prg(0xE000), Some(15));
+
+        // Bit 6 of the bank select swaps $8000 and $C000.
+        mmc3.write_prg(0x8000, 0x40);
+        assert_eq!(mmc3.read_prg(0x8000), Some(14));
+        assert_eq!(mmc3.read_prg(0xC000), Some(3));
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mmc3 = test_mmc3();
+        write_bank(&mut mmc3, 0, 6);
+        write_bank(&mut mmc3, 2, 9);
+        assert_eq!(mmc3.read_chr(0x0400), 7);
+        assert_eq!(mmc3.read_chr(0x1000), 9);
+
+        // Bit 7 of the bank select inverts the pattern tables.
+        mmc3.write_prg(0x8000, 0x80);
+        assert_eq!(mmc3.read_chr(0x0000), 9);
+        assert_eq!(mmc3.read_chr(0x1400), 7);
+    }
+
+    #[test]
+    fn test_mapper_info() {
+        let mut mmc3 = test_mmc3();
+        write_bank(&mut mmc3, 0, 6);
+        write_bank(&mut mmc3, 6, 3);
+        mmc3.write_prg(0xC000, 7);
+        mmc3.write_prg(0xC001, 0);
+        mmc3.clock_a12();
+
+        let info = MapperInfo::new(&mmc3, 4, true);
+        assert_eq!(info.name, "MMC3");
+        assert_eq!(info.prg_banks, [3, 0, 14, 15]);
+        assert_eq!(info.chr_banks, [6, 7, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(info.prg_ram, 0x2000);
+        assert_eq!(info.irq_counter, Some(7));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc3 = test_mmc3();
+        mmc3.write_prg(0xA000, 0x1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.write_prg(0xBFFE, 0x0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mmc3 = test_mmc3();
+        mmc3.write_prg(0x6000, 0x42);
+        assert_eq!(mmc3.read_prg(0x6000), Some(0x42));
+
+        mmc3.write_prg(0xA001, 0xC0);
+        mmc3.write_prg(0x6000, 0x17);
+        assert_eq!(mmc3.read_prg(0x6000), Some(0x42));
+
+        mmc3.write_prg(0xA001, 0x00);
+        assert_eq!(mmc3.read_prg(0x6000), None);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = test_mmc3();
+        mmc3.write_prg(0xC000, 2);
+        mmc3.write_prg(0xC001, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        // The first clock reloads the counter, the next two count it down.
+        mmc3.clock_a12();
+        mmc3.clock_a12();
+        assert!(!mmc3.irq_pending());
+        mmc3.clock_a12();
+        assert!(mmc3.irq_pending());
+
+        // Disabling acknowledges the IRQ.
+        mmc3.write_prg(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+
+        // While disabled the counter keeps running without raising IRQs.
+        mmc3.clock_a12();
+        mmc3.clock_a12();
+        mmc3.clock_a12();
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut mmc3 = test_mmc3();
+        write_bank(&mut mmc3, 6, 2);
+        mmc3.write_prg(0xA000, 0x1);
+        mmc3.write_prg(0xC000, 5);
+        mmc3.write_prg(0xE001, 0);
+        mmc3.clock_a12();
+
+        let mut state = Vec::new();
+        mmc3.save_state(&mut state).unwrap();
+        let mut restored = test_mmc3();
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x8000), Some(2));
+        assert_eq!(restored.mirroring(), Mirroring::Horizontal);
+        assert_eq!(restored.irq_counter, 5);
+        assert!(restored.irq_enabled);
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{self, Read, Write};
+
+use super::{BankSelect, Mapper};
+use crate::{cartridge::Mirroring, rom::Rom, state::SaveState};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// Boards built around the Namco 108 differ in how the CHR bank registers are
+/// wired and whether they control mirroring.
+///
+/// See: https://www.nesdev.org/wiki/Namco_108_family
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// Mapper 76: NAMCOT-3446, R2-R5 select 2 KB CHR banks and R0-R1 are
+    /// unused.
+    Namcot3446,
+
+    /// Mapper 88: NAMCOT-3443, CHR A16 is tied to PPU A12, so R0-R1 select
+    /// from the first and R2-R5 from the second 64 KB of CHR ROM.
+    Namcot3443,
+
+    /// Mapper 95: NAMCOT-3425, bit 5 of R0-R1 selects the nametable.
+    Namcot3425,
+
+    /// Mapper 154: NAMCOT-3453, wired as mapper 88 with bit 6 of any write
+    /// selecting a single screen.
+    Namcot3453,
+
+    /// Mapper 206: DxROM and the plain Namco 108 boards.
+    Dxrom,
+}
+
+impl Variant {
+    /// Returns the variant for the given iNES mapper number.
+    pub fn from_mapper(mapper: u16) -> Option<Self> {
+        match mapper {
+            76 => Some(Variant::Namcot3446),
+            88 => Some(Variant::Namcot3443),
+            95 => Some(Variant::Namcot3425),
+            154 => Some(Variant::Namcot3453),
+            206 => Some(Variant::Dxrom),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        match self {
+            Variant::Namcot3446 => "NAMCOT-3446",
+            Variant::Namcot3443 => "NAMCOT-3443",
+            Variant::Namcot3425 => "NAMCOT-3425",
+            Variant::Namcot3453 => "NAMCOT-3453",
+            Variant::Dxrom => "Namco 108",
+        }
+    }
+}
+
+/// The Namco 108 is the predecessor of the MMC3, providing the same PRG and
+/// CHR banking without the mode bits, mirroring control, PRG RAM or IRQ.
+pub struct Namco108 {
+    rom: Rom,
+    variant: Variant,
+    banks: BankSelect,
+    mirroring: Mirroring,
+}
+
+impl Namco108 {
+    /// Returns an instantiated Namco 108.
+    pub fn new(rom: Rom, variant: Variant) -> Self {
+        let mirroring = match variant {
+            Variant::Namcot3453 => Mirroring::SingleScreenLo,
+            _ => rom.header.mirroring(),
+        };
+
+        Namco108 {
+            rom,
+            variant,
+            banks: BankSelect::default(),
+            mirroring,
+        }
+    }
+
+    /// Returns the address in PRG ROM of the given CPU address.
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.rom.prg.len() / PRG_BANK_SIZE;
+        let bank = self.banks.prg_bank(addr, bank_count, false);
+        bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        let banks = (self.rom.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_register_bank(addr) % banks;
+        bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    /// Returns the 1 KB CHR bank selected for the given address, before
+    /// wrapping to the size of CHR ROM.
+    fn chr_register_bank(&self, addr: u16) -> usize {
+        let bank = self.banks.chr_bank(addr, false);
+
+        match self.variant {
+            Variant::Namcot3446 => {
+                let register = self.banks.register(2 + ((addr as usize >> 11) & 0x3));
+                (register as usize) << 1 | ((addr as usize >> 10) & 0x1)
+            }
+            Variant::Namcot3443 | Variant::Namcot3453 if addr < 0x1000 => bank & 0x3F,
+            Variant::Namcot3443 | Variant::Namcot3453 => bank | 0x40,
+            Variant::Namcot3425 => bank & 0x1F,
+            Variant::Dxrom => bank & 0x3F,
+        }
+    }
+}
+
+impl Mapper for Namco108 {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match addr {
+            // 4 x 8 KB PRG ROM banks.
+            0x8000..=0xFFFF => Some(self.rom.prg[self.prg_addr(addr)]),
+
+            // There is no PRG RAM.
+            _ => None,
+        }
+    }
+
+    /// Writes a byte to PRG ROM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+
+        if self.variant == Variant::Namcot3453 {
+            self.mirroring = match data & 0x40 {
+                0 => Mirroring::SingleScreenLo,
+                _ => Mirroring::SingleScreenHi,
+            };
+        }
+
+        // The registers only respond at $8000-$9FFF, and only to the low six
+        // bits of each value.
+        if addr < 0xA000 {
+            self.banks.write(addr, data & 0x3F);
+        }
+    }
+
+    /// Returns a byte from CHR ROM at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_addr(addr)]
+    }
+
+    /// Writes a byte to CHR ROM at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        // Boards with CHR RAM bank it like CHR ROM.
+        if self.rom.header.chr_size() == 0 {
+            let addr = self.chr_addr(addr);
+            self.rom.chr[addr] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        if self.variant != Variant::Namcot3425 {
+            return self.mirroring;
+        }
+
+        // Bit 5 of R0 drives CIRAM A10 for the nametables at $2000-$27FF and
+        // R1 for $2800-$2FFF. The PPU bus only supports the standard layouts,
+        // so the rarely used reverse of horizontal mirroring is treated as
+        // horizontal.
+        match (self.banks.register(0) & 0x20, self.banks.register(1) & 0x20) {
+            (0, 0) => Mirroring::SingleScreenLo,
+            (0, _) | (_, 0) => Mirroring::Horizontal,
+            _ => Mirroring::SingleScreenHi,
+        }
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        self.variant.name()
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        self.banks.banks()
+    }
+}
+
+impl SaveState for Namco108 {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.banks.save_state(w)?;
+        self.mirroring.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.banks.load_state(r)?;
+        self.mirroring.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    /// Returns a Namco 108 with 128 KB of PRG ROM and CHR ROM, with the
+    /// first byte of each bank set to its bank number.
+    fn test_namco108(variant: Variant) -> Namco108 {
+        let mut prg = vec![0; 8 * 0x4000];
+        for (i, bank) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+        let mut chr = vec![0; 16 * 0x2000];
+        for (i, bank) in chr.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+
+        let rom = test_rom(8, prg, 16, chr, None, None, None).unwrap();
+        Namco108::new(rom, variant)
+    }
+
+    fn write_bank(namco: &mut Namco108, register: u8, bank: u8) {
+        namco.write_prg(0x8000, register);
+        namco.write_prg(0x8001, bank);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut namco = test_namco108(Variant::Dxrom);
+        write_bank(&mut namco, 6, 3);
+        write_bank(&mut namco, 7, 5);
+
+        assert_eq!(namco.read_prg(0x8000), Some(3));
+        assert_eq!(namco.read_prg(0xA000), Some(5));
+        assert_eq!(namco.read_prg(0xC000), Some(14));
+        assert_eq!(namco.read_prg(0xE000), Some(15));
+        assert_eq!(namco.read_prg(0x6000), None);
+
+        // The mode bits of the MMC3 are ignored.
+        namco.write_prg(0x8000, 0xC6);
+        namco.write_prg(0x8001, 4);
+        assert_eq!(namco.read_prg(0x8000), Some(4));
+        assert_eq!(namco.read_chr(0x0000), 0);
+
+        // Writes above $9FFF are ignored.
+        namco.write_prg(0xA001, 7);
+        assert_eq!(namco.read_prg(0x8000), Some(4));
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut namco = test_namco108(Variant::Dxrom);
+        write_bank(&mut namco, 0, 6);
+        write_bank(&mut namco, 2, 9);
+        assert_eq!(namco.read_chr(0x0400), 7);
+        assert_eq!(namco.read_chr(0x1000), 9);
+
+        // NAMCOT-3446 has 2 KB banks selected by R2-R5.
+        let mut namco = test_namco108(Variant::Namcot3446);
+        write_bank(&mut namco, 3, 40);
+        assert_eq!(namco.read_chr(0x0800), 80);
+        assert_eq!(namco.read_chr(0x0C00), 81);
+
+        // NAMCOT-3443 maps the right pattern table to the upper 64 KB.
+        let mut namco = test_namco108(Variant::Namcot3443);
+        write_bank(&mut namco, 0, 2);
+        write_bank(&mut namco, 2, 2);
+        assert_eq!(namco.read_chr(0x0000), 2);
+        assert_eq!(namco.read_chr(0x1000), 66);
+    }
+
+    #[test]
+    fn test_chr_ram_is_banked() {
+        let rom = test_rom(8, vec![], 0, vec![], None, None, None).unwrap();
+        let mut namco = Namco108::new(rom, Variant::Dxrom);
+        write_bank(&mut namco, 2, 5);
+
+        namco.write_chr(0x1010, 0x55);
+        assert_eq!(namco.read_chr(0x1010), 0x55);
+        assert_eq!(namco.rom.chr[5 * CHR_BANK_SIZE + 0x10], 0x55);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut namco = test_namco108(Variant::Namcot3425);
+        write_bank(&mut namco, 1, 0x20);
+        assert_eq!(namco.mirroring(), Mirroring::Horizontal);
+        write_bank(&mut namco, 0, 0x20);
+        assert_eq!(namco.mirroring(), Mirroring::SingleScreenHi);
+
+        let mut namco = test_namco108(Variant::Namcot3453);
+        assert_eq!(namco.mirroring(), Mirroring::SingleScreenLo);
+        namco.write_prg(0xE000, 0x40);
+        assert_eq!(namco.mirroring(), Mirroring::SingleScreenHi);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut namco = test_namco108(Variant::Namcot3453);
+        write_bank(&mut namco, 6, 2);
+        write_bank(&mut namco, 2, 0x43);
+
+        let mut state = Vec::new();
+        namco.save_state(&mut state).unwrap();
+        let mut restored = test_namco108(Variant::Namcot3453);
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x8000), Some(2));
+        assert_eq!(restored.read_chr(0x1000), 67);
+        assert_eq!(restored.mirroring(), Mirroring::SingleScreenHi);
+    }
+}
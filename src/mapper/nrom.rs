@@ -1,21 +1,25 @@
-use super::Mapper;
-use crate::{cartridge::Mirroring, rom::Rom};
+use std::io::{self, Read, Write};
+
+use super::{Mapper, PrgRam};
+use crate::{cartridge::Mirroring, rom::Rom, state::SaveState};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
 
 /// NROM refers to the Nintendo cartridge boards NES-NROM-128, NES-NROM-256,
 /// their HVC counterparts, and clone boards. The iNES format assigns mapper 0
 /// to NROM.
 pub struct Nrom {
     rom: Rom,
-    ram: Vec<u8>,
+    ram: PrgRam,
 }
 
 impl Nrom {
     /// Returns an instantiated NROM.
     pub fn new(rom: Rom) -> Self {
-        Nrom {
-            rom,
-            ram: vec![0; 0x2000],
-        }
+        let ram = PrgRam::new(rom.header.prg_ram_size());
+
+        Nrom { rom, ram }
     }
 
     /// Returns the PRG ROM mask used for PRG ROM bank switching.
@@ -30,19 +34,22 @@ impl Nrom {
 
 impl Mapper for Nrom {
     /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    fn read_prg(&self, addr: u16) -> Option<u8> {
         match addr {
             // Special case for "Family Basic".
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF => self.ram.read(addr),
 
-            _ => self.rom.prg[(addr & self.prg_mask()) as usize],
+            0x8000..=0xFFFF => Some(self.rom.prg[(addr & self.prg_mask()) as usize]),
+
+            // Nothing is mapped to the expansion area.
+            _ => None,
         }
     }
 
     /// Writes a byte to PRG ROM at the given address.
     fn write_prg(&mut self, addr: u16, data: u8) {
         if let 0x6000..=0x7FFF = addr {
-            self.ram[(addr & 0x1FFF) as usize] = data;
+            self.ram.write(addr, data);
         }
     }
 
@@ -62,4 +69,64 @@ impl Mapper for Nrom {
     fn mirroring(&self) -> Mirroring {
         self.rom.header.mirroring()
     }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "NROM"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        (addr & self.prg_mask()) as usize / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        addr as usize / CHR_BANK_SIZE
+    }
+
+    /// Returns the PRG RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the PRG RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for Nrom {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let new = || Nrom::new(test_rom(1, vec![], 0, vec![], None, None, None).unwrap());
+
+        let mut nrom = new();
+        nrom.write_prg(0x6000, 0x55);
+        nrom.write_chr(0x1FFF, 0x66);
+
+        let mut state = Vec::new();
+        nrom.save_state(&mut state).unwrap();
+        let mut restored = new();
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x6000), Some(0x55));
+        assert_eq!(restored.read_chr(0x1FFF), 0x66);
+    }
 }
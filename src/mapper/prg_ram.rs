@@ -0,0 +1,133 @@
+use std::io::{self, Read, Write};
+
+use crate::state::{read_bool, write_bool, SaveState};
+
+/// PRG RAM (work RAM) mapped at $6000-$7FFF.
+///
+/// RAM smaller than the 8 KB window is mirrored across it. Boards without RAM
+/// have none allocated, and like mappers which have disabled their RAM they
+/// leave the CPU data bus undriven, so reads return open bus.
+pub struct PrgRam {
+    data: Vec<u8>,
+    enabled: bool,
+    write_protected: bool,
+}
+
+impl PrgRam {
+    /// Returns enabled, writable PRG RAM of the given size in bytes, which may
+    /// be zero for boards without RAM.
+    pub fn new(size: usize) -> Self {
+        PrgRam {
+            data: vec![0; size],
+            enabled: true,
+            write_protected: false,
+        }
+    }
+
+    /// Enables or disables the RAM. Disabled RAM ignores reads and writes.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Write protects the RAM, it can still be read while protected.
+    pub fn set_write_protected(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+    }
+
+    /// Returns the byte at the given address, or None if there is no RAM or
+    /// it is disabled.
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        if !self.enabled || self.data.is_empty() {
+            return None;
+        }
+
+        Some(self.data[self.index(addr)])
+    }
+
+    /// Writes the byte to the given address, unless there is no RAM or it is
+    /// disabled or write protected.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        if !self.enabled || self.write_protected || self.data.is_empty() {
+            return;
+        }
+
+        let index = self.index(addr);
+        self.data[index] = data;
+    }
+
+    /// Returns the contents of the RAM.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the contents of the RAM for writing, ignoring write protection.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Returns the index of the given address into the RAM.
+    fn index(&self, addr: u16) -> usize {
+        (addr & 0x1FFF) as usize % self.data.len()
+    }
+}
+
+impl SaveState for PrgRam {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.data)?;
+        write_bool(w, self.enabled)?;
+        write_bool(w, self.write_protected)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        r.read_exact(&mut self.data)?;
+        self.enabled = read_bool(r)?;
+        self.write_protected = read_bool(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write() {
+        let mut ram = PrgRam::new(0x2000);
+        ram.write(0x6001, 0x55);
+        assert_eq!(ram.read(0x6001), Some(0x55));
+        assert_eq!(ram.read(0x6002), Some(0));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut ram = PrgRam::new(0x800);
+        ram.write(0x6001, 0x55);
+        assert_eq!(ram.read(0x6801), Some(0x55));
+        assert_eq!(ram.read(0x7801), Some(0x55));
+    }
+
+    #[test]
+    fn test_disabled_reads_are_open_bus() {
+        let mut ram = PrgRam::new(0x2000);
+        ram.write(0x6000, 0x55);
+
+        ram.set_enabled(false);
+        assert_eq!(ram.read(0x6000), None);
+        ram.write(0x6000, 0xAA);
+
+        ram.set_enabled(true);
+        assert_eq!(ram.read(0x6000), Some(0x55));
+
+        assert_eq!(PrgRam::new(0).read(0x6000), None);
+    }
+
+    #[test]
+    fn test_write_protect() {
+        let mut ram = PrgRam::new(0x2000);
+        ram.write(0x6000, 0x55);
+
+        ram.set_write_protected(true);
+        ram.write(0x6000, 0xAA);
+        assert_eq!(ram.read(0x6000), Some(0x55));
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Read, Write};
+
+use super::Mapper;
+use crate::{cartridge::Mirroring, state::SaveState};
+
+/// Size of the CHR RAM used until CHR accesses are scripted.
+const CHR_RAM_SIZE: usize = 0x2000;
+
+type ReadFn<T> = Box<dyn Fn(u64, u16) -> T>;
+type WriteFn = Box<dyn FnMut(u64, u16, u8)>;
+
+/// A fake board for tests, whose reads and writes are handled by closures
+/// and which raises IRQs at chosen CPU cycles. It allows the bus, CPU and PPU
+/// to be tested against unusual mapper behaviour, such as a bank switch in
+/// the middle of an instruction or an IRQ during DMA, without writing a real
+/// mapper first.
+///
+/// Each closure is given the number of CPU cycles the mapper has been clocked
+/// for and the address. Until scripted, PRG ROM is mirrored over
+/// $8000-$FFFF, PRG writes are ignored and CHR is 8 KB of RAM. Closures
+/// share state with the test through an `Rc<Cell<_>>`.
+pub struct ScriptedMapper {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mirroring: Mirroring,
+
+    read_prg: Option<ReadFn<Option<u8>>>,
+    write_prg: Option<WriteFn>,
+    read_chr: Option<ReadFn<u8>>,
+    write_chr: Option<WriteFn>,
+
+    cycles: u64,
+
+    /// Cycles at which to assert the IRQ line, latest first.
+    irqs: Vec<u64>,
+    irq_ack: Option<u16>,
+    irq_pending: bool,
+}
+
+impl ScriptedMapper {
+    /// Returns a board with the given PRG ROM, which must not be empty.
+    pub fn new(prg: Vec<u8>) -> Self {
+        ScriptedMapper {
+            prg,
+            chr: vec![0; CHR_RAM_SIZE],
+            mirroring: Mirroring::Horizontal,
+            read_prg: None,
+            write_prg: None,
+            read_chr: None,
+            write_chr: None,
+            cycles: 0,
+            irqs: Vec::new(),
+            irq_ack: None,
+            irq_pending: false,
+        }
+    }
+
+    /// Handles reads of $4020-$FFFF with the closure.
+    pub fn on_read_prg(mut self, f: impl Fn(u64, u16) -> Option<u8> + 'static) -> Self {
+        self.read_prg = Some(Box::new(f));
+        self
+    }
+
+    /// Handles writes to $4020-$FFFF with the closure.
+    pub fn on_write_prg(mut self, f: impl FnMut(u64, u16, u8) + 'static) -> Self {
+        self.write_prg = Some(Box::new(f));
+        self
+    }
+
+    /// Handles reads of the pattern tables with the closure.
+    pub fn on_read_chr(mut self, f: impl Fn(u64, u16) -> u8 + 'static) -> Self {
+        self.read_chr = Some(Box::new(f));
+        self
+    }
+
+    /// Handles writes to the pattern tables with the closure.
+    pub fn on_write_chr(mut self, f: impl FnMut(u64, u16, u8) + 'static) -> Self {
+        self.write_chr = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the mirroring, horizontal by default.
+    pub fn mirroring(mut self, mirroring: Mirroring) -> Self {
+        self.mirroring = mirroring;
+        self
+    }
+
+    /// Asserts the IRQ line once the mapper has been clocked for the given
+    /// number of CPU cycles. It stays asserted until acknowledged.
+    pub fn irq_at(mut self, cycle: u64) -> Self {
+        self.irqs.push(cycle);
+        self.irqs.sort_by(|a, b| b.cmp(a));
+        self
+    }
+
+    /// Acknowledges the IRQ on writes to the given address.
+    pub fn irq_ack(mut self, addr: u16) -> Self {
+        self.irq_ack = Some(addr);
+        self
+    }
+}
+
+impl Mapper for ScriptedMapper {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match (&self.read_prg, addr) {
+            (Some(f), _) => f(self.cycles, addr),
+            (None, 0x8000..=0xFFFF) => Some(self.prg[(addr as usize - 0x8000) % self.prg.len()]),
+            (None, _) => None,
+        }
+    }
+
+    /// Writes a byte to PRG ROM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if self.irq_ack == Some(addr) {
+            self.irq_pending = false;
+        }
+        if let Some(f) = &mut self.write_prg {
+            f(self.cycles, addr, data);
+        }
+    }
+
+    /// Returns a byte from CHR at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        match &self.read_chr {
+            Some(f) => f(self.cycles, addr),
+            None => self.chr[addr as usize % CHR_RAM_SIZE],
+        }
+    }
+
+    /// Writes a byte to CHR at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        match &mut self.write_chr {
+            Some(f) => f(self.cycles, addr, data),
+            None => self.chr[addr as usize % CHR_RAM_SIZE] = data,
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "Scripted"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address, as if
+    /// unscripted.
+    fn prg_bank(&self, addr: u16) -> usize {
+        (addr as usize - 0x8000) % self.prg.len() / 0x2000
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        addr as usize / 0x400
+    }
+
+    /// Scripted reads can return anything, so only unscripted PRG ROM is
+    /// cached.
+    fn cacheable_prg(&self) -> bool {
+        self.read_prg.is_none()
+    }
+
+    /// Counts CPU cycles, asserting any IRQ due.
+    fn clock_cpu(&mut self) {
+        self.cycles += 1;
+        if self.irqs.last().is_some_and(|&at| at <= self.cycles) {
+            self.irqs.pop();
+            self.irq_pending = true;
+        }
+    }
+
+    /// Returns true if the mapper is asserting an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+/// The closures can't be saved, so save states of scripted boards are empty.
+impl SaveState for ScriptedMapper {
+    fn save_state(&self, _w: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_state(&mut self, _r: &mut dyn Read) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::{bus::SystemBus, cartridge::tests::test_scripted_cartridge, cpu::Cpu};
+
+    /// Returns a CPU running from $8000 with the board inserted.
+    fn test_cpu(mapper: ScriptedMapper) -> Cpu<'static> {
+        let cart = test_scripted_cartridge(mapper);
+        let mut cpu = Cpu::new(SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {}));
+        cpu.pc = 0x8000;
+        cpu
+    }
+
+    #[test]
+    fn test_bank_switch_mid_instruction() {
+        // LDA #$01, STA $8000, LDX #$11 in bank 0, LDX #$22 in bank 1.
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..7].copy_from_slice(&[0xA9, 0x01, 0x8D, 0x00, 0x80, 0xA2, 0x11]);
+        prg[0x4005..0x4007].copy_from_slice(&[0xA2, 0x22]);
+
+        let bank = Rc::new(Cell::new(0));
+        let (read_bank, write_bank) = (bank.clone(), bank.clone());
+        let mapper = ScriptedMapper::new(prg.clone())
+            .on_read_prg(move |_, addr| {
+                let addr = addr as usize - 0x8000;
+                Some(prg[(read_bank.get() * 0x4000 + addr) % 0x8000])
+            })
+            .on_write_prg(move |_, _, data| write_bank.set(data as usize));
+
+        let mut cpu = test_cpu(mapper);
+        for _ in 0..3 {
+            cpu.clock();
+        }
+
+        // The instruction after the write is fetched from the new bank.
+        assert_eq!(bank.get(), 1);
+        assert_eq!(cpu.x, 0x22);
+    }
+
+    #[test]
+    fn test_scripted_chr() {
+        let writes = Rc::new(Cell::new(0));
+        let counted = writes.clone();
+        let mut mapper = ScriptedMapper::new(vec![0; 0x4000])
+            .on_read_chr(|_, addr| (addr >> 10) as u8)
+            .on_write_chr(move |_, _, _| counted.set(counted.get() + 1))
+            .mirroring(Mirroring::Vertical);
+
+        mapper.write_chr(0x0000, 0xFF);
+        assert_eq!(writes.get(), 1);
+        assert_eq!(mapper.read_chr(0x1C00), 7);
+        assert_eq!(Mapper::mirroring(&mapper), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_irq_during_dma() {
+        // CLI, LDA #$02, STA $4014, with the IRQ handler at $9000.
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..6].copy_from_slice(&[0x58, 0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        prg[0x1000..0x1002].copy_from_slice(&[0xA2, 0x42]);
+        prg[0x7FFE..].copy_from_slice(&[0x00, 0x90]);
+
+        let mut cpu = test_cpu(ScriptedMapper::new(prg).irq_at(100).irq_ack(0xE000));
+        for _ in 0..3 {
+            cpu.clock();
+        }
+
+        // The IRQ raised during the DMA waits for the CPU to be released.
+        assert_eq!(cpu.bus.cpu_cycles(), 2 + 2 + 4 + 513);
+        assert_eq!(cpu.pc, 0x8006);
+        assert!(cpu.bus.irq_status());
+
+        cpu.clock();
+        assert_eq!(cpu.x, 0x42);
+    }
+}
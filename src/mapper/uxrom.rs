@@ -1,5 +1,14 @@
+use std::io::{self, Read, Write};
+
 use super::Mapper;
-use crate::{cartridge::Mirroring, rom::Rom, rom::PRG_PAGE_SIZE};
+use crate::{
+    cartridge::Mirroring,
+    rom::{Rom, PRG_PAGE_SIZE},
+    state::{read_u8, write_u8, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
 
 const FIXED_BANK_START: u16 = 0xC000;
 const FIXED_BANK_END: u16 = 0xFFFF;
@@ -16,24 +25,28 @@ impl Uxrom {
     pub fn new(rom: Rom) -> Self {
         Uxrom { rom, bank: 0 }
     }
+
+    /// Returns the address in PRG ROM of the given CPU address.
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank = match addr {
+            // 16 KB PRG ROM bank, fixed to the last bank
+            FIXED_BANK_START..=FIXED_BANK_END => self.rom.header.prg_size() - 1,
+
+            // 16 KB switchable PRG ROM bank.
+            _ => self.bank,
+        };
+        bank * PRG_PAGE_SIZE + (addr & PAGE_OFFSET_MASK) as usize
+    }
 }
 
 impl Mapper for Uxrom {
     /// Returns a byte from PRG ROM at the given address.
-    fn read_prg(&self, addr: u16) -> u8 {
+    fn read_prg(&self, addr: u16) -> Option<u8> {
         match addr {
-            // 16 KB PRG ROM bank, fixed to the last bank
-            FIXED_BANK_START..=FIXED_BANK_END => {
-                let index = (self.rom.header.prg_size() - 1) * PRG_PAGE_SIZE
-                    + (addr & PAGE_OFFSET_MASK) as usize;
-                self.rom.prg[index]
-            }
+            // There is no PRG RAM.
+            0x4020..=0x7FFF => None,
 
-            // 16 KB switchable PRG ROM bank.
-            _ => {
-                let index = self.bank * PRG_PAGE_SIZE + (addr & PAGE_OFFSET_MASK) as usize;
-                self.rom.prg[index]
-            }
+            _ => Some(self.rom.prg[self.prg_addr(addr)]),
         }
     }
 
@@ -42,7 +55,9 @@ impl Mapper for Uxrom {
         // Writes in the range 0x8000-0xFFFF select the 16 KB PRG ROM bank.
         // (UNROM uses bits 2-0; UOROM uses bits 3-0).
         if let 0x8000..=0xFFFF = addr {
-            self.bank = (data & 0xF) as usize;
+            // Banks past the end of the ROM wrap around, as the unused
+            // address lines aren't connected.
+            self.bank = (data & 0xF) as usize % self.rom.header.prg_size();
         }
     }
 
@@ -62,4 +77,65 @@ impl Mapper for Uxrom {
     fn mirroring(&self) -> Mirroring {
         self.rom.header.mirroring()
     }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "UxROM"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        addr as usize / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        format!("PRG: {}", self.bank)
+    }
+}
+
+impl SaveState for Uxrom {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        write_u8(w, self.bank as u8)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.bank = read_u8(r)? as usize % self.rom.header.prg_size();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut prg = vec![0; 4 * PRG_PAGE_SIZE];
+        for (i, bank) in prg.chunks_mut(PRG_PAGE_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+        let new = || Uxrom::new(test_rom(4, prg.clone(), 0, vec![], None, None, None).unwrap());
+
+        let mut uxrom = new();
+        uxrom.write_prg(0x8000, 2);
+        uxrom.write_chr(0x0010, 0x66);
+
+        let mut state = Vec::new();
+        uxrom.save_state(&mut state).unwrap();
+        let mut restored = new();
+        restored.load_state(&mut &state[..]).unwrap();
+
+        assert_eq!(restored.read_prg(0x8000), Some(2));
+        assert_eq!(restored.read_prg(0xC000), Some(3));
+        assert_eq!(restored.read_chr(0x0010), 0x66);
+    }
 }
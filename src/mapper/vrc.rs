@@ -0,0 +1,508 @@
+use std::io::{self, Read, Write};
+
+use super::{Mapper, PrgRam};
+use crate::{
+    cartridge::Mirroring,
+    rom::Rom,
+    state::{read_bool, read_u16, read_u8, write_bool, write_u16, write_u8, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// Number of PPU cycles per scanline, used by the IRQ prescaler.
+const IRQ_PRESCALER_PERIOD: i16 = 341;
+
+/// The Konami VRC2 and VRC4 boards connect the register select lines to
+/// different CPU address lines depending on the board revision.
+///
+/// See: https://www.nesdev.org/wiki/VRC2_and_VRC4
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// Mapper 21: VRC4a (A1, A2) and VRC4c (A6, A7).
+    Vrc4ac,
+
+    /// Mapper 22: VRC2a (A1, A0), CHR banks are selected in 2 KB units.
+    Vrc2a,
+
+    /// Mapper 23: VRC2b and VRC4f (A0, A1) and VRC4e (A2, A3).
+    Vrc2bVrc4ef,
+
+    /// Mapper 25: VRC2c and VRC4b (A1, A0) and VRC4d (A3, A2).
+    Vrc2cVrc4bd,
+}
+
+impl Variant {
+    /// Returns the variant for the given iNES mapper number.
+    pub fn from_mapper(mapper: u16) -> Option<Self> {
+        match mapper {
+            21 => Some(Variant::Vrc4ac),
+            22 => Some(Variant::Vrc2a),
+            23 => Some(Variant::Vrc2bVrc4ef),
+            25 => Some(Variant::Vrc2cVrc4bd),
+            _ => None,
+        }
+    }
+
+    /// Returns the CPU address lines wired to the chip's A0 and A1 by each
+    /// revision sharing the mapper number, in NES 2.0 submapper order.
+    fn lines(&self) -> &'static [(u16, u16)] {
+        match self {
+            // VRC4a, VRC4c.
+            Variant::Vrc4ac => &[(1, 2), (6, 7)],
+            Variant::Vrc2a => &[(1, 0)],
+            // VRC4f, VRC4e, VRC2b.
+            Variant::Vrc2bVrc4ef => &[(0, 1), (2, 3), (0, 1)],
+            // VRC4b, VRC4d, VRC2c.
+            Variant::Vrc2cVrc4bd => &[(1, 0), (3, 2), (1, 0)],
+        }
+    }
+
+    /// Returns the register (0-3) selected by the given CPU address.
+    ///
+    /// A NES 2.0 submapper names the board revision and so its address lines.
+    /// Without one the revision can't be known, so the lines of every
+    /// revision sharing the mapper number are combined.
+    fn register(&self, addr: u16, submapper: u8) -> u16 {
+        let lines = self.lines();
+        let lines = match (submapper as usize).checked_sub(1) {
+            Some(i) if i < lines.len() => &lines[i..=i],
+            _ => lines,
+        };
+
+        let line = |n: u16| (addr >> n) & 0x1;
+        lines
+            .iter()
+            .fold(0, |reg, &(a0, a1)| reg | line(a1) << 1 | line(a0))
+    }
+
+    /// Returns the names of the chips sharing the mapper number.
+    fn name(&self) -> &'static str {
+        match self {
+            Variant::Vrc4ac => "VRC4a/VRC4c",
+            Variant::Vrc2a => "VRC2a",
+            Variant::Vrc2bVrc4ef => "VRC2b/VRC4e/VRC4f",
+            Variant::Vrc2cVrc4bd => "VRC2c/VRC4b/VRC4d",
+        }
+    }
+
+    /// Returns true if the variant is a VRC4, which adds the PRG swap mode,
+    /// extra mirroring modes and the IRQ counter.
+    fn is_vrc4(&self) -> bool {
+        *self != Variant::Vrc2a
+    }
+}
+
+/// VRC2 and VRC4 are Konami ASICs providing 8 KB PRG ROM banking, 1 KB CHR ROM
+/// banking and mirroring control. The VRC4 adds a CPU cycle based IRQ counter.
+pub struct Vrc {
+    rom: Rom,
+    variant: Variant,
+
+    /// NES 2.0 submapper, selecting the board revision's address lines.
+    submapper: u8,
+
+    ram: PrgRam,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enable_after_ack: bool,
+    irq_cycle_mode: bool,
+    irq_pending: bool,
+}
+
+impl Vrc {
+    /// Returns an instantiated VRC2/VRC4.
+    pub fn new(rom: Rom, variant: Variant) -> Self {
+        let mirroring = rom.header.mirroring();
+        let ram = PrgRam::new(rom.header.prg_ram_size());
+        let submapper = rom.header.submapper();
+
+        Vrc {
+            rom,
+            variant,
+            submapper,
+            ram,
+
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: IRQ_PRESCALER_PERIOD,
+            irq_enabled: false,
+            irq_enable_after_ack: false,
+            irq_cycle_mode: false,
+            irq_pending: false,
+        }
+    }
+
+    /// Returns the number of 8 KB PRG ROM banks.
+    fn prg_bank_count(&self) -> usize {
+        self.rom.prg.len() / PRG_BANK_SIZE
+    }
+
+    /// Returns the 8 KB PRG ROM bank selected for the given address, before
+    /// wrapping to the size of PRG ROM.
+    fn prg_register_bank(&self, addr: u16) -> usize {
+        let second_last = self.prg_bank_count() - 2;
+
+        match (addr, self.prg_swap) {
+            (0x8000..=0x9FFF, false) => self.prg_banks[0] as usize,
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            _ => self.prg_bank_count() - 1,
+        }
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        let mut bank = self.chr_banks[(addr as usize / CHR_BANK_SIZE) & 0x7] as usize;
+
+        // VRC2a ignores the low bit of the bank number.
+        if self.variant == Variant::Vrc2a {
+            bank >>= 1;
+        }
+
+        let banks = (self.rom.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    /// Writes either the low or high bits of a 1 KB CHR bank number.
+    fn write_chr_bank(&mut self, addr: u16, reg: u16, data: u8) {
+        // $B000-$E003 select banks 0-7, two registers per bank.
+        let bank = (((addr - 0xB000) >> 12) * 2 + (reg >> 1)) as usize;
+
+        self.chr_banks[bank] = match reg & 0x1 {
+            0 => (self.chr_banks[bank] & 0x1F0) | (data & 0xF) as u16,
+            _ => (self.chr_banks[bank] & 0x00F) | ((data & 0x1F) as u16) << 4,
+        };
+    }
+
+    /// Writes the IRQ control register.
+    fn write_irq_control(&mut self, data: u8) {
+        self.irq_enable_after_ack = data & 0x1 != 0;
+        self.irq_enabled = data & 0x2 != 0;
+        self.irq_cycle_mode = data & 0x4 != 0;
+        self.irq_pending = false;
+
+        if self.irq_enabled {
+            self.irq_counter = self.irq_latch;
+            self.irq_prescaler = IRQ_PRESCALER_PERIOD;
+        }
+    }
+
+    /// Clocks the IRQ counter, raising an IRQ when it overflows.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match addr {
+            // 8 KB PRG RAM bank.
+            0x6000..=0x7FFF => self.ram.read(addr),
+
+            // 4 x 8 KB PRG ROM banks.
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank(addr);
+                Some(self.rom.prg[bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize])
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes a byte to PRG ROM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.ram.write(addr, data);
+            return;
+        }
+
+        let reg = self.variant.register(addr, self.submapper);
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_banks[0] = data & 0x1F,
+
+            (0x9000, 0) | (0x9000, 1) if self.variant.is_vrc4() => {
+                self.mirroring = match data & 0x3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLo,
+                    _ => Mirroring::SingleScreenHi,
+                };
+            }
+            (0x9000, 2) if self.variant.is_vrc4() => self.prg_swap = data & 0x2 != 0,
+            (0x9000, _) if !self.variant.is_vrc4() => {
+                self.mirroring = match data & 0x1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+
+            (0xA000, _) => self.prg_banks[1] = data & 0x1F,
+
+            (0xB000..=0xE000, _) => self.write_chr_bank(addr & 0xF000, reg, data),
+
+            (0xF000, 0) => self.irq_latch = (self.irq_latch & 0xF0) | (data & 0xF),
+            (0xF000, 1) => self.irq_latch = (self.irq_latch & 0x0F) | (data & 0xF) << 4,
+            (0xF000, 2) => self.write_irq_control(data),
+            (0xF000, _) => {
+                self.irq_pending = false;
+                self.irq_enabled = self.irq_enable_after_ack;
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Returns a byte from CHR ROM at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_addr(addr)]
+    }
+
+    /// Writes a byte to CHR ROM at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        // Boards with CHR RAM bank it like CHR ROM.
+        if self.rom.header.chr_size() == 0 {
+            let addr = self.chr_addr(addr);
+            self.rom.chr[addr] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the name of the chip.
+    fn name(&self) -> &'static str {
+        self.variant.name()
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_register_bank(addr) % self.prg_bank_count()
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / CHR_BANK_SIZE
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        format!(
+            "PRG: {:?} (swap: {}) CHR: {:?}",
+            self.prg_banks, self.prg_swap, self.chr_banks
+        )
+    }
+
+    /// Clocks the IRQ counter once per CPU cycle.
+    fn clock_cpu(&mut self) {
+        if !self.variant.is_vrc4() || !self.irq_enabled {
+            return;
+        }
+
+        // In scanline mode the prescaler divides CPU cycles by 113.667,
+        // clocking the counter roughly once per scanline.
+        if self.irq_cycle_mode {
+            self.clock_irq_counter();
+        } else {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += IRQ_PRESCALER_PERIOD;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    /// Returns true if the mapper is asserting an IRQ.
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Returns the IRQ counter of the VRC4.
+    fn irq_counter(&self) -> Option<u16> {
+        self.variant.is_vrc4().then_some(self.irq_counter as u16)
+    }
+
+    /// Returns the PRG RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the PRG RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for Vrc {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)?;
+
+        w.write_all(&self.prg_banks)?;
+        write_bool(w, self.prg_swap)?;
+        for bank in self.chr_banks {
+            write_u16(w, bank)?;
+        }
+        self.mirroring.save_state(w)?;
+
+        write_u8(w, self.irq_latch)?;
+        write_u8(w, self.irq_counter)?;
+        write_u16(w, self.irq_prescaler as u16)?;
+        write_bool(w, self.irq_enabled)?;
+        write_bool(w, self.irq_enable_after_ack)?;
+        write_bool(w, self.irq_cycle_mode)?;
+        write_bool(w, self.irq_pending)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)?;
+
+        r.read_exact(&mut self.prg_banks)?;
+        self.prg_swap = read_bool(r)?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = read_u16(r)?;
+        }
+        self.mirroring.load_state(r)?;
+
+        self.irq_latch = read_u8(r)?;
+        self.irq_counter = read_u8(r)?;
+        self.irq_prescaler = read_u16(r)? as i16;
+        self.irq_enabled = read_bool(r)?;
+        self.irq_enable_after_ack = read_bool(r)?;
+        self.irq_cycle_mode = read_bool(r)?;
+        self.irq_pending = read_bool(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    fn test_vrc(variant: Variant) -> Vrc {
+        let mut prg = vec![0; 8 * 0x4000];
+        for (i, bank) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+
+        let rom = test_rom(8, prg, 1, vec![], None, None, None).unwrap();
+        Vrc::new(rom, variant)
+    }
+
+    #[test]
+    fn test_register_address_lines() {
+        assert_eq!(Variant::Vrc4ac.register(0x9004, 0), 2);
+        assert_eq!(Variant::Vrc4ac.register(0x9080, 0), 2);
+        assert_eq!(Variant::Vrc2a.register(0xB001, 0), 2);
+        assert_eq!(Variant::Vrc2bVrc4ef.register(0xB008, 0), 2);
+        assert_eq!(Variant::Vrc2cVrc4bd.register(0xB002, 0), 1);
+        assert_eq!(Variant::Vrc2cVrc4bd.register(0xB004, 0), 2);
+    }
+
+    #[test]
+    fn test_submapper_address_lines() {
+        // VRC4a only watches A1 and A2, VRC4c only A6 and A7.
+        assert_eq!(Variant::Vrc4ac.register(0x9006, 1), 3);
+        assert_eq!(Variant::Vrc4ac.register(0x9080, 1), 0);
+        assert_eq!(Variant::Vrc4ac.register(0x9004, 2), 0);
+        assert_eq!(Variant::Vrc4ac.register(0x9080, 2), 2);
+
+        // VRC4e watches A2 and A3, where VRC4f has nothing connected.
+        assert_eq!(Variant::Vrc2bVrc4ef.register(0xB00C, 2), 3);
+        assert_eq!(Variant::Vrc2bVrc4ef.register(0xB00C, 1), 0);
+        assert_eq!(Variant::Vrc2cVrc4bd.register(0xB004, 2), 2);
+        assert_eq!(Variant::Vrc2cVrc4bd.register(0xB004, 3), 0);
+
+        // Unknown submappers combine the lines like submapper 0.
+        assert_eq!(Variant::Vrc2bVrc4ef.register(0xB008, 9), 2);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut vrc = test_vrc(Variant::Vrc2bVrc4ef);
+        vrc.write_prg(0x8000, 3);
+        vrc.write_prg(0xA000, 5);
+
+        assert_eq!(vrc.read_prg(0x8000), Some(3));
+        assert_eq!(vrc.read_prg(0xA000), Some(5));
+        assert_eq!(vrc.read_prg(0xC000), Some(14));
+        assert_eq!(vrc.read_prg(0xE000), Some(15));
+
+        // Swap mode fixes $8000 to the second last bank.
+        vrc.write_prg(0x9002, 0x2);
+        assert_eq!(vrc.read_prg(0x8000), Some(14));
+        assert_eq!(vrc.read_prg(0xC000), Some(3));
+    }
+
+    #[test]
+    fn test_chr_bank_select() {
+        let mut vrc = test_vrc(Variant::Vrc2bVrc4ef);
+        vrc.write_prg(0xC000, 0x5);
+        vrc.write_prg(0xC001, 0x1);
+
+        assert_eq!(vrc.chr_banks[2], 0x15);
+    }
+
+    #[test]
+    fn test_chr_ram_is_banked() {
+        let rom = test_rom(8, vec![], 0, vec![], None, None, None).unwrap();
+        let mut vrc = Vrc::new(rom, Variant::Vrc2bVrc4ef);
+        vrc.write_prg(0xB000, 0x3);
+
+        vrc.write_chr(0x0010, 0x55);
+        assert_eq!(vrc.read_chr(0x0010), 0x55);
+        assert_eq!(vrc.rom.chr[3 * CHR_BANK_SIZE + 0x10], 0x55);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut vrc = test_vrc(Variant::Vrc4ac);
+        vrc.write_prg(0x9000, 0x3);
+        assert_eq!(vrc.mirroring(), Mirroring::SingleScreenHi);
+
+        let mut vrc = test_vrc(Variant::Vrc2a);
+        vrc.write_prg(0x9000, 0x1);
+        assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_irq_cycle_mode() {
+        let mut vrc = test_vrc(Variant::Vrc2bVrc4ef);
+        vrc.write_prg(0xF000, 0xE);
+        vrc.write_prg(0xF001, 0xF);
+        vrc.write_prg(0xF002, 0x6);
+
+        vrc.clock_cpu();
+        assert!(!vrc.irq_pending());
+        vrc.clock_cpu();
+        assert!(vrc.irq_pending());
+
+        // Acknowledging the IRQ clears it.
+        vrc.write_prg(0xF003, 0);
+        assert!(!vrc.irq_pending());
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Read, Write};
+
+use super::{Mapper, PrgRam};
+use crate::{
+    cartridge::Mirroring,
+    rom::Rom,
+    state::{read_bool, write_bool, SaveState},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// The Vs. System boards, iNES mapper 99. Rather than registers in the ROM
+/// area, bit 2 of writes to the controller strobe at $4016 selects the 8 KB
+/// CHR bank, and on the boards with 40 KB of PRG ROM the first 8 KB of PRG
+/// ROM too. The work RAM at $6000 is mapped like PRG RAM.
+///
+/// See: https://www.nesdev.org/wiki/INES_Mapper_099
+pub struct VsSystem {
+    rom: Rom,
+    ram: PrgRam,
+    select: bool,
+}
+
+impl VsSystem {
+    /// Returns an instantiated Vs. System board.
+    pub fn new(rom: Rom) -> Self {
+        VsSystem {
+            ram: PrgRam::new(rom.header.prg_ram_size()),
+            rom,
+            select: false,
+        }
+    }
+
+    /// Returns the address in PRG ROM of the given CPU address.
+    fn prg_addr(&self, addr: u16) -> usize {
+        let len = self.rom.prg.len();
+        match addr {
+            // The extra 8 KB of 40 KB boards is switched in at $8000.
+            0x8000..=0x9FFF if self.select && len > 0x8000 => {
+                4 * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+            }
+
+            // 32 KB, or 16 KB mirrored, of fixed PRG ROM.
+            _ => (addr & 0x7FFF) as usize % len.min(0x8000),
+        }
+    }
+
+    /// Returns the address in CHR of the given PPU address.
+    fn chr_addr(&self, addr: u16) -> usize {
+        let banks = (self.rom.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.select as usize % banks;
+        bank * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Mapper for VsSystem {
+    /// Returns a byte from PRG ROM at the given address.
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => self.ram.read(addr),
+            0x8000..=0xFFFF => Some(self.rom.prg[self.prg_addr(addr)]),
+
+            _ => None,
+        }
+    }
+
+    /// Writes a byte to PRG RAM at the given address.
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.ram.write(addr, data);
+        }
+    }
+
+    /// Returns a byte from CHR ROM at the given address.
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.rom.chr[self.chr_addr(addr)]
+    }
+
+    /// Writes a byte to CHR ROM at the given address.
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.rom.header.chr_size() == 0 {
+            self.rom.chr[addr as usize] = data;
+        }
+    }
+
+    /// Returns the Mirroring mode.
+    fn mirroring(&self) -> Mirroring {
+        self.rom.header.mirroring()
+    }
+
+    /// Returns the name of the board.
+    fn name(&self) -> &'static str {
+        "Vs. System"
+    }
+
+    /// Returns the 8 KB PRG ROM bank mapped at the given address.
+    fn prg_bank(&self, addr: u16) -> usize {
+        self.prg_addr(addr) / PRG_BANK_SIZE
+    }
+
+    /// Returns the 1 KB CHR bank mapped at the given address.
+    fn chr_bank(&self, addr: u16) -> usize {
+        self.chr_addr(addr) / 0x400
+    }
+
+    /// Returns a description of the currently selected banks.
+    fn banks(&self) -> String {
+        format!("CHR: {}", self.select as u8)
+    }
+
+    /// Selects the banks with bit 2 of the strobe.
+    fn write_strobe(&mut self, data: u8) -> bool {
+        let select = data & 0x4 != 0;
+        let changed = select != self.select;
+        self.select = select;
+        changed
+    }
+
+    /// Returns the work RAM.
+    fn prg_ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Returns the work RAM for writing.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.ram.data_mut()
+    }
+}
+
+impl SaveState for VsSystem {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        self.rom.save_state(w)?;
+        self.ram.save_state(w)?;
+        write_bool(w, self.select)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.rom.load_state(r)?;
+        self.ram.load_state(r)?;
+        self.select = read_bool(r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::test_rom;
+
+    #[test]
+    fn test_strobe_banking() {
+        let mut prg = vec![0; 3 * 0x4000];
+        for (i, bank) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            bank[0] = i as u8;
+        }
+        let mut chr = vec![0; 2 * CHR_BANK_SIZE];
+        chr[CHR_BANK_SIZE] = 1;
+        let rom = test_rom(3, prg, 2, chr, None, Some(0x1), None).unwrap();
+        let mut vs = VsSystem::new(rom);
+
+        assert_eq!(vs.read_prg(0x8000), Some(0));
+        assert_eq!(vs.read_prg(0xE000), Some(3));
+        assert_eq!(vs.read_chr(0x0000), 0);
+
+        // Strobing the controllers leaves the banks alone.
+        assert!(!vs.write_strobe(0x1));
+        assert!(vs.write_strobe(0x4));
+        assert_eq!(vs.read_prg(0x8000), Some(4));
+        assert_eq!(vs.read_prg(0xA000), Some(1));
+        assert_eq!(vs.read_chr(0x0000), 1);
+
+        let mut state = Vec::new();
+        vs.save_state(&mut state).unwrap();
+        vs.write_strobe(0x0);
+        vs.load_state(&mut &state[..]).unwrap();
+        assert_eq!(vs.read_chr(0x0000), 1);
+    }
+}
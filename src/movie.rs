@@ -0,0 +1,377 @@
+use std::path::Path;
+
+/// Represents an event recorded in a movie.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The pressed state of every button of the controller in the given
+    /// port changed.
+    Input(usize, u8),
+
+    /// The reset button was pressed.
+    Reset,
+
+    /// The console was power cycled.
+    Power,
+}
+
+/// Buttons of a controller in the order FM2 movies list them, from bit 7 of
+/// the joypad state down to bit 0.
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/// Represents a recording of the inputs, resets and power cycles of a play
+/// session, each indexed by the frame it happened before.
+#[derive(Debug, Default, PartialEq)]
+pub struct Movie {
+    events: Vec<(u64, Event)>,
+
+    /// Seed of the unpredictable power-on state the movie was recorded from.
+    seed: Option<u64>,
+
+    /// Number of frames recorded, which may run past the last event.
+    length: u64,
+
+    /// Index of the next event to replay.
+    cursor: usize,
+
+    /// Frame the next call to [`Movie::replay`] is expected for.
+    position: u64,
+}
+
+impl Movie {
+    /// Loads the movie from the file at the given path, as an FM2 movie if
+    /// it has the .fm2 extension.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        match is_fm2(path) {
+            true => Self::from_fm2(&contents),
+            false => contents.parse(),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes the movie to the file at the given path, as an FM2 movie if it
+    /// has the .fm2 extension.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = match is_fm2(path) {
+            true => self.to_fm2(),
+            false => self.to_string(),
+        };
+        std::fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Returns the seed of the power-on state the movie must be replayed
+    /// from, if it was recorded with one.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Sets the seed of the power-on state the movie is recorded from.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    /// Records an event before the given frame.
+    pub fn record(&mut self, frame: u64, event: Event) {
+        self.events.push((frame, event));
+        self.length = self.length.max(frame + 1);
+    }
+
+    /// Sets the number of frames recorded, so that frames after the last
+    /// event are kept.
+    pub fn set_length(&mut self, frames: u64) {
+        self.length = self.length.max(frames);
+    }
+
+    /// Returns the events to replay before the given frame, in the order
+    /// they were recorded.
+    pub fn replay(&mut self, frame: u64) -> &[(u64, Event)] {
+        self.position = frame + 1;
+        let start = self.cursor;
+        while self
+            .events
+            .get(self.cursor)
+            .is_some_and(|(f, _)| *f <= frame)
+        {
+            self.cursor += 1;
+        }
+
+        &self.events[start..self.cursor]
+    }
+
+    /// Returns true if every frame has been replayed.
+    pub fn finished(&self) -> bool {
+        self.cursor == self.events.len() && self.position >= self.length
+    }
+
+    /// Parses a movie in the FM2 format of FCEUX, holding a line of
+    /// "|commands|RLDUTSBA|RLDUTSBA||" per frame after a header of "key
+    /// value" lines. Only standard controllers are supported.
+    ///
+    /// See: https://fceux.com/web/FM2.html
+    pub fn from_fm2(s: &str) -> Result<Self, String> {
+        let mut movie = Movie::default();
+        let mut ports = [true, true];
+        let mut buttons = [0; 2];
+
+        for (i, line) in s.lines().enumerate() {
+            let invalid = || format!("invalid FM2 line {}: {:?}", i + 1, line);
+
+            let Some(record) = line.strip_prefix('|') else {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                match (key, value.trim()) {
+                    ("binary", "1") => return Err("binary FM2 movies aren't supported".into()),
+                    ("fourscore", "1") => return Err("Four Score movies aren't supported".into()),
+                    ("port0" | "port1", "0") => ports[(key == "port1") as usize] = false,
+                    ("port0" | "port1", "1") => {}
+                    ("port0" | "port1", _) => {
+                        return Err(format!("unsupported controller on line {}", i + 1))
+                    }
+                    ("seed", seed) => movie.seed = Some(seed.parse().map_err(|_| invalid())?),
+                    _ => {}
+                }
+                continue;
+            };
+
+            let frame = movie.length;
+            let mut fields = record.split('|');
+            let commands: u8 = fields
+                .next()
+                .and_then(|c| c.parse().ok())
+                .ok_or_else(invalid)?;
+            if commands & 0x2 != 0 {
+                movie.record(frame, Event::Power);
+            } else if commands & 0x1 != 0 {
+                movie.record(frame, Event::Reset);
+            }
+
+            for port in (0..2).filter(|&port| ports[port]) {
+                let field = fields.next().filter(|f| f.len() == 8).ok_or_else(invalid)?;
+                let pressed = field
+                    .bytes()
+                    .enumerate()
+                    .filter(|(_, b)| *b != b'.' && *b != b' ')
+                    .fold(0, |pressed, (bit, _)| pressed | 0x80 >> bit);
+
+                if pressed != buttons[port] {
+                    movie.record(frame, Event::Input(port, pressed));
+                    buttons[port] = pressed;
+                }
+            }
+            movie.set_length(frame + 1);
+        }
+
+        Ok(movie)
+    }
+
+    /// Formats the movie as an FM2 movie with two standard controllers, see
+    /// [`Movie::from_fm2`].
+    pub fn to_fm2(&self) -> String {
+        let mut s = String::from("version 3\nemuVersion 0\nrerecordCount 0\n");
+        s.push_str("fourscore 0\nport0 1\nport1 1\nport2 0\n");
+        if let Some(seed) = self.seed {
+            s.push_str(&format!("seed {}\n", seed));
+        }
+
+        let mut events = self.events.iter().peekable();
+        let mut buttons = [0; 2];
+        for frame in 0..self.length {
+            let mut commands = 0;
+            while let Some((_, event)) = events.next_if(|(f, _)| *f == frame) {
+                match event {
+                    Event::Input(port, pressed) => buttons[*port] = *pressed,
+                    Event::Reset => commands |= 0x1,
+                    Event::Power => commands |= 0x2,
+                }
+            }
+
+            s.push_str(&format!("|{}|", commands));
+            for pressed in buttons {
+                for (bit, button) in FM2_BUTTONS.iter().enumerate() {
+                    s.push(match pressed & (0x80 >> bit) {
+                        0 => '.',
+                        _ => *button as char,
+                    });
+                }
+                s.push('|');
+            }
+            s.push_str("|\n");
+        }
+
+        s
+    }
+}
+
+/// Returns true if the path has the .fm2 extension.
+fn is_fm2(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fm2"))
+}
+
+impl std::str::FromStr for Movie {
+    type Err = String;
+
+    /// Parses lines of "<frame> input <buttons>", "<frame> input2 <buttons>"
+    /// for the second controller, "<frame> reset" or "<frame> power", and an
+    /// optional "seed <seed>" line. Blank lines and lines starting with '#'
+    /// are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut movie = Movie::default();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("invalid movie event on line {}: {:?}", i + 1, line);
+
+            if let Some(seed) = line.strip_prefix("seed ") {
+                movie.seed = Some(seed.trim().parse().map_err(|_| invalid())?);
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let frame = parts
+                .next()
+                .and_then(|f| f.parse::<u64>().ok())
+                .ok_or_else(invalid)?;
+            let buttons = |buttons: &str| {
+                u8::from_str_radix(buttons.trim_start_matches("0x"), 16).map_err(|_| invalid())
+            };
+            let event = match (parts.next(), parts.next()) {
+                (Some("input"), Some(b)) => Event::Input(0, buttons(b)?),
+                (Some("input2"), Some(b)) => Event::Input(1, buttons(b)?),
+                (Some("reset"), None) => Event::Reset,
+                (Some("power"), None) => Event::Power,
+                _ => return Err(invalid()),
+            };
+
+            if movie.events.last().is_some_and(|(f, _)| *f > frame) {
+                return Err(format!("movie events out of order on line {}", i + 1));
+            }
+            movie.record(frame, event);
+        }
+
+        Ok(movie)
+    }
+}
+
+impl std::fmt::Display for Movie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# res movie")?;
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?;
+        }
+        for (frame, event) in self.events.iter() {
+            match event {
+                Event::Input(0, buttons) => writeln!(f, "{} input {:02X}", frame, buttons)?,
+                Event::Input(_, buttons) => writeln!(f, "{} input2 {:02X}", frame, buttons)?,
+                Event::Reset => writeln!(f, "{} reset", frame)?,
+                Event::Power => writeln!(f, "{} power", frame)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        let mut movie = Movie::default();
+        movie.record(0, Event::Input(0, 0x09));
+        movie.record(12, Event::Reset);
+        movie.record(12, Event::Input(0, 0x00));
+        movie.record(40, Event::Input(1, 0x80));
+        movie.record(300, Event::Power);
+
+        let contents = movie.to_string();
+        assert_eq!(
+            contents,
+            "# res movie\n0 input 09\n12 reset\n12 input 00\n40 input2 80\n300 power\n"
+        );
+        assert_eq!(contents.parse::<Movie>().unwrap(), movie);
+    }
+
+    #[test]
+    fn test_seed_round_trip() {
+        let mut movie = Movie::default();
+        movie.set_seed(1234);
+        movie.record(5, Event::Input(0, 0x01));
+
+        let contents = movie.to_string();
+        assert_eq!(contents, "# res movie\nseed 1234\n5 input 01\n");
+        assert_eq!(contents.parse::<Movie>().unwrap().seed(), Some(1234));
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let mut movie = Movie::default();
+        movie.set_seed(7);
+        movie.record(1, Event::Input(0, 0x81));
+        movie.record(2, Event::Reset);
+        movie.record(2, Event::Input(1, 0x08));
+        movie.set_length(4);
+
+        let contents = movie.to_fm2();
+        assert!(contents.starts_with("version 3\n"));
+        assert!(contents.ends_with(
+            "seed 7\n\
+             |0|........|........||\n\
+             |0|R......A|........||\n\
+             |1|R......A|....T...||\n\
+             |0|R......A|....T...||\n"
+        ));
+        assert_eq!(Movie::from_fm2(&contents).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_fm2_from_fceux() {
+        // A single controller, with the buttons shown by spaces.
+        let contents = "version 3\nemuVersion 22020\nport0 1\nport1 0\nport2 0\n\
+                        |0|        ||\n|2|   U    ||\n|0|   U    ||\n";
+        let mut movie = Movie::from_fm2(contents).unwrap();
+
+        assert_eq!(movie.replay(0), &[]);
+        assert_eq!(
+            movie.replay(1),
+            &[(1, Event::Power), (1, Event::Input(0, 0x10))]
+        );
+        assert!(movie.replay(2).is_empty());
+        assert!(movie.finished());
+
+        assert!(Movie::from_fm2("port0 2\n").is_err());
+        assert!(Movie::from_fm2("fourscore 1\n").is_err());
+        assert!(Movie::from_fm2("|0|RLD|........||\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("12 jump".parse::<Movie>().is_err());
+        assert!("x reset".parse::<Movie>().is_err());
+        assert!("1 input ZZ".parse::<Movie>().is_err());
+        assert!("1 reset now".parse::<Movie>().is_err());
+        assert!("5 reset\n4 power".parse::<Movie>().is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut movie: Movie = "1 input 01\n3 reset\n3 input 00\n7 power".parse().unwrap();
+
+        assert!(movie.replay(0).is_empty());
+        assert_eq!(movie.replay(1), &[(1, Event::Input(0, 0x01))]);
+        assert!(movie.replay(2).is_empty());
+        assert_eq!(
+            movie.replay(3),
+            &[(3, Event::Reset), (3, Event::Input(0, 0x00))]
+        );
+        assert!(!movie.finished());
+        assert_eq!(movie.replay(10), &[(7, Event::Power)]);
+        assert!(movie.finished());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    apu::Panning,
+    bus::SystemBus,
+    cartridge::Cartridge,
+    config::EmuConfig,
+    cpu::Cpu,
+    ppu::{FrameStatus, MemoryInit, Rgb},
+    region::Region,
+};
+
+/// Sample rate of the audio produced when none is given.
+const DEFAULT_AUDIO_RATE: f32 = 44100.0;
+
+/// Configures a console and builds it around a cartridge, so new settings
+/// are added as a method here rather than as another parameter of the
+/// constructors of every component.
+///
+/// ```ignore
+/// let cpu = NesBuilder::new()
+///     .region(Region::Pal)
+///     .audio_rate(48000.0)
+///     .memory_init(MemoryInit::Pattern)
+///     .build(cart, |pixels| draw(pixels));
+/// ```
+#[derive(Clone)]
+pub struct NesBuilder {
+    region: Option<Region>,
+    config: EmuConfig,
+    palette: Option<[Rgb; 0x40]>,
+    audio_rate: f32,
+    memory_init: MemoryInit,
+    seed: Option<u64>,
+    stereo: Option<Panning>,
+    stems: bool,
+}
+
+impl Default for NesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NesBuilder {
+    /// Returns a builder for a console in the region of the cartridge, with
+    /// the fast profile, zeroed memory and mono 44.1 kHz audio.
+    pub fn new() -> Self {
+        NesBuilder {
+            region: None,
+            config: EmuConfig::default(),
+            palette: None,
+            audio_rate: DEFAULT_AUDIO_RATE,
+            memory_init: MemoryInit::Zero,
+            seed: None,
+            stereo: None,
+            stems: false,
+        }
+    }
+
+    /// Sets the TV system, overriding the one of the cartridge.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Sets which emulation behaviours are switched on, see [`EmuConfig`].
+    pub fn config(mut self, config: EmuConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the colours of the system palette, see
+    /// [`crate::ppu::parse_pal`].
+    pub fn palette(mut self, palette: [Rgb; 0x40]) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Sets the sample rate of the audio produced.
+    pub fn audio_rate(mut self, rate: f32) -> Self {
+        self.audio_rate = rate;
+        self
+    }
+
+    /// Sets the power-on contents of VRAM, palette RAM and OAM.
+    pub fn memory_init(mut self, init: MemoryInit) -> Self {
+        self.memory_init = init;
+        self
+    }
+
+    /// Sets the seed of the unpredictable power-on state of
+    /// [`EmuConfig::power_up`], random if not given.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Enables stereo output with the given pan positions, or mono output if
+    /// none are given.
+    pub fn stereo(mut self, panning: Option<Panning>) -> Self {
+        self.stereo = panning;
+        self
+    }
+
+    /// Sets whether the output of each APU channel is kept for recording, see
+    /// [`SystemBus::drain_stem_samples`].
+    pub fn stems(mut self, stems: bool) -> Self {
+        self.stems = stems;
+        self
+    }
+
+    /// Returns a console with the cartridge inserted, powered on and reset,
+    /// passing each rendered frame to the callback.
+    pub fn build<'a, F, R>(&self, cart: Cartridge, render: F) -> Cpu<'a>
+    where
+        F: FnMut(&[u8]) -> R + 'a,
+        R: Into<FrameStatus>,
+    {
+        let region = self.region.unwrap_or(cart.region());
+
+        let cart = Rc::new(RefCell::new(cart));
+        let mut bus = SystemBus::with_memory_init(cart, self.audio_rate, self.memory_init, render);
+        bus.set_config(self.config);
+        bus.set_region(region);
+        bus.set_stereo(self.stereo);
+        bus.set_stems(self.stems);
+        if let Some(palette) = &self.palette {
+            bus.set_palette(palette);
+        }
+
+        let mut cpu = Cpu::new(bus);
+        if self.config.power_up {
+            cpu.bus.power_up(self.seed);
+        }
+        cpu.set_idle_skip(self.config.idle_skip);
+        cpu.reset();
+
+        cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_cartridge;
+
+    #[test]
+    fn test_build() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut cpu = NesBuilder::new()
+            .region(Region::Pal)
+            .stereo(Some(Panning::default()))
+            .stems(true)
+            .build(cart, |_| {});
+
+        assert_eq!(cpu.bus.region(), Region::Pal);
+
+        // A stem sample is taken with each pair of stereo samples.
+        for _ in 0..100 {
+            cpu.bus.tick(100);
+        }
+        let mut stems = Vec::new();
+        cpu.bus.drain_stem_samples(&mut stems);
+        assert!(!stems.is_empty());
+        assert_eq!(cpu.bus.audio_samples().len(), 2 * stems.len());
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::instructions::OPCODES;
+
+/// Number of opcodes listed in the report.
+const HOTTEST: usize = 20;
+
+thread_local! {
+    static COUNTS: RefCell<[u64; 256]> = const { RefCell::new([0; 256]) };
+}
+
+/// Tallies an executed opcode.
+pub fn record(code: u8) {
+    COUNTS.with(|c| c.borrow_mut()[code as usize] += 1);
+}
+
+/// Clears the tallies, e.g. when a new game is loaded.
+pub fn reset() {
+    COUNTS.with(|c| *c.borrow_mut() = [0; 256]);
+}
+
+/// Returns a report of the hottest opcodes and the share of instructions
+/// executed by each addressing mode.
+pub fn report() -> String {
+    COUNTS.with(|c| format_report(&c.borrow()))
+}
+
+fn format_report(counts: &[u64; 256]) -> String {
+    let total: u64 = counts.iter().sum();
+    let mut out = format!("{} instructions executed\n", total);
+    if total == 0 {
+        return out;
+    }
+
+    let mut hottest: Vec<(u8, u64)> = (0..=255u8)
+        .map(|code| (code, counts[code as usize]))
+        .filter(|(_, n)| *n > 0)
+        .collect();
+    hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let _ = writeln!(out, "\nHottest opcodes:");
+    for (code, n) in hottest.iter().take(HOTTEST) {
+        let mnemonic = OPCODES.get(code).map_or("???", |op| op.mnemonic);
+        let _ = writeln!(
+            out,
+            "  {:02X} {:<4} {:>12} {:>3}%",
+            code,
+            mnemonic,
+            n,
+            n * 100 / total
+        );
+    }
+
+    let mut modes: HashMap<String, u64> = HashMap::new();
+    for (code, n) in hottest.iter() {
+        let mode = OPCODES
+            .get(code)
+            .map_or(String::from("Unknown"), |op| format!("{:?}", op.mode));
+        *modes.entry(mode).or_default() += n;
+    }
+    let mut modes: Vec<_> = modes.into_iter().collect();
+    modes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let _ = writeln!(out, "\nAddressing modes:");
+    for (mode, n) in modes {
+        let _ = writeln!(out, "  {:<16} {:>12} {:>3}%", mode, n, n * 100 / total);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut counts = [0; 256];
+        counts[0xA9] = 30; // LDA #
+        counts[0xAD] = 10; // LDA abs
+        counts[0xEA] = 60; // NOP
+
+        let report = format_report(&counts);
+        assert!(report.starts_with("100 instructions executed\n"));
+
+        let hottest: Vec<&str> = report.lines().skip(3).take(3).collect();
+        assert!(hottest[0].contains("EA NOP"));
+        assert!(hottest[1].contains("A9 LDA"));
+        assert!(hottest[2].contains("AD LDA"));
+        assert!(report.contains("Immediate"));
+    }
+}
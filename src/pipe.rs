@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::io::{ErrorKind, Read, Write};
+
+use crate::cpu::Cpu;
+
+/// Largest message accepted from the controlling process. Input messages are
+/// a single byte, anything much larger means the stream is out of sync.
+const MAX_MESSAGE_LEN: usize = 64;
+
+/// Runs the emulator in lockstep with an external process.
+///
+/// Every message is a little endian u32 length followed by that many bytes.
+/// For each frame the controlling process sends the joypad buttons as a one
+/// byte message, the frame is emulated, and a message containing the buttons
+/// followed by the RGB pixels of the frame is sent back.
+///
+/// Reads and writes block, so the emulator never runs more than one frame
+/// ahead of a slow reader. The session ends cleanly when either side closes
+/// its end of the pipe, and the number of frames run is returned.
+pub fn run<R: Read, W: Write>(
+    cpu: &mut Cpu,
+    frame: &RefCell<Vec<u8>>,
+    mut input: R,
+    mut output: W,
+) -> Result<u64, String> {
+    let mut frames = 0;
+    let mut message = Vec::new();
+
+    loop {
+        let buttons = match read_message(&mut input)? {
+            Some(m) if m.len() == 1 => m[0],
+            Some(m) => return Err(format!("expected 1 byte input message, got {}", m.len())),
+            None => return Ok(frames),
+        };
+        cpu.bus.set_controller_state(0, buttons);
+
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            if cpu.clock() {
+                return Err(format!("CPU halted at ${:04X}", cpu.pc));
+            }
+        }
+        frames += 1;
+
+        message.clear();
+        message.push(buttons);
+        message.extend_from_slice(&frame.borrow());
+        match write_message(&mut output, &message) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(frames),
+            result => result.map_err(|e| format!("could not write frame: {}", e))?,
+        }
+    }
+}
+
+/// Reads a length prefixed message, returning None if the stream has closed.
+fn read_message<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>, String> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result.map_err(|e| format!("could not read message: {}", e))?,
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(format!("message of {} bytes is too long", len));
+    }
+
+    let mut message = vec![0; len];
+    input
+        .read_exact(&mut message)
+        .map_err(|e| format!("could not read message: {}", e))?;
+    Ok(Some(message))
+}
+
+/// Writes a length prefixed message and flushes it to the reader.
+fn write_message<W: Write>(output: &mut W, message: &[u8]) -> std::io::Result<()> {
+    output.write_all(&(message.len() as u32).to_le_bytes())?;
+    output.write_all(message)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::SystemBus;
+    use crate::cartridge::Cartridge;
+    use crate::selfcheck;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_run_lockstep() {
+        let cart = Cartridge::new(&selfcheck::rom()).unwrap();
+        let frame = RefCell::new(Vec::new());
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |pixels| {
+            *frame.borrow_mut() = pixels.to_vec();
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        let mut input = Vec::new();
+        for buttons in [0x00, 0x81, 0x10] {
+            write_message(&mut input, &[buttons]).unwrap();
+        }
+        let mut output = Vec::new();
+
+        assert_eq!(run(&mut cpu, &frame, &input[..], &mut output), Ok(3));
+
+        let mut output = &output[..];
+        for buttons in [0x00, 0x81, 0x10] {
+            let message = output_message(&mut output);
+            assert_eq!(message[0], buttons);
+            assert_eq!(message.len(), 1 + 256 * 240 * 3);
+        }
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_read_message_rejects_long_messages() {
+        let mut input = Vec::new();
+        write_message(&mut input, &[0; MAX_MESSAGE_LEN + 1]).unwrap();
+
+        assert!(read_message(&mut &input[..]).is_err());
+        assert_eq!(read_message(&mut &[][..]), Ok(None));
+    }
+
+    /// Reads a frame message without the input message size limit.
+    fn output_message(output: &mut &[u8]) -> Vec<u8> {
+        let mut len = [0; 4];
+        output.read_exact(&mut len).unwrap();
+        let mut message = vec![0; u32::from_le_bytes(len) as usize];
+        output.read_exact(&mut message).unwrap();
+        message
+    }
+}
@@ -1,28 +1,85 @@
 mod control;
 mod frame;
+#[cfg(test)]
+mod fuzz;
+mod init;
+mod inspector;
 mod mask;
+mod overlay;
 mod palette;
 mod scroll;
+mod source;
 mod sprite;
 mod status;
 mod tile;
 
+use std::io::{self, Read, Write};
+
 use crate::bus::Memory;
+use crate::config::EmuConfig;
+use crate::region::Region;
+use crate::state::{
+    read_bool, read_u128, read_u16, read_u32, read_u64, read_u8, write_bool, write_u128, write_u16,
+    write_u32, write_u64, write_u8, SaveState,
+};
 use control::Control;
 use mask::Mask;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use scroll::Scroll;
 use status::Status;
 
-use self::frame::Frame;
-use self::palette::Rgb;
-use self::palette::COLOUR_PALETTE;
+pub use self::frame::Frame;
+pub use self::init::MemoryInit;
+pub use self::inspector::OamEntry;
+use self::overlay::Overlay;
+pub use self::overlay::RasterMarkers;
+use self::palette::PaletteTable;
+pub use self::palette::{parse_pal, Rgb};
+use self::source::Source;
 use self::sprite::Sprite;
+pub use self::tile::decode_row;
 use self::tile::Tile;
 
+/// Evaluates the expression, timing it as the given stage when the `ppuprof`
+/// feature is enabled.
+macro_rules! profile {
+    ($stage:ident, $e:expr) => {{
+        #[cfg(feature = "ppuprof")]
+        let _timer = crate::ppuprof::Timer::start(crate::ppuprof::Stage::$stage);
+        $e
+    }};
+}
+
 const OAM_SIZE: usize = 0x100;
 const OAM2_SIZE: usize = 0x8;
 
-type RenderFn<'rcall> = Box<dyn FnMut(&[u8]) + 'rcall>;
+/// Number of PPU dots after power-on during which writes to PPUCTRL,
+/// PPUMASK, PPUSCROLL and PPUADDR are ignored (29658 CPU cycles).
+///
+/// See: https://www.nesdev.org/wiki/PPU_power_up_state
+const WARM_UP_DOTS: u32 = 29658 * 3;
+
+/// Number of dots in a scanline.
+pub const DOTS_PER_SCANLINE: u64 = 341;
+
+type RenderFn<'rcall> = Box<dyn FnMut(&[u8]) -> FrameStatus + 'rcall>;
+
+/// Returned by the render callback to say whether it took the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameStatus {
+    /// The frame was taken, to be shown.
+    Accepted,
+
+    /// The consumer was busy and dropped the frame rather than wait.
+    Dropped,
+}
+
+/// Callbacks which return nothing always accept the frame.
+impl From<()> for FrameStatus {
+    fn from(_: ()) -> Self {
+        FrameStatus::Accepted
+    }
+}
 
 /// Represents the NES PPU.
 pub struct NesPpu<'rcall> {
@@ -38,8 +95,10 @@ pub struct NesPpu<'rcall> {
     clearing_oam: bool,
     sprite_0_rendering: bool,
     sprite_count: usize,
-    fg_lo_shift: [u8; OAM2_SIZE],
-    fg_hi_shift: [u8; OAM2_SIZE],
+
+    /// Decoded pattern row of each sprite in secondary OAM, a byte per
+    /// pixel from left to right.
+    fg_rows: [u64; OAM2_SIZE],
 
     /// Registers.
     ctrl: Control,
@@ -47,6 +106,10 @@ pub struct NesPpu<'rcall> {
     scroll: Scroll,
     status: Status,
 
+    /// Mask register write waiting for the dot it lands on, and the number
+    /// of dots until then.
+    pending_mask: Option<(u8, u32)>,
+
     /// Is the NMI interrupt set?
     pub nmi_interrupt: Option<bool>,
 
@@ -62,16 +125,47 @@ pub struct NesPpu<'rcall> {
     /// Current cycle.
     cycle: usize,
 
+    /// Dots clocked since power-on, used to timestamp rendering fetches.
+    dot: u64,
+
+    /// Dot on which the current frame started, when vblank last began.
+    frame_start: u64,
+
     next_tile: Tile,
-    bg_lo_shift: u16,
-    bg_hi_shift: u16,
-    bg_attr_lo_shift: u16,
-    bg_attr_hi_shift: u16,
+
+    /// Background pixels for the current and next tile, a byte per pixel
+    /// holding the pixel value in bits 0-1 and the palette in bits 2-3.
+    bg_shift: u128,
 
     /// Number of frames rendered by the PPU.
     frame_count: u128,
+
+    /// Number of rendered frames the render callback dropped.
+    dropped_frames: u64,
     odd_frame: bool,
 
+    /// Remaining dots of the power-on warm-up period.
+    warm_up: u32,
+
+    /// TV system the PPU is timed for.
+    region: Region,
+
+    /// Which of the PPU's behaviours are emulated.
+    config: EmuConfig,
+
+    /// Colours of every palette index under each greyscale and emphasis
+    /// setting.
+    palette: PaletteTable,
+
+    /// Colour pixels by their source rather than the palette.
+    priority_debug: bool,
+
+    /// Marks sprite 0 hits and scroll splits on the frame.
+    overlay: Overlay,
+
+    /// Outlines the sprites in OAM on the frame.
+    sprite_boxes: bool,
+
     /// Current frame.
     frame: Frame,
 
@@ -96,11 +190,16 @@ pub trait Ppu {
 
 impl<'a> NesPpu<'a> {
     /// Returns an instantiated PPU.
-    pub fn new<'rcall, F>(bus: Box<dyn Memory>, render_callback: F) -> NesPpu<'rcall>
+    pub fn new<'rcall, F, R>(
+        bus: Box<dyn Memory>,
+        init: MemoryInit,
+        mut render_callback: F,
+    ) -> NesPpu<'rcall>
     where
-        F: FnMut(&[u8]) + 'rcall,
+        F: FnMut(&[u8]) -> R + 'rcall,
+        R: Into<FrameStatus>,
     {
-        NesPpu {
+        let mut ppu = NesPpu {
             bus,
             open_bus: 0,
             open_bus_timer: 0,
@@ -110,8 +209,7 @@ impl<'a> NesPpu<'a> {
             clearing_oam: false,
             sprite_0_rendering: false,
             sprite_count: 0,
-            fg_lo_shift: [0; OAM2_SIZE],
-            fg_hi_shift: [0; OAM2_SIZE],
+            fg_rows: [0; OAM2_SIZE],
             buf: 0,
             addr_toggle: false,
             v_addr: Scroll::new(),
@@ -120,19 +218,30 @@ impl<'a> NesPpu<'a> {
             mask: Mask::new(),
             scroll: Scroll::new(),
             status: Status::new(),
+            pending_mask: None,
             scanline: 0,
             cycle: 0,
+            dot: 0,
+            frame_start: 0,
             next_tile: Tile::default(),
-            bg_lo_shift: 0,
-            bg_hi_shift: 0,
-            bg_attr_lo_shift: 0,
-            bg_attr_hi_shift: 0,
+            bg_shift: 0,
             nmi_interrupt: None,
             frame_count: 0,
+            dropped_frames: 0,
             odd_frame: false,
+            warm_up: 0,
+            region: Region::Ntsc,
+            config: EmuConfig::default(),
+            palette: PaletteTable::default(),
+            priority_debug: false,
+            overlay: Overlay::new(),
+            sprite_boxes: false,
             frame: Frame::new(),
-            render_callback: Box::from(render_callback),
-        }
+            render_callback: Box::new(move |pixels| render_callback(pixels).into()),
+        };
+
+        init.fill(&mut ppu.oam_data);
+        ppu
     }
 
     /// Increment the VRAM address based on the control register status.
@@ -144,6 +253,142 @@ impl<'a> NesPpu<'a> {
         self.v_addr.set_raw(new_addr);
     }
 
+    /// Returns the current scanline and cycle.
+    pub fn position(&self) -> (i32, usize) {
+        (self.scanline, self.cycle)
+    }
+
+    /// Returns the number of dots clocked since the current frame started at
+    /// the beginning of vblank.
+    pub fn frame_dots(&self) -> u64 {
+        self.dot.wrapping_sub(self.frame_start)
+    }
+
+    /// Returns the number of dots to clock until vblank begins, ignoring the
+    /// dot skipped on odd frames. The dot at the current position is yet to
+    /// be clocked, so this is 1 when it is the first dot of vblank.
+    pub fn dots_until_vblank(&self) -> u64 {
+        let frame = (self.region.last_scanline() as u64 + 2) * DOTS_PER_SCANLINE;
+        let vblank = (self.region.vblank_scanline() as u64 + 1) * DOTS_PER_SCANLINE + 1;
+        let now = (self.scanline + 1) as u64 * DOTS_PER_SCANLINE + self.cycle as u64;
+        (vblank + frame - now) % frame + 1
+    }
+
+    /// Returns true if the PPU raises an NMI at the start of vblank.
+    pub fn nmi_enabled(&self) -> bool {
+        self.ctrl.nmi_enabled()
+    }
+
+    /// Sets the TV system the PPU is timed for.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Sets which of the PPU's behaviours are emulated.
+    pub fn set_config(&mut self, config: EmuConfig) {
+        self.config = config;
+    }
+
+    /// Returns true if sprites are 8x16, unless forced to 8x8.
+    fn sprites_8x16(&self) -> bool {
+        self.ctrl.sprite_size() && self.config.sprites_8x16
+    }
+
+    /// Puts the PPU into its documented power-on state.
+    ///
+    /// Register writes are ignored until the warm-up period has elapsed and
+    /// the VBLANK and sprite overflow flags start in an unpredictable state,
+    /// which is derived from the given seed if one is provided.
+    pub fn power_up(&mut self, seed: Option<u64>) {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        self.ctrl.update(0);
+        self.mask.update(0);
+        self.scroll = Scroll::new();
+        self.v_addr = Scroll::new();
+        self.addr_toggle = false;
+        self.buf = 0;
+        self.odd_frame = false;
+
+        self.status.set_vblank_status(rng.gen());
+        self.status.set_sprite_overflow(rng.gen());
+
+        self.warm_up = WARM_UP_DOTS;
+    }
+
+    /// Returns the byte at the given PPU address without touching the read
+    /// buffer, open bus or VRAM address.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek_data(addr & 0x3FFF)
+    }
+
+    /// Writes the byte to the given PPU address without touching the open bus
+    /// or VRAM address.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.bus.write_data(addr & 0x3FFF, value)
+    }
+
+    /// Returns the number of rendered frames the render callback dropped.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Returns the object attribute memory.
+    pub fn oam(&self) -> &[u8; OAM_SIZE] {
+        &self.oam_data
+    }
+
+    /// Returns the 32 bytes of palette RAM.
+    pub fn palette_ram(&self) -> [u8; 32] {
+        let mut palette = [0; 32];
+        for (i, b) in palette.iter_mut().enumerate() {
+            *b = self.peek(0x3F00 + i as u16);
+        }
+        palette
+    }
+
+    /// Replaces the 64 colour palette pixels are drawn with.
+    pub fn set_palette(&mut self, palette: &[Rgb; 0x40]) {
+        self.palette = PaletteTable::new(palette);
+    }
+
+    /// Toggles the priority debug render mode, which colours each pixel by
+    /// its source instead of the palette, see [`Source::colour`].
+    pub fn toggle_priority_debug(&mut self) {
+        self.priority_debug = !self.priority_debug;
+    }
+
+    /// Sets the raster markers drawn over each frame, or None to draw none.
+    pub fn set_raster_overlay(&mut self, markers: Option<RasterMarkers>) {
+        self.overlay.set_markers(markers);
+    }
+
+    /// Toggles the raster overlay, see [`RasterMarkers`].
+    pub fn toggle_raster_overlay(&mut self) {
+        self.overlay.toggle();
+    }
+
+    /// Toggles outlining the bounding box of every sprite in OAM.
+    pub fn toggle_sprite_boxes(&mut self) {
+        self.sprite_boxes = !self.sprite_boxes;
+    }
+
+    /// Records a mid-frame write to the scroll or VRAM address for the
+    /// raster overlay.
+    fn record_split(&mut self) {
+        if self.rendering_enabled() {
+            self.overlay.record_split(self.scanline);
+        }
+    }
+
+    /// Returns true if the PPU is still warming up after power-on.
+    fn warming_up(&self) -> bool {
+        self.warm_up > 0
+    }
+
     /// Poll the NMI flag set by the Ppu
     pub fn poll_nmi(&mut self) -> bool {
         self.nmi_interrupt.take().is_some()
@@ -154,9 +399,29 @@ impl<'a> NesPpu<'a> {
         // Update the open bus timer
         self.update_open_bus();
 
+        self.warm_up = self.warm_up.saturating_sub(1);
+        self.dot = self.dot.wrapping_add(1);
+
+        // Apply a delayed mask write once its dot arrives, so rendering of
+        // this dot already sees it.
+        match self.pending_mask {
+            Some((value, 0)) => {
+                self.mask.update(value);
+                self.pending_mask = None;
+            }
+            Some((value, dots)) => self.pending_mask = Some((value, dots - 1)),
+            None => {}
+        }
+
         // Every odd frame on the first scanline, the first cycle is skipped if
         // background rendering is enabled. A flag is updated every frame.
-        if self.odd_frame && self.scanline == 0 && self.cycle == 0 && self.rendering_enabled() {
+        if self.odd_frame
+            && self.scanline == 0
+            && self.cycle == 0
+            && self.rendering_enabled()
+            && self.region.skips_odd_frame_dot()
+            && self.config.odd_frame_skip
+        {
             self.cycle = 1;
         }
 
@@ -168,69 +433,113 @@ impl<'a> NesPpu<'a> {
             self.status.set_sprite_overflow(false);
             self.status.set_vblank_status(false);
 
-            // Clear sprite shifters
-            self.fg_lo_shift.fill(0);
-            self.fg_hi_shift.fill(0);
+            // Clear sprite pattern rows
+            self.fg_rows.fill(0);
         }
 
         if self.scanline < 240 && self.rendering_enabled() {
             self.render_scanline()
         }
 
-        // Set NMI if enabled on cycle 241
-        if self.scanline == 241 && self.cycle == 1 {
+        // Set NMI if enabled on the first dot of vblank.
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
             self.status.set_vblank_status(true);
             if self.ctrl.nmi_enabled() {
                 self.nmi_interrupt = Some(true)
             }
 
             self.frame_count = self.frame_count.wrapping_add(1);
+            self.frame_start = self.dot;
+            self.overlay.draw(&mut self.frame);
+            if self.sprite_boxes {
+                let height = if self.sprites_8x16() { 16 } else { 8 };
+                inspector::draw_boxes(&self.oam_data, height, &mut self.frame);
+            }
 
-            (self.render_callback)(self.frame.pixels());
+            let status = profile!(Callback, (self.render_callback)(self.frame.pixels()));
+            if status == FrameStatus::Dropped {
+                self.dropped_frames += 1;
+            }
+
+            #[cfg(feature = "ppuprof")]
+            crate::ppuprof::end_frame();
         }
 
         // Calculate the pixel color
         if (0..240).contains(&self.scanline) && (1..257).contains(&self.cycle) {
-            let (bg_pixel, bg_palette) = self.get_bg_pixel_info();
+            profile!(Pixel, self.render_pixel());
+        }
 
-            // Hack to fix random sprite colors on left of first scanline.
-            let (fg_pixel, fg_palette, fg_priority) = match self.scanline != 0 {
-                true => self.get_fg_pixel_info(),
-                false => (0, 0, 0),
-            };
+        self.advance(1);
+    }
 
-            // Pixel priority logic.
-            let (pixel, palette) = match bg_pixel {
-                // Both foreground and background are 0, result is 0
-                0 if fg_pixel == 0 => (0, 0),
-                // Only background is 0, output foreground
-                0 if fg_pixel > 0 => (fg_pixel, fg_palette),
-                // Only foreground is 0, output background
-                1..=3 if fg_pixel == 0 => (bg_pixel, bg_palette),
-                // Both are non zero
-                _ => {
-                    // Collision is possible
-                    self.update_sprite_zero_hit();
-
-                    // The result is choosen based on the sprite priority
-                    // attribute.
-                    if fg_priority != 0 {
-                        (fg_pixel, fg_palette)
-                    } else {
-                        (bg_pixel, bg_palette)
-                    }
+    /// Clocks the PPU for the given number of dots. While rendering is
+    /// disabled, as it is through loading screens, the PPU only draws the
+    /// backdrop, so the dots between those which set and clear vblank are run
+    /// a scanline at a time rather than one by one.
+    pub fn clock_dots(&mut self, mut dots: u32) {
+        while dots > 0 {
+            match self.idle_dots().min(dots) {
+                0 => {
+                    self.clock();
+                    dots -= 1;
                 }
-            };
+                run => {
+                    self.clock_idle(run);
+                    dots -= run;
+                }
+            }
+        }
+    }
 
-            // Get the color from palette RAM
-            let colour = self.get_colour(palette, pixel);
+    /// Returns the number of dots from the current one which can be run in
+    /// bulk: those to the end of the scanline, stopping at the dots which set
+    /// and clear vblank. None can be while rendering or with a mask write
+    /// pending.
+    fn idle_dots(&self) -> u32 {
+        if self.rendering_enabled() || self.pending_mask.is_some() {
+            return 0;
+        }
 
-            self.frame
-                .set_pixel(self.cycle - 1, self.scanline as usize, colour);
+        let vblank_event = self.scanline == -1 || self.scanline == self.region.vblank_scanline();
+        match (vblank_event, self.cycle) {
+            (true, 0) => 1,
+            (true, 1) => 0,
+            _ => DOTS_PER_SCANLINE as u32 - self.cycle as u32,
         }
+    }
+
+    /// Runs the given number of dots of the current scanline with rendering
+    /// disabled, see [`NesPpu::idle_dots`].
+    fn clock_idle(&mut self, dots: u32) {
+        // The open bus decays on the first dot the timer has run out.
+        if dots > self.open_bus_timer {
+            self.open_bus = 0;
+        }
+        self.open_bus_timer = self.open_bus_timer.saturating_sub(dots);
+        self.warm_up = self.warm_up.saturating_sub(dots);
+        self.dot = self.dot.wrapping_add(dots as u64);
+
+        // Visible dots show the backdrop.
+        let dots = dots as usize;
+        let (start, end) = (self.cycle.max(1), (self.cycle + dots).min(257));
+        if (0..240).contains(&self.scanline) && start < end {
+            let colour = match self.priority_debug {
+                true => Source::Backdrop.colour(),
+                false => self.get_colour(0, 0),
+            };
+            for x in start..end {
+                self.frame.set_pixel(x - 1, self.scanline as usize, colour);
+            }
+        }
+
+        self.advance(dots);
+    }
 
-        // Update cycle count
-        self.cycle += 1;
+    /// Moves the given number of dots along the current scanline, wrapping to
+    /// the next.
+    fn advance(&mut self, dots: usize) {
+        self.cycle += dots;
 
         // Last cycle
         if self.cycle > 340 {
@@ -238,7 +547,7 @@ impl<'a> NesPpu<'a> {
             self.scanline += 1;
 
             // Last scanline
-            if self.scanline > 260 {
+            if self.scanline > self.region.last_scanline() {
                 self.scanline = -1;
                 self.odd_frame = !self.odd_frame;
             }
@@ -260,6 +569,23 @@ impl<'a> NesPpu<'a> {
         data
     }
 
+    /// Writes to the mask register, taking effect once the given number of
+    /// dots have been rendered with the old value.
+    ///
+    /// Games toggle rendering, greyscale and emphasis mid-scanline for raster
+    /// effects, so the change has to land on the right dot.
+    pub fn write_mask_after(&mut self, value: u8, dots: u32) {
+        if let Some((pending, _)) = self.pending_mask.take() {
+            self.mask.update(pending);
+        }
+
+        match dots {
+            0 => self.write_mask(value),
+            _ if self.warming_up() => {}
+            _ => self.pending_mask = Some((value, dots)),
+        }
+    }
+
     /// Returns if the rendering is enabled or not
     fn rendering_enabled(&self) -> bool {
         self.mask.show_sprites() | self.mask.show_background()
@@ -269,35 +595,31 @@ impl<'a> NesPpu<'a> {
     fn get_bg_pixel_info(&self) -> (u8, u8) {
         if self.mask.show_background() && (self.mask.leftmost_8pxl_background() || self.cycle >= 9)
         {
-            let mux = 0x8000 >> self.xfine;
-
-            let lo_pixel = ((self.bg_lo_shift & mux) != 0) as u8;
-            let hi_pixel = ((self.bg_hi_shift & mux) != 0) as u8;
-            let bg_pixel = (hi_pixel << 1) | lo_pixel;
+            let pixel = (self.bg_shift >> (120 - 8 * self.xfine as u32)) as u8;
 
-            let lo_pal = ((self.bg_attr_lo_shift & mux) != 0) as u8;
-            let hi_pal = ((self.bg_attr_hi_shift & mux) != 0) as u8;
-            let bg_palette = (hi_pal << 1) | lo_pal;
-
-            return (bg_pixel, bg_palette);
+            return (pixel & 0x3, pixel >> 2);
         }
 
         (0, 0)
     }
 
-    /// Returns pixel value, palette index and attribute byte of current
+    /// Returns pixel value, palette index, priority and OAM index of current
     /// foreground pixel.
-    fn get_fg_pixel_info(&mut self) -> (u8, u8, u8) {
+    ///
+    /// Every sprite covering the current dot is considered, in secondary OAM
+    /// order, so where sprites overlap the opaque pixel of the sprite with
+    /// the lowest OAM index wins, even if it is behind the background.
+    fn get_fg_pixel_info(&mut self) -> (u8, u8, u8, u8) {
         if self.mask.show_sprites() && (self.mask.leftmost_8pxl_sprite() || self.cycle >= 9) {
             self.sprite_0_rendering = false;
+            let x = self.cycle - 1;
             for i in 0..self.sprite_count {
-                if self.oam2_data[i].x != 0 {
+                let offset = x.wrapping_sub(self.oam2_data[i].x as usize);
+                if offset >= 8 {
                     continue;
                 }
 
-                let lo_pixel = ((self.fg_lo_shift[i] & 0x80) != 0) as u8;
-                let hi_pixel = ((self.fg_hi_shift[i] & 0x80) != 0) as u8;
-                let fg_pixel = (hi_pixel << 1) | lo_pixel;
+                let fg_pixel = (self.fg_rows[i] >> (56 - 8 * offset)) as u8;
 
                 let fg_palette = (self.oam2_data[i].attr & 0x3) + 0x4;
                 let fg_priority = ((self.oam2_data[i].attr & 0x20) == 0) as u8;
@@ -307,12 +629,12 @@ impl<'a> NesPpu<'a> {
                     if self.oam2_data[i].index == 0 {
                         self.sprite_0_rendering = true;
                     }
-                    return (fg_pixel, fg_palette, fg_priority);
+                    return (fg_pixel, fg_palette, fg_priority, self.oam2_data[i].index);
                 }
             }
         }
 
-        (0, 0, 0)
+        (0, 0, 0, 0)
     }
 
     /// Update the sprite 0 hit flag.
@@ -320,7 +642,11 @@ impl<'a> NesPpu<'a> {
         // Sprite 0 hit is a collision between a non 0 sprite pixel and bg pixel
         // To be possible, we have to be drawing a sprite 0 pixel and both
         // sprite and background rendering has to be enabled.
-        if !self.sprite_0_rendering || !self.mask.show_background() || !self.mask.show_sprites() {
+        if !self.sprite_0_rendering
+            || !self.mask.show_background()
+            || !self.mask.show_sprites()
+            || !self.config.sprite0_hit
+        {
             return;
         }
 
@@ -328,11 +654,20 @@ impl<'a> NesPpu<'a> {
         // first 8 pixels.
         if !(self.mask.leftmost_8pxl_background() | self.mask.leftmost_8pxl_sprite()) {
             if (9..256).contains(&self.cycle) {
-                self.status.set_sprite_zero_hit(true);
+                self.set_sprite_zero_hit();
             }
         } else if (1..256).contains(&self.cycle) {
-            self.status.set_sprite_zero_hit(true);
+            self.set_sprite_zero_hit();
+        }
+    }
+
+    /// Sets the sprite 0 hit flag at the current dot.
+    fn set_sprite_zero_hit(&mut self) {
+        if !self.status.sprite_zero_hit() {
+            self.overlay
+                .record_hit(self.cycle - 1, self.scanline as usize);
         }
+        self.status.set_sprite_zero_hit(true);
     }
 
     /// Returns the RBG value of the pixel with greyscale and colour emphasis
@@ -340,22 +675,70 @@ impl<'a> NesPpu<'a> {
     fn get_colour(&mut self, palette: u8, pixel: u8) -> Rgb {
         let index = self
             .bus
-            .read_data(0x3F00 + ((palette as u16) << 2) + pixel as u16)
-            & self.mask.grayscale_mask();
+            .read_data(0x3F00 + ((palette as u16) << 2) + pixel as u16);
 
-        let c = COLOUR_PALETTE[(index as usize) & 0x3F];
+        self.palette.colour(self.mask.palette_variant(), index)
+    }
 
-        match self.mask.colour_emphasis_enabled() {
-            false => c,
-            true => {
-                let (r, g, b) = self.mask.emphasise();
-                Rgb(
-                    (c.0 as f64 * r) as u8,
-                    (c.1 as f64 * g) as u8,
-                    (c.2 as f64 * b) as u8,
-                )
+    /// Chooses between the background and sprite pixel at the current dot
+    /// and draws it to the frame.
+    fn render_pixel(&mut self) {
+        let (bg_pixel, bg_palette) = self.get_bg_pixel_info();
+
+        // Hack to fix random sprite colors on left of first scanline.
+        let (fg_pixel, fg_palette, fg_priority, fg_index) = match self.scanline != 0 {
+            true => self.get_fg_pixel_info(),
+            false => (0, 0, 0, 0),
+        };
+        let sprite = Source::Sprite {
+            index: fg_index / 4,
+            behind: fg_priority == 0,
+        };
+
+        // Pixel priority logic.
+        let (pixel, palette, source) = match bg_pixel {
+            // Both foreground and background are 0, result is 0
+            0 if fg_pixel == 0 => (0, 0, Source::Backdrop),
+            // Only background is 0, output foreground
+            0 if fg_pixel > 0 => (fg_pixel, fg_palette, sprite),
+            // Only foreground is 0, output background
+            1..=3 if fg_pixel == 0 => (
+                bg_pixel,
+                bg_palette,
+                Source::Background {
+                    palette: bg_palette,
+                },
+            ),
+            // Both are non zero
+            _ => {
+                // Collision is possible
+                self.update_sprite_zero_hit();
+
+                // The result is choosen based on the sprite priority
+                // attribute.
+                if fg_priority != 0 {
+                    (fg_pixel, fg_palette, sprite)
+                } else {
+                    (
+                        bg_pixel,
+                        bg_palette,
+                        Source::Background {
+                            palette: bg_palette,
+                        },
+                    )
+                }
             }
-        }
+        };
+
+        // Get the color from palette RAM, or classify the pixel by its
+        // source when debugging priority.
+        let colour = match self.priority_debug {
+            true => source.colour(),
+            false => self.get_colour(palette, pixel),
+        };
+
+        self.frame
+            .set_pixel(self.cycle - 1, self.scanline as usize, colour);
     }
 
     /// Process the current cycle of a rendering scanline.
@@ -366,10 +749,17 @@ impl<'a> NesPpu<'a> {
         }
 
         // Background
-        self.render_scanline_background();
+        profile!(Background, self.render_scanline_background());
 
         // Sprites
-        self.render_scanline_sprites();
+        profile!(Sprites, self.render_scanline_sprites());
+    }
+
+    /// Reads a byte for rendering, reporting the address to the bus so
+    /// mappers can watch the address lines.
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.bus.observe(addr, self.dot);
+        self.bus.read_data(addr)
     }
 
     /// Renders the background for the current scanline.
@@ -385,12 +775,12 @@ impl<'a> NesPpu<'a> {
 
                     // At the address is the id of the pattern to draw
                     let vaddr = self.v_addr.tile_addr();
-                    self.next_tile.id = self.bus.read_data(vaddr);
+                    self.next_tile.id = self.fetch(vaddr);
                 }
                 2 => {
                     // Get the address of the tile attribute
                     let vaddr = self.v_addr.tile_attr_addr();
-                    self.next_tile.attr = self.bus.read_data(vaddr);
+                    self.next_tile.attr = self.fetch(vaddr);
 
                     // Attribute byte: BRBL TRTL
                     // BR: Bottom right metatile
@@ -426,20 +816,19 @@ impl<'a> NesPpu<'a> {
                     // 0 1 1 0 0 0 1 0  =  0 1 1 0 0 0 0 0  +  0 0 0 0 0 0 1 0
                     // 0 1 1 0 0 1 2 0  =  0 1 1 0 0 1 1 0  +  0 0 0 0 0 0 1 0
 
-                    let vaddr = self.ctrl.bgrnd_pattern_addr()
-                        + ((self.next_tile.id as u16) << 4)
-                        + self.v_addr.yfine() as u16;
+                    //
+                    // Both bitplanes are read from the decoded pattern cache
+                    // here, only the high bitplane's address is reported at
+                    // the next step.
+                    let vaddr = self.bg_pattern_addr();
 
-                    self.next_tile.lo = self.bus.read_data(vaddr);
+                    self.bus.observe(vaddr, self.dot);
+                    self.next_tile.pixels = self.bus.pattern_row(vaddr);
                 }
                 6 => {
                     // Same thing but + 8 for the high bitplane
-                    let vaddr = self.ctrl.bgrnd_pattern_addr()
-                        + ((self.next_tile.id as u16) << 4)
-                        + self.v_addr.yfine() as u16
-                        + 8;
-
-                    self.next_tile.hi = self.bus.read_data(vaddr);
+                    let vaddr = self.bg_pattern_addr() + 8;
+                    self.bus.observe(vaddr, self.dot);
                 }
                 // Increment horizontal scroll
                 7 => self.increment_xscroll(),
@@ -474,9 +863,6 @@ impl<'a> NesPpu<'a> {
             self.clearing_oam = false;
         }
 
-        // Update foreground shifters
-        self.shift_fg();
-
         // All the sprite evaluation is done in 1 cycle (this is NOT how it is
         // done on the real hardware).
         if self.cycle == 257 && self.scanline >= 0 {
@@ -489,12 +875,11 @@ impl<'a> NesPpu<'a> {
                 index: 0xFF,
             });
 
-            // Reset the shifters.
-            self.fg_lo_shift.fill(0);
-            self.fg_hi_shift.fill(0);
+            // Reset the pattern rows.
+            self.fg_rows.fill(0);
 
             let mut sprite_count = 0;
-            let sprite_size = if self.ctrl.sprite_size() { 16 } else { 8 };
+            let sprite_size = if self.sprites_8x16() { 16 } else { 8 };
 
             // Every sprite attributes in OAM is 4 bytes, thus step by 4
             // 0: Y pos
@@ -532,11 +917,43 @@ impl<'a> NesPpu<'a> {
             self.sprite_count = if sprite_count > 8 { 8 } else { sprite_count };
         }
 
+        // The sprite pattern fetches for the next scanline happen over dots
+        // 257-320 on the real hardware: two garbage nametable fetches and then
+        // the two pattern bytes for each of the 8 slots. The pattern data is
+        // loaded at dot 321 below, but the addresses are reported at the
+        // right dots so mappers watching A12 see the real timing.
+        //
+        // See: https://www.nesdev.org/wiki/PPU_rendering#Cycles_257-320
+        if (257..321).contains(&self.cycle) {
+            let slot = (self.cycle - 257) / 8;
+            let addr = match (self.cycle - 257) % 8 {
+                0 | 2 => Some(0x2000 | (self.v_addr.raw() & 0x0FFF)),
+                4 => Some(self.sprite_fetch_addr(slot)),
+                6 => Some(self.sprite_fetch_addr(slot) + 8),
+                _ => None,
+            };
+
+            if let Some(addr) = addr {
+                self.bus.observe(addr, self.dot);
+            }
+        }
+
         if self.cycle == 321 {
             self.load_sprites();
         }
     }
 
+    /// Returns the pattern address fetched for the given sprite slot. Empty
+    /// slots, and every slot on the pre-render scanline, fetch tile $FF.
+    fn sprite_fetch_addr(&self, slot: usize) -> u16 {
+        let used = slot < self.sprite_count && self.scanline >= 0;
+        match (used, self.sprites_8x16()) {
+            (true, _) => self.sprite_pattern_addr(slot),
+            (false, false) => self.ctrl.sprite_pattern_addr() | 0x0FF0,
+            (false, true) => 0x1FE0,
+        }
+    }
+
     /// Shifts the background shifters.
     ///
     /// Every 8 cycles, the data for the next tile is loaded into the upper 8
@@ -544,41 +961,16 @@ impl<'a> NesPpu<'a> {
     /// from one of the lower 8 bits.
     fn shift_bg(&mut self) {
         if self.mask.show_background() {
-            self.bg_lo_shift <<= 1;
-            self.bg_hi_shift <<= 1;
-            self.bg_attr_lo_shift <<= 1;
-            self.bg_attr_hi_shift <<= 1;
-        }
-    }
-
-    /// Shifts the foreground shifters.
-    fn shift_fg(&mut self) {
-        if self.mask.show_sprites() && (2..258).contains(&self.cycle) {
-            for (i, sprite) in self
-                .oam2_data
-                .iter_mut()
-                .take(self.sprite_count)
-                .enumerate()
-            {
-                if sprite.x > 0 {
-                    sprite.x -= 1;
-                } else {
-                    self.fg_lo_shift[i] <<= 1;
-                    self.fg_hi_shift[i] <<= 1;
-                }
-            }
+            self.bg_shift <<= 8;
         }
     }
 
     /// Loads the next background tile into the shifters.
     fn load_next_tile(&mut self) {
         if self.rendering_enabled() {
-            self.bg_lo_shift |= self.next_tile.lo as u16;
-            self.bg_hi_shift |= self.next_tile.hi as u16;
-
-            let attr = self.next_tile.attr;
-            self.bg_attr_lo_shift |= if attr & 0x1 != 0 { 0xFF } else { 0x00 };
-            self.bg_attr_hi_shift |= if attr & 0x2 != 0 { 0xFF } else { 0x00 };
+            // The palette is stored above the pixel value in every byte.
+            let attr = (self.next_tile.attr as u64) << 2;
+            self.bg_shift |= (self.next_tile.pixels | (attr * 0x0101_0101_0101_0101)) as u128;
         }
     }
 
@@ -618,63 +1010,63 @@ impl<'a> NesPpu<'a> {
         }
     }
 
-    /// Load sprites from secondary OAM into the shifters.
-    fn load_sprites(&mut self) {
+    /// Returns the address of the low bitplane of the next background tile's
+    /// pattern row.
+    fn bg_pattern_addr(&self) -> u16 {
+        self.ctrl.bgrnd_pattern_addr()
+            + ((self.next_tile.id as u16) << 4)
+            + self.v_addr.yfine() as u16
+    }
+
+    /// Returns the address of the pattern row for the sprite in the given
+    /// secondary OAM slot.
+    fn sprite_pattern_addr(&self, i: usize) -> u16 {
         let scanline = self.scanline as u8;
 
-        for i in 0..self.sprite_count {
-            let sprite_addr = match !self.ctrl.sprite_size() {
-                true => {
-                    let offset = self.ctrl.sprite_pattern_addr();
-                    let flipped_v = self.oam2_data[i].attr & 0x80 != 0;
-                    let tile_id = self.oam2_data[i].id;
-                    let row = match flipped_v {
-                        true => (7 - (scanline - self.oam2_data[i].y)) as u16,
-                        false => (scanline - self.oam2_data[i].y) as u16,
-                    };
-
-                    offset | (tile_id as u16) << 4 | row
-                }
-                false => {
-                    let offset = ((self.oam2_data[i].id & 0x01) as u16) << 12;
-                    let flipped_v = self.oam2_data[i].attr & 0x80 != 0;
-                    let top_half = scanline - self.oam2_data[i].y < 8;
-                    let tile_id = match (flipped_v, top_half) {
-                        (false, true) | (true, false) => self.oam2_data[i].id & 0xFE,
-                        (false, false) | (true, true) => (self.oam2_data[i].id & 0xFE) + 1,
-                    };
-                    let row = match flipped_v {
-                        true => {
-                            7_u16.wrapping_sub(scanline.wrapping_sub(self.oam2_data[i].y) as u16)
-                                & 0x7
-                        }
-
-                        false => ((scanline - self.oam2_data[i].y) & 0x7) as u16,
-                    };
-
-                    offset | (tile_id as u16) << 4 | row
-                }
-            };
+        match !self.sprites_8x16() {
+            true => {
+                let offset = self.ctrl.sprite_pattern_addr();
+                let flipped_v = self.oam2_data[i].attr & 0x80 != 0;
+                let tile_id = self.oam2_data[i].id;
+                let row = match flipped_v {
+                    true => (7 - (scanline - self.oam2_data[i].y)) as u16,
+                    false => (scanline - self.oam2_data[i].y) as u16,
+                };
+
+                offset | (tile_id as u16) << 4 | row
+            }
+            false => {
+                let offset = ((self.oam2_data[i].id & 0x01) as u16) << 12;
+                let flipped_v = self.oam2_data[i].attr & 0x80 != 0;
+                let top_half = scanline - self.oam2_data[i].y < 8;
+                let tile_id = match (flipped_v, top_half) {
+                    (false, true) | (true, false) => self.oam2_data[i].id & 0xFE,
+                    (false, false) | (true, true) => (self.oam2_data[i].id & 0xFE) + 1,
+                };
+                let row = match flipped_v {
+                    true => {
+                        7_u16.wrapping_sub(scanline.wrapping_sub(self.oam2_data[i].y) as u16) & 0x7
+                    }
 
-            let sprite_lo = self.bus.read_data(sprite_addr);
-            let sprite_hi = self.bus.read_data(sprite_addr.wrapping_add(8));
+                    false => ((scanline - self.oam2_data[i].y) & 0x7) as u16,
+                };
 
-            // Flip horizontal closure.
-            let flip_h = |mut v: u8| {
-                v = (v & 0xF0) >> 4 | (v & 0x0F) << 4;
-                v = (v & 0xCC) >> 2 | (v & 0x33) << 2;
-                v = (v & 0xAA) >> 1 | (v & 0x55) << 1;
-                v
-            };
+                offset | (tile_id as u16) << 4 | row
+            }
+        }
+    }
 
-            self.fg_lo_shift[i] = match self.oam2_data[i].attr & 0x40 != 0 {
-                true => flip_h(sprite_lo),
-                false => sprite_lo,
-            };
+    /// Load the pattern rows of the sprites in secondary OAM.
+    fn load_sprites(&mut self) {
+        for i in 0..self.sprite_count {
+            let sprite_addr = self.sprite_pattern_addr(i);
 
-            self.fg_hi_shift[i] = match self.oam2_data[i].attr & 0x40 != 0 {
-                true => flip_h(sprite_hi),
-                false => sprite_hi,
+            let row = self.bus.pattern_row(sprite_addr);
+
+            // Flipping horizontally reverses the pixels, a byte each.
+            self.fg_rows[i] = match self.oam2_data[i].attr & 0x40 != 0 {
+                true => row.swap_bytes(),
+                false => row,
             };
         }
     }
@@ -683,6 +1075,10 @@ impl<'a> NesPpu<'a> {
 impl Ppu for NesPpu<'_> {
     /// Writes value to the address register.
     fn write_addr(&mut self, value: u8) {
+        if self.warming_up() {
+            return;
+        }
+
         // Because the PPU address is a 14 bit address and the CPU uses an 8 bit
         // bus, we have to write in two steps. The PPU uses a toggle to choose
         // which part of the address to write.
@@ -697,12 +1093,17 @@ impl Ppu for NesPpu<'_> {
             // Otherwise, set the high bits of the scroll.
             false => self.scroll.set_addr_hi(value & 0x3F),
         }
+        self.record_split();
 
         self.addr_toggle = !self.addr_toggle;
     }
 
     /// Writes to the control register.
     fn write_ctrl(&mut self, value: u8) {
+        if self.warming_up() {
+            return;
+        }
+
         // Set the register to data
         self.ctrl.update(value);
 
@@ -713,11 +1114,19 @@ impl Ppu for NesPpu<'_> {
 
     /// Writes to the mask register.
     fn write_mask(&mut self, value: u8) {
+        if self.warming_up() {
+            return;
+        }
+
         self.mask.update(value);
     }
 
     /// Writes to the scroll register.
     fn write_scroll(&mut self, value: u8) {
+        if self.warming_up() {
+            return;
+        }
+
         // Writing to the scroll register uses the same latch as the address
         // register.
         match self.addr_toggle {
@@ -734,6 +1143,7 @@ impl Ppu for NesPpu<'_> {
         }
         // Update the toggle
         self.addr_toggle = !self.addr_toggle;
+        self.record_split();
     }
 
     fn write_oam_addr(&mut self, value: u8) {
@@ -817,23 +1227,137 @@ impl Ppu for NesPpu<'_> {
     }
 }
 
+impl SaveState for NesPpu<'_> {
+    /// Saves the registers, OAM, rendering pipeline and the PPU bus. The
+    /// frame being drawn is not saved, so the first frame after loading may
+    /// be partly stale.
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_u8(w, self.open_bus)?;
+        write_u32(w, self.open_bus_timer)?;
+
+        write_u8(w, self.oam_addr)?;
+        w.write_all(&self.oam_data)?;
+        for sprite in &self.oam2_data {
+            w.write_all(&[sprite.y, sprite.id, sprite.attr, sprite.x, sprite.index])?;
+        }
+        write_bool(w, self.clearing_oam)?;
+        write_bool(w, self.sprite_0_rendering)?;
+        write_u8(w, self.sprite_count as u8)?;
+        for row in &self.fg_rows {
+            write_u64(w, *row)?;
+        }
+
+        write_u8(w, self.ctrl.bits())?;
+        write_u8(w, self.mask.bits())?;
+        // A pending mask write's count is saved plus one, so 0 means None.
+        write_u8(w, self.pending_mask.map_or(0, |(value, _)| value))?;
+        write_u32(w, self.pending_mask.map_or(0, |(_, dots)| dots + 1))?;
+        write_u16(w, self.scroll.raw())?;
+        write_u8(w, self.status.snapshot())?;
+        // None, Some(false) and Some(true) are saved as 0, 1 and 2.
+        write_u8(w, self.nmi_interrupt.map_or(0, |n| n as u8 + 1))?;
+
+        write_u8(w, self.buf)?;
+        write_bool(w, self.addr_toggle)?;
+        write_u16(w, self.v_addr.raw())?;
+        write_u8(w, self.xfine)?;
+
+        write_u32(w, self.scanline as u32)?;
+        write_u16(w, self.cycle as u16)?;
+        write_u64(w, self.dot)?;
+        write_u64(w, self.frame_start)?;
+
+        write_u64(w, self.next_tile.pixels)?;
+        write_u8(w, self.next_tile.attr)?;
+        write_u8(w, self.next_tile.id)?;
+        write_u128(w, self.bg_shift)?;
+
+        write_u128(w, self.frame_count)?;
+        write_bool(w, self.odd_frame)?;
+        write_u32(w, self.warm_up)?;
+
+        self.bus.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        self.open_bus = read_u8(r)?;
+        self.open_bus_timer = read_u32(r)?;
+
+        self.oam_addr = read_u8(r)?;
+        r.read_exact(&mut self.oam_data)?;
+        for sprite in &mut self.oam2_data {
+            let mut b = [0; 5];
+            r.read_exact(&mut b)?;
+            *sprite = Sprite {
+                y: b[0],
+                id: b[1],
+                attr: b[2],
+                x: b[3],
+                index: b[4],
+            };
+        }
+        self.clearing_oam = read_bool(r)?;
+        self.sprite_0_rendering = read_bool(r)?;
+        self.sprite_count = (read_u8(r)? as usize).min(OAM2_SIZE);
+        for row in &mut self.fg_rows {
+            *row = read_u64(r)?;
+        }
+
+        self.ctrl.update(read_u8(r)?);
+        self.mask.update(read_u8(r)?);
+        let pending = read_u8(r)?;
+        self.pending_mask = read_u32(r)?.checked_sub(1).map(|dots| (pending, dots));
+        self.scroll.set_raw(read_u16(r)?);
+        self.status.restore(read_u8(r)?);
+        self.nmi_interrupt = match read_u8(r)? {
+            0 => None,
+            n => Some(n == 2),
+        };
+
+        self.buf = read_u8(r)?;
+        self.addr_toggle = read_bool(r)?;
+        self.v_addr.set_raw(read_u16(r)?);
+        self.xfine = read_u8(r)?;
+
+        self.scanline = read_u32(r)? as i32;
+        self.cycle = read_u16(r)? as usize;
+        self.dot = read_u64(r)?;
+        self.frame_start = read_u64(r)?;
+
+        self.next_tile.pixels = read_u64(r)?;
+        self.next_tile.attr = read_u8(r)?;
+        self.next_tile.id = read_u8(r)?;
+        self.bg_shift = read_u128(r)?;
+
+        self.frame_count = read_u128(r)?;
+        self.odd_frame = read_bool(r)?;
+        self.warm_up = read_u32(r)?;
+
+        self.bus.load_state(r)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::{cell::RefCell, rc::Rc};
 
     use crate::{
         bus::PPUBus,
-        cartridge::{tests::test_cartridge, Mirroring},
+        cartridge::{
+            tests::{test_cartridge, test_chr_ram_cartridge},
+            Mirroring,
+        },
     };
 
+    use super::palette::COLOUR_PALETTE;
     use super::*;
 
     /// Returns an instatiated PPU with an empty ROM loaded.
     pub fn new_empty_rom_ppu(mirroring: Option<Mirroring>) -> NesPpu<'static> {
         let cart = test_cartridge(vec![], mirroring).unwrap();
 
-        let bus = PPUBus::new(Rc::new(RefCell::new(cart)));
-        NesPpu::new(Box::new(bus), |_| {})
+        let bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+        NesPpu::new(Box::new(bus), MemoryInit::Zero, |_| {})
     }
 
     #[test]
@@ -1017,4 +1541,290 @@ pub mod tests {
         ppu.write_oam_addr(0x11);
         assert_eq!(ppu.read_oam_data(), 0x66);
     }
+
+    #[test]
+    fn test_power_up_ignores_writes_during_warm_up() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.power_up(Some(1));
+
+        ppu.write_ctrl(0x80);
+        ppu.write_addr(0x23);
+        assert!(!ppu.ctrl.nmi_enabled());
+        assert!(!ppu.addr_toggle);
+
+        for _ in 0..WARM_UP_DOTS {
+            ppu.clock();
+        }
+
+        ppu.write_ctrl(0x80);
+        ppu.write_addr(0x23);
+        assert!(ppu.ctrl.nmi_enabled());
+        assert!(ppu.addr_toggle);
+    }
+
+    #[test]
+    fn test_power_up_seeded_status() {
+        let status = |seed| {
+            let mut ppu = new_empty_rom_ppu(None);
+            ppu.power_up(Some(seed));
+            ppu.status.snapshot()
+        };
+
+        assert_eq!(status(42), status(42));
+        assert!((0..32).map(status).any(|s| s & 0x80 != 0));
+        assert!((0..32).map(status).any(|s| s & 0x80 == 0));
+    }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.write_addr(0x23);
+        ppu.write_addr(0x05);
+
+        ppu.poke(0x2305, 0x66);
+        ppu.poke(0x3F01, 0x21);
+        assert_eq!(ppu.peek(0x2305), 0x66);
+        assert_eq!(ppu.peek(0x6305), 0x66);
+        assert_eq!(ppu.palette_ram()[1], 0x21);
+
+        // Neither touches the VRAM address, read buffer or open bus.
+        assert_eq!(ppu.v_addr.raw(), 0x2305);
+        assert_eq!(ppu.buf, 0);
+        assert_eq!(ppu.open_bus, 0);
+
+        ppu.write_oam_data(0x77);
+        assert_eq!(ppu.oam()[0], 0x77);
+    }
+
+    #[test]
+    fn test_mid_scanline_mask_write() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.poke(0x3F00, 0x16);
+        ppu.write_mask(0x08);
+
+        let run_to = |ppu: &mut NesPpu, position| {
+            while ppu.position() != position {
+                ppu.clock();
+            }
+        };
+
+        // Dot 100 draws the pixel at x = 99, so greyscale starts there.
+        run_to(&mut ppu, (100, 100));
+        ppu.write_mask(0x09);
+
+        // A delayed write leaves the next 9 dots greyscale.
+        run_to(&mut ppu, (100, 150));
+        ppu.write_mask_after(0x08, 9);
+        run_to(&mut ppu, (101, 0));
+
+        let pixel = |x| ppu.frame.get_pixel(x, 100).unwrap();
+        let colour = ppu.frame.get_pixel(0, 100).unwrap();
+        assert_eq!(pixel(98), colour);
+        assert_ne!(pixel(99), colour);
+        assert_eq!(pixel(157), pixel(99));
+        assert_eq!(pixel(158), colour);
+    }
+
+    #[test]
+    fn test_state_keeps_pending_mask() {
+        let mut ppu = new_empty_rom_ppu(None);
+        ppu.warm_up = 0;
+        ppu.write_mask_after(0x18, 3);
+
+        let mut state = Vec::new();
+        ppu.save_state(&mut state).unwrap();
+        let mut restored = new_empty_rom_ppu(None);
+        restored.load_state(&mut &state[..]).unwrap();
+        assert_eq!(restored.pending_mask, Some((0x18, 3)));
+
+        ppu.write_mask_after(0x00, 0);
+        state.clear();
+        ppu.save_state(&mut state).unwrap();
+        restored.load_state(&mut &state[..]).unwrap();
+        assert_eq!(restored.pending_mask, None);
+    }
+
+    #[test]
+    fn test_overlapping_sprites() {
+        let cart = test_chr_ram_cartridge(vec![]).unwrap();
+        let bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+        let mut ppu = NesPpu::new(Box::new(bus), MemoryInit::Zero, |_| {});
+
+        // Tile 1 is transparent on the left and colour 1 on the right, tile 2
+        // is colour 2 throughout.
+        for row in 0..8 {
+            ppu.poke(0x0010 + row, 0x0F);
+            ppu.poke(0x0028 + row, 0xFF);
+        }
+        ppu.poke(0x3F11, 0x16);
+        ppu.poke(0x3F12, 0x2A);
+
+        // Sprite 1 at x = 10 is drawn over sprite 2 at x = 12, apart from
+        // where it is transparent.
+        ppu.oam_data[4..12].copy_from_slice(&[49, 1, 0, 10, 49, 2, 0, 12]);
+        ppu.write_mask(0x14);
+
+        while ppu.position() != (53, 0) {
+            ppu.clock();
+        }
+
+        let pixel = |x| ppu.frame.get_pixel(x, 52).unwrap();
+        let (backdrop, front, behind) = (pixel(0), pixel(14), pixel(12));
+        assert_eq!(front, COLOUR_PALETTE[0x16]);
+        assert_eq!(behind, COLOUR_PALETTE[0x2A]);
+
+        let expected = [
+            backdrop, backdrop, behind, behind, front, front, front, front, behind, behind,
+            backdrop,
+        ];
+        assert_eq!((10..21).map(pixel).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_force_sprites_8x8() {
+        let render = |config: EmuConfig| {
+            let cart = test_chr_ram_cartridge(vec![]).unwrap();
+            let bus = PPUBus::new(Rc::new(RefCell::new(cart)), MemoryInit::Zero);
+            let mut ppu = NesPpu::new(Box::new(bus), MemoryInit::Zero, |_| {});
+            ppu.set_config(config);
+
+            // An 8x16 sprite of tiles 2 and 3, colour 2 throughout.
+            for row in 0..8 {
+                ppu.poke(0x0028 + row, 0xFF);
+                ppu.poke(0x0038 + row, 0xFF);
+            }
+            ppu.poke(0x3F12, 0x2A);
+            ppu.oam_data[4..8].copy_from_slice(&[49, 2, 0, 10]);
+            ppu.write_ctrl(0x20);
+            ppu.write_mask(0x14);
+
+            while ppu.position() != (61, 0) {
+                ppu.clock();
+            }
+            ppu.frame.get_pixel(12, 60).unwrap()
+        };
+
+        // The lower half of the sprite is only drawn in 8x16 mode.
+        assert_eq!(render(EmuConfig::default()), COLOUR_PALETTE[0x2A]);
+        let config = EmuConfig {
+            sprites_8x16: false,
+            ..EmuConfig::default()
+        };
+        assert_ne!(render(config), COLOUR_PALETTE[0x2A]);
+    }
+
+    #[test]
+    fn test_set_palette() {
+        let mut ppu = new_empty_rom_ppu(None);
+        let mut palette = COLOUR_PALETTE;
+        palette[0x21] = Rgb(1, 2, 3);
+        ppu.set_palette(&palette);
+        ppu.poke(0x3F00, 0x21);
+        ppu.write_mask(0x08);
+
+        while ppu.position() != (1, 0) {
+            ppu.clock();
+        }
+        assert_eq!(ppu.frame.get_pixel(0, 0), Some(Rgb(1, 2, 3)));
+    }
+
+    /// Counts the A12 edges seen while rendering a frame with the given
+    /// PPUCTRL value.
+    fn count_a12_edges(ctrl: u8) -> usize {
+        struct A12Bus {
+            a12: crate::mapper::A12Filter,
+            edges: Rc<RefCell<usize>>,
+        }
+
+        impl Memory for A12Bus {
+            fn write_data(&mut self, _addr: u16, _value: u8) {}
+
+            fn read_data(&mut self, _addr: u16) -> u8 {
+                0
+            }
+
+            fn peek_data(&self, _addr: u16) -> u8 {
+                0
+            }
+
+            fn observe(&mut self, addr: u16, dot: u64) {
+                if self.a12.observe(addr, dot) {
+                    *self.edges.borrow_mut() += 1;
+                }
+            }
+        }
+
+        let edges = Rc::new(RefCell::new(0));
+        let bus = A12Bus {
+            a12: Default::default(),
+            edges: Rc::clone(&edges),
+        };
+
+        let mut ppu = NesPpu::new(Box::new(bus), MemoryInit::Zero, |_| {});
+        ppu.write_ctrl(ctrl);
+        ppu.write_mask(0x1E);
+
+        // Skip the partial first frame, then count a whole one.
+        while ppu.read_frame_count() == 0 {
+            ppu.clock();
+        }
+        *edges.borrow_mut() = 0;
+        while ppu.read_frame_count() == 1 {
+            ppu.clock();
+        }
+
+        let count = *edges.borrow();
+        count
+    }
+
+    #[test]
+    fn test_a12_edge_per_scanline() {
+        // Background at $0000 and sprites at $1000 give one edge per rendered
+        // scanline during the sprite fetches.
+        assert_eq!(count_a12_edges(0x08), 241);
+
+        // Background at $1000 and sprites at $0000 give one edge per rendered
+        // scanline during the background prefetch, plus one for the first
+        // background fetch after A12 has been low throughout VBLANK.
+        assert_eq!(count_a12_edges(0x10), 242);
+
+        // With both tables at $1000, A12 only drops briefly for nametable
+        // fetches so the filter only sees the edge after VBLANK.
+        assert_eq!(count_a12_edges(0x18), 1);
+
+        // Nothing is fetched from $1000 with both tables at $0000.
+        assert_eq!(count_a12_edges(0x00), 0);
+    }
+
+    #[test]
+    fn test_clock_dots_matches_clock_while_idle() {
+        let mut stepped = new_empty_rom_ppu(None);
+        let mut bulk = new_empty_rom_ppu(None);
+        for ppu in [&mut stepped, &mut bulk] {
+            ppu.write_ctrl(0x80);
+            ppu.bus.write_data(0x3F00, 0x21);
+            ppu.refresh_open_bus(0x55);
+        }
+
+        // Run past two vblanks with rendering disabled, in the uneven steps
+        // of the PAL clock ratio.
+        let dots = 2 * 262 * DOTS_PER_SCANLINE as u32 + 100;
+        for _ in 0..dots {
+            stepped.clock();
+        }
+        let mut left = dots;
+        while left > 0 {
+            let step = left.min(3 + left % 2);
+            bulk.clock_dots(step);
+            left -= step;
+        }
+
+        assert_eq!(bulk.position(), stepped.position());
+        assert_eq!(bulk.frame_dots(), stepped.frame_dots());
+        assert_eq!(bulk.read_frame_count(), 2);
+        assert_eq!(bulk.poll_nmi(), stepped.poll_nmi());
+        assert_eq!(bulk.read_status(), stepped.read_status());
+        assert_eq!(bulk.frame.pixels(), stepped.frame.pixels());
+        assert_eq!(bulk.frame.get_pixel(0, 0), Some(COLOUR_PALETTE[0x21]));
+    }
 }
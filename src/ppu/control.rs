@@ -86,4 +86,9 @@ impl Control {
     pub fn update(&mut self, data: u8) {
         self.bits = data;
     }
+
+    /// Returns the value of the register.
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
 }
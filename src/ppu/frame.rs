@@ -1,23 +1,38 @@
-use super::palette;
+use super::palette::Rgb;
 
 /// Frame represents one rendered frame of pixels.
+///
+/// Pixels are stored row by row as packed RGB24, i.e. three bytes per pixel
+/// starting at the top left of the screen.
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Frame {
-    const WIDTH: usize = 256;
-    const HEIGHT: usize = 240;
+    /// Width of a frame in pixels.
+    pub const WIDTH: usize = 256;
+
+    /// Height of a frame in pixels.
+    pub const HEIGHT: usize = 240;
+
+    /// Number of bytes used by each pixel.
+    pub const BYTES_PER_PIXEL: usize = 3;
 
     /// Returns a new frame.
     pub fn new() -> Self {
         Frame {
-            data: vec![0; (Frame::WIDTH) * (Frame::HEIGHT) * 3],
+            data: vec![0; Frame::WIDTH * Frame::HEIGHT * Frame::BYTES_PER_PIXEL],
         }
     }
 
     /// Sets a pixel in the given position with the given colour.
-    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: palette::Rgb) {
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: Rgb) {
         let base = y * 3 * Frame::WIDTH + x * 3;
         if base + 2 < self.data.len() {
             self.data[base] = rgb.0;
@@ -26,8 +41,108 @@ impl Frame {
         }
     }
 
+    /// Returns the colour of the pixel in the given position, or None if the
+    /// position is outside of the frame.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return None;
+        }
+
+        let base = (y * Frame::WIDTH + x) * Frame::BYTES_PER_PIXEL;
+        Some(Rgb(
+            self.data[base],
+            self.data[base + 1],
+            self.data[base + 2],
+        ))
+    }
+
     /// Returns the current frame contents.
     pub fn pixels(&self) -> &[u8] {
         &self.data
     }
+
+    /// Returns an iterator over the raw RGB24 bytes of each row, from top to
+    /// bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.data
+            .chunks_exact(Frame::WIDTH * Frame::BYTES_PER_PIXEL)
+    }
+
+    /// Returns an iterator over the colour of every pixel, row by row.
+    pub fn iter(&self) -> impl Iterator<Item = Rgb> + '_ {
+        self.data
+            .chunks_exact(Frame::BYTES_PER_PIXEL)
+            .map(|p| Rgb(p[0], p[1], p[2]))
+    }
+
+    /// Returns the frame contents as packed RGBA32 with an opaque alpha
+    /// channel.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.iter()
+            .flat_map(|Rgb(r, g, b)| [r, g, b, 0xFF])
+            .collect()
+    }
+
+    /// Returns the frame contents as an image buffer.
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> image::RgbImage {
+        image::RgbImage::from_raw(Frame::WIDTH as u32, Frame::HEIGHT as u32, self.data.clone())
+            .expect("frame buffer matches its dimensions")
+    }
+}
+
+impl From<&[u8]> for Frame {
+    /// Creates a frame from packed RGB24 data, such as the buffer passed to
+    /// the render callback. Missing data is zero filled.
+    fn from(pixels: &[u8]) -> Self {
+        let mut frame = Frame::new();
+        let len = pixels.len().min(frame.data.len());
+        frame.data[..len].copy_from_slice(&pixels[..len]);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set_pixel() {
+        let mut frame = Frame::new();
+        frame.set_pixel(10, 20, Rgb(1, 2, 3));
+
+        assert_eq!(frame.get_pixel(10, 20), Some(Rgb(1, 2, 3)));
+        assert_eq!(frame.get_pixel(11, 20), Some(Rgb(0, 0, 0)));
+        assert_eq!(frame.get_pixel(Frame::WIDTH, 0), None);
+        assert_eq!(frame.get_pixel(0, Frame::HEIGHT), None);
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 1, Rgb(4, 5, 6));
+
+        let rows: Vec<&[u8]> = frame.rows().collect();
+        assert_eq!(rows.len(), Frame::HEIGHT);
+        assert_eq!(rows[1][0..3], [4, 5, 6]);
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, Rgb(7, 8, 9));
+
+        let rgba = frame.to_rgba();
+        assert_eq!(rgba.len(), Frame::WIDTH * Frame::HEIGHT * 4);
+        assert_eq!(rgba[4..8], [7, 8, 9, 0xFF]);
+    }
+
+    #[test]
+    fn test_from_pixels() {
+        let mut frame = Frame::new();
+        frame.set_pixel(3, 3, Rgb(1, 1, 1));
+
+        let copy = Frame::from(frame.pixels());
+        assert_eq!(copy.get_pixel(3, 3), Some(Rgb(1, 1, 1)));
+    }
 }
@@ -0,0 +1,229 @@
+//! Property tests driving random sequences of register accesses against the
+//! PPU and comparing the results with a simplified reference model.
+
+use proptest::prelude::*;
+
+use super::*;
+
+/// Size of the PPU address space.
+const ADDR_SPACE: usize = 0x4000;
+
+/// A PPU bus backed by plain RAM, so the register behaviour can be tested
+/// without mirroring or a cartridge getting in the way.
+struct RamBus {
+    ram: Vec<u8>,
+}
+
+impl Memory for RamBus {
+    fn write_data(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize % ADDR_SPACE] = value;
+    }
+
+    fn read_data(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize % ADDR_SPACE]
+    }
+
+    fn peek_data(&self, addr: u16) -> u8 {
+        self.ram[addr as usize % ADDR_SPACE]
+    }
+}
+
+/// A single CPU access to a PPU register.
+#[derive(Clone, Debug)]
+enum Op {
+    WriteCtrl(u8),
+    WriteMask(u8),
+    ReadStatus,
+    WriteOamAddr(u8),
+    WriteOamData(u8),
+    ReadOamData,
+    WriteScroll(u8),
+    WriteAddr(u8),
+    WriteData(u8),
+    ReadData,
+    OamDma(u8),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        any::<u8>().prop_map(Op::WriteCtrl),
+        any::<u8>().prop_map(Op::WriteMask),
+        Just(Op::ReadStatus),
+        any::<u8>().prop_map(Op::WriteOamAddr),
+        any::<u8>().prop_map(Op::WriteOamData),
+        Just(Op::ReadOamData),
+        any::<u8>().prop_map(Op::WriteScroll),
+        any::<u8>().prop_map(Op::WriteAddr),
+        any::<u8>().prop_map(Op::WriteData),
+        Just(Op::ReadData),
+        any::<u8>().prop_map(Op::OamDma),
+    ]
+}
+
+/// Returns the OAM DMA page generated from the given seed.
+fn dma_page(seed: u8) -> [u8; 256] {
+    let mut page = [0; 256];
+    for (i, b) in page.iter_mut().enumerate() {
+        *b = seed.wrapping_add(i as u8);
+    }
+    page
+}
+
+/// Reference model of the PPU registers, written directly from the loopy
+/// register description.
+///
+/// See: https://www.nesdev.org/wiki/PPU_scrolling#PPU_internal_registers
+struct Model {
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    increment: u16,
+    grayscale: bool,
+    buf: u8,
+    open_bus: u8,
+    oam_addr: u8,
+    oam: [u8; 256],
+    ram: Vec<u8>,
+}
+
+impl Model {
+    fn new() -> Self {
+        Model {
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            increment: 1,
+            grayscale: false,
+            buf: 0,
+            open_bus: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            ram: vec![0; ADDR_SPACE],
+        }
+    }
+
+    /// Applies the access to the model, returning the value read if any.
+    fn apply(&mut self, op: &Op) -> Option<u8> {
+        match *op {
+            Op::WriteCtrl(value) => {
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0x3) << 10);
+                self.increment = if value & 0x4 != 0 { 32 } else { 1 };
+            }
+            Op::WriteMask(value) => self.grayscale = value & 0x1 != 0,
+            Op::ReadStatus => {
+                self.w = false;
+                return Some(self.open_bus & 0x1F);
+            }
+            Op::WriteOamAddr(value) => self.oam_addr = value,
+            Op::WriteOamData(value) => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            Op::ReadOamData => {
+                let mask = if self.oam_addr & 0x3 == 2 { 0xE3 } else { 0xFF };
+                self.open_bus = self.oam[self.oam_addr as usize] & mask;
+                return Some(self.open_bus);
+            }
+            Op::WriteScroll(value) => {
+                if self.w {
+                    self.t = (self.t & !0x73E0)
+                        | ((value as u16 & 0x7) << 12)
+                        | ((value as u16 >> 3) << 5);
+                } else {
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.x = value & 0x7;
+                }
+                self.w = !self.w;
+            }
+            Op::WriteAddr(value) => {
+                if self.w {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                } else {
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                }
+                self.w = !self.w;
+            }
+            Op::WriteData(value) => {
+                self.ram[self.v as usize % ADDR_SPACE] = value;
+                self.open_bus = value;
+                self.increment_v();
+            }
+            Op::ReadData => {
+                let mut result = self.buf;
+                self.buf = self.ram[self.v as usize % ADDR_SPACE];
+
+                // Palette reads are not buffered.
+                if self.v & 0x3F00 == 0x3F00 {
+                    result = (self.open_bus & 0xC0) | (self.buf & 0x3F);
+                    if self.grayscale {
+                        result &= 0x30;
+                    }
+                }
+
+                self.open_bus = result;
+                self.increment_v();
+                return Some(result);
+            }
+            Op::OamDma(seed) => {
+                for b in dma_page(seed) {
+                    self.oam[self.oam_addr as usize] = b;
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn increment_v(&mut self) {
+        self.v = self.v.wrapping_add(self.increment) & 0x7FFF;
+    }
+}
+
+/// Applies the access to the PPU, returning the value read if any.
+fn apply(ppu: &mut NesPpu, op: &Op) -> Option<u8> {
+    match *op {
+        Op::WriteCtrl(value) => ppu.write_ctrl(value),
+        Op::WriteMask(value) => ppu.write_mask(value),
+        Op::ReadStatus => return Some(ppu.read_status()),
+        Op::WriteOamAddr(value) => ppu.write_oam_addr(value),
+        Op::WriteOamData(value) => ppu.write_oam_data(value),
+        Op::ReadOamData => return Some(ppu.read_oam_data()),
+        Op::WriteScroll(value) => ppu.write_scroll(value),
+        Op::WriteAddr(value) => ppu.write_addr(value),
+        Op::WriteData(value) => ppu.write_data(value),
+        Op::ReadData => return Some(ppu.read_data()),
+        Op::OamDma(seed) => ppu.write_oam_dma(&dma_page(seed)),
+    }
+
+    None
+}
+
+proptest! {
+    #[test]
+    fn test_register_sequences_match_model(ops in prop::collection::vec(op(), 1..200)) {
+        let bus = RamBus { ram: vec![0; ADDR_SPACE] };
+        let mut ppu = NesPpu::new(Box::new(bus), MemoryInit::Zero, |_| {});
+        let mut model = Model::new();
+
+        for op in ops.iter() {
+            let got = apply(&mut ppu, op);
+            let want = model.apply(op);
+            prop_assert_eq!(got, want, "read after {:?}", op);
+
+            prop_assert_eq!(ppu.v_addr.raw(), model.v, "v after {:?}", op);
+            prop_assert_eq!(ppu.scroll.raw(), model.t, "t after {:?}", op);
+            prop_assert_eq!(ppu.xfine, model.x, "x after {:?}", op);
+            prop_assert_eq!(ppu.addr_toggle, model.w, "w after {:?}", op);
+            prop_assert_eq!(ppu.oam_addr, model.oam_addr, "OAMADDR after {:?}", op);
+        }
+
+        prop_assert_eq!(ppu.oam_data, model.oam);
+        for addr in 0..ADDR_SPACE as u16 {
+            prop_assert_eq!(ppu.bus.read_data(addr), model.ram[addr as usize]);
+        }
+    }
+}
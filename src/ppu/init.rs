@@ -0,0 +1,60 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Policy for the contents of VRAM, palette RAM and OAM at power-on. Real
+/// hardware starts with unpredictable contents, so software relying on
+/// cleared memory can be caught by not clearing it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MemoryInit {
+    /// Every byte is zero.
+    #[default]
+    Zero,
+
+    /// Every byte is random, derived from the given seed.
+    Random(u64),
+
+    /// Repeating blocks of four $00 bytes then four $FF bytes, a common
+    /// power-on pattern which is the same on every run.
+    Pattern,
+}
+
+impl MemoryInit {
+    /// Fills the memory according to the policy. Random contents are salted
+    /// with the size of the memory, so VRAM, palette RAM and OAM seeded from
+    /// the same policy don't share contents.
+    pub fn fill(&self, mem: &mut [u8]) {
+        match *self {
+            MemoryInit::Zero => mem.fill(0),
+            MemoryInit::Random(seed) => StdRng::seed_from_u64(seed ^ mem.len() as u64).fill(mem),
+            MemoryInit::Pattern => {
+                for (i, b) in mem.iter_mut().enumerate() {
+                    *b = if i & 0x4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let mut mem = [0x55; 16];
+        MemoryInit::Zero.fill(&mut mem);
+        assert_eq!(mem, [0; 16]);
+
+        MemoryInit::Pattern.fill(&mut mem);
+        assert_eq!(mem[..8], [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let random = |seed, len| {
+            let mut mem = vec![0; len];
+            MemoryInit::Random(seed).fill(&mut mem);
+            mem
+        };
+        assert_eq!(random(1, 16), random(1, 16));
+        assert_ne!(random(1, 16), random(2, 16));
+        assert_ne!(random(1, 16)[..8], random(1, 32)[..8]);
+        assert_ne!(random(1, 16), vec![0; 16]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+use super::frame::Frame;
+use super::palette::Rgb;
+
+/// Colour of the bounding boxes of sprites in front of the background.
+const FRONT_COLOUR: Rgb = Rgb(0, 255, 0);
+
+/// Colour of the bounding boxes of sprites behind the background.
+const BEHIND_COLOUR: Rgb = Rgb(255, 160, 0);
+
+/// Sprites at or below this Y position are off the bottom of the screen,
+/// which is how games hide unused entries.
+const HIDDEN_Y: u8 = 0xEF;
+
+/// An entry of OAM decoded for the sprite inspector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OamEntry {
+    /// Index of the entry, 0 to 63.
+    pub index: usize,
+
+    /// Position of the sprite's top-left corner. Sprites are drawn a
+    /// scanline below their Y position.
+    pub x: u8,
+    pub y: u8,
+
+    pub tile: u8,
+    pub attr: u8,
+}
+
+impl OamEntry {
+    /// Returns the 64 entries of the given OAM.
+    pub fn all(oam: &[u8; 0x100]) -> impl Iterator<Item = OamEntry> + '_ {
+        oam.chunks_exact(4).enumerate().map(|(index, e)| OamEntry {
+            index,
+            x: e[3],
+            y: e[0],
+            tile: e[1],
+            attr: e[2],
+        })
+    }
+
+    /// Returns the palette of the sprite, 4 to 7.
+    pub fn palette(&self) -> u8 {
+        4 + (self.attr & 0x3)
+    }
+
+    /// Returns true if the sprite is drawn behind the background.
+    pub fn behind(&self) -> bool {
+        self.attr & 0x20 != 0
+    }
+
+    /// Returns true if the sprite is flipped horizontally.
+    pub fn flip_h(&self) -> bool {
+        self.attr & 0x40 != 0
+    }
+
+    /// Returns true if the sprite is flipped vertically.
+    pub fn flip_v(&self) -> bool {
+        self.attr & 0x80 != 0
+    }
+
+    /// Returns true if the sprite is on screen.
+    pub fn visible(&self) -> bool {
+        self.y < HIDDEN_Y
+    }
+}
+
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}: x {:3} y {:3} tile {:02X} palette {} {:6} {}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.palette(),
+            if self.behind() { "behind" } else { "front" },
+            if self.flip_h() { 'H' } else { '-' },
+            if self.flip_v() { 'V' } else { '-' },
+        )?;
+        if !self.visible() {
+            write!(f, " hidden")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outlines the bounding box of each visible sprite in OAM on the frame, in
+/// a colour showing whether it is in front of or behind the background.
+/// Sprites are 8 pixels wide and the given number of pixels tall.
+pub fn draw_boxes(oam: &[u8; 0x100], height: usize, frame: &mut Frame) {
+    for entry in OamEntry::all(oam).filter(OamEntry::visible) {
+        let colour = match entry.behind() {
+            true => BEHIND_COLOUR,
+            false => FRONT_COLOUR,
+        };
+
+        let (left, top) = (entry.x as usize, entry.y as usize + 1);
+        let (right, bottom) = (left + 7, top + height - 1);
+        for x in left..=right.min(Frame::WIDTH - 1) {
+            frame.set_pixel(x, top, colour);
+            if bottom < Frame::HEIGHT {
+                frame.set_pixel(x, bottom, colour);
+            }
+        }
+        for y in top..=bottom.min(Frame::HEIGHT - 1) {
+            frame.set_pixel(left, y, colour);
+            if right < Frame::WIDTH {
+                frame.set_pixel(right, y, colour);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns OAM with every entry hidden apart from the given ones.
+    fn test_oam(entries: &[(usize, [u8; 4])]) -> [u8; 0x100] {
+        let mut oam = [0xFF; 0x100];
+        for (index, entry) in entries {
+            oam[index * 4..index * 4 + 4].copy_from_slice(entry);
+        }
+        oam
+    }
+
+    #[test]
+    fn test_entries() {
+        let oam = test_oam(&[(0, [0x3F, 0x12, 0x62, 0x80]), (5, [0x10, 0x01, 0x80, 0x08])]);
+        let entries: Vec<_> = OamEntry::all(&oam).collect();
+        assert_eq!(entries.len(), 64);
+
+        assert_eq!(
+            entries[0].to_string(),
+            "00: x 128 y  63 tile 12 palette 6 behind H-"
+        );
+        assert_eq!(
+            entries[5].to_string(),
+            "05: x   8 y  16 tile 01 palette 4 front  -V"
+        );
+        assert_eq!(
+            entries[1].to_string(),
+            "01: x 255 y 255 tile FF palette 7 behind HV hidden"
+        );
+    }
+
+    #[test]
+    fn test_draw_boxes() {
+        let oam = test_oam(&[(0, [9, 0, 0x00, 20]), (1, [0xE8, 0, 0x20, 252])]);
+        let mut frame = Frame::new();
+        draw_boxes(&oam, 16, &mut frame);
+
+        // Sprites are drawn a scanline below their Y position.
+        assert_eq!(frame.get_pixel(20, 10), Some(FRONT_COLOUR));
+        assert_eq!(frame.get_pixel(27, 25), Some(FRONT_COLOUR));
+        assert_eq!(frame.get_pixel(23, 9), Some(Rgb(0, 0, 0)));
+        assert_eq!(frame.get_pixel(23, 17), Some(Rgb(0, 0, 0)));
+
+        // Boxes are clipped to the edges of the frame.
+        assert_eq!(frame.get_pixel(252, 233), Some(BEHIND_COLOUR));
+        assert_eq!(frame.get_pixel(255, 233), Some(BEHIND_COLOUR));
+        assert_eq!(frame.get_pixel(252, 239), Some(BEHIND_COLOUR));
+    }
+}
@@ -58,35 +58,45 @@ impl Mask {
         (self.bits & SHOW_SPRITES) == SHOW_SPRITES
     }
 
-    /// Returns the current colour emphasis.
-    pub fn emphasise(&self) -> (f64, f64, f64) {
-        let mut r = 1.0;
-        let mut g = 1.0;
-        let mut b = 1.0;
+    /// Returns the current colour emphasis as quarters of each component's
+    /// intensity. Emphasising a colour dims the other two to three quarters.
+    pub fn emphasise(&self) -> (u16, u16, u16) {
+        let mut r = 4;
+        let mut g = 4;
+        let mut b = 4;
 
         if (self.bits & EMPHASISE_RED) == EMPHASISE_RED {
-            g = 0.75;
-            b = 0.75;
+            g = 3;
+            b = 3;
         }
         if (self.bits & EMPHASISE_GREEN) == EMPHASISE_GREEN {
-            r = 0.75;
-            b = 0.75;
+            r = 3;
+            b = 3;
         }
         if (self.bits & EMPHASISE_BLUE) == EMPHASISE_BLUE {
-            r = 0.75;
-            b = 0.75;
+            r = 3;
+            g = 3;
         }
 
         (r, g, b)
     }
 
-    /// Returns true if one of the color emphasis bits is set.
-    pub fn colour_emphasis_enabled(&self) -> bool {
-        self.bits & (EMPHASISE_RED | EMPHASISE_GREEN | EMPHASISE_BLUE) != 0
+    /// Returns the greyscale and colour emphasis bits packed into a number
+    /// from 0 to 15, the emphasis in bits 1-3 above greyscale in bit 0, to
+    /// select the matching row of a [`PaletteTable`].
+    ///
+    /// [`PaletteTable`]: super::palette::PaletteTable
+    pub fn palette_variant(&self) -> u8 {
+        (self.bits >> 4) & 0x0E | self.bits & GRAYSCALE
     }
 
     /// Updates the state of the register.
     pub fn update(&mut self, data: u8) {
         self.bits = data;
     }
+
+    /// Returns the value of the register.
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
 }
@@ -0,0 +1,169 @@
+use clap::ValueEnum;
+
+use super::frame::Frame;
+use super::palette::Rgb;
+
+/// Colour of the sprite 0 hit marker.
+const HIT_COLOUR: Rgb = Rgb(255, 0, 255);
+
+/// Colour of the scroll split lines.
+const SPLIT_COLOUR: Rgb = Rgb(0, 255, 255);
+
+/// Length of each arm of the sprite 0 hit marker in pixels.
+const HIT_RADIUS: usize = 3;
+
+/// The raster markers drawn by the debug overlay.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RasterMarkers {
+    /// A cross on the pixel where sprite 0 hit was first set.
+    Sprite0,
+
+    /// A dotted line across each scanline on which the scroll or VRAM
+    /// address was written while rendering.
+    Splits,
+
+    /// Both of the above.
+    All,
+}
+
+impl RasterMarkers {
+    fn hit(&self) -> bool {
+        *self != RasterMarkers::Splits
+    }
+
+    fn splits(&self) -> bool {
+        *self != RasterMarkers::Sprite0
+    }
+}
+
+/// Records where raster effects happen during a frame and marks them on the
+/// finished frame, to diagnose shaking status bars and misplaced splits.
+pub struct Overlay {
+    enabled: bool,
+    markers: RasterMarkers,
+
+    /// Position of the first sprite 0 hit this frame.
+    hit: Option<(usize, usize)>,
+
+    /// Visible scanlines with a mid-frame $2005 or $2006 write.
+    splits: [bool; Frame::HEIGHT],
+}
+
+impl Overlay {
+    /// Returns an overlay which draws nothing.
+    pub fn new() -> Self {
+        Overlay {
+            enabled: false,
+            markers: RasterMarkers::All,
+            hit: None,
+            splits: [false; Frame::HEIGHT],
+        }
+    }
+
+    /// Sets the markers to draw, or None to turn the overlay off. All
+    /// markers are drawn if it is later toggled on.
+    pub fn set_markers(&mut self, markers: Option<RasterMarkers>) {
+        self.enabled = markers.is_some();
+        self.markers = markers.unwrap_or(RasterMarkers::All);
+    }
+
+    /// Turns the overlay on or off.
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Records sprite 0 hit being set at the given pixel.
+    pub fn record_hit(&mut self, x: usize, y: usize) {
+        if self.hit.is_none() {
+            self.hit = Some((x, y));
+        }
+    }
+
+    /// Records a scroll or address write on the given scanline.
+    pub fn record_split(&mut self, scanline: i32) {
+        if let Ok(y @ 0..Frame::HEIGHT) = usize::try_from(scanline) {
+            self.splits[y] = true;
+        }
+    }
+
+    /// Draws the markers recorded this frame onto it, then clears them for
+    /// the next frame.
+    pub fn draw(&mut self, frame: &mut Frame) {
+        if self.enabled {
+            if self.markers.splits() {
+                for (y, _) in self.splits.iter().enumerate().filter(|(_, s)| **s) {
+                    for x in (0..Frame::WIDTH).step_by(2) {
+                        frame.set_pixel(x, y, SPLIT_COLOUR);
+                    }
+                }
+            }
+
+            if let (true, Some((x, y))) = (self.markers.hit(), self.hit) {
+                for d in 0..=HIT_RADIUS {
+                    for (px, py) in [(x + d, y), (x.wrapping_sub(d), y)] {
+                        if px < Frame::WIDTH {
+                            frame.set_pixel(px, py, HIT_COLOUR);
+                        }
+                    }
+                    for py in [y + d, y.wrapping_sub(d)] {
+                        if py < Frame::HEIGHT {
+                            frame.set_pixel(x, py, HIT_COLOUR);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.hit = None;
+        self.splits.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let mut overlay = Overlay::new();
+        overlay.set_markers(Some(RasterMarkers::All));
+        overlay.record_hit(0, 30);
+        overlay.record_hit(100, 100);
+        overlay.record_split(31);
+        overlay.record_split(-1);
+        overlay.record_split(240);
+
+        let mut frame = Frame::new();
+        overlay.draw(&mut frame);
+        assert_eq!(frame.get_pixel(0, 30), Some(HIT_COLOUR));
+        assert_eq!(frame.get_pixel(3, 30), Some(HIT_COLOUR));
+        assert_eq!(frame.get_pixel(0, 27), Some(HIT_COLOUR));
+        assert_eq!(frame.get_pixel(100, 100), Some(Rgb(0, 0, 0)));
+        assert_eq!(frame.get_pixel(10, 31), Some(SPLIT_COLOUR));
+        assert_eq!(frame.get_pixel(11, 31), Some(Rgb(0, 0, 0)));
+
+        // The markers are cleared once drawn.
+        let mut frame = Frame::new();
+        overlay.draw(&mut frame);
+        assert!(frame.pixels().iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn test_markers() {
+        let mut overlay = Overlay::new();
+        overlay.set_markers(Some(RasterMarkers::Splits));
+        overlay.record_hit(10, 10);
+        overlay.record_split(20);
+
+        let mut frame = Frame::new();
+        overlay.draw(&mut frame);
+        assert_eq!(frame.get_pixel(10, 10), Some(Rgb(0, 0, 0)));
+        assert_eq!(frame.get_pixel(0, 20), Some(SPLIT_COLOUR));
+
+        overlay.toggle();
+        overlay.record_split(20);
+        let mut frame = Frame::new();
+        overlay.draw(&mut frame);
+        assert_eq!(frame.get_pixel(0, 20), Some(Rgb(0, 0, 0)));
+    }
+}
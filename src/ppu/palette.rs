@@ -1,5 +1,7 @@
-// Represents a NES colour.
-#[derive(Clone, Copy)]
+use super::mask::Mask;
+
+/// Represents a NES colour.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rgb(pub u8, pub u8, pub u8);
 
 /// NES colour palette.
@@ -17,3 +19,126 @@ pub static COLOUR_PALETTE: [Rgb; 0x40] = [
     Rgb(236, 238, 236), Rgb(168, 204, 236), Rgb(188, 188, 236), Rgb(212, 178, 236), Rgb(236, 174, 236), Rgb(236, 174, 212), Rgb(236, 180, 176), Rgb(228, 196, 144),
     Rgb(204, 210, 120), Rgb(180, 222, 120), Rgb(168, 226, 144), Rgb(152, 226, 180), Rgb(160, 214, 228), Rgb(160, 162, 160), Rgb(0, 0, 0),       Rgb(0, 0, 0),
 ];
+
+/// Parses a palette file of 64 RGB triplets, as dumped from a PPU or written
+/// by other emulators. Files with emphasised colours after the first 64 have
+/// the rest ignored, as emphasis is applied separately.
+pub fn parse_pal(bytes: &[u8]) -> Result<[Rgb; 0x40], String> {
+    if bytes.len() < 0x40 * 3 || !bytes.len().is_multiple_of(3) {
+        return Err(format!(
+            "palette files are 64 RGB colours, not {} bytes",
+            bytes.len()
+        ));
+    }
+
+    let mut palette = [Rgb(0, 0, 0); 0x40];
+    for (colour, rgb) in palette.iter_mut().zip(bytes.chunks(3)) {
+        *colour = Rgb(rgb[0], rgb[1], rgb[2]);
+    }
+    Ok(palette)
+}
+
+/// Number of greyscale and colour emphasis combinations, see
+/// [`Mask::palette_variant`].
+const VARIANTS: usize = 16;
+
+/// The palette with every combination of greyscale and colour emphasis
+/// applied ahead of time, so looking up a pixel's colour is a single index
+/// rather than integer maths per pixel.
+pub struct PaletteTable {
+    colours: [Rgb; 0x40 * VARIANTS],
+}
+
+impl PaletteTable {
+    /// Returns the table for the given 64 colour palette.
+    pub fn new(palette: &[Rgb; 0x40]) -> Self {
+        let mut colours = [Rgb(0, 0, 0); 0x40 * VARIANTS];
+        let mut mask = Mask::new();
+
+        for (variant, row) in colours.chunks_mut(0x40).enumerate() {
+            let variant = variant as u8;
+            mask.update((variant & 0x0E) << 4 | (variant & 0x01));
+            let (r, g, b) = mask.emphasise();
+
+            for (index, colour) in row.iter_mut().enumerate() {
+                let c = palette[index & mask.grayscale_mask() as usize];
+                *colour = Rgb(
+                    (c.0 as u16 * r / 4) as u8,
+                    (c.1 as u16 * g / 4) as u8,
+                    (c.2 as u16 * b / 4) as u8,
+                );
+            }
+        }
+
+        PaletteTable { colours }
+    }
+
+    /// Returns the colour at the given palette index with the given
+    /// greyscale and colour emphasis variant applied.
+    pub fn colour(&self, variant: u8, index: u8) -> Rgb {
+        self.colours[(variant as usize % VARIANTS) * 0x40 + (index & 0x3F) as usize]
+    }
+}
+
+impl Default for PaletteTable {
+    fn default() -> Self {
+        PaletteTable::new(&COLOUR_PALETTE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pal() {
+        let mut bytes: Vec<u8> = (0..0x40 * 3).map(|b| b as u8).collect();
+        let palette = parse_pal(&bytes).unwrap();
+        assert_eq!(palette[0], Rgb(0, 1, 2));
+        assert_eq!(palette[0x3F], Rgb(189, 190, 191));
+
+        // Emphasised colours after the first 64 are ignored.
+        bytes.extend(vec![0xFF; 7 * 0x40 * 3]);
+        assert_eq!(parse_pal(&bytes).unwrap(), palette);
+
+        assert!(parse_pal(&bytes[..0x40 * 3 - 3]).is_err());
+        assert!(parse_pal(&bytes[..0x40 * 3 + 1]).is_err());
+    }
+
+    #[test]
+    fn test_palette_table() {
+        let table = PaletteTable::default();
+        let mut mask = Mask::new();
+        assert_eq!(
+            table.colour(mask.palette_variant(), 0x16),
+            COLOUR_PALETTE[0x16]
+        );
+
+        // Greyscale keeps only the brightness column.
+        mask.update(0x01);
+        assert_eq!(
+            table.colour(mask.palette_variant(), 0x16),
+            COLOUR_PALETTE[0x10]
+        );
+
+        // Emphasising red dims green and blue to three quarters.
+        mask.update(0x20);
+        let c = COLOUR_PALETTE[0x20];
+        assert_eq!(
+            table.colour(mask.palette_variant(), 0x20),
+            Rgb(c.0, (c.1 as u16 * 3 / 4) as u8, (c.2 as u16 * 3 / 4) as u8)
+        );
+
+        // Every emphasis bit with greyscale dims all three.
+        mask.update(0xE1);
+        let c = COLOUR_PALETTE[0x30];
+        assert_eq!(
+            table.colour(mask.palette_variant(), 0x3D),
+            Rgb(
+                (c.0 as u16 * 3 / 4) as u8,
+                (c.1 as u16 * 3 / 4) as u8,
+                (c.2 as u16 * 3 / 4) as u8
+            )
+        );
+    }
+}
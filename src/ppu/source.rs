@@ -0,0 +1,70 @@
+use super::palette::Rgb;
+
+/// Identifies which part of the pixel mux produced a pixel, used by the
+/// priority debug render mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// Neither the background nor a sprite had an opaque pixel.
+    Backdrop,
+
+    /// Background pixel using the given palette.
+    Background { palette: u8 },
+
+    /// Sprite pixel from the given sprite number (0-63), `behind` is set for sprites
+    /// with the background priority attribute.
+    Sprite { index: u8, behind: bool },
+}
+
+impl Source {
+    /// Returns the classification colour of the source. Backgrounds are
+    /// shades of blue by palette, sprites in front of the background are
+    /// red to yellow by OAM index, sprites behind the background are purple
+    /// and sprite 0 is always green.
+    pub fn colour(&self) -> Rgb {
+        match *self {
+            Source::Backdrop => Rgb(32, 32, 32),
+            Source::Background { palette } => Rgb(0, 48 + palette * 32, 128 + palette * 40),
+            Source::Sprite { index: 0, .. } => Rgb(0, 255, 0),
+            Source::Sprite {
+                behind: true,
+                index,
+            } => Rgb(160, 0, 128 + index * 2),
+            Source::Sprite { index, .. } => Rgb(255, 64 + index * 2, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colours_are_distinct() {
+        let colours = [
+            Source::Backdrop.colour(),
+            Source::Background { palette: 0 }.colour(),
+            Source::Background { palette: 3 }.colour(),
+            Source::Sprite {
+                index: 0,
+                behind: false,
+            }
+            .colour(),
+            Source::Sprite {
+                index: 4,
+                behind: false,
+            }
+            .colour(),
+            Source::Sprite {
+                index: 4,
+                behind: true,
+            }
+            .colour(),
+        ];
+
+        for (i, a) in colours.iter().enumerate() {
+            for b in colours.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
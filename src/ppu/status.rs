@@ -51,6 +51,11 @@ impl Status {
         }
     }
 
+    /// Returns true if sprite zero hit is set.
+    pub fn sprite_zero_hit(&self) -> bool {
+        self.bits & SPRITE_ZERO_HIT != 0
+    }
+
     /// Sets sprite zero overflow.
     pub fn set_sprite_overflow(&mut self, status: bool) {
         if status {
@@ -69,4 +74,9 @@ impl Status {
     pub fn snapshot(&self) -> u8 {
         self.bits
     }
+
+    /// Restores the register to a value returned by `snapshot`.
+    pub fn restore(&mut self, bits: u8) {
+        self.bits = bits;
+    }
 }
@@ -1,7 +1,28 @@
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Tile {
-    pub lo: u8,
-    pub hi: u8,
+    /// Decoded pattern row, see [`decode_row`].
+    pub pixels: u64,
     pub attr: u8,
     pub id: u8,
 }
+
+/// Decodes a row of a 2bpp pattern from its low and high bitplanes into one
+/// byte per pixel, with the leftmost pixel in the most significant byte.
+pub fn decode_row(lo: u8, hi: u8) -> u64 {
+    (0..8).fold(0, |row, i| {
+        let pixel = ((lo >> (7 - i)) & 1) | (((hi >> (7 - i)) & 1) << 1);
+        (row << 8) | pixel as u64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_row() {
+        assert_eq!(decode_row(0b0110_0110, 0b0000_0010), 0x0001_0100_0001_0300);
+        assert_eq!(decode_row(0xFF, 0xFF), 0x0303_0303_0303_0303);
+        assert_eq!(decode_row(0, 0), 0);
+    }
+}
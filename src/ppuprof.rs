@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// Stages of the PPU timed by the profiler.
+#[derive(Clone, Copy)]
+pub enum Stage {
+    /// Background fetches and scrolling.
+    Background,
+
+    /// Sprite evaluation and fetches.
+    Sprites,
+
+    /// Choosing between the background and sprite pixel and its colour.
+    Pixel,
+
+    /// Handing the finished frame to the frontend.
+    Callback,
+}
+
+const STAGES: [(Stage, &str); 4] = [
+    (Stage::Background, "background"),
+    (Stage::Sprites, "sprites"),
+    (Stage::Pixel, "pixel mux"),
+    (Stage::Callback, "frame callback"),
+];
+
+/// Time spent in each stage, indexed by [`Stage`].
+#[derive(Clone, Copy, Default)]
+struct Profile {
+    /// Time spent in the frame being rendered.
+    frame: [Duration; 4],
+
+    /// Time spent in all completed frames, and in the slowest frame.
+    total: [Duration; 4],
+    worst: [Duration; 4],
+    frames: u64,
+}
+
+impl Profile {
+    const fn new() -> Self {
+        Profile {
+            frame: [Duration::ZERO; 4],
+            total: [Duration::ZERO; 4],
+            worst: [Duration::ZERO; 4],
+            frames: 0,
+        }
+    }
+
+    fn end_frame(&mut self) {
+        for i in 0..STAGES.len() {
+            self.total[i] += self.frame[i];
+            self.worst[i] = self.worst[i].max(self.frame[i]);
+        }
+        self.frame = [Duration::ZERO; 4];
+        self.frames += 1;
+    }
+}
+
+thread_local! {
+    static PROFILE: RefCell<Profile> = const { RefCell::new(Profile::new()) };
+}
+
+/// Times a stage until it is dropped.
+pub struct Timer {
+    stage: Stage,
+    start: Instant,
+}
+
+impl Timer {
+    /// Starts timing the given stage.
+    pub fn start(stage: Stage) -> Self {
+        Timer {
+            stage,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        PROFILE.with(|p| p.borrow_mut().frame[self.stage as usize] += elapsed);
+    }
+}
+
+/// Adds the time spent rendering the current frame to the totals.
+pub fn end_frame() {
+    PROFILE.with(|p| p.borrow_mut().end_frame());
+}
+
+/// Clears the timings, e.g. when a new game is loaded.
+pub fn reset() {
+    PROFILE.with(|p| *p.borrow_mut() = Profile::new());
+}
+
+/// Returns a report of the average and worst time per frame spent in each
+/// stage.
+pub fn report() -> String {
+    PROFILE.with(|p| format_report(&p.borrow()))
+}
+
+fn format_report(profile: &Profile) -> String {
+    let mut out = format!("{} frames profiled\n", profile.frames);
+    if profile.frames == 0 {
+        return out;
+    }
+
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let _ = writeln!(
+        out,
+        "\n{:<16} {:>10} {:>10}",
+        "PPU stage", "avg ms", "worst ms"
+    );
+    for (stage, name) in STAGES {
+        let i = stage as usize;
+        let _ = writeln!(
+            out,
+            "{:<16} {:>10.3} {:>10.3}",
+            name,
+            ms(profile.total[i]) / profile.frames as f64,
+            ms(profile.worst[i])
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut profile = Profile::new();
+        profile.frame[Stage::Pixel as usize] = Duration::from_millis(3);
+        profile.end_frame();
+        profile.frame[Stage::Pixel as usize] = Duration::from_millis(1);
+        profile.end_frame();
+
+        let report = format_report(&profile);
+        assert!(report.starts_with("2 frames profiled\n"));
+
+        let pixel = report.lines().find(|l| l.starts_with("pixel mux")).unwrap();
+        assert_eq!(
+            pixel.split_whitespace().collect::<Vec<_>>()[2..],
+            ["2.000", "3.000"]
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::ppu::FrameStatus;
+
+/// What is shown on display refreshes between emulated frames, when the
+/// display refreshes faster than the console.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum FramePolicy {
+    /// Show the latest frame again.
+    Repeat,
+
+    /// Show the latest frame once, then black until the next one. Reduces
+    /// motion blur on sample-and-hold displays at the cost of brightness.
+    Black,
+
+    /// Show an even blend of the previous and latest frames when a new frame
+    /// arrives, smoothing motion between them.
+    Blend,
+}
+
+/// What to show on a single display refresh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Show {
+    Frame,
+    Black,
+    Blend,
+}
+
+/// Difference in Hz below which a display is taken to refresh at the
+/// console's frame rate. Displays report whole hertz, so a 60.0988 Hz game
+/// on a display reporting 59 or 60 Hz is still paced a frame per refresh.
+const MATCHED_RATE: f64 = 1.5;
+
+/// Paces the emulation against the display refresh rate, deciding how many
+/// frames to emulate and what to show on each refresh.
+pub struct Presenter {
+    policy: FramePolicy,
+    refresh_rate: Option<f64>,
+
+    /// Fraction of an emulated frame owed from earlier refreshes.
+    owed: f64,
+}
+
+impl Presenter {
+    /// Returns a presenter for a display with the given refresh rate, or one
+    /// which refreshes once per emulated frame if none is given.
+    pub fn new(policy: FramePolicy, refresh_rate: Option<f64>) -> Self {
+        Presenter {
+            policy,
+            refresh_rate,
+            owed: 0.0,
+        }
+    }
+
+    /// Returns the time between display refreshes. A display refreshing at
+    /// about the console's frame rate is treated as refreshing at exactly
+    /// that rate, so a frame is emulated per refresh.
+    pub fn refresh_time(&self, frame_rate: f64) -> f64 {
+        match self.refresh_rate {
+            Some(rate) if (rate - frame_rate).abs() > MATCHED_RATE => 1.0 / rate,
+            _ => 1.0 / frame_rate,
+        }
+    }
+
+    /// Advances by one display refresh, returning the number of frames to
+    /// emulate before it.
+    pub fn tick(&mut self, frame_rate: f64) -> u32 {
+        self.owed += frame_rate * self.refresh_time(frame_rate);
+
+        // Allow for rounding, so equal rates always emulate one frame.
+        let due = (self.owed + 1e-9).floor();
+        self.owed = (self.owed - due).max(0.0);
+        due as u32
+    }
+
+    /// Returns what to show on a refresh, given whether a new frame was
+    /// emulated for it.
+    pub fn show(&self, new_frame: bool) -> Show {
+        match (self.policy, new_frame) {
+            (FramePolicy::Black, false) => Show::Black,
+            (FramePolicy::Blend, true) => Show::Blend,
+            _ => Show::Frame,
+        }
+    }
+}
+
+/// Triple-buffered queue of rendered frames, between the PPU's render
+/// callback and the display.
+///
+/// The callback must never wait for the display. Each frame is copied into
+/// the spare buffer, which then becomes the latest, so the latest and
+/// previous frames are always ready to present and no buffer is reallocated.
+/// While the consumer is busy, e.g. emulating frames which will not be
+/// shown, frames are dropped without being copied.
+///
+/// The picture can also be held back a number of frames, for sound systems
+/// which lag behind the display, with one more buffer per frame of delay.
+pub struct FrameQueue {
+    buffers: Vec<Vec<u8>>,
+    latest: usize,
+    delay: usize,
+    busy: bool,
+}
+
+impl Default for FrameQueue {
+    fn default() -> Self {
+        FrameQueue {
+            buffers: vec![Vec::new(); 3],
+            latest: 0,
+            delay: 0,
+            busy: false,
+        }
+    }
+}
+
+impl FrameQueue {
+    /// Holds back the frames presented by the given number of frames,
+    /// discarding any taken so far.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.buffers = vec![Vec::new(); delay + 3];
+        self.latest = 0;
+        self.delay = delay;
+    }
+
+    /// Sets whether frames pushed from now on are dropped.
+    pub fn set_busy(&mut self, busy: bool) {
+        self.busy = busy;
+    }
+
+    /// Takes a rendered frame, unless busy.
+    pub fn push(&mut self, frame: &[u8]) -> FrameStatus {
+        if self.busy {
+            return FrameStatus::Dropped;
+        }
+
+        self.latest = (self.latest + 1) % self.buffers.len();
+        let buffer = &mut self.buffers[self.latest];
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+        FrameStatus::Accepted
+    }
+
+    /// Returns the latest frame, empty if none has been taken.
+    pub fn latest(&self) -> &[u8] {
+        self.behind(self.delay)
+    }
+
+    /// Returns the frame taken most recently, ignoring any delay, so it can
+    /// be drawn over before it is shown.
+    pub fn newest_mut(&mut self) -> &mut [u8] {
+        &mut self.buffers[self.latest]
+    }
+
+    /// Returns the frame taken before the latest, empty if there is none.
+    pub fn previous(&self) -> &[u8] {
+        self.behind(self.delay + 1)
+    }
+
+    /// Returns the frame taken the given number of frames before the newest.
+    fn behind(&self, frames: usize) -> &[u8] {
+        let len = self.buffers.len();
+        &self.buffers[(self.latest + len - frames) % len]
+    }
+}
+
+/// Number of refreshes a present may take before the display is considered
+/// stalled.
+const STALL_REFRESHES: u32 = 4;
+
+/// How long presents are skipped for after one stalls, before trying again.
+const STALL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Decides whether to present each refresh, so a display which can't take
+/// frames never holds up emulation or audio.
+///
+/// Presents are skipped while the window is minimised or hidden, when some
+/// platforms block them until it is shown again. A compositor may also block
+/// presents to an occluded window without saying so, so a present which
+/// stalls for several refreshes skips presenting for a while before trying
+/// again. Showing the window resumes presenting straight away.
+pub struct PresentGate {
+    visible: bool,
+    stalled_until: Option<Instant>,
+}
+
+impl PresentGate {
+    /// Returns a gate for a visible window.
+    pub fn new() -> Self {
+        PresentGate {
+            visible: true,
+            stalled_until: None,
+        }
+    }
+
+    /// Records whether the window is visible, i.e. not minimised or hidden.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        if visible {
+            self.stalled_until = None;
+        }
+    }
+
+    /// Returns true if a frame should be presented now.
+    pub fn open(&self, now: Instant) -> bool {
+        self.visible && self.stalled_until.is_none_or(|until| now >= until)
+    }
+
+    /// Records how long a present took, given the time of a refresh, backing
+    /// off if it stalled. Returns true if it did.
+    pub fn presented(&mut self, now: Instant, took: Duration, refresh: Duration) -> bool {
+        let stalled = took > refresh * STALL_REFRESHES;
+        self.stalled_until = stalled.then(|| now + STALL_BACKOFF);
+        stalled
+    }
+}
+
+/// Blends two RGB24 frames evenly into dst.
+pub fn blend(a: &[u8], b: &[u8], dst: &mut Vec<u8>) {
+    dst.clear();
+    dst.extend(
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| ((*a as u16 + *b as u16) / 2) as u8),
+    );
+}
+
+/// Writes the RGB24 frame into dst darkened and tinted red, shown in place of
+/// the game once emulation has stopped on an error.
+pub fn error_screen(frame: &[u8], dst: &mut Vec<u8>) {
+    dst.clear();
+    dst.extend(frame.chunks_exact(3).flat_map(|rgb| {
+        let luma = (rgb[0] as u16 + rgb[1] as u16 + rgb[2] as u16) / 3;
+        [(64 + luma / 2) as u8, (luma / 4) as u8, (luma / 4) as u8]
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_matching_rates() {
+        let mut presenter = Presenter::new(FramePolicy::Repeat, None);
+        assert!((0..100).all(|_| presenter.tick(60.0988) == 1));
+    }
+
+    #[test]
+    fn test_tick_high_refresh() {
+        // At 144Hz, 60 frames are emulated over 144 refreshes.
+        let mut presenter = Presenter::new(FramePolicy::Repeat, Some(144.0));
+        let ticks: Vec<u32> = (0..144).map(|_| presenter.tick(60.0)).collect();
+
+        assert_eq!(ticks.iter().sum::<u32>(), 60);
+        assert!(ticks.iter().all(|t| *t <= 1));
+    }
+
+    #[test]
+    fn test_tick_pal() {
+        // A 50Hz game on a 60Hz display repeats every sixth frame.
+        let mut presenter = Presenter::new(FramePolicy::Repeat, Some(60.0));
+        let ticks: Vec<u32> = (0..600).map(|_| presenter.tick(50.007)).collect();
+        assert_eq!(ticks.iter().sum::<u32>(), 500);
+        assert!(ticks.iter().all(|t| *t <= 1));
+
+        // Displays reporting about the console's rate emulate a frame per
+        // refresh.
+        let mut presenter = Presenter::new(FramePolicy::Repeat, Some(59.0));
+        assert!((0..100).all(|_| presenter.tick(60.0988) == 1));
+    }
+
+    #[test]
+    fn test_present_gate() {
+        let refresh = Duration::from_millis(16);
+        let now = Instant::now();
+        let mut gate = PresentGate::new();
+        assert!(gate.open(now));
+
+        gate.set_visible(false);
+        assert!(!gate.open(now));
+        gate.set_visible(true);
+        assert!(gate.open(now));
+
+        // A stalled present backs off, then tries again.
+        assert!(!gate.presented(now, refresh, refresh));
+        assert!(gate.presented(now, Duration::from_millis(200), refresh));
+        assert!(!gate.open(now + Duration::from_millis(500)));
+        assert!(gate.open(now + STALL_BACKOFF));
+
+        // Showing the window ends the back off early.
+        gate.set_visible(true);
+        assert!(gate.open(now));
+    }
+
+    #[test]
+    fn test_show() {
+        let black = Presenter::new(FramePolicy::Black, Some(120.0));
+        assert_eq!(black.show(true), Show::Frame);
+        assert_eq!(black.show(false), Show::Black);
+
+        let blend = Presenter::new(FramePolicy::Blend, Some(120.0));
+        assert_eq!(blend.show(true), Show::Blend);
+        assert_eq!(blend.show(false), Show::Frame);
+    }
+
+    #[test]
+    fn test_frame_queue() {
+        let mut queue = FrameQueue::default();
+        assert!(queue.latest().is_empty());
+
+        assert_eq!(queue.push(&[1]), FrameStatus::Accepted);
+        assert_eq!(queue.push(&[2]), FrameStatus::Accepted);
+        assert_eq!(queue.push(&[3]), FrameStatus::Accepted);
+        assert_eq!((queue.previous(), queue.latest()), (&[2][..], &[3][..]));
+
+        queue.set_busy(true);
+        assert_eq!(queue.push(&[4]), FrameStatus::Dropped);
+        assert_eq!((queue.previous(), queue.latest()), (&[2][..], &[3][..]));
+    }
+
+    #[test]
+    fn test_frame_queue_delay() {
+        let mut queue = FrameQueue::default();
+        queue.set_delay(2);
+        queue.push(&[1]);
+        queue.push(&[2]);
+        assert!(queue.latest().is_empty());
+
+        queue.push(&[3]);
+        queue.push(&[4]);
+        assert_eq!((queue.previous(), queue.latest()), (&[1][..], &[2][..]));
+
+        // The newest frame can be drawn over before it is shown.
+        queue.newest_mut()[0] = 5;
+        queue.push(&[6]);
+        queue.push(&[7]);
+        assert_eq!(queue.latest(), &[5][..]);
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut dst = Vec::new();
+        blend(&[0, 100, 255], &[255, 50, 255], &mut dst);
+        assert_eq!(dst, vec![127, 75, 255]);
+    }
+
+    #[test]
+    fn test_error_screen() {
+        let mut dst = Vec::new();
+        error_screen(&[0, 0, 0, 255, 255, 255], &mut dst);
+        assert_eq!(dst, vec![64, 0, 0, 191, 63, 63]);
+    }
+}
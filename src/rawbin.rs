@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::bus::{Device, SystemBus};
+use crate::cartridge::Cartridge;
+use crate::cpu::{Cpu, Memory};
+use crate::rom::{Format, Header, CHR_PAGE_SIZE, PRG_PAGE_SIZE};
+use crate::trace::write_trace;
+
+/// Why a raw program stopped running.
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    /// A BRK or jam instruction at the address halted the CPU.
+    Halt(u16),
+
+    /// The program jumped or branched to itself at the address, the usual
+    /// way test programs signal success or failure.
+    Trap(u16),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Halt(addr) => write!(f, "halted at ${:04X}", addr),
+            Stop::Trap(addr) => write!(f, "trapped at ${:04X}", addr),
+        }
+    }
+}
+
+/// Returns the iNES image of an empty NROM board. The bus always has a
+/// cartridge slot, but it is mapped out in favour of flat RAM.
+fn empty_cartridge() -> Vec<u8> {
+    let mut rom = Header::new(0, 1, 1).to_bytes(Format::Ines).to_vec();
+    rom.resize(rom.len() + PRG_PAGE_SIZE + CHR_PAGE_SIZE, 0);
+    rom
+}
+
+/// Loads a flat 6502 binary, with no iNES header, into 64 KB of RAM at the
+/// given address and runs it from the entry point, writing a trace line for
+/// each instruction.
+///
+/// The RAM covers the whole address space, so the PPU, APU and I/O registers
+/// are not reachable. The program runs until it halts the CPU or traps in a
+/// jump or branch to itself.
+pub fn run(
+    program: &[u8],
+    load_addr: u16,
+    entry: u16,
+    out: &mut impl Write,
+) -> Result<Stop, String> {
+    if load_addr as usize + program.len() > 0x10000 {
+        return Err(format!(
+            "{} byte program doesn't fit in memory at ${:04X}",
+            program.len(),
+            load_addr
+        ));
+    }
+
+    let cart = Cartridge::new(&empty_cartridge())?;
+    let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+    bus.map(0x0000, 0xFFFF, Device::FlatRam);
+    for (i, byte) in program.iter().enumerate() {
+        bus.mem_write_byte(load_addr + i as u16, *byte);
+    }
+
+    let mut cpu = Cpu::new(bus);
+    cpu.set_halt_on_brk(true);
+    cpu.pc = entry;
+
+    let mut line = String::new();
+    loop {
+        let pc = cpu.pc;
+        line.clear();
+        write_trace(&mut cpu, &mut line);
+        writeln!(out, "{}", line).map_err(|e| e.to_string())?;
+
+        if cpu.clock() {
+            return Ok(Stop::Halt(pc));
+        }
+        if cpu.pc == pc {
+            return Ok(Stop::Trap(pc));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        // LDX #$02 / DEX / BNE $0302 / STX $2000 / JMP $0308
+        let program = [
+            0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0x8E, 0x00, 0x20, 0x4C, 0x08, 0x03,
+        ];
+
+        let mut out = Vec::new();
+        let stop = run(&program, 0x0300, 0x0300, &mut out).unwrap();
+        assert_eq!(stop, Stop::Trap(0x0308));
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("0300  A2 02     LDX #$02"));
+
+        // The store lands in RAM rather than the PPU.
+        assert!(lines[6].starts_with("0308  4C 08 03  JMP $0308"));
+        assert!(out.contains("STX $2000 = 00"));
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let mut out = Vec::new();
+        assert!(run(&[0xEA; 0x20], 0xFFF0, 0xFFF0, &mut out).is_err());
+        assert_eq!(
+            run(&[0xEA, 0x00], 0x0000, 0x0000, &mut out),
+            Ok(Stop::Halt(1))
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+
+/// Represents the TV system a game was made for, which determines the CPU,
+/// PPU and APU timing.
+///
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+
+    /// The Dendy and other PAL famiclones, which pair the PAL frame with the
+    /// NTSC clock ratio and APU, so most NTSC games run on them unchanged.
+    Dendy,
+}
+
+impl Region {
+    /// Returns the CPU clock rate in Hz as a fraction. The CPU clock divides
+    /// the master clock, 236.25 / 11 MHz on NTSC and 26.6017125 MHz on PAL
+    /// and Dendy, by 12, 16 and 15 respectively.
+    pub fn cpu_clock_fraction(&self) -> (u64, u64) {
+        match self {
+            Region::Ntsc => (19687500, 11),
+            Region::Pal => (53203425, 32),
+            Region::Dendy => (53203425, 30),
+        }
+    }
+
+    /// Returns the number of frames rendered per second.
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Returns the ratio of PPU dots to CPU cycles as a fraction. The NTSC and
+    /// Dendy PPUs run exactly three times faster than the CPU, the PAL PPU
+    /// 3.2 times.
+    pub fn ppu_clock_ratio(&self) -> (u8, u8) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    /// Returns the number of the last scanline in a frame, before wrapping to
+    /// the pre-render scanline.
+    pub fn last_scanline(&self) -> i32 {
+        match self {
+            Region::Ntsc => 260,
+            Region::Pal | Region::Dendy => 310,
+        }
+    }
+
+    /// Returns the scanline vblank begins on. The Dendy has 50 post-render
+    /// scanlines before it rather than after, so NTSC games get the same
+    /// vblank length.
+    pub fn vblank_scanline(&self) -> i32 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Returns true if the APU uses the PAL noise and DMC periods and frame
+    /// sequencer timing, rather than the NTSC ones.
+    pub fn pal_apu(&self) -> bool {
+        *self == Region::Pal
+    }
+
+    /// Returns true if the first dot of odd frames is skipped when rendering
+    /// is enabled.
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+
+/// Keeps a ring of recent save states so play can be stepped backwards.
+///
+/// A snapshot is taken every given number of frames. Only the newest is kept
+/// in full; each older one is stored as the difference from the snapshot
+/// after it, which is small as most of the machine, such as the ROM banks
+/// and most of RAM, doesn't change between frames. Rewinding walks the
+/// differences back from the newest snapshot, and once the ring is full the
+/// oldest is dropped.
+pub struct Rewind {
+    interval: u32,
+    frames: u32,
+    capacity: usize,
+
+    /// The newest snapshot, if there is one left to rewind to.
+    latest: Vec<u8>,
+    has_latest: bool,
+
+    /// Differences from each snapshot back to the one before it, oldest
+    /// first.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Returns an empty ring taking a snapshot every given number of frames
+    /// and holding up to the given number of snapshots.
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            frames: 0,
+            capacity: capacity.max(1),
+            latest: Vec::new(),
+            has_latest: false,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Counts a frame played forwards, returning true if a snapshot is due.
+    pub fn tick(&mut self) -> bool {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return false;
+        }
+        self.frames = 0;
+        true
+    }
+
+    /// Adds a snapshot of the machine, as written by [`crate::state::save`].
+    pub fn push(&mut self, state: &[u8]) {
+        if self.has_latest && self.latest.len() == state.len() {
+            // Reuse the buffer of the oldest difference once the ring is full.
+            let mut delta = match self.deltas.len() + 1 >= self.capacity {
+                true => self.deltas.pop_front().unwrap_or_default(),
+                false => Vec::new(),
+            };
+            encode(state, &self.latest, &mut delta);
+            if self.capacity > 1 {
+                self.deltas.push_back(delta);
+            }
+        } else {
+            // States of a different size, e.g. from another ROM, can't be
+            // compared, so the history starts over.
+            self.deltas.clear();
+        }
+
+        self.latest.clear();
+        self.latest.extend_from_slice(state);
+        self.has_latest = true;
+    }
+
+    /// Takes the newest snapshot off the ring into `state`, returning false
+    /// if there are none left.
+    pub fn pop(&mut self, state: &mut Vec<u8>) -> bool {
+        if !self.has_latest {
+            return false;
+        }
+
+        state.clear();
+        state.extend_from_slice(&self.latest);
+        match self.deltas.pop_back() {
+            Some(delta) => apply(&delta, &mut self.latest),
+            None => self.has_latest = false,
+        }
+        self.frames = 0;
+        true
+    }
+
+    /// Drops every snapshot, e.g. when the machine is replaced.
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.has_latest = false;
+        self.frames = 0;
+    }
+
+    /// Returns the number of snapshots held.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        match self.has_latest {
+            true => self.deltas.len() + 1,
+            false => 0,
+        }
+    }
+}
+
+/// Encodes the difference from `new` to the equal length `old` as a list of
+/// runs, each the number of unchanged bytes to skip, then the number of
+/// changed bytes followed by them XORed with their new value.
+fn encode(new: &[u8], old: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+
+    let mut i = 0;
+    while i < new.len() {
+        let start = i;
+        while i < new.len() && new[i] == old[i] {
+            i += 1;
+        }
+        let skip = i - start;
+        if i == new.len() {
+            break;
+        }
+
+        let start = i;
+        while i < new.len() && new[i] != old[i] {
+            i += 1;
+        }
+        write_varint(out, skip);
+        write_varint(out, i - start);
+        out.extend(new[start..i].iter().zip(&old[start..i]).map(|(n, o)| n ^ o));
+    }
+}
+
+/// Applies a difference written by [`encode`] to `state`, turning the newer
+/// snapshot back into the older one.
+fn apply(delta: &[u8], state: &mut [u8]) {
+    let mut r = delta;
+    let mut i = 0;
+    while !r.is_empty() {
+        i += read_varint(&mut r);
+        let len = read_varint(&mut r);
+        for (s, d) in state[i..i + len].iter_mut().zip(&r[..len]) {
+            *s ^= d;
+        }
+        i += len;
+        r = &r[len..];
+    }
+}
+
+/// Writes a number as a LEB128 varint, 7 bits per byte.
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Reads a LEB128 varint written by [`write_varint`].
+fn read_varint(r: &mut &[u8]) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    while let Some((&b, rest)) = r.split_first() {
+        *r = rest;
+        n |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_apply() {
+        let old: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut new = old.clone();
+        new[0] = 7;
+        new[500..700].fill(0xAA);
+        new[999] = 1;
+
+        let mut delta = Vec::new();
+        encode(&new, &old, &mut delta);
+        assert!(delta.len() < 220);
+
+        apply(&delta, &mut new);
+        assert_eq!(new, old);
+
+        // Identical snapshots differ by nothing.
+        encode(&old, &old, &mut delta);
+        assert!(delta.is_empty());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut rewind = Rewind::new(1, 3);
+        for i in 1..=4 {
+            rewind.push(&[i; 16]);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        // Snapshots come back newest first, and the oldest was dropped.
+        let mut state = Vec::new();
+        for i in [4, 3, 2] {
+            assert!(rewind.pop(&mut state));
+            assert_eq!(state, [i; 16]);
+        }
+        assert!(!rewind.pop(&mut state));
+
+        // History starts over when the state size changes.
+        rewind.push(&[1; 16]);
+        rewind.push(&[2; 8]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut rewind = Rewind::new(3, 10);
+        let due: Vec<bool> = (0..6).map(|_| rewind.tick()).collect();
+        assert_eq!(due, [false, false, true, false, false, true]);
+    }
+}
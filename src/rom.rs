@@ -1,9 +1,26 @@
-use crate::cartridge::Mirroring;
+use std::io::{self, Read, Write};
+
+use crate::{cartridge::Mirroring, region::Region, state::SaveState};
 
 const INES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 pub const PRG_PAGE_SIZE: usize = 16384;
 pub const CHR_PAGE_SIZE: usize = 8192;
 
+/// Layout of a serialised header.
+///
+/// See: https://www.nesdev.org/wiki/NES_2.0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// The original iNES layout, flags 8-10 are the rarely used extensions.
+    Ines,
+
+    /// NES 2.0, which extends the mapper number and ROM sizes and describes
+    /// PRG RAM and the TV system precisely in flags 8-12.
+    Nes2,
+}
+
 /// Represents the iNES header.
 ///
 /// 0-3     Constant $4E $45 $53 $1A (ASCII "NES" followed by MS-DOS end-of-file)
@@ -15,14 +32,25 @@ pub const CHR_PAGE_SIZE: usize = 8192;
 /// 9       Flags 9 – TV system (rarely used extension)
 /// 10      Flags 10 – TV system, PRG-RAM presence (unofficial, rarely used extension)
 /// 11-15   Unused padding (should be filled with zero, but some rippers put their name across bytes 7-15)
+///
+/// The fields are held in a form both iNES and NES 2.0 headers can be read
+/// into and written back out from, see [`Header::from_bytes`] and
+/// [`Header::to_bytes`].
+#[derive(Clone, Debug, PartialEq)]
 pub struct Header {
     /// Size of PRG ROM in 16 KB units
-    prg_size: u8,
+    prg_size: u16,
 
     /// Size of CHR ROM in 8 KB units (value 0 means the board uses CHR RAM)
-    chr_size: u8,
+    chr_size: u16,
+
+    /// Mapper number, up to 255 in iNES headers and 4095 in NES 2.0.
+    mapper: u16,
 
-    /// Flags 6 – Mapper, mirroring, battery, trainer
+    /// NES 2.0 submapper number, distinguishing boards sharing a mapper.
+    submapper: u8,
+
+    /// Low nybble of flags 6 – Mirroring, battery, trainer
     ///
     /// 00110001
     /// ||||||||
@@ -34,7 +62,7 @@ pub struct Header {
     /// ++++----- Lower nybble of mapper number
     flags_6: u8,
 
-    /// Flags 7 – Mapper, VS/Playchoice, NES 2.0
+    /// Low bits of flags 7 – VS/Playchoice
     ///
     /// 76543210
     /// ||||||||
@@ -44,35 +72,43 @@ pub struct Header {
     /// ++++----- Upper nybble of mapper number
     flags_7: u8,
 
-    /// Flags 8 – PRG-RAM size (rarely used extension)
-    ///
-    /// 76543210
-    /// ||||||||
-    /// ++++++++- PRG RAM size
-    flags_8: u8,
+    /// Size of PRG RAM in bytes, including any battery backed RAM.
+    prg_ram_size: usize,
 
-    /// Flags 9 – TV system (rarely used extension)
-    ///
-    /// 76543210
-    /// ||||||||
-    /// |||||||+- TV system (0: NTSC; 1: PAL)
-    /// +++++++-- Reserved, set to zero
-    flags_9: u8,
-
-    /// Flags 10 – TV system, PRG-RAM presence (unofficial, rarely used extension)
-    ///
-    /// 76543210
-    ///   ||  ||
-    ///   ||  ++- TV system (0: NTSC; 2: PAL; 1/3: dual compatible)
-    ///   |+----- PRG RAM ($6000-$7FFF) (0: present; 1: not present)
-    ///   +------ 0: Board has no bus conflicts; 1: Board has bus conflict
-    flags_10: u8,
+    /// TV system the ROM was made for.
+    region: Region,
 }
 
 impl Header {
+    /// Returns a header for a ROM with the given mapper and PRG and CHR ROM
+    /// sizes, in 16 KB and 8 KB units. The ROM has horizontal mirroring, 8 KB
+    /// of PRG RAM and is for NTSC consoles, which the setters below change.
+    pub fn new(mapper: u16, prg_size: u16, chr_size: u16) -> Header {
+        Header {
+            prg_size,
+            chr_size,
+            mapper,
+            submapper: 0,
+            flags_6: 0,
+            flags_7: 0,
+            prg_ram_size: 0x2000,
+            region: Region::Ntsc,
+        }
+    }
+
     /// Returns the mapper number.
-    pub fn mapper(&self) -> u8 {
-        (self.flags_7 & 0xF0) | (self.flags_6 >> 4)
+    pub fn mapper(&self) -> u16 {
+        self.mapper
+    }
+
+    /// Returns the NES 2.0 submapper number, 0 for iNES headers.
+    pub fn submapper(&self) -> u8 {
+        self.submapper
+    }
+
+    /// Sets the NES 2.0 submapper number, which iNES headers don't record.
+    pub fn set_submapper(&mut self, submapper: u8) {
+        self.submapper = submapper & 0xF;
     }
 
     /// Returns true if the ROM provides four-screen VRAM.
@@ -90,6 +126,38 @@ impl Header {
         }
     }
 
+    /// Sets the hard-wired mirroring. Headers can't describe single screen
+    /// mirroring, which is always under mapper control, so it is recorded as
+    /// horizontal.
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.flags_6 &= !0x9;
+        self.flags_6 |= match mirroring {
+            Mirroring::Vertical => 0x1,
+            Mirroring::FourScreen => 0x8,
+            _ => 0x0,
+        };
+    }
+
+    /// Returns the TV system declared by the header, defaulting to NTSC.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Sets the TV system the ROM was made for.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Returns true if the ROM is for the Vs. System arcade cabinet.
+    pub fn vs_system(&self) -> bool {
+        self.flags_7 & 0x1 != 0
+    }
+
+    /// Returns true if the ROM contains PlayChoice-10 hint screen data.
+    fn playchoice(&self) -> bool {
+        self.flags_7 & 0x2 != 0
+    }
+
     /// Returns the size of the PRG ROM in bytes.
     pub fn prg_size(&self) -> usize {
         self.prg_size as usize
@@ -100,27 +168,148 @@ impl Header {
         self.chr_size as usize
     }
 
+    /// Returns the size of the PRG RAM in bytes. A size of 0 in an iNES
+    /// header means 8 KB for compatibility, unless flags 10 declares there is
+    /// none.
+    pub fn prg_ram_size(&self) -> usize {
+        self.prg_ram_size
+    }
+
+    /// Sets the size of the PRG RAM in bytes. iNES headers record it in 8 KB
+    /// units and NES 2.0 headers in powers of two, so other sizes are
+    /// rounded up when written.
+    pub fn set_prg_ram_size(&mut self, size: usize) {
+        self.prg_ram_size = size;
+    }
+
+    /// Returns true if the cartridge has battery backed memory.
+    pub fn battery(&self) -> bool {
+        self.flags_6 & 0x2 != 0
+    }
+
+    /// Sets whether the cartridge has battery backed memory.
+    pub fn set_battery(&mut self, battery: bool) {
+        self.flags_6 = (self.flags_6 & !0x2) | (battery as u8) << 1;
+    }
+
     /// Returns true if the ROM contains a trainer.
     pub fn skip_trainer(&self) -> bool {
         self.flags_6 & 0x4 != 0
     }
 
-    /// Returns the iNES version.
-    fn ines_version(&self) -> u8 {
-        (self.flags_7 >> 2) & 0x3
+    /// Sets whether a trainer is stored before the PRG ROM.
+    pub fn set_trainer(&mut self, trainer: bool) {
+        self.flags_6 = (self.flags_6 & !0x4) | (trainer as u8) << 2;
     }
 
-    /// Creates a new header with default values.
-    fn from_bytes(bytes: &[u8]) -> Header {
-        Header {
-            prg_size: bytes[4],
-            chr_size: bytes[5],
-            flags_6: bytes[6],
-            flags_7: bytes[7],
-            flags_8: bytes[8],
-            flags_9: bytes[9],
-            flags_10: bytes[10],
+    /// Parses a 16 byte header, in either iNES or NES 2.0 format. The tag
+    /// isn't checked.
+    pub fn from_bytes(bytes: &[u8]) -> Header {
+        let mut header = Header {
+            prg_size: bytes[4] as u16,
+            chr_size: bytes[5] as u16,
+            mapper: ((bytes[7] & 0xF0) | (bytes[6] >> 4)) as u16,
+            submapper: 0,
+            flags_6: bytes[6] & 0x0F,
+            flags_7: bytes[7] & 0x03,
+            prg_ram_size: 0,
+            region: Region::Ntsc,
+        };
+
+        if ines_version(bytes) == 2 {
+            header.mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+            header.submapper = bytes[8] >> 4;
+            header.prg_size |= ((bytes[9] & 0x0F) as u16) << 8;
+            header.chr_size |= ((bytes[9] >> 4) as u16) << 8;
+
+            // Volatile and battery backed RAM sizes are shift counts.
+            let ram = |shift: u8| match shift {
+                0 => 0,
+                _ => 64 << shift,
+            };
+            header.prg_ram_size = ram(bytes[10] & 0x0F) + ram(bytes[10] >> 4);
+
+            header.region = match bytes[12] & 0x3 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc,
+            };
+        } else {
+            if bytes[10] & 0x10 == 0 {
+                header.prg_ram_size = bytes[8].max(1) as usize * 0x2000;
+            }
+
+            if bytes[9] & 0x1 != 0 || bytes[10] & 0x3 == 2 {
+                header.region = Region::Pal;
+            }
         }
+
+        header
+    }
+
+    /// Serialises the header in the given format. Values an iNES header
+    /// can't hold, such as mapper numbers above 255, are truncated.
+    pub fn to_bytes(&self, format: Format) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(&INES_TAG);
+        bytes[4] = self.prg_size as u8;
+        bytes[5] = self.chr_size as u8;
+        bytes[6] = ((self.mapper as u8) << 4) | self.flags_6;
+        bytes[7] = (self.mapper as u8 & 0xF0) | self.flags_7;
+
+        match format {
+            Format::Ines => {
+                match self.prg_ram_size {
+                    0 => bytes[10] |= 0x10,
+                    size => bytes[8] = size.div_ceil(0x2000).min(0xFF) as u8,
+                }
+                // iNES has no flag for the Dendy, the closest is PAL.
+                if self.region != Region::Ntsc {
+                    bytes[9] = 0x1;
+                    bytes[10] |= 0x2;
+                }
+            }
+            Format::Nes2 => {
+                bytes[7] |= 0x08;
+                bytes[8] = (self.submapper << 4) | ((self.mapper >> 8) as u8 & 0x0F);
+                bytes[9] =
+                    (((self.chr_size >> 8) as u8) << 4) | ((self.prg_size >> 8) as u8 & 0x0F);
+
+                // The RAM is either all battery backed or all volatile.
+                let shift = ram_shift(self.prg_ram_size);
+                bytes[10] = match self.battery() {
+                    true => shift << 4,
+                    false => shift,
+                };
+
+                // CHR RAM is always 8 KB.
+                if self.chr_size == 0 {
+                    bytes[11] = ram_shift(CHR_PAGE_SIZE);
+                }
+                bytes[12] = match self.region {
+                    Region::Ntsc => 0,
+                    Region::Pal => 1,
+                    Region::Dendy => 3,
+                };
+            }
+        }
+
+        bytes
+    }
+}
+
+/// Returns the iNES version, 2 for NES 2.0 headers.
+fn ines_version(bytes: &[u8]) -> u8 {
+    (bytes[7] >> 2) & 0x3
+}
+
+/// Returns the NES 2.0 shift count for a RAM size, the RAM holding 64 bytes
+/// shifted left by the count. Sizes which aren't a power of two are rounded
+/// up.
+fn ram_shift(size: usize) -> u8 {
+    match size {
+        0 => 0,
+        _ => (size.max(128).next_power_of_two().trailing_zeros() - 6).min(15) as u8,
     }
 }
 
@@ -136,18 +325,26 @@ pub struct Rom {
 
     /// Contains pattern tables and graphics.
     pub chr: Vec<u8>,
+
+    /// Number of bytes following the data described by the header.
+    excess: usize,
 }
 
 impl Rom {
+    /// Parses a ROM from the contents of an iNES file. Files which are
+    /// truncated or declare no PRG ROM are rejected.
     pub fn new(raw: &[u8]) -> Result<Rom, String> {
-        if raw[0..4] != INES_TAG {
+        if raw.len() < HEADER_SIZE || raw[0..4] != INES_TAG {
             return Err("File is not in iNES file format".to_string());
         }
 
         let header = Header::from_bytes(raw);
-        if header.ines_version() != 0 {
+        if ines_version(raw) != 0 {
             return Err("NES2.0 format is not supported".to_string());
         }
+        if header.prg_size() == 0 {
+            return Err("ROM has no PRG ROM".to_string());
+        }
 
         // PRG is sized in 16kb units.
         let prg_size = header.prg_size() * PRG_PAGE_SIZE;
@@ -155,17 +352,89 @@ impl Rom {
         // CHR is sized in 8kb units.
         let chr_size = header.chr_size() * CHR_PAGE_SIZE;
 
-        let prg_start = 16 + if header.skip_trainer() { 512 } else { 0 };
+        let prg_start = HEADER_SIZE
+            + if header.skip_trainer() {
+                TRAINER_SIZE
+            } else {
+                0
+            };
         let chr_start = prg_start + prg_size;
 
-        let prg = raw[prg_start..(prg_start + prg_size)].to_vec();
+        if raw.len() < prg_start {
+            return Err(format!(
+                "trainer is truncated, expected {} bytes but found {}",
+                TRAINER_SIZE,
+                raw.len() - HEADER_SIZE
+            ));
+        }
+        if raw.len() < chr_start {
+            return Err(format!(
+                "PRG ROM is truncated, expected {} bytes but found {}",
+                prg_size,
+                raw.len() - prg_start
+            ));
+        }
+        if raw.len() < chr_start + chr_size {
+            return Err(format!(
+                "CHR ROM is truncated, expected {} bytes but found {}",
+                chr_size,
+                raw.len() - chr_start
+            ));
+        }
+
+        let prg = raw[prg_start..chr_start].to_vec();
         let chr = if header.chr_size() > 0 {
             raw[chr_start..(chr_start + chr_size)].to_vec()
         } else {
             vec![0; CHR_PAGE_SIZE]
         };
 
-        Ok(Rom { header, prg, chr })
+        let hint_size = if header.playchoice() {
+            CHR_PAGE_SIZE
+        } else {
+            0
+        };
+        let excess = raw.len().saturating_sub(chr_start + chr_size + hint_size);
+
+        Ok(Rom {
+            header,
+            prg,
+            chr,
+            excess,
+        })
+    }
+
+    /// Returns the CRC32 of the PRG and CHR ROM data.
+    pub fn checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.prg);
+        if self.header.chr_size() > 0 {
+            hasher.update(&self.chr);
+        }
+        hasher.finalize()
+    }
+
+    /// Returns the number of bytes following the data described by the
+    /// header. Trailing data usually indicates an overdump.
+    pub fn excess(&self) -> usize {
+        self.excess
+    }
+}
+
+/// Only CHR RAM is saved, the rest of the ROM is read only.
+impl SaveState for Rom {
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()> {
+        if self.header.chr_size() == 0 {
+            w.write_all(&self.chr)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()> {
+        if self.header.chr_size() == 0 {
+            r.read_exact(&mut self.chr)?;
+        }
+        Ok(())
     }
 }
 
@@ -306,4 +575,143 @@ pub mod tests {
             Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
         }
     }
+
+    #[test]
+    fn test_vs_system() {
+        let mut header = [0; 16];
+        assert!(!Header::from_bytes(&header).vs_system());
+
+        header[7] = 0x1;
+        assert!(Header::from_bytes(&header).vs_system());
+    }
+
+    #[test]
+    fn test_region() {
+        let mut header = [0; 16];
+        assert_eq!(Header::from_bytes(&header).region(), Region::Ntsc);
+
+        header[9] = 0x1;
+        assert_eq!(Header::from_bytes(&header).region(), Region::Pal);
+
+        header[9] = 0x0;
+        header[10] = 0x2;
+        assert_eq!(Header::from_bytes(&header).region(), Region::Pal);
+
+        // Only NES 2.0 headers can select the Dendy.
+        let mut header = Header::new(0, 1, 1);
+        header.set_region(Region::Dendy);
+        let bytes = header.to_bytes(Format::Nes2);
+        assert_eq!(bytes[12], 3);
+        assert_eq!(Header::from_bytes(&bytes).region(), Region::Dendy);
+        let bytes = header.to_bytes(Format::Ines);
+        assert_eq!(Header::from_bytes(&bytes).region(), Region::Pal);
+    }
+
+    #[test]
+    fn test_prg_ram_size() {
+        let mut header = [0; 16];
+        assert_eq!(Header::from_bytes(&header).prg_ram_size(), 0x2000);
+
+        header[8] = 0x2;
+        assert_eq!(Header::from_bytes(&header).prg_ram_size(), 0x4000);
+
+        header[10] = 0x10;
+        assert_eq!(Header::from_bytes(&header).prg_ram_size(), 0);
+    }
+
+    #[test]
+    fn test_ines_round_trip() {
+        let mut header = Header::new(206, 8, 16);
+        header.set_mirroring(Mirroring::FourScreen);
+        header.set_battery(true);
+        header.set_prg_ram_size(0x4000);
+        header.set_region(Region::Pal);
+
+        let bytes = header.to_bytes(Format::Ines);
+        assert_eq!(bytes[..8], [0x4E, 0x45, 0x53, 0x1A, 8, 16, 0xEA, 0xC0]);
+        assert_eq!(Header::from_bytes(&bytes), header);
+
+        // No PRG RAM is declared with flags 10.
+        header.set_prg_ram_size(0);
+        assert_eq!(Header::from_bytes(&header.to_bytes(Format::Ines)), header);
+    }
+
+    #[test]
+    fn test_nes2_round_trip() {
+        let mut header = Header::new(0x123, 0x240, 0x301);
+        header.set_submapper(5);
+        header.set_mirroring(Mirroring::Vertical);
+        header.set_trainer(true);
+        header.set_prg_ram_size(0x800);
+        header.set_region(Region::Pal);
+
+        let bytes = header.to_bytes(Format::Nes2);
+        assert_eq!(bytes[6..13], [0x35, 0x28, 0x51, 0x32, 0x05, 0x00, 0x01]);
+        assert_eq!(Header::from_bytes(&bytes), header);
+        assert_eq!(Header::from_bytes(&bytes).submapper(), 5);
+
+        // Battery backed RAM is recorded as non-volatile.
+        header.set_battery(true);
+        header.set_prg_ram_size(0x2000);
+        let bytes = header.to_bytes(Format::Nes2);
+        assert_eq!(bytes[10], 0x70);
+        assert_eq!(Header::from_bytes(&bytes), header);
+    }
+
+    #[test]
+    fn test_ram_shift_rounds_up() {
+        assert_eq!(ram_shift(0), 0);
+        assert_eq!(ram_shift(0x2000), 7);
+        assert_eq!(ram_shift(0x2001), 8);
+        assert_eq!(ram_shift(1), 1);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let rom = |header: [u8; 4], len: usize| {
+            let mut raw = INES_TAG.to_vec();
+            raw.extend(header);
+            raw.resize(len, 0);
+            Rom::new(&raw).err()
+        };
+
+        assert_eq!(
+            Rom::new(&INES_TAG).err(),
+            Some(String::from("File is not in iNES file format"))
+        );
+        assert_eq!(
+            rom([0, 1, 0, 0], 16 + CHR_PAGE_SIZE),
+            Some(String::from("ROM has no PRG ROM"))
+        );
+        assert_eq!(
+            rom([1, 1, 0x4, 0], 100),
+            Some(String::from(
+                "trainer is truncated, expected 512 bytes but found 84"
+            ))
+        );
+        assert_eq!(
+            rom([2, 1, 0, 0], 16 + PRG_PAGE_SIZE),
+            Some(String::from(
+                "PRG ROM is truncated, expected 32768 bytes but found 16384"
+            ))
+        );
+        assert_eq!(
+            rom([1, 1, 0, 0], 16 + PRG_PAGE_SIZE + 10),
+            Some(String::from(
+                "CHR ROM is truncated, expected 8192 bytes but found 10"
+            ))
+        );
+        assert_eq!(rom([1, 1, 0, 0], 16 + PRG_PAGE_SIZE + CHR_PAGE_SIZE), None);
+    }
+
+    #[test]
+    fn test_excess() {
+        let rom = test_rom(1, vec![], 1, vec![], None, None, None).unwrap();
+        assert_eq!(rom.excess(), 0);
+
+        let mut raw = INES_TAG.to_vec();
+        raw.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(vec![0; PRG_PAGE_SIZE + 100]);
+        assert_eq!(Rom::new(&raw).unwrap().excess(), 100);
+    }
 }
@@ -0,0 +1,407 @@
+use clap::ValueEnum;
+
+/// Number of bytes used to represent a single RGB24 pixel.
+const BYTES_PER_PIXEL: usize = 3;
+
+/// Represents an integer upscaler that post-processes a rendered frame on the
+/// CPU before it is presented.
+pub trait Scaler {
+    /// Returns the integer factor the scaler multiplies each dimension by.
+    fn factor(&self) -> usize;
+
+    /// Scales the RGB24 frame of the given dimensions into dst, which is
+    /// resized to fit the output.
+    fn scale(&self, src: &[u8], width: usize, height: usize, dst: &mut Vec<u8>);
+}
+
+/// The available scaling filters.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ScaleFilter {
+    /// No post-processing, the frame is uploaded as-is.
+    None,
+
+    /// AdvMAME2x / Scale2x edge-preserving 2x upscaler.
+    Scale2x,
+
+    /// AdvMAME3x / Scale3x edge-preserving 3x upscaler.
+    Scale3x,
+
+    /// hq2x 2x upscaler, which blends edges rather than keeping them sharp.
+    Hq2x,
+}
+
+impl ScaleFilter {
+    /// Returns the scaler for the filter, or None if no scaling is required.
+    pub fn scaler(&self) -> Option<Box<dyn Scaler>> {
+        match self {
+            ScaleFilter::None => None,
+            ScaleFilter::Scale2x => Some(Box::new(Scale2x)),
+            ScaleFilter::Scale3x => Some(Box::new(Scale3x)),
+            ScaleFilter::Hq2x => Some(Box::new(Hq2x)),
+        }
+    }
+
+    /// Returns the integer factor the filter multiplies each dimension by.
+    pub fn factor(&self) -> usize {
+        self.scaler().map_or(1, |s| s.factor())
+    }
+}
+
+/// Represents the Scale2x algorithm, which expands each pixel into a 2x2 block
+/// while avoiding the blurriness of bilinear filtering.
+///
+/// See: https://www.scale2x.it/algorithm
+pub struct Scale2x;
+
+impl Scaler for Scale2x {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&self, src: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+        let out_w = width * 2;
+        dst.resize(out_w * height * 2 * BYTES_PER_PIXEL, 0);
+
+        for y in 0..height {
+            for x in 0..width {
+                //   A
+                // C P B
+                //   D
+                let n = Neighbours::new(src, width, height, x, y);
+                let (p, a, b, c, d) = (n.e, n.b, n.f, n.d, n.h);
+
+                let mut out = [p; 4];
+                if c == a && c != d && a != b {
+                    out[0] = a;
+                }
+                if a == b && a != c && b != d {
+                    out[1] = b;
+                }
+                if d == c && d != b && c != a {
+                    out[2] = c;
+                }
+                if b == d && b != a && d != c {
+                    out[3] = d;
+                }
+
+                for (i, px) in out.iter().enumerate() {
+                    put_pixel(dst, out_w, x * 2 + i % 2, y * 2 + i / 2, *px);
+                }
+            }
+        }
+    }
+}
+
+/// Represents the Scale3x algorithm, which expands each pixel into a 3x3 block
+/// using the same edge detection rules as Scale2x.
+///
+/// See: https://www.scale2x.it/algorithm
+pub struct Scale3x;
+
+impl Scaler for Scale3x {
+    fn factor(&self) -> usize {
+        3
+    }
+
+    fn scale(&self, src: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+        let out_w = width * 3;
+        dst.resize(out_w * height * 3 * BYTES_PER_PIXEL, 0);
+
+        for y in 0..height {
+            for x in 0..width {
+                // A B C
+                // D E F
+                // G H I
+                let Neighbours {
+                    a,
+                    b,
+                    c,
+                    d,
+                    e,
+                    f,
+                    g,
+                    h,
+                    i,
+                } = Neighbours::new(src, width, height, x, y);
+
+                let mut out = [e; 9];
+                if b != h && d != f {
+                    if d == b {
+                        out[0] = d;
+                    }
+                    if (d == b && e != c) || (b == f && e != a) {
+                        out[1] = b;
+                    }
+                    if b == f {
+                        out[2] = f;
+                    }
+                    if (d == b && e != g) || (d == h && e != a) {
+                        out[3] = d;
+                    }
+                    if (b == f && e != i) || (h == f && e != c) {
+                        out[5] = f;
+                    }
+                    if d == h {
+                        out[6] = d;
+                    }
+                    if (d == h && e != i) || (h == f && e != g) {
+                        out[7] = h;
+                    }
+                    if h == f {
+                        out[8] = f;
+                    }
+                }
+
+                for (n, px) in out.iter().enumerate() {
+                    put_pixel(dst, out_w, x * 3 + n % 3, y * 3 + n / 3, *px);
+                }
+            }
+        }
+    }
+}
+
+/// Represents the hq2x algorithm, which expands each pixel into a 2x2 block
+/// blended with the neighbours towards each corner, smoothing edges.
+///
+/// Neighbours are compared with the centre in YUV, so pixels count as alike
+/// when the difference is hard to see. hq2x picks the blend for each corner
+/// from a 256 entry table indexed by which of the 8 neighbours differ. This
+/// uses a reduced set of rules instead, looking only at the two neighbours
+/// beside each corner and the one diagonal to it, so a few patterns are
+/// blended slightly differently from the table.
+///
+/// See: https://en.wikipedia.org/wiki/Hqx
+pub struct Hq2x;
+
+impl Scaler for Hq2x {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&self, src: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+        let out_w = width * 2;
+        dst.resize(out_w * height * 2 * BYTES_PER_PIXEL, 0);
+
+        for y in 0..height {
+            for x in 0..width {
+                // A B C
+                // D E F
+                // G H I
+                let n = Neighbours::new(src, width, height, x, y);
+                let out = [
+                    hq2x_corner(n.e, n.b, n.d, n.a),
+                    hq2x_corner(n.e, n.b, n.f, n.c),
+                    hq2x_corner(n.e, n.h, n.d, n.g),
+                    hq2x_corner(n.e, n.h, n.f, n.i),
+                ];
+
+                for (i, px) in out.iter().enumerate() {
+                    put_pixel(dst, out_w, x * 2 + i % 2, y * 2 + i / 2, *px);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the hq2x output for the corner of the centre pixel between the two
+/// given edge neighbours, with the neighbour diagonal to the corner.
+fn hq2x_corner(centre: Pixel, edge1: Pixel, edge2: Pixel, diagonal: Pixel) -> Pixel {
+    let (e1, e2, dg) = (
+        yuv_differ(centre, edge1),
+        yuv_differ(centre, edge2),
+        yuv_differ(centre, diagonal),
+    );
+
+    match (e1, e2) {
+        // An edge cuts across the corner.
+        (true, true) if !yuv_differ(edge1, edge2) => match dg {
+            true => blend(&[(centre, 2), (edge1, 1), (edge2, 1)]),
+            false => blend(&[(centre, 6), (edge1, 1), (edge2, 1)]),
+        },
+
+        // Three areas meet at the corner, so only the diagonal is blended.
+        (true, true) => blend(&[(centre, 3), (diagonal, 1)]),
+
+        // A straight edge runs past the corner.
+        (true, false) | (false, true) if dg => blend(&[(centre, 3), (diagonal, 1)]),
+        (true, false) => blend(&[(centre, 3), (edge1, 1)]),
+        (false, true) => blend(&[(centre, 3), (edge2, 1)]),
+
+        // Within an area, where blending is barely visible.
+        (false, false) => blend(&[(centre, 2), (edge1, 1), (edge2, 1)]),
+    }
+}
+
+/// Returns true if the pixels differ visibly, using hq2x's thresholds for
+/// the difference in luma and each chroma component.
+fn yuv_differ(a: Pixel, b: Pixel) -> bool {
+    let yuv = |p: Pixel| {
+        let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+        (
+            (299 * r + 587 * g + 114 * b) / 1000,
+            (-169 * r - 331 * g + 500 * b) / 1000,
+            (500 * r - 419 * g - 81 * b) / 1000,
+        )
+    };
+    let ((y1, u1, v1), (y2, u2, v2)) = (yuv(a), yuv(b));
+    (y1 - y2).abs() > 0x30 || (u1 - u2).abs() > 0x07 || (v1 - v2).abs() > 0x06
+}
+
+/// Returns the weighted average of the pixels, whose weights must add up to
+/// a power of two.
+fn blend(pixels: &[(Pixel, u16)]) -> Pixel {
+    let total: u16 = pixels.iter().map(|(_, w)| w).sum();
+    std::array::from_fn(|c| {
+        let sum: u16 = pixels.iter().map(|(p, w)| p[c] as u16 * w).sum();
+        (sum / total) as u8
+    })
+}
+
+type Pixel = [u8; BYTES_PER_PIXEL];
+
+/// The 3x3 neighbourhood of a source pixel, clamped at the frame edges.
+///
+/// A B C
+/// D E F
+/// G H I
+struct Neighbours {
+    a: Pixel,
+    b: Pixel,
+    c: Pixel,
+    d: Pixel,
+    e: Pixel,
+    f: Pixel,
+    g: Pixel,
+    h: Pixel,
+    i: Pixel,
+}
+
+impl Neighbours {
+    /// Returns the neighbourhood of the pixel at the given position.
+    fn new(src: &[u8], width: usize, height: usize, x: usize, y: usize) -> Self {
+        let xl = x.saturating_sub(1);
+        let xr = (x + 1).min(width - 1);
+        let yu = y.saturating_sub(1);
+        let yd = (y + 1).min(height - 1);
+
+        let px = |x: usize, y: usize| get_pixel(src, width, x, y);
+
+        Neighbours {
+            a: px(xl, yu),
+            b: px(x, yu),
+            c: px(xr, yu),
+            d: px(xl, y),
+            e: px(x, y),
+            f: px(xr, y),
+            g: px(xl, yd),
+            h: px(x, yd),
+            i: px(xr, yd),
+        }
+    }
+}
+
+/// Returns the pixel at the given position.
+fn get_pixel(buf: &[u8], width: usize, x: usize, y: usize) -> Pixel {
+    let base = (y * width + x) * BYTES_PER_PIXEL;
+    [buf[base], buf[base + 1], buf[base + 2]]
+}
+
+/// Sets the pixel at the given position.
+fn put_pixel(buf: &mut [u8], width: usize, x: usize, y: usize, px: Pixel) {
+    let base = (y * width + x) * BYTES_PER_PIXEL;
+    buf[base..base + BYTES_PER_PIXEL].copy_from_slice(&px);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Pixel = [0, 0, 0];
+    const WHITE: Pixel = [0xFF, 0xFF, 0xFF];
+
+    /// Returns a frame built from the given rows of pixels.
+    fn frame(rows: &[&[Pixel]]) -> Vec<u8> {
+        rows.iter()
+            .flat_map(|r| r.iter().flatten().copied())
+            .collect()
+    }
+
+    #[test]
+    fn test_scale2x_flat_colour() {
+        let src = frame(&[&[WHITE, WHITE], &[WHITE, WHITE]]);
+        let mut dst = vec![];
+        Scale2x.scale(&src, 2, 2, &mut dst);
+
+        assert_eq!(dst.len(), 4 * 4 * BYTES_PER_PIXEL);
+        assert!(dst.iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn test_scale2x_diagonal_edge() {
+        // W B
+        // B B
+        let src = frame(&[&[WHITE, BLACK], &[BLACK, BLACK]]);
+        let mut dst = vec![];
+        Scale2x.scale(&src, 2, 2, &mut dst);
+
+        // The white pixel's bottom right corner is smoothed into the edge.
+        assert_eq!(get_pixel(&dst, 4, 0, 0), WHITE);
+        assert_eq!(get_pixel(&dst, 4, 1, 1), BLACK);
+        assert_eq!(get_pixel(&dst, 4, 3, 3), BLACK);
+    }
+
+    #[test]
+    fn test_scale3x_flat_colour() {
+        let src = frame(&[&[BLACK, BLACK], &[BLACK, BLACK]]);
+        let mut dst = vec![0xFF; 4];
+        Scale3x.scale(&src, 2, 2, &mut dst);
+
+        assert_eq!(dst.len(), 6 * 6 * BYTES_PER_PIXEL);
+        assert!(dst.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_hq2x_flat_colour() {
+        let src = frame(&[&[WHITE, WHITE], &[WHITE, WHITE]]);
+        let mut dst = vec![];
+        Hq2x.scale(&src, 2, 2, &mut dst);
+
+        assert_eq!(dst.len(), 4 * 4 * BYTES_PER_PIXEL);
+        assert!(dst.iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn test_hq2x_diagonal_edge() {
+        // W B
+        // B B
+        let src = frame(&[&[WHITE, BLACK], &[BLACK, BLACK]]);
+        let mut dst = vec![];
+        Hq2x.scale(&src, 2, 2, &mut dst);
+
+        // The edge across the white pixel's bottom right corner is blended,
+        // while its far corner keeps its colour.
+        assert_eq!(get_pixel(&dst, 4, 0, 0), WHITE);
+        assert_eq!(get_pixel(&dst, 4, 1, 1), [0x7F; 3]);
+
+        // The black pixels beside it are shaded towards it along the edge.
+        assert_eq!(get_pixel(&dst, 4, 2, 0), [0x3F; 3]);
+        assert_eq!(get_pixel(&dst, 4, 3, 3), BLACK);
+    }
+
+    #[test]
+    fn test_yuv_differ() {
+        assert!(yuv_differ(WHITE, BLACK));
+        assert!(!yuv_differ([100, 100, 100], [110, 110, 110]));
+
+        // Colours of equal brightness still differ in chroma.
+        assert!(yuv_differ([0xFF, 0, 0], [0, 0x82, 0x3D]));
+    }
+
+    #[test]
+    fn test_scale_filter_factor() {
+        assert_eq!(ScaleFilter::None.factor(), 1);
+        assert_eq!(ScaleFilter::Scale2x.factor(), 2);
+        assert_eq!(ScaleFilter::Scale3x.factor(), 3);
+        assert_eq!(ScaleFilter::Hq2x.factor(), 2);
+    }
+}
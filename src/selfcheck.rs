@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::movie::{Event, Movie};
+use crate::nes::NesBuilder;
+use crate::rom::{Format, Header};
+
+/// Number of frames the self-check runs for by default.
+pub const DEFAULT_FRAMES: u64 = 600;
+
+/// NROM program run by the self-check.
+///
+/// On reset it enables NMIs, rendering and the first pulse channel then
+/// spins. Each NMI reads the joypad into $10, counts frames in $11, adds the
+/// buttons to a running total in $12 and feeds the total to the pulse timer,
+/// a nametable byte and a palette entry so every subsystem sees the input.
+///
+/// ```text
+/// C000  SEI / CLD / LDX #$FF / TXS
+/// C005  LDA #$80 / STA $2000 / LDA #$1E / STA $2001
+/// C00F  LDA #$01 / STA $4015 / LDA #$BF / STA $4000
+/// C019  JMP $C019
+/// C01C  LDA #$01 / STA $4016 / LDA #$00 / STA $4016 / LDX #$08
+/// C028  LDA $4016 / LSR A / ROL $10 / DEX / BNE $C028
+/// C031  INC $11 / LDA $10 / CLC / ADC $12 / STA $12
+/// C03A  STA $4002 / LDA #$08 / STA $4003
+/// C042  LDA $2002 / LDA #$20 / STA $2006 / LDA $11 / STA $2006
+/// C04F  LDA $12 / STA $2007
+/// C054  LDA #$3F / STA $2006 / LDA $11 / AND #$1F / STA $2006
+/// C060  LDA $12 / STA $2007
+/// C065  LDA #$00 / STA $2005 / STA $2005 / LDA #$80 / STA $2000
+/// C072  RTI
+/// C073  RTI
+/// ```
+const PROGRAM: [u8; 0x74] = [
+    0x78, 0xD8, 0xA2, 0xFF, 0x9A, 0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9,
+    0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0x4C, 0x19, 0xC0, 0xA9, 0x01, 0x8D, 0x16,
+    0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xA2, 0x08, 0xAD, 0x16, 0x40, 0x4A, 0x26, 0x10, 0xCA, 0xD0,
+    0xF7, 0xE6, 0x11, 0xA5, 0x10, 0x18, 0x65, 0x12, 0x85, 0x12, 0x8D, 0x02, 0x40, 0xA9, 0x08, 0x8D,
+    0x03, 0x40, 0xAD, 0x02, 0x20, 0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA5, 0x11, 0x8D, 0x06, 0x20, 0xA5,
+    0x12, 0x8D, 0x07, 0x20, 0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA5, 0x11, 0x29, 0x1F, 0x8D, 0x06, 0x20,
+    0xA5, 0x12, 0x8D, 0x07, 0x20, 0xA9, 0x00, 0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20, 0xA9, 0x80, 0x8D,
+    0x00, 0x20, 0x40, 0x40,
+];
+
+/// NMI, reset and IRQ vectors of the program.
+const VECTORS: [u16; 3] = [0xC01C, 0xC000, 0xC073];
+
+/// Fixed input script replayed by the self-check, in the movie format.
+const SCRIPT: &str = "\
+30 input 08
+31 input 00
+60 input 01
+90 input 81
+120 input 40
+150 input 10
+180 input 24
+240 input 00
+300 reset
+330 input 02
+400 input C3
+500 input 00
+";
+
+/// Returns the iNES image of the self-check ROM.
+///
+/// There is no public domain homebrew bundled with the emulator, so the ROM
+/// is built here from a small hand assembled program and a generated pattern
+/// table. Keeping it in code means the hash never depends on a file on disk.
+pub fn rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    for (i, vector) in VECTORS.iter().enumerate() {
+        prg[0x3FFA + i * 2..0x3FFC + i * 2].copy_from_slice(&vector.to_le_bytes());
+    }
+
+    let chr = (0..0x2000u32).map(|i| (i.wrapping_mul(37) ^ (i >> 4)) as u8);
+
+    let mut rom = Header::new(0, 1, 1).to_bytes(Format::Ines).to_vec();
+    rom.extend(prg);
+    rom.extend(chr);
+    rom
+}
+
+/// Runs the self-check ROM with the fixed input script for the given number
+/// of frames and returns a hash of the final machine state.
+///
+/// Only integer state is hashed: CPU registers, work RAM, PPU memory, the
+/// last rendered frame and the number of audio samples produced. The hash
+/// must be identical on every platform and build.
+pub fn run(frames: u64) -> Result<u32, String> {
+    let (cpu, screen, samples) = play(frames)?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[cpu.a, cpu.x, cpu.y, cpu.status, cpu.sp]);
+    hasher.update(&cpu.pc.to_le_bytes());
+    hasher.update(cpu.bus.ram());
+    for addr in 0x2000..0x3000 {
+        hasher.update(&[cpu.bus.ppu_peek(addr)]);
+    }
+    hasher.update(&cpu.bus.palette_ram());
+    hasher.update(cpu.bus.oam());
+    hasher.update(&screen);
+    hasher.update(&samples.to_le_bytes());
+
+    Ok(hasher.finalize())
+}
+
+/// Runs the self-check ROM headlessly with the fixed input script for the
+/// given number of frames. Returns the machine, the last rendered frame and
+/// the number of audio samples produced.
+fn play(frames: u64) -> Result<(Cpu<'static>, Vec<u8>, u64), String> {
+    let cart = Cartridge::new(&rom())?;
+
+    let screen = Rc::new(RefCell::new(Vec::new()));
+    let frame = Rc::clone(&screen);
+    let mut cpu = NesBuilder::new().build(cart, move |pixels| {
+        let mut frame = frame.borrow_mut();
+        frame.clear();
+        frame.extend_from_slice(pixels);
+    });
+
+    let mut script: Movie = SCRIPT.parse()?;
+    let mut samples = 0u64;
+    for frame in 0..frames {
+        for (_, event) in script.replay(frame) {
+            match *event {
+                Event::Input(port, buttons) => cpu.bus.set_controller_state(port, buttons),
+                Event::Reset => cpu.soft_reset(),
+                Event::Power => return Err(String::from("power cycles are not supported")),
+            }
+        }
+
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            if cpu.clock() {
+                return Err(format!("CPU halted at ${:04X}", cpu.pc));
+            }
+        }
+        samples += cpu.bus.audio_samples().len() as u64;
+    }
+
+    let screen = screen.take();
+    Ok((cpu, screen, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_is_deterministic() {
+        let hash = run(60).unwrap();
+        assert_eq!(hash, run(60).unwrap());
+        assert_ne!(hash, run(59).unwrap());
+    }
+
+    #[test]
+    fn test_scripted_run_reaches_known_state() {
+        // Ten seconds of play, through the reset at frame 300.
+        let (cpu, screen, samples) = play(600).unwrap();
+
+        // The reset doesn't clear RAM, so the NMI handler has counted 599
+        // frames, and added up the buttons of the script, read bit reversed,
+        // to 34166.
+        let ram = cpu.bus.ram();
+        assert_eq!(ram[0x10], 0x00, "last buttons read");
+        assert_eq!(ram[0x11], (599 % 256) as u8, "frames counted");
+        assert_eq!(ram[0x12], (34166 % 256) as u8, "running total");
+
+        // Any change to rendering of the nametable and palette the program
+        // writes shows up in the last frame.
+        assert_eq!(screen.len(), 256 * 240 * 3);
+        assert_eq!(crc32fast::hash(&screen), 0xC9579873);
+        assert!(samples > 0);
+    }
+}
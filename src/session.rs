@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+/// Represents the list of ROMs which can be cycled between without
+/// restarting the emulator.
+pub struct Session {
+    roms: Vec<PathBuf>,
+    current: usize,
+}
+
+impl Session {
+    /// Returns a session containing the given ROMs followed by the entries of
+    /// the playlist file, if one is provided.
+    pub fn new(roms: &[String], playlist: Option<&str>) -> Result<Self, String> {
+        let mut paths: Vec<PathBuf> = roms.iter().map(PathBuf::from).collect();
+
+        if let Some(playlist) = playlist {
+            let contents = std::fs::read_to_string(playlist)
+                .map_err(|e| format!("could not read playlist {}: {}", playlist, e))?;
+
+            let base = Path::new(playlist).parent().unwrap_or(Path::new(""));
+            paths.extend(parse_playlist(&contents, base));
+        }
+
+        if paths.is_empty() {
+            return Err(String::from("no ROMs provided"));
+        }
+
+        Ok(Session {
+            roms: paths,
+            current: 0,
+        })
+    }
+
+    /// Returns the path of the currently selected ROM.
+    pub fn current(&self) -> &Path {
+        &self.roms[self.current]
+    }
+
+    /// Selects the next ROM, wrapping around to the first.
+    pub fn next(&mut self) -> &Path {
+        self.current = (self.current + 1) % self.roms.len();
+        self.current()
+    }
+
+    /// Selects the previous ROM, wrapping around to the last.
+    pub fn prev(&mut self) -> &Path {
+        self.current = (self.current + self.roms.len() - 1) % self.roms.len();
+        self.current()
+    }
+}
+
+/// Returns the ROM paths listed in a playlist, one per line. Blank lines and
+/// lines starting with # are skipped, relative paths are resolved against the
+/// playlist's directory.
+fn parse_playlist(contents: &str, base: &Path) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| base.join(l))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_playlist() {
+        let contents = "# favourites\nsmb.nes\n\n  /roms/zelda.nes  \n";
+        let roms = parse_playlist(contents, Path::new("/home/nes"));
+
+        assert_eq!(
+            roms,
+            vec![
+                PathBuf::from("/home/nes/smb.nes"),
+                PathBuf::from("/roms/zelda.nes")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycle_roms() {
+        let roms = vec![String::from("a.nes"), String::from("b.nes")];
+        let mut session = Session::new(&roms, None).unwrap();
+
+        assert_eq!(session.current(), Path::new("a.nes"));
+        assert_eq!(session.next(), Path::new("b.nes"));
+        assert_eq!(session.next(), Path::new("a.nes"));
+        assert_eq!(session.prev(), Path::new("b.nes"));
+    }
+
+    #[test]
+    fn test_empty_session() {
+        assert!(Session::new(&[], None).is_err());
+    }
+}
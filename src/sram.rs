@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use crate::{cartridge::Cartridge, cpu::Cpu, nes::NesBuilder, state};
+
+/// Returns the path the PRG RAM of the ROM at the given path is dumped to by
+/// the hotkey.
+pub fn dump_path(rom: &Path) -> PathBuf {
+    rom.with_extension("sram")
+}
+
+/// Writes a dump of PRG RAM to the file at the given path.
+pub fn write(path: &Path, ram: &[u8]) -> Result<(), String> {
+    if ram.is_empty() {
+        return Err(String::from("the cartridge has no PRG RAM"));
+    }
+    std::fs::write(path, ram).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Exports the PRG RAM held in the ROM's save state to a file, so battery
+/// saves can be backed up or edited. Returns the number of bytes written.
+pub fn export(rom: &Path, state: &Path, file: &Path) -> Result<usize, String> {
+    let (mut cpu, checksum) = boot(rom)?;
+    state::load_file(state, &mut cpu, checksum)?;
+
+    let ram = cpu.bus.prg_ram();
+    write(file, &ram)?;
+    Ok(ram.len())
+}
+
+/// Imports a file into the PRG RAM held in the ROM's save state, leaving the
+/// rest of the state as it was. Without a save state, one is written of the
+/// machine at power on, so the game boots with the imported RAM. Returns the
+/// number of bytes read.
+pub fn import(rom: &Path, state: &Path, file: &Path) -> Result<usize, String> {
+    let (mut cpu, checksum) = boot(rom)?;
+    if state.exists() {
+        state::load_file(state, &mut cpu, checksum)?;
+    }
+
+    let ram = std::fs::read(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    cpu.bus.set_prg_ram(&ram)?;
+    state::save_file(state, &cpu, checksum)?;
+    Ok(ram.len())
+}
+
+/// Loads the ROM at the given path into a headless machine at power on.
+fn boot(path: &Path) -> Result<(Cpu<'static>, u32), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cart = Cartridge::new(&bytes)?;
+    let checksum = cart.checksum();
+
+    Ok((NesBuilder::new().build(cart, |_| {}), checksum))
+}
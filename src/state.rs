@@ -0,0 +1,209 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::cpu::Cpu;
+use crate::events::CoreEvent;
+
+/// Identifies a save state file.
+const MAGIC: &[u8; 4] = b"RESS";
+
+/// Version of the save state layout, bumped whenever a component changes what
+/// it saves so old states are rejected rather than misread.
+const VERSION: u8 = 13;
+
+/// Implemented by components whose state is captured in a save state.
+///
+/// Components write their fields in a fixed order with the helpers below and
+/// read them back in the same order. Configuration which comes from the
+/// command line or the ROM header, such as the region or audio sample rate,
+/// is not saved.
+pub trait SaveState {
+    /// Writes the component's state.
+    fn save_state(&self, w: &mut dyn Write) -> io::Result<()>;
+
+    /// Restores the component's state from data written by `save_state`.
+    fn load_state(&mut self, r: &mut dyn Read) -> io::Result<()>;
+}
+
+/// Writes a save state of the machine, tagged with the checksum of the ROM it
+/// was taken from.
+pub fn save(cpu: &Cpu, checksum: u32, w: &mut dyn Write) -> Result<(), String> {
+    let result = (|| {
+        w.write_all(MAGIC)?;
+        write_u8(w, VERSION)?;
+        write_u32(w, checksum)?;
+        cpu.save_state(w)
+    })();
+
+    result.map_err(|e| format!("could not write save state: {}", e))
+}
+
+/// Restores a save state taken from the ROM with the given checksum.
+pub fn load(cpu: &mut Cpu, checksum: u32, r: &mut dyn Read) -> Result<(), String> {
+    restore(cpu, checksum, r)?;
+    cpu.bus.push_event(CoreEvent::StateLoaded);
+    Ok(())
+}
+
+/// Restores a save state like [`load`] without reporting it, for states
+/// restored every frame such as when rewinding.
+pub fn restore(cpu: &mut Cpu, checksum: u32, r: &mut dyn Read) -> Result<(), String> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)
+        .map_err(|e| format!("could not read save state: {}", e))?;
+    if &magic != MAGIC {
+        return Err(String::from("not a save state"));
+    }
+
+    let version = read_u8(r).map_err(|e| format!("could not read save state: {}", e))?;
+    if version != VERSION {
+        return Err(format!("unsupported save state version {}", version));
+    }
+
+    let rom = read_u32(r).map_err(|e| format!("could not read save state: {}", e))?;
+    if rom != checksum {
+        return Err(format!(
+            "save state is for ROM {:08X}, not {:08X}",
+            rom, checksum
+        ));
+    }
+
+    cpu.load_state(r)
+        .map_err(|e| format!("could not read save state: {}", e))
+}
+
+/// Writes a save state to the file at the given path.
+pub fn save_file(path: &Path, cpu: &Cpu, checksum: u32) -> Result<(), String> {
+    let mut data = Vec::new();
+    save(cpu, checksum, &mut data)?;
+    std::fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Restores a save state from the file at the given path.
+pub fn load_file(path: &Path, cpu: &mut Cpu, checksum: u32) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    load(cpu, checksum, &mut &data[..]).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn write_u8(w: &mut dyn Write, v: u8) -> io::Result<()> {
+    w.write_all(&[v])
+}
+
+pub fn write_bool(w: &mut dyn Write, v: bool) -> io::Result<()> {
+    write_u8(w, v as u8)
+}
+
+pub fn write_u16(w: &mut dyn Write, v: u16) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub fn write_u32(w: &mut dyn Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub fn write_u64(w: &mut dyn Write, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub fn write_u128(w: &mut dyn Write, v: u128) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub fn read_u8(r: &mut dyn Read) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+pub fn read_bool(r: &mut dyn Read) -> io::Result<bool> {
+    Ok(read_u8(r)? != 0)
+}
+
+pub fn read_u16(r: &mut dyn Read) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+pub fn read_u32(r: &mut dyn Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+pub fn read_u64(r: &mut dyn Read) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+pub fn read_u128(r: &mut dyn Read) -> io::Result<u128> {
+    let mut b = [0; 16];
+    r.read_exact(&mut b)?;
+    Ok(u128::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::SystemBus;
+    use crate::cartridge::Cartridge;
+    use crate::selfcheck;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn new_cpu(frame: &RefCell<Vec<u8>>) -> Cpu<'_> {
+        let cart = Cartridge::new(&selfcheck::rom()).unwrap();
+        let bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |pixels| {
+            *frame.borrow_mut() = pixels.to_vec();
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu
+    }
+
+    fn run_frames(cpu: &mut Cpu, frames: u32) {
+        for _ in 0..frames {
+            let frame_count = cpu.bus.ppu_frame_count();
+            while cpu.bus.ppu_frame_count() == frame_count {
+                cpu.clock();
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frame_a = RefCell::new(Vec::new());
+        let mut a = new_cpu(&frame_a);
+        a.bus.set_controller_state(0, 0x81);
+        run_frames(&mut a, 20);
+
+        let mut state = Vec::new();
+        save(&a, 1, &mut state).unwrap();
+
+        let frame_b = RefCell::new(Vec::new());
+        let mut b = new_cpu(&frame_b);
+        b.bus.events();
+        load(&mut b, 1, &mut &state[..]).unwrap();
+        assert_eq!(b.bus.events(), [CoreEvent::StateLoaded]);
+
+        // Both machines continue identically from the saved point.
+        run_frames(&mut a, 5);
+        run_frames(&mut b, 5);
+        assert_eq!(a.pc, b.pc);
+        assert_eq!(a.bus.ram(), b.bus.ram());
+        assert_eq!(*frame_a.borrow(), *frame_b.borrow());
+    }
+
+    #[test]
+    fn test_load_rejects_other_roms() {
+        let frame = RefCell::new(Vec::new());
+        let mut cpu = new_cpu(&frame);
+
+        let mut state = Vec::new();
+        save(&cpu, 1, &mut state).unwrap();
+
+        assert!(load(&mut cpu, 2, &mut &state[..]).is_err());
+        assert!(load(&mut cpu, 1, &mut &state[1..]).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Represents the play time of each game, keyed by the checksum of its ROM
+/// so renamed or moved files keep their statistics.
+pub struct PlayTime {
+    path: Option<PathBuf>,
+    totals: HashMap<u32, Duration>,
+}
+
+impl PlayTime {
+    /// Loads the play time statistics from the file at the given path. A
+    /// missing file is treated as empty and invalid lines are skipped.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let contents = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .unwrap_or_default();
+
+        PlayTime {
+            path,
+            totals: parse(&contents),
+        }
+    }
+
+    /// Returns the default location of the statistics file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|d| d.join("res").join("playtime.txt"))
+    }
+
+    /// Returns the total play time of the game with the given checksum.
+    pub fn get(&self, checksum: u32) -> Duration {
+        self.totals.get(&checksum).copied().unwrap_or_default()
+    }
+
+    /// Adds to the play time of the game with the given checksum.
+    pub fn add(&mut self, checksum: u32, played: Duration) {
+        *self.totals.entry(checksum).or_default() += played;
+    }
+
+    /// Writes the statistics back to the file they were loaded from.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        std::fs::write(path, format(&self.totals)).map_err(|e| e.to_string())
+    }
+}
+
+/// Returns a short human readable duration, e.g. "2h 05m".
+pub fn format_duration(d: Duration) -> String {
+    let mins = d.as_secs() / 60;
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
+
+/// Parses lines of "<checksum> <seconds>".
+fn parse(contents: &str) -> HashMap<u32, Duration> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (crc, secs) = line.trim().split_once(' ')?;
+            let crc = u32::from_str_radix(crc, 16).ok()?;
+            let secs = secs.trim().parse::<f64>().ok()?;
+            Some((crc, Duration::from_secs_f64(secs)))
+        })
+        .collect()
+}
+
+/// Formats the totals as lines of "<checksum> <seconds>", sorted by checksum.
+fn format(totals: &HashMap<u32, Duration>) -> String {
+    let mut lines: Vec<_> = totals
+        .iter()
+        .map(|(crc, d)| format!("{:08X} {:.0}\n", crc, d.as_secs_f64()))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        let mut totals = HashMap::new();
+        totals.insert(0x1234ABCD, Duration::from_secs(3600));
+        totals.insert(0x00000001, Duration::from_secs(5));
+
+        let contents = format(&totals);
+        assert_eq!(contents, "00000001 5\n1234ABCD 3600\n");
+        assert_eq!(parse(&contents), totals);
+    }
+
+    #[test]
+    fn test_parse_skips_invalid_lines() {
+        let totals = parse("nonsense\nZZZZ 10\n0000000A 20\n");
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[&0xA], Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_add() {
+        let mut stats = PlayTime::load(None);
+        stats.add(1, Duration::from_secs(30));
+        stats.add(1, Duration::from_secs(45));
+
+        assert_eq!(stats.get(1), Duration::from_secs(75));
+        assert_eq!(stats.get(2), Duration::ZERO);
+        assert!(stats.save().is_ok());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(7500)), "2h 05m");
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Names of the stem files, in the order of the APU channels.
+pub const STEM_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// Length of the RIFF header and the fmt and data chunk headers.
+const HEADER_LEN: u32 = 44;
+
+/// Records the output of each APU channel to its own WAV file, such as
+/// pulse1.wav, for remixing. Every file is given a sample from each tap of
+/// [`crate::bus::SystemBus::drain_stem_samples`], so they stay sample aligned
+/// and can be lined up by their start.
+pub struct Stems {
+    writers: Vec<WavWriter<BufWriter<File>>>,
+}
+
+impl Stems {
+    /// Creates the stem files in the given directory, creating it if needed.
+    pub fn create(dir: &Path, sample_rate: u32) -> Result<Self, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+        let writers = STEM_NAMES
+            .iter()
+            .map(|name| {
+                let path = dir.join(name).with_extension("wav");
+                File::create(&path)
+                    .and_then(|f| WavWriter::new(BufWriter::new(f), sample_rate))
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Stems { writers })
+    }
+
+    /// Appends samples of every channel to their files.
+    pub fn write(&mut self, samples: &[[f32; 5]]) -> Result<(), String> {
+        for (i, writer) in self.writers.iter_mut().enumerate() {
+            for sample in samples {
+                writer.write(sample[i]).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the lengths of the files, which are unreadable until done.
+    pub fn finish(self) -> Result<(), String> {
+        for writer in self.writers {
+            writer.finish().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes mono 16-bit PCM WAV audio. The lengths in the header are written
+/// as zero and filled in by [`WavWriter::finish`], as they aren't known until
+/// the recording stops.
+///
+/// See: http://soundfile.sapp.org/doc/WaveFormat/
+struct WavWriter<W: Write + Seek> {
+    w: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header of a file at the given sample rate.
+    fn new(mut w: W, sample_rate: u32) -> io::Result<Self> {
+        w.write_all(b"RIFF")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&1u16.to_le_bytes())?; // Mono
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * 2).to_le_bytes())?; // Bytes per second
+        w.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+        w.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { w, samples: 0 })
+    }
+
+    /// Appends a sample from -1.0 to 1.0, clipping any outside.
+    fn write(&mut self, sample: f32) -> io::Result<()> {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.w.write_all(&sample.to_le_bytes())?;
+        self.samples += 1;
+        Ok(())
+    }
+
+    /// Fills in the lengths in the header and returns the writer.
+    fn finish(mut self) -> io::Result<W> {
+        let data_len = self.samples * 2;
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w
+            .write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.w.write_all(&data_len.to_le_bytes())?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_wav_writer() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        for sample in [0.0, 1.0, -2.0] {
+            wav.write(sample).unwrap();
+        }
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), HEADER_LEN as usize + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], (HEADER_LEN - 8 + 6).to_le_bytes());
+        assert_eq!(bytes[24..28], 44100u32.to_le_bytes());
+        assert_eq!(bytes[40..44], 6u32.to_le_bytes());
+        assert_eq!(bytes[44..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}
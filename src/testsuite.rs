@@ -0,0 +1,373 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::cartridge::Cartridge;
+use crate::cpu::{Cpu, Memory};
+use crate::nes::NesBuilder;
+
+/// Number of frames a test ROM may run for before it is considered to have
+/// timed out.
+const DEFAULT_MAX_FRAMES: u32 = 60 * 60;
+
+/// Number of frames to wait before resetting when a ROM requests it.
+const RESET_DELAY_FRAMES: u32 = 6;
+
+/// Status codes and signature written to PRG RAM by test ROMs following the
+/// blargg convention.
+///
+/// See: https://github.com/christopherpow/nes-test-roms/blob/master/readme.txt
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+/// How a test ROM reports that it has passed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Criteria {
+    /// The ROM writes a result code to $6000 and a message to $6004.
+    Status,
+
+    /// The CRC32 of the screen matches once the given number of frames have
+    /// been rendered.
+    ScreenHash { frames: u32, crc: u32 },
+
+    /// The ROM runs for the given number of frames without crashing.
+    Frames(u32),
+}
+
+/// Manifest of well known community test ROMs.
+///
+/// ROMs found in the test directory which are not listed are run with the
+/// $6000 status protocol if they use it, or until they time out otherwise.
+const MANIFEST: &str = include_str!("testsuite/manifest.txt");
+
+/// Name of the optional manifest in the test directory.
+const LOCAL_MANIFEST: &str = "manifest.txt";
+
+/// Result of running a single test ROM.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed(String),
+    Failed(String),
+    TimedOut,
+    Crashed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed(msg) => write!(f, "PASS  {}", msg),
+            Outcome::Failed(msg) => write!(f, "FAIL  {}", msg),
+            Outcome::TimedOut => write!(f, "TIME  timed out"),
+            Outcome::Crashed(msg) => write!(f, "CRASH {}", msg),
+        }
+    }
+}
+
+/// Runs every test ROM in the given directory and prints a summary table.
+/// Returns the process exit code, which is non-zero if any ROM did not pass.
+pub fn run(dir: &Path) -> i32 {
+    let mut roms = Vec::new();
+    if let Err(e) = find_roms(dir, &mut roms) {
+        eprintln!("could not scan {}: {}", dir.display(), e);
+        return 1;
+    }
+    roms.sort();
+
+    let mut manifest = match parse_manifest(MANIFEST) {
+        Ok(manifest) => manifest,
+        Err(e) => panic!("built-in manifest is invalid: {}", e),
+    };
+    if let Ok(local) = std::fs::read_to_string(dir.join(LOCAL_MANIFEST)) {
+        match parse_manifest(&local) {
+            Ok(local) => manifest.extend(local),
+            Err(e) => {
+                eprintln!("could not parse {}: {}", LOCAL_MANIFEST, e);
+                return 1;
+            }
+        }
+    }
+
+    // Panics are reported as crashes in the summary.
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = 0;
+    for path in roms.iter() {
+        let name = path.strip_prefix(dir).unwrap_or(path);
+        let criteria = manifest.get(name).copied();
+
+        let outcome = match std::fs::read(path) {
+            Ok(bytes) => run_rom(&bytes, criteria),
+            Err(e) => Outcome::Crashed(e.to_string()),
+        };
+        if !matches!(outcome, Outcome::Passed(_)) {
+            failures += 1;
+        }
+
+        println!("{:<50} {}", name.display(), outcome);
+    }
+
+    let _ = panic::take_hook();
+
+    println!(
+        "\n{} passed, {} failed, {} total",
+        roms.len() - failures,
+        failures,
+        roms.len()
+    );
+
+    (failures > 0) as i32
+}
+
+/// Recursively collects the paths of all .nes files in the directory.
+pub fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("nes"))
+        {
+            roms.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a manifest into the pass criteria for each ROM path.
+fn parse_manifest(contents: &str) -> Result<HashMap<PathBuf, Criteria>, String> {
+    let mut manifest = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+
+        let number = |field: Option<&&str>, radix| {
+            field
+                .and_then(|f| u32::from_str_radix(f, radix).ok())
+                .ok_or(format!("line {}: invalid number", i + 1))
+        };
+
+        let criteria = match fields.get(1) {
+            Some(&"status") => Criteria::Status,
+            Some(&"frames") => Criteria::Frames(number(fields.get(2), 10)?),
+            Some(&"screen") => Criteria::ScreenHash {
+                frames: number(fields.get(2), 10)?,
+                crc: number(fields.get(3), 16)?,
+            },
+            _ => return Err(format!("line {}: unknown criteria", i + 1)),
+        };
+
+        manifest.insert(PathBuf::from(fields[0]), criteria);
+    }
+
+    Ok(manifest)
+}
+
+/// Runs the ROM headlessly until it reports a result. ROMs without criteria
+/// are judged by the status protocol if they use it.
+pub fn run_rom(bytes: &[u8], criteria: Option<Criteria>) -> Outcome {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let cart = Cartridge::new(bytes)?;
+
+        let screen = Rc::new(RefCell::new(Vec::new()));
+        let frame = Rc::clone(&screen);
+        let mut cpu = NesBuilder::new().build(cart, move |pixels| {
+            let mut frame = frame.borrow_mut();
+            frame.clear();
+            frame.extend_from_slice(pixels);
+        });
+
+        Ok(run_cpu(&mut cpu, &screen, criteria))
+    }));
+
+    match result {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Outcome::Crashed(e),
+        Err(e) => Outcome::Crashed(panic_message(e)),
+    }
+}
+
+/// Returns the message a caught panic was raised with.
+pub fn panic_message(e: Box<dyn Any + Send>) -> String {
+    e.downcast_ref::<String>()
+        .cloned()
+        .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or_else(|| String::from("panicked"))
+}
+
+/// Clocks the CPU a frame at a time, checking the pass criteria after each.
+fn run_cpu(cpu: &mut Cpu, screen: &RefCell<Vec<u8>>, criteria: Option<Criteria>) -> Outcome {
+    let max_frames = match criteria {
+        Some(Criteria::ScreenHash { frames, .. }) | Some(Criteria::Frames(frames)) => frames,
+        _ => DEFAULT_MAX_FRAMES,
+    };
+
+    let mut reset_at = None;
+    for frame in 0..max_frames {
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            if cpu.clock() {
+                return Outcome::Failed(String::from("CPU halted"));
+            }
+        }
+
+        if !matches!(criteria, None | Some(Criteria::Status)) || !has_signature(cpu) {
+            continue;
+        }
+
+        match cpu.bus.mem_read_byte(STATUS_ADDR) {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    reset_at = None;
+                    cpu.reset();
+                }
+                Some(_) => {}
+            },
+            0 => return Outcome::Passed(read_text(cpu)),
+            code => return Outcome::Failed(format!("#{} {}", code, read_text(cpu))),
+        }
+    }
+
+    match criteria {
+        Some(Criteria::Frames(frames)) => Outcome::Passed(format!("ran {} frames", frames)),
+        Some(Criteria::ScreenHash { crc, .. }) => {
+            let actual = crc32fast::hash(&screen.borrow());
+            match actual == crc {
+                true => Outcome::Passed(String::from("screen matches")),
+                false => Outcome::Failed(format!("screen hash {:08X}", actual)),
+            }
+        }
+        _ => Outcome::TimedOut,
+    }
+}
+
+/// Returns true if the ROM has written the status protocol signature.
+fn has_signature(cpu: &mut Cpu) -> bool {
+    (0..3).all(|i| cpu.bus.mem_read_byte(SIGNATURE_ADDR + i) == SIGNATURE[i as usize])
+}
+
+/// Returns the null terminated message written by the ROM, on a single line.
+fn read_text(cpu: &mut Cpu) -> String {
+    let mut text = String::new();
+    for addr in TEXT_ADDR..0x8000 {
+        match cpu.bus.mem_read_byte(addr) {
+            0 => break,
+            b => text.push(b as char),
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an NROM image running the given program from $8000.
+    fn test_image(program: &[u8]) -> Vec<u8> {
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+
+        // Reset vector.
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        image.extend(prg);
+        image.extend(vec![0; 0x2000]);
+        image
+    }
+
+    /// Returns a program which reports the given status code and message.
+    fn status_program(code: u8, text: &str) -> Vec<u8> {
+        let mut program = vec![];
+        let bytes = SIGNATURE.iter().chain(text.as_bytes()).chain([&0]);
+        for (i, b) in bytes.enumerate() {
+            let addr = SIGNATURE_ADDR + i as u16;
+            program.extend([0xA9, *b, 0x8D, addr as u8, (addr >> 8) as u8]);
+        }
+
+        // LDA #code; STA $6000; JMP *
+        program.extend([0xA9, code, 0x8D, 0x00, 0x60]);
+        let jmp = 0x8000 + program.len() as u16;
+        program.extend([0x4C, jmp as u8, (jmp >> 8) as u8]);
+        program
+    }
+
+    #[test]
+    fn test_status_passed() {
+        let image = test_image(&status_program(0, "\nPassed\n"));
+        assert_eq!(
+            run_rom(&image, Some(Criteria::Status)),
+            Outcome::Passed(String::from("Passed"))
+        );
+    }
+
+    #[test]
+    fn test_status_failed() {
+        let image = test_image(&status_program(3, "Failed"));
+        assert_eq!(
+            run_rom(&image, None),
+            Outcome::Failed(String::from("#3 Failed"))
+        );
+    }
+
+    #[test]
+    fn test_frames() {
+        // JMP $8000
+        let image = test_image(&[0x4C, 0x00, 0x80]);
+        assert!(matches!(
+            run_rom(&image, Some(Criteria::Frames(2))),
+            Outcome::Passed(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rom() {
+        assert!(matches!(run_rom(&[0; 16], None), Outcome::Crashed(_)));
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            "# comment\n\na.nes status\nb/b.nes frames 10\nc.nes screen 5 1A2B3C4D\n",
+        )
+        .unwrap();
+
+        assert_eq!(manifest[Path::new("a.nes")], Criteria::Status);
+        assert_eq!(manifest[Path::new("b/b.nes")], Criteria::Frames(10));
+        assert_eq!(
+            manifest[Path::new("c.nes")],
+            Criteria::ScreenHash {
+                frames: 5,
+                crc: 0x1A2B3C4D
+            }
+        );
+
+        assert!(parse_manifest("a.nes frames x").is_err());
+        assert!(parse_manifest("a.nes unknown").is_err());
+    }
+
+    #[test]
+    fn test_builtin_manifest() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+        assert_eq!(
+            manifest[Path::new("apu_test/apu_test.nes")],
+            Criteria::Status
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::cartridge::Cartridge;
+use crate::nes::NesBuilder;
+use crate::ppu::Frame;
+use crate::testsuite::{find_roms, panic_message};
+
+/// Number of identical frames after which the screen is considered still.
+pub const DEFAULT_STABLE_FRAMES: u32 = 60;
+
+/// Number of frames a ROM may run for before its last frame is saved instead.
+const MAX_FRAMES: u32 = 60 * 60;
+
+/// Rows at the top and bottom of the frame hidden by the overscan of a
+/// typical TV, which are left out of thumbnails. Many games leave garbage in
+/// them, which would otherwise keep the screen from ever looking still.
+const OVERSCAN_ROWS: usize = 8;
+
+/// Height of a thumbnail in pixels.
+const VISIBLE_HEIGHT: usize = Frame::HEIGHT - 2 * OVERSCAN_ROWS;
+
+/// Detects when the screen has stopped changing, such as on a title screen.
+/// Blank screens, which many games show while loading, don't count.
+pub struct StillDetector {
+    required: u32,
+    last: Option<u32>,
+    count: u32,
+}
+
+impl StillDetector {
+    /// Returns a detector for a screen left unchanged for the given number
+    /// of frames.
+    pub fn new(required: u32) -> Self {
+        StillDetector {
+            required: required.max(1),
+            last: None,
+            count: 0,
+        }
+    }
+
+    /// Observes the visible pixels of a frame, returning true once the same
+    /// non-blank frame has been observed the required number of times in a
+    /// row.
+    pub fn observe(&mut self, pixels: &[u8]) -> bool {
+        if is_blank(pixels) {
+            self.last = None;
+            self.count = 0;
+            return false;
+        }
+
+        let hash = crc32fast::hash(pixels);
+        if self.last == Some(hash) {
+            self.count += 1;
+        } else {
+            self.last = Some(hash);
+            self.count = 1;
+        }
+
+        self.count >= self.required
+    }
+}
+
+/// Returns true if every pixel is the same colour.
+fn is_blank(pixels: &[u8]) -> bool {
+    pixels
+        .chunks_exact(Frame::BYTES_PER_PIXEL)
+        .all(|p| p == &pixels[..Frame::BYTES_PER_PIXEL])
+}
+
+/// Returns the rows of a rendered frame inside the overscan.
+fn visible(frame: &[u8]) -> &[u8] {
+    let row = Frame::WIDTH * Frame::BYTES_PER_PIXEL;
+    &frame[OVERSCAN_ROWS * row..(OVERSCAN_ROWS + VISIBLE_HEIGHT) * row]
+}
+
+/// Result of capturing a thumbnail of a single ROM.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The screen was still after the given number of frames.
+    Still(u32),
+
+    /// The screen never kept still, the last frame was saved instead.
+    TimedOut,
+
+    /// The ROM could not be run or the thumbnail could not be saved.
+    Failed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Still(frames) => write!(f, "SAVE  still after {} frames", frames),
+            Outcome::TimedOut => write!(f, "TIME  saved last frame"),
+            Outcome::Failed(msg) => write!(f, "FAIL  {}", msg),
+        }
+    }
+}
+
+/// Saves a thumbnail of every ROM in the given directory, either next to the
+/// ROM or in the same place under the output directory, and prints a
+/// summary. Returns the process exit code, which is non-zero if any ROM
+/// failed.
+pub fn run(dir: &Path, out: Option<&Path>, stable_frames: u32) -> i32 {
+    let mut roms = Vec::new();
+    if let Err(e) = find_roms(dir, &mut roms) {
+        eprintln!("could not scan {}: {}", dir.display(), e);
+        return 1;
+    }
+    roms.sort();
+
+    // Panics are reported as failures in the summary.
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = 0;
+    for path in roms.iter() {
+        let name = path.strip_prefix(dir).unwrap_or(path);
+        let thumb = match out {
+            Some(out) => out.join(name),
+            None => PathBuf::from(path),
+        }
+        .with_extension("png");
+
+        let outcome = match std::fs::read(path) {
+            Ok(bytes) => save_thumbnail(&bytes, stable_frames, &thumb),
+            Err(e) => Outcome::Failed(e.to_string()),
+        };
+        if matches!(outcome, Outcome::Failed(_)) {
+            failures += 1;
+        }
+
+        println!("{:<50} {}", name.display(), outcome);
+    }
+
+    let _ = panic::take_hook();
+
+    println!(
+        "\n{} saved, {} failed, {} total",
+        roms.len() - failures,
+        failures,
+        roms.len()
+    );
+
+    (failures > 0) as i32
+}
+
+/// Captures a thumbnail of the ROM and saves it as a PNG at the given path.
+fn save_thumbnail(bytes: &[u8], stable_frames: u32, path: &Path) -> Outcome {
+    let captured = panic::catch_unwind(AssertUnwindSafe(|| capture(bytes, stable_frames)));
+    let (pixels, still_at) = match captured {
+        Ok(Ok(captured)) => captured,
+        Ok(Err(e)) => return Outcome::Failed(e),
+        Err(e) => return Outcome::Failed(panic_message(e)),
+    };
+
+    let image = image::RgbImage::from_raw(Frame::WIDTH as u32, VISIBLE_HEIGHT as u32, pixels)
+        .expect("visible pixels match the thumbnail dimensions");
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .map_err(|e| e.to_string())
+        .and_then(|_| image.save(path).map_err(|e| e.to_string()));
+
+    match (saved, still_at) {
+        (Err(e), _) => Outcome::Failed(format!("{}: {}", path.display(), e)),
+        (Ok(_), Some(frames)) => Outcome::Still(frames),
+        (Ok(_), None) => Outcome::TimedOut,
+    }
+}
+
+/// Runs the ROM headlessly until the screen keeps still for the given number
+/// of frames. Returns the visible pixels of the last frame, and the number of
+/// frames it took if the screen kept still.
+pub fn capture(bytes: &[u8], stable_frames: u32) -> Result<(Vec<u8>, Option<u32>), String> {
+    let cart = Cartridge::new(bytes)?;
+
+    let screen = Rc::new(RefCell::new(Vec::new()));
+    let frame = Rc::clone(&screen);
+    let mut cpu = NesBuilder::new().build(cart, move |pixels| {
+        let mut frame = frame.borrow_mut();
+        frame.clear();
+        frame.extend_from_slice(pixels);
+    });
+
+    let mut detector = StillDetector::new(stable_frames);
+    for frame in 1..=MAX_FRAMES {
+        let frame_count = cpu.bus.ppu_frame_count();
+        while cpu.bus.ppu_frame_count() == frame_count {
+            if cpu.clock() {
+                return Err(String::from("CPU halted"));
+            }
+        }
+
+        if detector.observe(visible(&screen.borrow())) {
+            return Ok((visible(&screen.borrow()).to_vec(), Some(frame)));
+        }
+    }
+
+    let pixels = visible(&screen.borrow()).to_vec();
+    Ok((pixels, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(colour: u8) -> Vec<u8> {
+        vec![colour; Frame::WIDTH * VISIBLE_HEIGHT * Frame::BYTES_PER_PIXEL]
+    }
+
+    fn title(colour: u8) -> Vec<u8> {
+        let mut pixels = solid(0);
+        pixels[1000] = colour;
+        pixels
+    }
+
+    #[test]
+    fn test_still_detector() {
+        let mut detector = StillDetector::new(3);
+        assert!(!detector.observe(&title(1)));
+        assert!(!detector.observe(&title(1)));
+
+        // A change restarts the count.
+        assert!(!detector.observe(&title(2)));
+        assert!(!detector.observe(&title(2)));
+        assert!(detector.observe(&title(2)));
+    }
+
+    #[test]
+    fn test_blank_screens_are_ignored() {
+        let mut detector = StillDetector::new(2);
+        for _ in 0..10 {
+            assert!(!detector.observe(&solid(0x40)));
+        }
+    }
+
+    #[test]
+    fn test_visible_crops_overscan() {
+        let row = Frame::WIDTH * Frame::BYTES_PER_PIXEL;
+        let mut frame = vec![0; Frame::HEIGHT * row];
+        frame[OVERSCAN_ROWS * row] = 1;
+        frame[(Frame::HEIGHT - OVERSCAN_ROWS) * row - 1] = 2;
+
+        let pixels = visible(&frame);
+        assert_eq!(pixels.len(), VISIBLE_HEIGHT * row);
+        assert_eq!(pixels.first(), Some(&1));
+        assert_eq!(pixels.last(), Some(&2));
+    }
+
+    #[test]
+    fn test_invalid_rom() {
+        assert!(capture(&[0; 16], DEFAULT_STABLE_FRAMES).is_err());
+    }
+}
@@ -1,132 +1,200 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
 use crate::cpu::AddressingMode;
 use crate::cpu::Cpu;
 use crate::cpu::Memory;
-use crate::instructions::OPCODES;
+use crate::disasm::{self, Operand};
 
+/// Returns the trace line of the instruction at the program counter, in the
+/// nestest.log format.
 pub fn trace(cpu: &mut Cpu) -> String {
-    // Get the current opcode.
-    let code = cpu.mem_read_byte(cpu.pc);
-    let op = *OPCODES.get(&code).unwrap();
-
-    let begin = cpu.pc;
-    let mut hex_dump = vec![];
-    hex_dump.push(code);
-
-    // Get the operands and memory used by the current opcode.
-    let (mem_addr, stored_value) = match op.mode {
-        AddressingMode::Immediate | AddressingMode::Implied => (0, 0),
-        _ => {
-            let (addr, _) = cpu.get_operand_mode_address(&op.mode, begin + 1);
-            (addr, cpu.mem_read_byte(addr))
-        }
+    let mut line = String::new();
+    write_trace(cpu, &mut line);
+    line
+}
+
+/// Columns which may follow the registers in a trace line.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Columns {
+    /// The PPU scanline and dot, e.g. `PPU:  0, 21`.
+    pub ppu: bool,
+
+    /// The number of CPU cycles run, e.g. `CYC:7`.
+    pub cycles: bool,
+}
+
+impl Columns {
+    /// Every column, as in the nestest.log with cycles and the logs of FCEUX
+    /// and Mesen.
+    pub const ALL: Columns = Columns {
+        ppu: true,
+        cycles: true,
     };
+}
 
-    // Build an assembly string representation of the operation.
-    let asm_op = match op.len {
-        1 => match op.code {
-            0x0A | 0x4A | 0x2A | 0x6A => "A ".to_string(),
-            _ => String::from(""),
-        },
-        2 => {
-            let address: u8 = cpu.mem_read_byte(begin + 1);
-            hex_dump.push(address);
-
-            match op.mode {
-                AddressingMode::Immediate => format!("#${:02x}", address),
-                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::ZeroPageX => format!(
-                    "${:02x},X @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::ZeroPageY => format!(
-                    "${:02x},Y @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::IndirectX => format!(
-                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
-                    address,
-                    (address.wrapping_add(cpu.x)),
-                    mem_addr,
-                    stored_value
-                ),
-                AddressingMode::IndirectY => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
-                    address,
-                    (mem_addr.wrapping_sub(cpu.y as u16)),
-                    mem_addr,
-                    stored_value
-                ),
-                AddressingMode::Implied => {
-                    let address: usize =
-                        (begin as usize + 2).wrapping_add((address as i8) as usize);
-                    format!("${:04x}", address)
-                }
-
-                _ => panic!(
-                    "unexpected addressing mode {:?} has op-len 2. code {:02x}",
-                    op.mode, op.code
-                ),
-            }
+/// Appends the trace line of [`trace`] to the buffer. Traces are written for
+/// every instruction, so the line is built in place rather than from
+/// temporary strings, and a cleared buffer can be reused without allocating.
+pub fn write_trace(cpu: &mut Cpu, out: &mut String) {
+    write_trace_with(cpu, out, Columns::default())
+}
+
+/// Appends the trace line of [`trace`] to the buffer followed by the given
+/// columns, see [`write_trace`].
+pub fn write_trace_with(cpu: &mut Cpu, out: &mut String, columns: Columns) {
+    let start = out.len();
+    let line = disasm::decode(cpu, cpu.pc);
+
+    // Dump the bytes of the instruction, padded to the longest.
+    let _ = write!(out, "{:04x} ", line.addr);
+    for byte in line.bytes() {
+        let _ = write!(out, " {:02x}", byte);
+    }
+    pad(out, start + 14);
+    let _ = write!(out, " {: >4} ", line.mnemonic);
+
+    // Follow the operand with the addresses it resolves to and the value
+    // stored there, as nestest.log does.
+    let stored = |cpu: &mut Cpu, mode: AddressingMode| {
+        let (addr, _) = cpu.get_operand_mode_address(&mode, line.addr.wrapping_add(1));
+        (addr, cpu.mem_read_byte(addr))
+    };
+    let _ = match line.operand {
+        Operand::None
+        | Operand::Accumulator
+        | Operand::Immediate(_)
+        | Operand::Jump(_)
+        | Operand::Relative(_) => {
+            write!(out, "{}", line.operand)
         }
-        3 => {
-            let address_lo = cpu.mem_read_byte(begin + 1);
-            let address_hi = cpu.mem_read_byte(begin + 2);
-            hex_dump.push(address_lo);
-            hex_dump.push(address_hi);
-
-            let address = cpu.mem_read_word(begin + 1);
-
-            match op.mode {
-                AddressingMode::Implied => {
-                    if op.code == 0x6C {
-                        let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read_byte(address);
-                            let hi = cpu.mem_read_byte(address & 0xFF00);
-                            (hi as u16) << 8 | (lo as u16)
-                        } else {
-                            cpu.mem_read_word(address)
-                        };
-
-                        format!("(${:04x}) = {:04x}", address, jmp_addr)
-                    } else {
-                        format!("${:04x}", address)
-                    }
-                }
-                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::AbsoluteX => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::AbsoluteY => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                _ => panic!(
-                    "unexpected addressing mode {:?} has op-len 3. code {:02x}",
-                    op.mode, op.code
-                ),
-            }
+        Operand::ZeroPage(_) | Operand::Absolute(_) => {
+            let mode = match line.len {
+                2 => AddressingMode::ZeroPage,
+                _ => AddressingMode::Absolute,
+            };
+            let (_, value) = stored(cpu, mode);
+            write!(out, "{} = {:02x}", line.operand, value)
+        }
+        Operand::ZeroPageX(_) | Operand::ZeroPageY(_) => {
+            let mode = match line.operand {
+                Operand::ZeroPageX(_) => AddressingMode::ZeroPageX,
+                _ => AddressingMode::ZeroPageY,
+            };
+            let (addr, value) = stored(cpu, mode);
+            write!(out, "{} @ {:02x} = {:02x}", line.operand, addr, value)
+        }
+        Operand::AbsoluteX(_) | Operand::AbsoluteY(_) => {
+            let mode = match line.operand {
+                Operand::AbsoluteX(_) => AddressingMode::AbsoluteX,
+                _ => AddressingMode::AbsoluteY,
+            };
+            let (addr, value) = stored(cpu, mode);
+            write!(out, "{} @ {:04x} = {:02x}", line.operand, addr, value)
+        }
+        Operand::IndirectX(ptr) => {
+            let (addr, value) = stored(cpu, AddressingMode::IndirectX);
+            let ptr = ptr.wrapping_add(cpu.x);
+            write!(
+                out,
+                "{} @ {:02x} = {:04x} = {:02x}",
+                line.operand, ptr, addr, value
+            )
+        }
+        Operand::IndirectY(_) => {
+            let (addr, value) = stored(cpu, AddressingMode::IndirectY);
+            let base = addr.wrapping_sub(cpu.y as u16);
+            write!(
+                out,
+                "{} = {:04x} @ {:04x} = {:02x}",
+                line.operand, base, addr, value
+            )
+        }
+        Operand::Indirect(ptr) => {
+            // JMP doesn't carry into the high byte of the pointer.
+            let lo = cpu.mem_read_byte(ptr);
+            let hi = cpu.mem_read_byte(ptr & 0xFF00 | (ptr as u8).wrapping_add(1) as u16);
+            write!(
+                out,
+                "{} = {:04x}",
+                line.operand,
+                u16::from_le_bytes([lo, hi])
+            )
         }
-        _ => String::from(""),
     };
 
-    let hex_str = hex_dump
-        .iter()
-        .map(|z| format!("{:02x}", z))
-        .collect::<Vec<String>>()
-        .join(" ");
-    let asm_str = format!(
-        "{:04x}  {:8} {: >4} {}",
-        begin, hex_str, op.mnemonic, asm_op
-    )
-    .trim()
-    .to_string();
-
-    format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        asm_str, cpu.a, cpu.x, cpu.y, cpu.status, cpu.sp
-    )
-    .to_ascii_uppercase()
+    // Implied instructions leave a trailing space before the registers.
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    pad(out, start + 47);
+    let _ = write!(
+        out,
+        " A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        cpu.a, cpu.x, cpu.y, cpu.status, cpu.sp
+    );
+
+    out[start..].make_ascii_uppercase();
+
+    if columns.ppu {
+        let (scanline, dot) = cpu.bus.ppu_position();
+        let _ = write!(out, " PPU:{:>3},{:>3}", scanline, dot);
+    }
+    if columns.cycles {
+        let _ = write!(out, " CYC:{}", cpu.bus.cpu_cycles());
+    }
+}
+
+/// Pads the buffer with spaces up to the given length.
+fn pad(out: &mut String, len: usize) {
+    while out.len() < len {
+        out.push(' ');
+    }
+}
+
+/// Returns the trace line of [`trace`] followed by the PPU scanline and dot
+/// and the number of CPU cycles run, matching the full nestest.log format.
+pub fn trace_full(cpu: &mut Cpu) -> String {
+    let mut line = String::new();
+    write_trace_with(cpu, &mut line, Columns::ALL);
+    line
+}
+
+/// Streams a trace line with every column for each instruction to a file,
+/// for comparing a run against the logs of other emulators. Lines are
+/// buffered, as a second of emulation runs to around 600,000 of them.
+pub struct TraceLog {
+    out: Box<dyn Write>,
+    line: String,
+}
+
+impl TraceLog {
+    /// Creates the file at the given path, replacing any there.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Returns a log written to the given writer, which should be buffered.
+    pub fn new(out: impl Write + 'static) -> Self {
+        TraceLog {
+            out: Box::new(out),
+            line: String::with_capacity(128),
+        }
+    }
+
+    /// Writes the trace line of the instruction at the program counter.
+    pub fn write(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        self.line.clear();
+        write_trace_with(cpu, &mut self.line, Columns::ALL);
+        self.line.push('\n');
+        self.out.write_all(self.line.as_bytes())
+    }
+
+    /// Writes out any buffered lines.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
 }
 
 #[cfg(test)]
@@ -149,6 +217,7 @@ mod tests {
         bus.mem_write_byte(104, 0x00);
 
         let mut cpu = Cpu::new(bus);
+        cpu.set_halt_on_brk(true);
         cpu.pc = 0x64;
         cpu.a = 1;
         cpu.x = 2;
@@ -190,6 +259,7 @@ mod tests {
         bus.mem_write_byte(0x400, 0xAA);
 
         let mut cpu = Cpu::new(bus);
+        cpu.set_halt_on_brk(true);
         cpu.pc = 0x64;
         cpu.y = 0;
 
@@ -208,4 +278,53 @@ mod tests {
             result[0]
         );
     }
+
+    /// A writer whose output the test can read back.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_log() {
+        let cart = test_cartridge(vec![], None).unwrap();
+        let mut bus = SystemBus::new(Rc::new(RefCell::new(cart)), 44100.0, |_| {});
+        bus.mem_write_byte(100, 0xCA);
+        bus.mem_write_byte(101, 0xEA);
+
+        let mut cpu = Cpu::new(bus);
+        cpu.pc = 0x64;
+        cpu.bus.tick(7);
+
+        let mut line = String::new();
+        let columns = Columns {
+            ppu: true,
+            cycles: false,
+        };
+        write_trace_with(&mut cpu, &mut line, columns);
+        assert!(line.ends_with("SP:FD PPU:  0, 21"));
+
+        let buffer = SharedBuffer::default();
+        let mut log = TraceLog::new(buffer.clone());
+        for _ in 0..2 {
+            log.write(&mut cpu).unwrap();
+            cpu.clock();
+        }
+        log.flush().unwrap();
+
+        let logged = String::from_utf8(buffer.0.take()).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("SP:FD PPU:  0, 21 CYC:7"));
+        assert!(lines[1].starts_with("0065  EA        NOP"));
+        assert!(lines[1].ends_with("SP:FD PPU:  0, 27 CYC:9"));
+    }
 }
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Bits of $4016 driven by the cabinet's service button and coin slots.
+pub const VS_SERVICE: u8 = 0x04;
+pub const VS_COIN_1: u8 = 0x20;
+pub const VS_COIN_2: u8 = 0x40;
+
+/// The inputs of a Vs. System cabinet beyond its controllers: the coin
+/// slots, the service button and the 8 DIP switches, which are read through
+/// the otherwise unused bits of the controller ports.
+///
+/// See: https://www.nesdev.org/wiki/Vs._System
+pub struct Cabinet {
+    dip_switches: u8,
+    buttons: u8,
+}
+
+impl Cabinet {
+    /// Returns a cabinet with the given DIP switches, switch 1 in bit 0.
+    pub fn new(dip_switches: u8) -> Self {
+        Cabinet {
+            dip_switches,
+            buttons: 0,
+        }
+    }
+
+    /// Sets whether the given VS_* coin slot or service button bits are
+    /// held.
+    pub fn set_pressed(&mut self, buttons: u8, pressed: bool) {
+        match pressed {
+            true => self.buttons |= buttons,
+            false => self.buttons &= !buttons,
+        }
+    }
+
+    /// Returns the bits read alongside the first controller at $4016: the
+    /// service button in bit 2, DIP switches 1 and 2 in bits 3 and 4, and the
+    /// coin slots in bits 5 and 6.
+    pub fn port1(&self) -> u8 {
+        self.buttons | (self.dip_switches & 0x03) << 3
+    }
+
+    /// Returns the bits read alongside the second controller at $4017: DIP
+    /// switches 3 to 8 in bits 2 to 7.
+    pub fn port2(&self) -> u8 {
+        self.dip_switches & 0xFC
+    }
+}
+
+/// Represents the DIP switch settings of each Vs. System game, keyed by the
+/// checksum of its ROM.
+pub struct DipSwitches {
+    path: Option<PathBuf>,
+    settings: HashMap<u32, u8>,
+}
+
+impl DipSwitches {
+    /// Loads the settings from the file at the given path. A missing file is
+    /// treated as empty and invalid lines are skipped.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let contents = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .unwrap_or_default();
+
+        DipSwitches {
+            path,
+            settings: parse(&contents),
+        }
+    }
+
+    /// Returns the default location of the settings file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("res").join("dips.txt"))
+    }
+
+    /// Returns the DIP switches of the game with the given checksum, all off
+    /// if they have never been set.
+    pub fn get(&self, checksum: u32) -> u8 {
+        self.settings.get(&checksum).copied().unwrap_or(0)
+    }
+
+    /// Sets the DIP switches of the game with the given checksum.
+    pub fn set(&mut self, checksum: u32, dip_switches: u8) {
+        self.settings.insert(checksum, dip_switches);
+    }
+
+    /// Writes the settings back to the file they were loaded from.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        std::fs::write(path, format(&self.settings)).map_err(|e| e.to_string())
+    }
+}
+
+/// Parses lines of "<checksum> <switches>", both in hex.
+fn parse(contents: &str) -> HashMap<u32, u8> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (crc, dips) = line.trim().split_once(' ')?;
+            let crc = u32::from_str_radix(crc, 16).ok()?;
+            let dips = u8::from_str_radix(dips.trim(), 16).ok()?;
+            Some((crc, dips))
+        })
+        .collect()
+}
+
+/// Formats the settings as lines of "<checksum> <switches>", sorted by
+/// checksum.
+fn format(settings: &HashMap<u32, u8>) -> String {
+    let mut lines: Vec<_> = settings
+        .iter()
+        .map(|(crc, dips)| format!("{:08X} {:02X}\n", crc, dips))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ports() {
+        let mut cabinet = Cabinet::new(0b1010_0110);
+        assert_eq!(cabinet.port1(), 0b0001_0000);
+        assert_eq!(cabinet.port2(), 0b1010_0100);
+
+        cabinet.set_pressed(VS_COIN_1 | VS_SERVICE, true);
+        assert_eq!(cabinet.port1(), 0b0011_0100);
+        cabinet.set_pressed(VS_SERVICE, false);
+        assert_eq!(cabinet.port1(), 0b0011_0000);
+    }
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        let mut dips = DipSwitches::load(None);
+        dips.set(0x1234ABCD, 0x81);
+        dips.set(0x00000001, 0x00);
+        assert_eq!(dips.get(0x1234ABCD), 0x81);
+        assert_eq!(dips.get(2), 0);
+
+        let contents = format(&dips.settings);
+        assert_eq!(contents, "00000001 00\n1234ABCD 81\n");
+        assert_eq!(parse(&contents), dips.settings);
+        assert_eq!(parse("nonsense\n0000000A 1FF\n").len(), 0);
+        assert!(dips.save().is_ok());
+    }
+}